@@ -0,0 +1,65 @@
+// Remove escape sequences of terminal output: CSI (`ESC [ ... m` and the like), OSC
+// (`ESC ] ... BEL`), other `ESC x` pairs and control characters except tabs and line breaks.
+pub fn strip(s: &str) -> String {
+    enum State {
+        Text,
+        Escape,
+        // Parameter and intermediate bytes up to a final byte in `@`..=`~`
+        Csi,
+        // Up to BEL or `ESC \`
+        Osc,
+        OscEscape,
+    }
+
+    let mut res = String::with_capacity(s.len());
+    let mut state = State::Text;
+    for c in s.chars() {
+        state = match state {
+            State::Text => match c {
+                '\x1b' => State::Escape,
+                '\u{9b}' => State::Csi,
+                '\u{9d}' => State::Osc,
+                '\t' | '\n' | '\r' => {
+                    res.push(c);
+                    State::Text
+                }
+                c if c.is_control() => State::Text,
+                c => {
+                    res.push(c);
+                    State::Text
+                }
+            },
+            State::Escape => match c {
+                '[' => State::Csi,
+                ']' => State::Osc,
+                // Intermediate bytes like `ESC ( B`
+                ' '..='/' => State::Escape,
+                _ => State::Text,
+            },
+            State::Csi => match c {
+                '@'..='~' => State::Text,
+                ' '..='?' => State::Csi,
+                // Broken sequence. Keep the text after it.
+                '\n' => {
+                    res.push(c);
+                    State::Text
+                }
+                _ => State::Text,
+            },
+            State::Osc => match c {
+                '\x07' | '\u{9c}' => State::Text,
+                '\x1b' => State::OscEscape,
+                '\n' => {
+                    res.push(c);
+                    State::Text
+                }
+                _ => State::Osc,
+            },
+            State::OscEscape => match c {
+                '\\' => State::Text,
+                _ => State::Osc,
+            },
+        };
+    }
+    res
+}
@@ -0,0 +1,52 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// A file next to `path` so that renaming it is atomic
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.acc-{}.tmp", name, std::process::id()))
+}
+
+// Write `contents` to a temporary file, sync it and rename it over `path`. The file at `path`
+// is left as it was if anything fails.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with(path, contents, |_| Ok(()))
+}
+
+// `before_rename` runs on the synced temporary file. An error from it aborts the write.
+pub fn write_with<F>(path: &Path, contents: &[u8], before_rename: F) -> io::Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    // Replace the file a symlink points to rather than the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let temp = temp_path(&path);
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        before_rename(&temp)?;
+        fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    // Make the rename itself durable
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
@@ -1,32 +1,85 @@
 use std::borrow::Cow;
-use std::cmp::{max, min};
-use std::collections::BTreeMap;
+use std::cmp::{max, min, Reverse};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::ffi::OsStr;
 use std::fs;
-use std::io::BufReader;
-use std::io::BufWriter;
+use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
 
 use termion;
 use unicode_width::UnicodeWidthChar;
 
+use crate::ansi;
+use crate::atomic_write;
+use crate::command;
 use crate::compiler::CompileId;
 use crate::compiler::CompileResult;
 use crate::compiler::Compiler;
 use crate::config;
 use crate::config::types::keys;
+use crate::config::types::FinalNewline;
 use crate::core::Cursor;
 use crate::core::CursorRange;
 use crate::core::Id;
+use crate::datetime::DateTime;
 use crate::draw;
-use crate::draw::{styles, CharStyle, LinenumView, View};
+use crate::draw::{styles, CharStyle, CursorShape, LinenumView, View};
 use crate::draw_cache::DrawCache;
 use crate::formatter;
+use crate::history::History;
+use crate::indent::{self, IndentStyle};
+use crate::keymap::Keymap;
+use crate::loader::{Loader, LARGE_FILE};
 use crate::lsp::LSPClient;
+use crate::recent::RecentFiles;
 use crate::ropey_util::RopeExt;
+use crate::spell::Dictionary;
 use crate::syntax;
+use crate::text_object;
+use crate::undo_file;
 use crate::Core;
 
+// What `:set autoread` does about the file on disk
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiskChange {
+    Unchanged,
+    // The buffer is unmodified and can be reloaded
+    Reload,
+    // Both the buffer and the file are changed. Ask before reloading.
+    Conflict,
+}
+
+// The line number gutter. `Ctrl-k` cycles through them in this order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineNumbers {
+    Absolute,
+    // The distance from the cursor line, which shows its own number
+    Relative,
+    Off,
+}
+
+impl LineNumbers {
+    pub fn next(self) -> Self {
+        match self {
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+            LineNumbers::Off => LineNumbers::Absolute,
+        }
+    }
+
+    // The `:set` option
+    pub fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Absolute => "number",
+            LineNumbers::Relative => "relativenumber",
+            LineNumbers::Off => "nonumber",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Yank {
     pub insert_newline: bool,
     pub content: String,
@@ -41,17 +94,127 @@ impl Default for Yank {
     }
 }
 
-fn get_rows(s: &str, width: usize) -> usize {
+#[derive(Clone, Copy)]
+pub struct VisualSelection {
+    pub anchor: Cursor,
+    pub cursor: Cursor,
+    pub line_mode: bool,
+}
+
+// Line shown instead of the real one while previewing `:s`
+pub struct PreviewLine {
+    pub text: String,
+    // Char ranges of replaced text
+    pub changed: Vec<(usize, usize)>,
+}
+
+// Words which precede a name in its definition like `fn name`
+const DEFINITION_KEYWORDS: [&str; 15] = [
+    "fn",
+    "let",
+    "mut",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "type",
+    "const",
+    "static",
+    "mod",
+    "macro_rules!",
+    "def",
+    "class",
+    "function",
+];
+
+const MESSAGE_LOG_SIZE: usize = 100;
+// Keys kept for `:set showkeys`
+const RECENT_KEYS: usize = 12;
+// Lines above the screen scanned for the nesting of rainbow brackets
+const RAINBOW_LOOKBACK: usize = 1000;
+
+fn reflow(lines: &[String], width: usize) -> Vec<String> {
+    let is_prefix_char = |c: char| c.is_whitespace() || "/#*>;".contains(c);
+    let prefix = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            l.chars()
+                .take_while(|&c| is_prefix_char(c))
+                .collect::<String>()
+        })
+        .fold(None, |acc: Option<String>, p| {
+            Some(match acc {
+                None => p,
+                Some(acc) => acc
+                    .chars()
+                    .zip(p.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect(),
+            })
+        })
+        .unwrap_or_default();
+    let prefix_len = prefix.chars().count();
+
+    let mut res = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+    for word in lines.iter().flat_map(|l| {
+        l.chars()
+            .skip(prefix_len)
+            .collect::<String>()
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    }) {
+        let word_len = word.chars().count();
+        if line_len > 0 && prefix_len + line_len + 1 + word_len > width {
+            res.push(format!("{}{}", prefix, line));
+            line.clear();
+            line_len = 0;
+        }
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        line.push_str(&word);
+        line_len += word_len;
+    }
+    if line_len > 0 || res.is_empty() {
+        res.push(format!("{}{}", prefix, line).trim_end().to_string());
+    }
+    res
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Screen rows of a line. Wrapped rows start at `indent` with `:set breakindent`. Tabs fill
+// cells up to the next multiple of `tab_width`, which is 0 unless `:set list`.
+fn get_rows(s: &str, width: usize, indent: usize, tab_width: usize) -> usize {
+    let indent = min(indent, width / 2);
     let mut x = 0;
     let mut y = 1;
+    // Column in the line
+    let mut col = 0;
 
     for c in s.chars() {
-        let w = c.width().unwrap_or(0);
-        if x + w < width {
-            x += w;
+        // A tab is drawn cell by cell
+        let (n, w) = if c == '\t' && tab_width > 0 {
+            (tab_width - col % tab_width, 1)
         } else {
-            y += 1;
-            x = w;
+            (1, draw::char_width(c))
+        };
+        col += n * w;
+        for _ in 0..n {
+            if x + w < width {
+                x += w;
+            } else {
+                y += 1;
+                x = indent + w;
+            }
         }
     }
     y
@@ -59,17 +222,89 @@ fn get_rows(s: &str, width: usize) -> usize {
 
 pub struct Buffer<'a> {
     path: Option<PathBuf>,
+    // `[filetype.name]` of the config. Detected from `path` or set by `:setfiletype`.
+    filetype: Option<String>,
     pub core: Core,
     pub search: Vec<char>,
+    pub search_offset: command::SearchOffset,
+    // `:set commentstring=//\ %s`
+    pub commentstring: Option<String>,
+    // `:set matchpairs=(:),<:>`
+    pub matchpairs: Option<String>,
+    // The file opened before the current one and its cursor for `Ctrl-6`
+    alternate: Option<(PathBuf, Cursor)>,
     syntax_parent: &'a syntax::SyntaxParent,
     config: &'a config::ConfigWithDefault,
     syntax: syntax::Syntax<'a>,
     pub snippet: BTreeMap<String, String>,
+    keymap: Keymap,
+    // `:iabbrev teh the`
+    pub abbreviations: BTreeMap<String, String>,
     pub yank: Yank,
+    pub registers: BTreeMap<char, Yank>,
+    pub marks: BTreeMap<char, Cursor>,
+    pub last_visual: Option<VisualSelection>,
+    // Positions to go back by Ctrl-o with their files
+    pub jumps: Vec<(Option<PathBuf>, Cursor)>,
+    // Extra cursors besides `core.cursor()` for multi-cursor editing
+    pub cursors: Vec<Cursor>,
+    // Recent messages for `:messages`, oldest first
+    pub messages: VecDeque<String>,
+    pub preview: BTreeMap<usize, PreviewLine>,
+    pub recent: RecentFiles,
+    pub command_history: History,
+    pub search_history: History,
+    // Where undo history is persisted with `undofile = true`
+    pub undo_dir: Option<PathBuf>,
+    // Results of `:grep` as (row, col, line)
+    pub quickfix: Vec<(usize, usize, String)>,
+    // Folds as inclusive rows, sorted by the first row
+    pub folds: Vec<(usize, usize)>,
+    pub closed_folds: BTreeSet<(usize, usize)>,
+    // `:set foldmethod=indent`. Folds are computed again after the text changes.
+    fold_indent: bool,
+    folds_update: Option<Id>,
+    pub quickfix_index: usize,
+    // Pattern, replacement and `g` flag of the last `:s`
+    pub last_substitute: Option<(regex::Regex, String, bool)>,
+    // `:set spell`. `dictionary` is loaded on the first `:set spell`.
+    pub spell: bool,
+    pub dictionary: Option<Dictionary>,
+    // `:set list`. Render tabs, trailing spaces and end of lines.
+    pub list: bool,
+    // `:set cursorline`. Shade the row of the cursor.
+    pub cursorline: bool,
+    // `:set heatmap`. Tint line numbers of lines edited in this session by their age.
+    pub heatmap: bool,
+    // `:set showkeys`. The last keys typed are shown at the top right.
+    pub showkeys: bool,
+    pub recent_keys: VecDeque<String>,
+    // Detected from the file when it is opened
+    pub indent_style: Option<IndentStyle>,
+    // `:set scrollbar`. Draw a scrollbar on the right edge.
+    pub scrollbar: bool,
+    // `:set number`, `:set relativenumber` and `:set nonumber`
+    pub line_numbers: LineNumbers,
+    // `:zen`. Hide line numbers and center the text in `zen_width` columns.
+    pub zen: bool,
+    // `:set breakindent`. Indent wrapped rows like the start of the line.
+    pub breakindent: bool,
+    // `:set multiline`. `\n` in a search and `:s` patterns match across line breaks.
+    pub multiline: bool,
+    // `:set autoread`. Reload the file when it is changed on disk.
+    pub autoread: bool,
+    // `:set buftype=nofile`. Never counted as modified, so nothing asks to save it.
+    pub scratch: bool,
+    // Modified time of the file when it was opened or saved
+    mtime: Option<SystemTime>,
+    // Reading a large file in the background
+    loader: Option<Loader>,
     last_save: Id,
     pub lsp: Option<LSPClient>,
     compiler: Option<Compiler<'a>>,
     row_offset: usize,
+    // Size of the split window. The terminal size if `None`.
+    pub window_size: Option<(usize, usize)>,
     last_compiler_result: Option<CompileResult>,
     cache: DrawCache<'a>,
     buffer_update: Id,
@@ -77,206 +312,1940 @@ pub struct Buffer<'a> {
     last_compiler_compiled: CompileId,
 }
 
-impl<'a> Buffer<'a> {
-    fn windows_size() -> (usize, usize) {
-        let (cols, rows) = termion::terminal_size().unwrap();
-        (rows as usize, cols as usize)
+impl<'a> Buffer<'a> {
+    fn windows_size(&self) -> (usize, usize) {
+        if let Some(size) = self.window_size {
+            return size;
+        }
+        let (cols, rows) = termion::terminal_size().unwrap();
+        (rows as usize, cols as usize)
+    }
+
+    pub fn row_offset(&self) -> usize {
+        self.row_offset
+    }
+
+    pub fn set_row_offset(&mut self, row_offset: usize) {
+        self.row_offset = min(row_offset, self.core.buffer().len_lines() - 1);
+    }
+
+    pub fn new(
+        syntax_parent: &'a syntax::SyntaxParent,
+        config: &'a config::ConfigWithDefault,
+    ) -> Self {
+        let syntax = syntax_parent.load_syntax_or_txt("txt");
+
+        let mut res = Self {
+            path: None,
+            filetype: None,
+            core: Core::default(),
+            search: Vec::new(),
+            search_offset: command::SearchOffset::default(),
+            commentstring: None,
+            matchpairs: None,
+            alternate: None,
+            cache: DrawCache::new(&syntax),
+            syntax,
+            snippet: BTreeMap::new(),
+            keymap: Keymap::default(),
+            abbreviations: BTreeMap::new(),
+            yank: Yank::default(),
+            registers: BTreeMap::new(),
+            marks: BTreeMap::new(),
+            last_visual: None,
+            jumps: Vec::new(),
+            cursors: Vec::new(),
+            messages: VecDeque::new(),
+            preview: BTreeMap::new(),
+            recent: RecentFiles::default(),
+            command_history: History::default(),
+            search_history: History::default(),
+            undo_dir: None,
+            quickfix: Vec::new(),
+            folds: Vec::new(),
+            closed_folds: BTreeSet::new(),
+            fold_indent: false,
+            folds_update: None,
+            quickfix_index: 0,
+            last_substitute: None,
+            list: false,
+            cursorline: false,
+            heatmap: false,
+            showkeys: false,
+            recent_keys: VecDeque::new(),
+            indent_style: None,
+            scrollbar: false,
+            line_numbers: LineNumbers::Absolute,
+            zen: false,
+            breakindent: false,
+            multiline: false,
+            scratch: false,
+            autoread: false,
+            mtime: None,
+            loader: None,
+            spell: false,
+            dictionary: None,
+            last_save: Id::default(),
+            lsp: None,
+            compiler: config.get::<keys::Compiler>(None, None).map(Compiler::new),
+            row_offset: 0,
+            window_size: None,
+            last_compiler_result: None,
+            syntax_parent,
+            config,
+            buffer_update: Id::default(),
+            last_compiler_submit: CompileId::default(),
+            last_compiler_compiled: CompileId::default(),
+        };
+        res.reset_undo_memory();
+        // An empty buffer is not modified
+        res.last_save = res.core.buffer_changed();
+        res.restart_lsp();
+        res.reset_snippet();
+        res.reset_keymap();
+        res
+    }
+
+    fn extension(&self) -> Option<&OsStr> {
+        self.path.as_ref().and_then(|p| p.extension())
+    }
+
+    pub fn get_config<A: typemap::Key>(&self) -> Option<&'a A::Value> {
+        self.config.get::<A>(self.path(), self.filetype())
+    }
+
+    fn reset_snippet(&mut self) {
+        self.snippet = self.config.snippets(self.path(), self.filetype());
+    }
+
+    pub fn filetype(&self) -> Option<&str> {
+        self.filetype.as_deref()
+    }
+
+    // `:setfiletype`. Settings, syntax and snippets of the file type replace the detected ones.
+    pub fn set_filetype(&mut self, filetype: &str) {
+        self.filetype = Some(filetype.into());
+        if let Some(extension) = config::filetype_extension(filetype) {
+            self.set_syntax(extension);
+        }
+        self.set_language();
+        self.reset_snippet();
+        self.reset_keymap();
+        self.reset_undo_memory();
+    }
+
+    pub fn extend_cache_duration(&mut self, duration: std::time::Duration) {
+        self.cache
+            .extend_cache_duration(self.core.buffer(), duration);
+    }
+
+    pub fn indent_width(&self) -> usize {
+        match self.indent_style {
+            Some(IndentStyle::Spaces(width)) => width,
+            _ => self.get_config::<keys::IndentWidth>().cloned().unwrap_or(4),
+        }
+    }
+
+    // One level of indent
+    pub fn indent_unit(&self) -> String {
+        let expandtab = self
+            .get_config::<keys::ExpandTab>()
+            .cloned()
+            .unwrap_or(true);
+        if self.indent_style == Some(IndentStyle::Tabs)
+            || (self.indent_style.is_none() && !expandtab)
+        {
+            "\t".into()
+        } else {
+            " ".repeat(self.indent_width())
+        }
+    }
+
+    // Follow the indentation of the file if it is clear
+    pub fn detect_indent(&mut self) {
+        self.indent_style = indent::detect(self.core.buffer().lines().map(String::from));
+    }
+
+    pub fn is_ansi_color(&self) -> bool {
+        self.get_config::<keys::ANSIColor>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn is_emacs_insert_keys(&self) -> bool {
+        self.get_config::<keys::EmacsInsertKeys>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn is_undo_file(&self) -> bool {
+        self.get_config::<keys::UndoFile>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // The leader and the trailer like `*/`. `commentstring`, then the file type's `comment`,
+    // then a guess from the text.
+    pub fn comment_leader(&self) -> (String, String) {
+        if let Some(commentstring) = self.commentstring.as_ref() {
+            let mut parts = commentstring.splitn(2, "%s");
+            let leader = parts.next().unwrap_or("").trim_end();
+            let trailer = parts.next().unwrap_or("").trim_start();
+            return (leader.into(), trailer.into());
+        }
+        let leader = self
+            .config
+            .get_file::<keys::Comment>(self.path(), self.filetype())
+            .cloned()
+            .or_else(|| self.guess_comment_leader())
+            .or_else(|| self.get_config::<keys::Comment>().cloned())
+            .unwrap_or_else(|| "#".into());
+        (leader, String::new())
+    }
+
+    // From a shebang or a comment on the first non-blank line
+    fn guess_comment_leader(&self) -> Option<String> {
+        let line = self
+            .core
+            .buffer()
+            .lines()
+            .map(String::from)
+            .find(|l| !l.trim().is_empty())?;
+        let line = line.trim_start();
+        if let Some(command) = line.strip_prefix("#!") {
+            // `#!/usr/bin/env node` or `#!/usr/bin/lua`
+            let mut words = command.split_whitespace();
+            let mut interpreter = words.next()?.rsplit('/').next()?;
+            if interpreter == "env" {
+                interpreter = words.next()?;
+            }
+            let leader = match interpreter {
+                "node" | "deno" => "//",
+                "lua" => "--",
+                _ => "#",
+            };
+            return Some(leader.into());
+        }
+        ["//", "--", ";", "#", "%"]
+            .iter()
+            .find(|leader| line.starts_with(*leader))
+            .map(|leader| leader.to_string())
+    }
+
+    pub fn normal_cursor(&self) -> CursorShape {
+        self.get_config::<keys::CursorNormal>()
+            .cloned()
+            .unwrap_or(CursorShape::Block)
+    }
+
+    pub fn insert_cursor(&self) -> CursorShape {
+        self.get_config::<keys::CursorInsert>()
+            .cloned()
+            .unwrap_or(CursorShape::Bar)
+    }
+
+    pub fn replace_cursor(&self) -> CursorShape {
+        self.get_config::<keys::CursorReplace>()
+            .cloned()
+            .unwrap_or(CursorShape::Underline)
+    }
+
+    pub fn visual_cursor(&self) -> CursorShape {
+        self.get_config::<keys::CursorVisual>()
+            .cloned()
+            .unwrap_or(CursorShape::Block)
+    }
+
+    pub fn is_rainbow_brackets(&self) -> bool {
+        self.get_config::<keys::RainbowBrackets>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Pairs `%` jumps between
+    pub fn match_pairs(&self) -> Vec<(char, char)> {
+        let pairs = self
+            .matchpairs
+            .as_deref()
+            .or_else(|| self.get_config::<keys::MatchPairs>().map(String::as_str))
+            .unwrap_or("(:),{:},[:]");
+        text_object::parse_match_pairs(pairs)
+    }
+
+    // Shown on rows past the last line. A space hides it.
+    pub fn end_of_buffer(&self) -> char {
+        self.get_config::<keys::EndOfBuffer>()
+            .cloned()
+            .unwrap_or('~')
+    }
+
+    pub fn leader(&self) -> char {
+        self.get_config::<keys::Leader>().cloned().unwrap_or(' ')
+    }
+
+    // `None` waits for the rest of a key sequence forever
+    pub fn timeoutlen(&self) -> Option<Duration> {
+        self.get_config::<keys::TimeoutLen>()
+            .filter(|&&ms| ms > 0)
+            .map(|&ms| Duration::from_millis(ms))
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    // Built once for the file, not for each key
+    fn reset_keymap(&mut self) {
+        self.keymap = self
+            .get_config::<keys::Keymap>()
+            .map(Keymap::new)
+            .unwrap_or_default();
+    }
+
+    pub fn leader_mappings(&self) -> BTreeMap<String, String> {
+        self.get_config::<keys::LeaderMappings>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn date_format(&self) -> String {
+        self.get_config::<keys::DateFormat>()
+            .cloned()
+            .unwrap_or_else(|| "%Y-%m-%d".into())
+    }
+
+    pub fn time_format(&self) -> String {
+        self.get_config::<keys::TimeFormat>()
+            .cloned()
+            .unwrap_or_else(|| "%H:%M".into())
+    }
+
+    // In bytes. The oldest undo steps are dropped beyond it.
+    pub fn undo_memory(&self) -> Option<usize> {
+        self.get_config::<keys::UndoMemory>().map(|kb| kb * 1024)
+    }
+
+    pub fn is_smart_home(&self) -> bool {
+        self.get_config::<keys::SmartHome>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn zen_width(&self) -> usize {
+        self.get_config::<keys::ZenWidth>().cloned().unwrap_or(80)
+    }
+
+    // Columns for text in a window of `cols` columns
+    fn text_cols(&self, cols: usize) -> usize {
+        let cols = if self.scrollbar {
+            cols.saturating_sub(1)
+        } else {
+            cols
+        };
+        if self.zen {
+            min(cols, self.zen_width())
+        } else if self.line_numbers == LineNumbers::Off {
+            cols
+        } else {
+            cols.saturating_sub(LinenumView::prefix_width(self.core.buffer().len_lines()))
+        }
+    }
+
+    pub fn text_width(&self) -> usize {
+        self.get_config::<keys::TextWidth>().cloned().unwrap_or(79)
+    }
+
+    // Load the word list from `spellfile` or `/usr/share/dict/words`
+    pub fn load_dictionary(&mut self) -> bool {
+        if self.dictionary.is_none() {
+            let path = self
+                .get_config::<keys::SpellFile>()
+                .cloned()
+                .unwrap_or_else(|| "/usr/share/dict/words".into());
+            self.dictionary = Dictionary::load(Path::new(&path));
+        }
+        self.dictionary.is_some()
+    }
+
+    pub fn restart_lsp(&mut self) {
+        let ext = self
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        self.lsp = self
+            .config
+            .get::<keys::LSP>(self.path(), self.filetype())
+            .and_then(|c| LSPClient::start(c.command(), ext));
+    }
+
+    fn set_syntax(&mut self, extension: &str) {
+        self.syntax = self.syntax_parent.load_syntax_or_txt(extension);
+        self.cache = DrawCache::new(&self.syntax);
+    }
+
+    pub fn set_language(&mut self) {
+        self.compiler = self.get_config::<keys::Compiler>().map(Compiler::new);
+        self.restart_lsp();
+    }
+
+    pub fn indent(&mut self) {
+        self.core.indent(self.indent_width(), &self.indent_unit());
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|p| p.as_path())
+    }
+
+    pub fn set_path(&mut self, path: PathBuf) {
+        if self.extension() != path.extension() {
+            self.set_language();
+        }
+        self.filetype = config::detect_filetype(&path);
+        self.path = Some(path);
+        self.reset_undo_memory();
+        self.reset_keymap();
+    }
+
+    // A new text keeps `:set virtualedit` and follows the undo memory of the file type
+    fn replace_core(&mut self, mut core: Core) {
+        core.virtual_edit = self.core.virtual_edit;
+        self.core = core;
+        self.reset_undo_memory();
+    }
+
+    fn reset_undo_memory(&mut self) {
+        self.core.undo_memory = self.undo_memory();
+    }
+
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) {
+        let mut file = fs::File::open(path.as_ref()).ok();
+        let size = file
+            .as_ref()
+            .and_then(|f| f.metadata().ok())
+            .map(|m| m.len())
+            .unwrap_or(0);
+        self.loader = None;
+        self.set_alternate(path.as_ref());
+        // A large file is kept in `file` and read in the background after the setup
+        let mut core = match file.take() {
+            Some(f) if size <= LARGE_FILE => Core::from_reader(BufReader::new(f)).unwrap(),
+            f => {
+                file = f;
+                Core::default()
+            }
+        };
+
+        self.path = Some(path.as_ref().to_path_buf());
+        self.filetype = config::detect_filetype(path.as_ref());
+        if self.is_undo_file() && file.is_none() {
+            if let Some(dir) = self.undo_dir.as_ref() {
+                undo_file::load(dir, path.as_ref(), &mut core);
+            }
+        }
+        self.recent.push(path.as_ref());
+
+        let syntax_extension = self
+            .get_config::<keys::SyntaxExtension>()
+            .cloned()
+            .or_else(|| {
+                path.as_ref()
+                    .extension()
+                    .or_else(|| path.as_ref().file_name())
+                    .unwrap_or_default()
+                    .to_str()
+                    .map(String::from)
+            })
+            .unwrap_or_default();
+        self.set_syntax(&syntax_extension);
+
+        self.row_offset = 0;
+        self.last_save = core.buffer_changed();
+        self.mtime = self.disk_mtime();
+        self.scratch = false;
+        self.replace_core(core);
+        if file.is_none() && self.get_config::<keys::StripAnsi>() == Some(&true) {
+            let last = self.core.buffer().len_lines() - 1;
+            if self.strip_ansi(0, last) > 0 {
+                self.core.commit();
+            }
+        }
+        self.set_language();
+        self.cache = DrawCache::new(&self.syntax);
+        self.compile(false);
+        self.reset_snippet();
+        self.reset_keymap();
+        self.reset_folds();
+        if let Some(file) = file {
+            // Detected when the whole text is loaded
+            self.indent_style = None;
+            self.load_from(file, size);
+        } else {
+            self.detect_indent();
+        }
+    }
+
+    fn set_alternate(&mut self, next: &Path) {
+        if let Some(path) = self.path.as_ref().filter(|&p| p != next) {
+            self.alternate = Some((path.clone(), self.core.cursor()));
+        }
+    }
+
+    pub fn has_alternate(&self) -> bool {
+        self.alternate.is_some()
+    }
+
+    // `Ctrl-6`. Returns false if no other file has been opened.
+    pub fn open_alternate(&mut self) -> bool {
+        if let Some((path, cursor)) = self.alternate.clone() {
+            self.open(&path);
+            let cursor = self.core.clamp_cursor(cursor);
+            self.core.set_cursor(cursor);
+            self.show_cursor();
+            true
+        } else {
+            false
+        }
+    }
+
+    // `:bd`. The alternate file is the only other buffer, so it takes the place of the current
+    // one. Returns false if there was none and the buffer is left empty.
+    pub fn delete_buffer(&mut self) -> bool {
+        let opened = self.open_alternate();
+        if !opened {
+            self.new_file();
+        }
+        self.alternate = None;
+        opened
+    }
+
+    // `:enew`. An empty buffer without a path.
+    pub fn new_file(&mut self) {
+        self.loader = None;
+        self.set_alternate(Path::new(""));
+        self.path = None;
+        self.filetype = None;
+        self.replace_core(Core::default());
+        self.row_offset = 0;
+        self.last_save = self.core.buffer_changed();
+        self.mtime = None;
+        self.scratch = false;
+        self.indent_style = None;
+        self.reset_folds();
+        self.set_syntax("txt");
+        self.set_language();
+        self.reset_snippet();
+        self.reset_keymap();
+    }
+
+    // Read the text in the background. The first chunk is waited for to show something.
+    pub fn load_from<R: Read + Send + 'static>(&mut self, reader: R, size: u64) {
+        self.replace_core(Core::default());
+        self.last_save = self.core.buffer_changed();
+        self.cache.dirty_from(0);
+        let mut loader = Loader::spawn(reader, size);
+        let first = loader.recv();
+        self.loader = Some(loader);
+        match first {
+            Some(chunk) => self.append_loaded(chunk),
+            None => self.loaded(),
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    pub fn load_progress(&self) -> Option<usize> {
+        self.loader.as_ref().map(Loader::progress)
+    }
+
+    // Append chunks read so far without blocking
+    pub fn poll_load(&mut self) {
+        while let Some(loader) = self.loader.as_mut() {
+            match loader.try_recv() {
+                Some(Some(chunk)) => self.append_loaded(chunk),
+                Some(None) => self.loaded(),
+                None => break,
+            }
+        }
+    }
+
+    // Wait for the rest of the text
+    pub fn finish_load(&mut self) {
+        while let Some(loader) = self.loader.as_mut() {
+            match loader.recv() {
+                Some(chunk) => self.append_loaded(chunk),
+                None => self.loaded(),
+            }
+        }
+    }
+
+    fn append_loaded(&mut self, chunk: io::Result<String>) {
+        match chunk {
+            Ok(s) => {
+                let row = self.core.buffer().len_lines() - 1;
+                self.core.append_loaded(&s);
+                self.cache.dirty_from(row);
+            }
+            Err(err) => {
+                self.log_message(&format!("Failed to read: {}", err));
+                self.loaded();
+            }
+        }
+    }
+
+    fn loaded(&mut self) {
+        self.loader = None;
+        self.detect_indent();
+        self.folds_update = None;
+        if self.is_undo_file() {
+            if let (Some(dir), Some(path)) = (self.undo_dir.as_ref(), self.path.as_ref()) {
+                undo_file::load(dir, path, &mut self.core);
+            }
+        }
+        self.last_save = self.core.buffer_changed();
+    }
+
+    // Changed since it was opened or saved
+    pub fn is_modified(&self) -> bool {
+        !self.scratch && self.last_save != self.core.buffer_changed()
+    }
+
+    pub fn disk_mtime(&self) -> Option<SystemTime> {
+        self.path
+            .as_ref()
+            .and_then(|path| fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+    }
+
+    // Compare `mtime` of the file on disk to the one recorded at load or save
+    pub fn disk_change(&self, mtime: Option<SystemTime>) -> DiskChange {
+        if !self.autoread || mtime.is_none() || mtime == self.mtime {
+            DiskChange::Unchanged
+        } else if self.is_modified() {
+            DiskChange::Conflict
+        } else {
+            DiskChange::Reload
+        }
+    }
+
+    // Keep the buffer and stop asking about the current file on disk
+    pub fn ignore_disk_change(&mut self, mtime: Option<SystemTime>) {
+        self.mtime = mtime;
+    }
+
+    // Open the file again keeping the cursor and the scroll position
+    pub fn reload(&mut self) {
+        if let Some(path) = self.path.clone() {
+            let cursor = self.core.cursor();
+            let row_offset = self.row_offset;
+            self.open(path);
+            let cursor = self.core.clamp_cursor(cursor);
+            self.core.set_cursor(cursor);
+            self.set_row_offset(row_offset);
+        }
+    }
+
+    pub fn final_newline(&self) -> FinalNewline {
+        self.get_config::<keys::FinalNewline>()
+            .cloned()
+            .unwrap_or(FinalNewline::Ensure)
+    }
+
+    // The text to write. The buffer itself is left as it is.
+    fn saved_text(&self) -> String {
+        let text = self.core.get_string();
+        match self.final_newline() {
+            FinalNewline::Preserve => text,
+            FinalNewline::Ensure if text.is_empty() || text.ends_with('\n') => text,
+            FinalNewline::Ensure => text + "\n",
+            FinalNewline::Trim => {
+                let mut lines: Vec<&str> = text.split('\n').collect();
+                while lines.last().map(|l| l.trim().is_empty()) == Some(true) {
+                    lines.pop();
+                }
+                if lines.is_empty() {
+                    String::new()
+                } else {
+                    lines.join("\n") + "\n"
+                }
+            }
+        }
+    }
+
+    pub fn save(&mut self, is_optimize: bool) -> bool {
+        self.finish_load();
+        let text = self.saved_text();
+        let saved = match self.path.clone() {
+            Some(path) => match atomic_write::write(&path, text.as_bytes()) {
+                Ok(()) => true,
+                Err(err) => {
+                    self.log_message(&format!("Failed to save {}: {}", path.display(), err));
+                    false
+                }
+            },
+            None => false,
+        };
+        if saved {
+            self.last_save = self.core.buffer_changed();
+            self.mtime = self.disk_mtime();
+            if let Some(path) = self.path.clone() {
+                self.recent.push(&path);
+                if self.is_undo_file() {
+                    if let Some(dir) = self.undo_dir.as_ref() {
+                        undo_file::save(dir, &path, &mut self.core, &text);
+                    }
+                }
+            }
+            self.compile(is_optimize);
+        }
+        saved
+    }
+
+    // `:w path`. Write the text to `path` keeping the path of the buffer. A buffer without a path
+    // takes `path` once it is written.
+    pub fn save_as(&mut self, path: PathBuf) -> bool {
+        self.finish_load();
+        let text = self.saved_text();
+        if let Err(err) = atomic_write::write(&path, text.as_bytes()) {
+            self.log_message(&format!("Failed to save {}: {}", path.display(), err));
+            return false;
+        }
+        if self.path.is_none() {
+            self.set_path(path);
+            // Written again by the settings of the file type, which records the save
+            return self.save(false);
+        }
+        true
+    }
+
+    // Store to the unnamed register and also to `register` if it is given.
+    pub fn set_yank(&mut self, register: Option<char>, yank: Yank) {
+        if let Some(register) = register.filter(|&r| r != '"') {
+            self.registers.insert(register, yank.clone());
+        }
+        self.yank = yank;
+    }
+
+    // A delete spanning lines shifts `"1`..`"8` to `"2`..`"9` and goes to `"1`. A smaller one
+    // goes to `"-`.
+    pub fn set_delete(&mut self, register: Option<char>, yank: Yank) {
+        if yank.insert_newline || yank.content.contains('\n') {
+            for i in (1..9).rev() {
+                let from = std::char::from_digit(i, 10).unwrap();
+                let to = std::char::from_digit(i + 1, 10).unwrap();
+                if let Some(older) = self.registers.remove(&from) {
+                    self.registers.insert(to, older);
+                }
+            }
+            self.registers.insert('1', yank.clone());
+        } else {
+            self.registers.insert('-', yank.clone());
+        }
+        self.set_yank(register, yank);
+    }
+
+    pub fn get_yank(&self, register: Option<char>) -> Option<&Yank> {
+        match register {
+            None | Some('"') => Some(&self.yank),
+            Some(register) => self.registers.get(&register),
+        }
+    }
+
+    // For `:xxd`. 16 bytes a row as offset, hex and ASCII like `hexdump -C`.
+    pub fn hex_dump(&self) -> Vec<String> {
+        self.core
+            .get_string()
+            .as_bytes()
+            .chunks(16)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut hex = String::new();
+                for j in 0..16 {
+                    if j == 8 {
+                        hex.push(' ');
+                    }
+                    match chunk.get(j) {
+                        Some(b) => hex.push_str(&format!("{:02x} ", b)),
+                        None => hex.push_str("   "),
+                    }
+                }
+                let ascii: String = chunk
+                    .iter()
+                    .map(|&b| {
+                        if b.is_ascii_graphic() || b == b' ' {
+                            b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                format!("{:08x}  {} |{}|", i * 16, hex, ascii)
+            })
+            .collect()
+    }
+
+    // Expand the word just before the cursor if it is an abbreviation.
+    // Called in Insert mode before a non-word character is inserted.
+    pub fn expand_abbreviation(&mut self) {
+        let cursor = self.core.cursor();
+        let line = self.core.current_line();
+        let start = (0..cursor.col)
+            .rev()
+            .take_while(|&i| {
+                let c = line.char(i);
+                c.is_alphanumeric() || c == '_'
+            })
+            .last();
+        let start = if let Some(start) = start {
+            start
+        } else {
+            return;
+        };
+        let word: String = line.slice(start..cursor.col).chars().collect();
+        if let Some(expansion) = self.abbreviations.get(&word).cloned() {
+            for _ in start..cursor.col {
+                self.core.cursor_dec();
+                self.core.delete();
+            }
+            for c in expansion.chars() {
+                self.core.insert(c);
+            }
+        }
+    }
+
+    // For `:iabbrev` without arguments
+    pub fn abbreviation_list(&self) -> Vec<String> {
+        self.abbreviations
+            .iter()
+            .map(|(lhs, rhs)| format!("{}  {}", lhs, rhs))
+            .collect()
+    }
+
+    // Paste like `p` (`after`) or `P` and return the position just after the pasted text
+    pub fn paste(&mut self, yank: &Yank, after: bool) -> Cursor {
+        if yank.insert_newline {
+            if after {
+                self.core.insert_newline();
+            } else {
+                self.core.insert_newline_here();
+            }
+        } else if after {
+            self.core.cursor_right();
+        }
+
+        for c in yank.content.chars() {
+            self.core.insert(c);
+        }
+        self.core.cursor()
+    }
+
+    // `]p` and `[p`. `yank` as lines indented like the current line.
+    pub fn indented_yank(&self, yank: &Yank) -> Yank {
+        let line = self.core.buffer().l(self.core.cursor().row).to_string();
+        let tab_width = self.indent_width();
+        let columns = indent::indent_columns(&line, tab_width);
+        // Tabs like the current line, or like the file if the line is not indented
+        let tabs = line.starts_with('\t')
+            || (columns == 0 && self.indent_style == Some(IndentStyle::Tabs));
+        Yank {
+            insert_newline: true,
+            content: indent::shift_to(&yank.content, columns, tab_width, tabs),
+        }
+    }
+
+    // `:date` and `:time`. Insert `time` formatted by `format` at the cursor.
+    pub fn insert_time(&mut self, time: &DateTime, format: &str) {
+        for c in time.format(format).chars() {
+            self.core.insert(c);
+        }
+    }
+
+    // For `:registers`
+    pub fn register_list(&self) -> Vec<String> {
+        std::iter::once(('"', &self.yank))
+            .chain(self.registers.iter().map(|(&name, yank)| (name, yank)))
+            .filter(|(_, yank)| !yank.content.is_empty())
+            .map(|(name, yank)| {
+                let preview: String = yank
+                    .content
+                    .chars()
+                    .take(60)
+                    .map(|c| match c {
+                        '\n' => "^J".to_string(),
+                        '\t' => "^I".to_string(),
+                        c => c.to_string(),
+                    })
+                    .collect();
+                let kind = if yank.insert_newline { 'l' } else { 'c' };
+                format!("\"{}  {}  {}", name, kind, preview)
+            })
+            .collect()
+    }
+
+    pub fn record_key(&mut self, event: &termion::event::Event) {
+        if let Some(key) = draw::key_notation(event) {
+            if self.recent_keys.len() == RECENT_KEYS {
+                self.recent_keys.pop_front();
+            }
+            self.recent_keys.push_back(key);
+        }
+    }
+
+    pub fn log_message(&mut self, message: &str) {
+        if self.messages.len() == MESSAGE_LOG_SIZE {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message.to_string());
+    }
+
+    // `'a` goes to the first non-blank of the line and `` `a `` to the position itself.
+    // Marks don't follow edits, so the position is clamped.
+    pub fn mark_position(&self, name: char, linewise: bool) -> Option<Cursor> {
+        let mark = self.core.clamp_cursor(*self.marks.get(&name)?);
+        if linewise {
+            Some(self.core.first_non_blank(mark.row))
+        } else {
+            Some(mark)
+        }
+    }
+
+    // Remember the cursor position for `Ctrl-o`
+    pub fn push_jump(&mut self) {
+        let jump = (self.path.clone(), self.core.cursor());
+        if self.jumps.last() != Some(&jump) {
+            self.jumps.push(jump);
+        }
+    }
+
+    pub fn jump_back(&mut self) -> Result<(), String> {
+        let (path, cursor) = self.jumps.last().cloned().ok_or("Jump list is empty")?;
+        let other = path.filter(|p| self.path.as_ref() != Some(p));
+        if other.is_some() && self.is_modified() {
+            return Err("No write since last change".into());
+        }
+        self.jumps.pop();
+        if let Some(path) = other {
+            self.open(path);
+        }
+        let cursor = self.core.clamp_cursor(cursor);
+        self.core.set_cursor(cursor);
+        self.show_cursor();
+        Ok(())
+    }
+
+    // Add a cursor on the next line at the same column and make it the main one.
+    pub fn add_cursor_below(&mut self) -> bool {
+        let cursor = self.core.cursor();
+        if cursor.row + 1 >= self.core.buffer().len_lines() {
+            return false;
+        }
+        let next = self.core.clamp_cursor(Cursor {
+            row: cursor.row + 1,
+            col: cursor.col,
+        });
+        self.cursors.push(cursor);
+        self.core.set_cursor(next);
+        self.show_cursor();
+        true
+    }
+
+    // Apply `f` at every cursor. Edits go from bottom to top so that unvisited cursors stay valid.
+    pub fn edit_at_cursors<F: FnMut(&mut Core)>(&mut self, mut f: F) {
+        let mut cursors = self.cursors.clone();
+        cursors.push(self.core.cursor());
+        let mut order: Vec<usize> = (0..cursors.len()).collect();
+        order.sort_by_key(|&i| Reverse(cursors[i]));
+        for i in order {
+            self.core.set_cursor(cursors[i]);
+            f(&mut self.core);
+            cursors[i] = self.core.cursor();
+        }
+        let main = cursors.pop().unwrap();
+        self.core.set_cursor(main);
+        self.cursors = cursors;
+    }
+
+    pub fn word_under_cursor(&self) -> Option<String> {
+        let line: Vec<char> = self.core.current_line().chars().collect();
+        let col = self.core.cursor().col;
+        if !line.get(col).cloned().map(is_word_char).unwrap_or(false) {
+            return None;
+        }
+        let mut l = col;
+        while l > 0 && is_word_char(line[l - 1]) {
+            l -= 1;
+        }
+        let mut r = col;
+        while r < line.len() && is_word_char(line[r]) {
+            r += 1;
+        }
+        Some(line[l..r].iter().collect())
+    }
+
+    // Words starting with `prefix`, nearest to the cursor first
+    pub fn complete_words(&self, prefix: &str) -> Vec<String> {
+        let row = self.core.cursor().row;
+        let mut words: Vec<(usize, String)> = Vec::new();
+        for (i, line) in self
+            .lines(0, self.core.buffer().len_lines() - 1)
+            .iter()
+            .enumerate()
+        {
+            let distance = max(i, row) - min(i, row);
+            for word in line.split(|c| !is_word_char(c)) {
+                if word.len() <= prefix.len() || !word.starts_with(prefix) {
+                    continue;
+                }
+                if let Some(w) = words.iter_mut().find(|(_, w)| w == word) {
+                    w.0 = min(w.0, distance);
+                } else {
+                    words.push((distance, word.to_string()));
+                }
+            }
+        }
+        // Stable sort keeps the buffer order among equally near words
+        words.sort_by_key(|(distance, _)| *distance);
+        words.into_iter().map(|(_, w)| w).collect()
+    }
+
+    // Cursor for a match starting at `start` moved by `search_offset`
+    pub fn search_offset_cursor(&self, start: Cursor) -> Cursor {
+        let buffer = self.core.buffer();
+        let shift = |n: isize| {
+            let i = buffer.line_to_char(start.row) as isize + n;
+            let i = min(max(0, i) as usize, buffer.len_chars().saturating_sub(1));
+            let row = buffer.char_to_line(i);
+            Cursor {
+                row,
+                col: i - buffer.line_to_char(row),
+            }
+        };
+        let cursor = match self.search_offset {
+            command::SearchOffset::Line(n) => Cursor {
+                row: max(0, start.row as isize + n) as usize,
+                col: 0,
+            },
+            command::SearchOffset::Start(n) => shift(start.col as isize + n),
+            command::SearchOffset::End(n) => {
+                shift(start.col as isize + self.search_len() as isize - 1 + n)
+            }
+        };
+        self.core.clamp_cursor(cursor)
+    }
+
+    // Where `n` (`forward`) or `N` continues from, undoing `search_offset` at the cursor.
+    // The cursor itself if it cannot be moved there by the offset.
+    pub fn search_origin(&self, forward: bool) -> Cursor {
+        let cursor = self.core.cursor();
+        let n = match self.search_offset {
+            command::SearchOffset::Line(n) => {
+                let row = cursor.row as isize - n;
+                if row < 0 || row >= self.core.buffer().len_lines() as isize {
+                    return cursor;
+                }
+                let row = row as usize;
+                let col = if forward {
+                    self.core.buffer().l(row).len_chars()
+                } else {
+                    0
+                };
+                return Cursor { row, col };
+            }
+            command::SearchOffset::Start(n) => n,
+            command::SearchOffset::End(n) => self.search_len() as isize - 1 + n,
+        };
+        let buffer = self.core.buffer();
+        let i = buffer.line_to_char(cursor.row) as isize + cursor.col as isize - n;
+        if i < 0 || i > buffer.len_chars() as isize {
+            return cursor;
+        }
+        let i = i as usize;
+        let row = buffer.char_to_line(i);
+        Cursor {
+            row,
+            col: i - buffer.line_to_char(row),
+        }
+    }
+
+    // Heuristic definition search: the first occurrence preceded by a keyword like `fn`,
+    // or the first occurrence if there is no such one.
+    pub fn find_definition(&self, word: &str) -> Option<Cursor> {
+        let word: Vec<char> = word.chars().collect();
+        let mut first = None;
+        for (row, line) in self.core.buffer().lines().enumerate() {
+            let line: Vec<char> = line.chars().collect();
+            for col in 0..line.len() {
+                if !line[col..].starts_with(&word)
+                    || (col > 0 && is_word_char(line[col - 1]))
+                    || line.get(col + word.len()).cloned().map(is_word_char) == Some(true)
+                {
+                    continue;
+                }
+                let cursor = Cursor { row, col };
+                first = first.or(Some(cursor));
+
+                let prev: String = line[..col].iter().collect();
+                if prev
+                    .split_whitespace()
+                    .last()
+                    .map(|w| DEFINITION_KEYWORDS.contains(&w))
+                    .unwrap_or(false)
+                {
+                    return Some(cursor);
+                }
+            }
+        }
+        first
+    }
+
+    pub fn show_cursor(&mut self) {
+        if self.row_offset >= self.core.cursor().row {
+            self.row_offset = self.core.cursor().row;
+        } else {
+            let (rows, cols) = self.windows_size();
+            let cols = self.text_cols(cols);
+            if cols == 0 {
+                return;
+            }
+            let rows = rows - 1;
+            let row = self.core.cursor().row;
+            let mut i = row + 1;
+            let mut sum = 0;
+            while i > 0 && sum + self.line_rows(i - 1, cols) <= rows {
+                sum += self.line_rows(i - 1, cols);
+                i -= 1;
+            }
+            // A line taller than the window is shown from its top
+            self.row_offset = max(min(i, row), self.row_offset);
+        }
+    }
+
+    // Screen rows of the line `i` wrapped at `cols`
+    fn line_rows(&self, i: usize, cols: usize) -> usize {
+        let line = Cow::from(self.core.buffer().visible_part(i, self.cursor_col(i)).1);
+        get_rows(&line, cols, self.break_indent(&line), self.tab_cells())
+    }
+
+    // Cells of a tab. Tabs are drawn only with `:set list`.
+    fn tab_cells(&self) -> usize {
+        if self.list {
+            self.indent_width()
+        } else {
+            0
+        }
+    }
+
+    // `:set breakindent`. Columns of the blank cells at the start of wrapped rows of `line`.
+    fn break_indent(&self, line: &str) -> usize {
+        if self.breakindent {
+            indent::indent_columns(line, self.tab_cells())
+        } else {
+            0
+        }
+    }
+
+    // The column of the cursor on the line `i`, or 0 on the other lines
+    fn cursor_col(&self, i: usize) -> usize {
+        let cursor = self.core.cursor();
+        if cursor.row == i {
+            cursor.col
+        } else {
+            0
+        }
+    }
+
+    // Ctrl-e and Ctrl-y. The cursor stays on its line unless it goes off the screen.
+    pub fn scroll_lines(&mut self, n: usize, down: bool) {
+        let len_lines = self.core.buffer().len_lines();
+        self.row_offset = if down {
+            min(self.row_offset + n, len_lines - 1)
+        } else {
+            self.row_offset.saturating_sub(n)
+        };
+
+        let (rows, cols) = self.windows_size();
+        let cols = self.text_cols(cols);
+        let rows = rows - 1;
+        let mut bottom = self.row_offset;
+        let mut sum = 0;
+        while cols > 0 && bottom < len_lines && sum + self.line_rows(bottom, cols) <= rows {
+            sum += self.line_rows(bottom, cols);
+            bottom += 1;
+        }
+        let bottom = max(self.row_offset, bottom.saturating_sub(1));
+
+        let mut cursor = self.core.cursor();
+        cursor.row = min(max(cursor.row, self.row_offset), bottom);
+        if cursor.row != self.core.cursor().row {
+            let cursor = self.core.clamp_cursor(cursor);
+            self.core.set_cursor(cursor);
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        if self.row_offset < 3 {
+            self.row_offset = 0;
+        } else {
+            self.row_offset -= 3;
+        }
+    }
+
+    // Scroll by a click at `row` and `col` of a text area of `height` x `width`.
+    // Returns false if it is not on the scrollbar.
+    pub fn click_scrollbar(&mut self, row: usize, col: usize, height: usize, width: usize) -> bool {
+        if !self.scrollbar || col + 1 != width || row >= height {
+            return false;
+        }
+        self.row_offset = draw::scrollbar_row_offset(height, row, self.core.buffer().len_lines());
+        true
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.row_offset = min(self.row_offset + 3, self.core.buffer().len_lines() - 1);
+    }
+
+    // Move to 1-based `line` and `col` and scroll it into the middle of the screen
+    pub fn goto_position(&mut self, line: usize, col: usize) {
+        let cursor = self.core.clamp_cursor(Cursor {
+            row: line.saturating_sub(1),
+            col: col.saturating_sub(1),
+        });
+        self.core.set_cursor(cursor);
+        self.show_cursor_middle();
+    }
+
+    pub fn show_cursor_middle(&mut self) {
+        let (rows, _) = self.windows_size();
+        if rows / 2 > self.core.cursor().row {
+            self.row_offset = 0;
+        } else {
+            self.row_offset = self.core.cursor().row - rows / 2;
+        }
+    }
+
+    pub fn format(&mut self) {
+        let _ = self.format_with_error();
+    }
+
+    // Returns `Ok(false)` if no formatter is configured and the formatter's stderr on failure.
+    pub fn format_with_error(&mut self) -> Result<bool, String> {
+        let src = self.core.get_string();
+        let formatter = self.get_config::<keys::Formatter>();
+
+        if let Some(formatter) = formatter {
+            let formatted = formatter::system_format_with_error(formatter.command(), &src)?;
+            if formatted != src {
+                self.core.set_string(formatted, false);
+            }
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn range_rows(&self, range: command::Range) -> Option<(usize, usize)> {
+        let marks = &self.marks;
+        range.rows(
+            self.core.cursor().row,
+            self.core.buffer().len_lines(),
+            |c| marks.get(&c).map(|m| m.row),
+        )
+    }
+
+    // 1-based line of the end of the range. 0 is before the first line as in `:0put`.
+    pub fn range_line(&self, range: command::Range) -> Option<usize> {
+        let marks = &self.marks;
+        range.1.line(
+            self.core.cursor().row,
+            self.core.buffer().len_lines(),
+            |c| marks.get(&c).map(|m| m.row),
+        )
+    }
+
+    // 1-based line of a destination address like `:m 0` or `:t $`
+    pub fn address_line(&self, address: command::Address) -> Option<usize> {
+        self.range_line(command::Range(address, address))
+    }
+
+    fn lines(&self, first: usize, last: usize) -> Vec<String> {
+        (first..=last)
+            .map(|i| String::from(self.core.buffer().l(i)))
+            .collect()
+    }
+
+    // Returns the number of substitutions
+    pub fn substitute(
+        &mut self,
+        first: usize,
+        last: usize,
+        re: &regex::Regex,
+        replacement: &str,
+        global: bool,
+    ) -> usize {
+        if self.multiline {
+            return self.substitute_joined(first, last, re, replacement, global);
+        }
+        let mut count = 0;
+        let mut lines = Vec::new();
+        for line in self.lines(first, last) {
+            let n = re.find_iter(&line).count();
+            if global {
+                count += n;
+                lines.push(re.replace_all(&line, replacement).into_owned());
+            } else {
+                count += min(n, 1);
+                lines.push(re.replace(&line, replacement).into_owned());
+            }
+        }
+        if count > 0 {
+            self.core.replace_lines(first, last, lines.join("\n"));
+        }
+        count
+    }
+
+    // `:set multiline`. Match in the lines joined by `\n`. Without `global`, only the first match
+    // starting on each line is replaced.
+    fn substitute_joined(
+        &mut self,
+        first: usize,
+        last: usize,
+        re: &regex::Regex,
+        replacement: &str,
+        global: bool,
+    ) -> usize {
+        let text = self.lines(first, last).join("\n");
+        let mut res = String::new();
+        let mut count = 0;
+        let mut end = 0;
+        // Rows where matches start, counted up to `scanned`
+        let mut row = 0;
+        let mut scanned = 0;
+        let mut last_row = None;
+        for caps in re.captures_iter(&text) {
+            let m = caps.get(0).unwrap();
+            row += text[scanned..m.start()].matches('\n').count();
+            scanned = m.start();
+            if !global && last_row == Some(row) {
+                continue;
+            }
+            last_row = Some(row);
+            res.push_str(&text[end..m.start()]);
+            caps.expand(replacement, &mut res);
+            end = m.end();
+            count += 1;
+        }
+        res.push_str(&text[end..]);
+        if count > 0 {
+            self.core.replace_lines(first, last, res);
+        }
+        count
+    }
+
+    // The pattern of `/`. `\n` is a line break with `:set multiline`.
+    pub fn search_text(&self) -> String {
+        let search: String = self.search.iter().collect();
+        if self.multiline {
+            search.replace("\\n", "\n")
+        } else {
+            search
+        }
+    }
+
+    // Characters of the text `search_text` matches, with `\n` as one
+    fn search_len(&self) -> usize {
+        self.search_text().chars().count()
+    }
+
+    // Compute the result of `substitute` for visible lines without modifying the buffer
+    pub fn preview_substitute(
+        &mut self,
+        first: usize,
+        last: usize,
+        re: &regex::Regex,
+        replacement: &str,
+        global: bool,
+    ) {
+        self.preview.clear();
+        let (rows, _) = self.windows_size();
+        let first = max(first, self.row_offset);
+        let last = min(last, self.row_offset + rows);
+        if first > last {
+            return;
+        }
+
+        for (row, line) in (first..=last).zip(self.lines(first, last)) {
+            let mut text = String::new();
+            let mut changed = Vec::new();
+            let mut end = 0;
+            for caps in re.captures_iter(&line) {
+                let m = caps.get(0).unwrap();
+                text.push_str(&line[end..m.start()]);
+                let l = text.chars().count();
+                caps.expand(replacement, &mut text);
+                changed.push((l, text.chars().count()));
+                end = m.end();
+                if !global {
+                    break;
+                }
+            }
+            if !changed.is_empty() {
+                text.push_str(&line[end..]);
+                self.preview.insert(row, PreviewLine { text, changed });
+            }
+        }
+    }
+
+    // Every match of `re` as (row, col, line)
+    pub fn grep(&self, re: &regex::Regex) -> Vec<(usize, usize, String)> {
+        let mut res = Vec::new();
+        for (row, line) in self
+            .lines(0, self.core.buffer().len_lines() - 1)
+            .into_iter()
+            .enumerate()
+        {
+            for m in re.find_iter(&line) {
+                let col = line[..m.start()].chars().count();
+                res.push((row, col, line.clone()));
+            }
+        }
+        res
     }
 
-    pub fn new(
-        syntax_parent: &'a syntax::SyntaxParent,
-        config: &'a config::ConfigWithDefault,
-    ) -> Self {
-        let syntax = syntax_parent.load_syntax_or_txt("txt");
+    // Move to the `i`th entry of the quickfix list and remember where it jumped from
+    pub fn quickfix_jump(&mut self, i: usize) -> Option<String> {
+        if i < self.quickfix.len() {
+            self.push_jump();
+        }
+        self.quickfix_select(i)
+    }
 
-        let mut res = Self {
-            path: None,
-            core: Core::default(),
-            search: Vec::new(),
-            cache: DrawCache::new(&syntax),
-            syntax,
-            snippet: BTreeMap::new(),
-            yank: Yank::default(),
-            last_save: Id::default(),
-            lsp: None,
-            compiler: config.get::<keys::Compiler>(None).map(Compiler::new),
-            row_offset: 0,
-            last_compiler_result: None,
-            syntax_parent,
-            config,
-            buffer_update: Id::default(),
-            last_compiler_submit: CompileId::default(),
-            last_compiler_compiled: CompileId::default(),
-        };
-        res.restart_lsp();
-        res.reset_snippet();
-        res
+    // Move to the `i`th entry without adding to the jump list, e.g. browsing the list
+    pub fn quickfix_select(&mut self, i: usize) -> Option<String> {
+        let (row, col, line) = self.quickfix.get(i)?.clone();
+        self.quickfix_index = i;
+        let cursor = self.core.clamp_cursor(Cursor { row, col });
+        self.core.set_cursor(cursor);
+        self.show_cursor();
+        Some(format!(
+            "({} of {}) {}",
+            i + 1,
+            self.quickfix.len(),
+            line.trim()
+        ))
     }
 
-    fn extension(&self) -> Option<&OsStr> {
-        self.path.as_ref().and_then(|p| p.extension())
+    fn reset_folds(&mut self) {
+        self.folds.clear();
+        self.closed_folds.clear();
+        self.folds_update = None;
     }
 
-    pub fn get_config<A: typemap::Key>(&self) -> Option<&'a A::Value> {
-        self.config.get::<A>(self.path())
+    pub fn add_fold(&mut self, first: usize, last: usize) {
+        if !self.folds.contains(&(first, last)) {
+            self.folds.push((first, last));
+            self.folds.sort();
+        }
     }
 
-    fn reset_snippet(&mut self) {
-        self.snippet = self.config.snippets(self.path());
+    // `zd`. Delete the innermost fold at `row`.
+    pub fn delete_fold(&mut self, row: usize) -> bool {
+        let innermost = self
+            .folds
+            .iter()
+            .rposition(|&(first, last)| first <= row && row <= last);
+        if let Some(i) = innermost {
+            let fold = self.folds.remove(i);
+            self.closed_folds.remove(&fold);
+        }
+        innermost.is_some()
     }
 
-    pub fn extend_cache_duration(&mut self, duration: std::time::Duration) {
-        self.cache
-            .extend_cache_duration(self.core.buffer(), duration);
+    // `:set foldmethod=indent` or `manual`. Manual folds start from the indent folds.
+    pub fn set_fold_indent(&mut self, fold_indent: bool) {
+        self.fold_indent = fold_indent;
+        self.folds_update = None;
     }
 
-    pub fn indent_width(&self) -> usize {
-        self.get_config::<keys::IndentWidth>().cloned().unwrap_or(4)
+    // Fold by indentation if the text has changed since the last time. Manual folds do not move
+    // with the text, so those which no longer fit in it are dropped.
+    pub fn update_folds(&mut self) {
+        if self.folds_update == Some(self.core.buffer_changed()) {
+            return;
+        }
+        self.folds_update = Some(self.core.buffer_changed());
+        if self.fold_indent {
+            self.folds = indent::folds(
+                self.core.buffer().lines().map(String::from),
+                self.indent_width(),
+            );
+        } else {
+            let len_lines = self.core.buffer().len_lines();
+            self.folds.retain(|&(_, last)| last < len_lines);
+        }
+        let folds = &self.folds;
+        self.closed_folds.retain(|f| folds.contains(f));
     }
 
-    pub fn is_ansi_color(&self) -> bool {
-        self.get_config::<keys::ANSIColor>()
+    // The outermost closed fold which contains `row`
+    pub fn closed_fold(&self, row: usize) -> Option<(usize, usize)> {
+        self.closed_folds
+            .iter()
+            .filter(|&&(first, last)| first <= row && row <= last)
+            .min_by_key(|&&(first, last)| (first, Reverse(last)))
             .cloned()
-            .unwrap_or_default()
     }
 
-    pub fn restart_lsp(&mut self) {
-        let ext = self
-            .extension()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .into_owned();
-        self.lsp = self
-            .config
-            .get::<keys::LSP>(self.path())
-            .and_then(|c| LSPClient::start(c.command(), ext));
+    // `za`. Open the closed fold at `row`, or close the innermost open one.
+    pub fn toggle_fold(&mut self, row: usize) -> bool {
+        if let Some(fold) = self.closed_fold(row) {
+            self.closed_folds.remove(&fold);
+            return true;
+        }
+        let innermost = self
+            .folds
+            .iter()
+            .filter(|&&(first, last)| first <= row && row <= last)
+            .min_by_key(|&&(first, last)| last - first)
+            .cloned();
+        if let Some(fold) = innermost {
+            self.closed_folds.insert(fold);
+            self.move_out_of_fold();
+        }
+        innermost.is_some()
     }
 
-    fn set_syntax(&mut self, extension: &str) {
-        self.syntax = self.syntax_parent.load_syntax_or_txt(extension);
-        self.cache = DrawCache::new(&self.syntax);
+    // `zM`
+    pub fn close_all_folds(&mut self) {
+        self.closed_folds = self.folds.iter().cloned().collect();
+        self.move_out_of_fold();
     }
 
-    pub fn set_language(&mut self) {
-        self.compiler = self.get_config::<keys::Compiler>().map(Compiler::new);
-        self.restart_lsp();
+    // The cursor stays on the first row of a closed fold
+    pub fn move_out_of_fold(&mut self) {
+        if let Some((first, _)) = self.closed_fold(self.core.cursor().row) {
+            let cursor = self.core.first_non_blank(first);
+            self.core.set_cursor(cursor);
+            self.show_cursor();
+        }
     }
 
-    pub fn indent(&mut self) {
-        self.core.indent(self.indent_width());
+    // `j` and `k` move over a closed fold as over a line
+    pub fn skip_closed_fold(&mut self, down: bool) {
+        let cursor = self.core.cursor();
+        if let Some((first, last)) = self.closed_fold(cursor.row) {
+            let row = if !down || cursor.row == first || last + 1 >= self.core.buffer().len_lines()
+            {
+                first
+            } else {
+                last + 1
+            };
+            let cursor = self.core.clamp_cursor(Cursor { row, ..cursor });
+            self.core.set_cursor(cursor);
+        }
     }
 
-    pub fn path(&self) -> Option<&Path> {
-        self.path.as_ref().map(|p| p.as_path())
+    // The row `zj`, `zk`, `[z` or `]z` moves to from `row`
+    pub fn fold_motion(&self, row: usize, motion: char) -> Option<usize> {
+        let len_lines = self.core.buffer().len_lines();
+        let folds = self.folds.iter().filter(|f| f.1 < len_lines);
+        match motion {
+            // The start of the next fold
+            'j' => folds.map(|f| f.0).filter(|&first| first > row).min(),
+            // The end of the previous fold
+            'k' => folds.map(|f| f.1).filter(|&last| last < row).max(),
+            // The start of the current fold, or of the enclosing one if already there
+            '[' => folds.filter(|f| f.0 < row && row <= f.1).map(|f| f.0).max(),
+            ']' => folds.filter(|f| f.0 <= row && row < f.1).map(|f| f.1).min(),
+            _ => None,
+        }
     }
 
-    pub fn set_path(&mut self, path: PathBuf) {
-        if self.extension() != path.extension() {
-            self.set_language();
+    // The next (or previous) misspelled word from the cursor, wrapping around
+    pub fn find_misspelling(&self, forward: bool) -> Option<Cursor> {
+        let dictionary = self.dictionary.as_ref()?;
+        let cursor = self.core.cursor();
+        let len_lines = self.core.buffer().len_lines();
+        for k in 0..=len_lines {
+            let row = if forward {
+                (cursor.row + k) % len_lines
+            } else {
+                (cursor.row + len_lines - k % len_lines) % len_lines
+            };
+            let line = self.core.buffer().l(row).to_string();
+            let mut cols = dictionary.misspellings(&line).into_iter().map(|(l, _)| l);
+            let col = if forward {
+                cols.find(|&col| k > 0 || col > cursor.col)
+            } else {
+                cols.rev().find(|&col| k > 0 || col < cursor.col)
+            };
+            if let Some(col) = col {
+                return Some(Cursor { row, col });
+            }
         }
-        self.path = Some(path);
+        None
     }
 
-    pub fn open<P: AsRef<Path>>(&mut self, path: P) {
-        let core = if let Ok(f) = fs::File::open(path.as_ref()) {
-            Core::from_reader(BufReader::new(f)).unwrap()
-        } else {
-            Core::default()
-        };
+    // A path like token under the cursor for `gf`
+    pub fn path_under_cursor(&self) -> Option<String> {
+        let is_path_char = |c: char| c.is_alphanumeric() || "/._-~+".contains(c);
+        let line: Vec<char> = self.core.current_line().chars().collect();
+        let col = self.core.cursor().col;
+        if !line.get(col).cloned().map(is_path_char).unwrap_or(false) {
+            return None;
+        }
+        let mut l = col;
+        while l > 0 && is_path_char(line[l - 1]) {
+            l -= 1;
+        }
+        let mut r = col;
+        while r < line.len() && is_path_char(line[r]) {
+            r += 1;
+        }
+        // Not a part of the path at the end of a sentence
+        while r > l + 1 && line[r - 1] == '.' {
+            r -= 1;
+        }
+        Some(line[l..r].iter().collect())
+    }
 
-        self.path = Some(path.as_ref().to_path_buf());
+    // Resolve `path` relative to the directory of the current file, then the working directory
+    pub fn resolve_path(&self, path: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(shellexpand::tilde(path).as_ref());
+        let dir = self
+            .path
+            .as_ref()
+            .and_then(|p| p.parent())
+            .map(|dir| dir.join(&path));
+        dir.into_iter()
+            .chain(std::iter::once(path))
+            .find(|p| p.is_file())
+    }
 
-        let syntax_extension = self
-            .get_config::<keys::SyntaxExtension>()
-            .cloned()
-            .or_else(|| {
-                path.as_ref()
-                    .extension()
-                    .or_else(|| path.as_ref().file_name())
-                    .unwrap_or_default()
-                    .to_str()
-                    .map(String::from)
-            })
-            .unwrap_or_default();
-        self.set_syntax(&syntax_extension);
+    // Char range and text of the misspelled word under the cursor
+    pub fn misspelling_under_cursor(&self) -> Option<(usize, usize, String)> {
+        let dictionary = self.dictionary.as_ref()?;
+        let line = self.core.current_line().to_string();
+        let col = self.core.cursor().col;
+        dictionary
+            .misspellings(&line)
+            .into_iter()
+            .find(|&(l, r)| l <= col && col < r)
+            .map(|(l, r)| (l, r, line.chars().skip(l).take(r - l).collect()))
+    }
 
-        self.row_offset = 0;
-        self.last_save = core.buffer_changed();
-        self.core = core;
-        self.set_language();
-        self.cache = DrawCache::new(&self.syntax);
-        self.compile(false);
-        self.reset_snippet();
+    // Rewrap lines so that none exceeds `textwidth`, keeping their common indent and comment prefix.
+    // Returns the number of resulting lines.
+    pub fn reflow_lines(&mut self, first: usize, last: usize) -> usize {
+        let lines = reflow(&self.lines(first, last), self.text_width());
+        let len = lines.len();
+        self.core.replace_lines(first, last, lines.join("\n"));
+        len
     }
 
-    pub fn save(&mut self, is_optimize: bool) -> bool {
-        let saved = if let Some(path) = self.path.as_ref() {
-            if let Ok(f) = fs::File::create(path) {
-                self.core.buffer().write_to(BufWriter::new(f)).unwrap();
-                true
+    // Comment out lines after their common indent, or uncomment them if all are commented.
+    // Blank lines are left as they are. Returns true if commented.
+    pub fn toggle_comment(&mut self, first: usize, last: usize) -> bool {
+        let (leader, trailer) = self.comment_leader();
+        let lines = self.lines(first, last);
+        let is_blank = |l: &String| l.trim().is_empty();
+        let uncomment = lines.iter().filter(|l| !is_blank(l)).all(|l| {
+            let l = l.trim();
+            l.len() >= leader.len() + trailer.len()
+                && l.starts_with(leader.as_str())
+                && l.ends_with(trailer.as_str())
+        });
+        // In characters as the whitespace may be multibyte
+        let indent = lines
+            .iter()
+            .filter(|l| !is_blank(l))
+            .map(|l| l.chars().count() - l.trim_start().chars().count())
+            .min()
+            .unwrap_or(0);
+        let lines: Vec<String> = lines
+            .into_iter()
+            .map(|l| {
+                if is_blank(&l) {
+                    l
+                } else if uncomment {
+                    let i = l.len() - l.trim_start().len();
+                    let mut rest = &l.trim_end()[i + leader.len()..];
+                    rest = rest.strip_prefix(' ').unwrap_or(rest);
+                    if !trailer.is_empty() {
+                        rest = &rest[..rest.len() - trailer.len()];
+                        rest = rest.strip_suffix(' ').unwrap_or(rest);
+                    }
+                    format!("{}{}", &l[..i], rest)
+                } else {
+                    let i = l.char_indices().nth(indent).map_or(l.len(), |(i, _)| i);
+                    if trailer.is_empty() {
+                        format!("{}{} {}", &l[..i], leader, &l[i..])
+                    } else {
+                        format!("{}{} {} {}", &l[..i], leader, &l[i..], trailer)
+                    }
+                }
+            })
+            .collect();
+        self.core.replace_lines(first, last, lines.join("\n"));
+        !uncomment
+    }
+
+    // Ctrl-t and Ctrl-d in insert mode. The cursor stays on the same character.
+    pub fn shift_line(&mut self, right: bool) {
+        let indent_width = max(1, self.indent_width());
+        let cursor = self.core.cursor();
+        let line = self.lines(cursor.row, cursor.row).remove(0);
+        let rest = line.trim_start_matches(&[' ', '\t'][..]);
+        let len = line.chars().count() - rest.chars().count();
+        let width = line.chars().take(len).fold(0, |w, c| {
+            if c == '\t' {
+                w + indent_width - w % indent_width
             } else {
-                false
+                w + 1
             }
+        });
+        let width = if right {
+            (width / indent_width + 1) * indent_width
         } else {
-            false
+            width.saturating_sub(1) / indent_width * indent_width
         };
-        if saved {
-            self.compile(is_optimize);
+        let indent = if self.indent_style == Some(IndentStyle::Tabs) {
+            "\t".repeat(width / indent_width) + &" ".repeat(width % indent_width)
+        } else {
+            " ".repeat(width)
+        };
+        let new_len = indent.chars().count();
+        self.core
+            .replace_lines(cursor.row, cursor.row, indent + rest);
+        let col = if cursor.col >= len {
+            cursor.col - len + new_len
+        } else {
+            min(cursor.col, new_len)
+        };
+        self.core.set_cursor(Cursor {
+            row: cursor.row,
+            col,
+        });
+    }
+
+    // Rewrite leading whitespace with spaces, or with tabs as far as possible if `to_tabs`.
+    // Returns the number of changed lines.
+    pub fn retab(&mut self, first: usize, last: usize, to_tabs: bool) -> usize {
+        let tab_width = max(1, self.indent_width());
+        let mut changed = 0;
+        let lines: Vec<String> = self
+            .lines(first, last)
+            .into_iter()
+            .map(|l| {
+                let rest = l.trim_start_matches(&[' ', '\t'][..]);
+                let width = l[..l.len() - rest.len()].chars().fold(0, |w, c| {
+                    if c == '\t' {
+                        w + tab_width - w % tab_width
+                    } else {
+                        w + 1
+                    }
+                });
+                let indent = if to_tabs {
+                    "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width)
+                } else {
+                    " ".repeat(width)
+                };
+                let line = indent + rest;
+                if line != l {
+                    changed += 1;
+                }
+                line
+            })
+            .collect();
+        if changed > 0 {
+            self.core.replace_lines(first, last, lines.join("\n"));
         }
-        saved
+        changed
     }
 
-    pub fn show_cursor(&mut self) {
-        if self.row_offset >= self.core.cursor().row {
-            self.row_offset = self.core.cursor().row;
-        } else {
-            let (rows, cols) = Self::windows_size();
-            if cols < LinenumView::prefix_width(self.core.buffer().len_lines()) {
-                return;
-            }
-            let cols = cols - LinenumView::prefix_width(self.core.buffer().len_lines());
-            let rows = rows - 1;
-            let mut i = self.core.cursor().row + 1;
-            let mut sum = 0;
-            while i > 0 && sum + get_rows(&Cow::from(self.core.buffer().l(i - 1)), cols) <= rows {
-                sum += get_rows(&Cow::from(self.core.buffer().l(i - 1)), cols);
-                i -= 1;
+    // `:Align`. Pad the text before each `delim` (only the first one if `first_only`) so that
+    // they line up across the lines. Returns the number of changed lines.
+    // `:AnsiStrip`. Returns the number of changed lines.
+    pub fn strip_ansi(&mut self, first: usize, last: usize) -> usize {
+        let lines = self.lines(first, last);
+        let stripped: Vec<String> = lines.iter().map(|l| ansi::strip(l)).collect();
+        let changed = lines.iter().zip(&stripped).filter(|(a, b)| a != b).count();
+        if changed > 0 {
+            self.core.replace_lines(first, last, stripped.join("\n"));
+        }
+        changed
+    }
+
+    pub fn align(&mut self, first: usize, last: usize, delim: &str, first_only: bool) -> usize {
+        let width = |s: &str| s.chars().map(|c| c.width().unwrap_or(0)).sum::<usize>();
+        let lines = self.lines(first, last);
+        let fields: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|line| {
+                if first_only {
+                    line.splitn(2, delim).collect()
+                } else {
+                    line.split(delim).collect()
+                }
+            })
+            .collect();
+        // Widths of the columns before each delimiter
+        let mut widths: Vec<usize> = Vec::new();
+        for fields in &fields {
+            for (k, field) in fields.iter().take(fields.len() - 1).enumerate() {
+                if k == widths.len() {
+                    widths.push(0);
+                }
+                widths[k] = max(widths[k], width(field));
             }
-            self.row_offset = max(i, self.row_offset);
         }
+        let aligned: Vec<String> = fields
+            .iter()
+            .map(|fields| {
+                let mut line = String::new();
+                for (k, field) in fields.iter().enumerate() {
+                    line.push_str(field);
+                    if k + 1 < fields.len() {
+                        line.push_str(&" ".repeat(widths[k] - width(field)));
+                        line.push_str(delim);
+                    }
+                }
+                line
+            })
+            .collect();
+        let changed = lines.iter().zip(&aligned).filter(|(a, b)| a != b).count();
+        if changed > 0 {
+            self.core.replace_lines(first, last, aligned.join("\n"));
+        }
+        changed
     }
 
-    pub fn scroll_up(&mut self) {
-        if self.row_offset < 3 {
-            self.row_offset = 0;
+    // Stable. With `key`, lines are compared by the text after its first match and lines
+    // without a match go first.
+    pub fn sort_lines(
+        &mut self,
+        first: usize,
+        last: usize,
+        reverse: bool,
+        ignore_case: bool,
+        key: Option<&regex::Regex>,
+    ) {
+        let mut lines: Vec<(String, String)> = self
+            .lines(first, last)
+            .into_iter()
+            .map(|line| {
+                let k = match key {
+                    Some(re) => re.find(&line).map_or("", |m| &line[m.end()..]),
+                    None => &line,
+                };
+                let k = if ignore_case {
+                    k.to_lowercase()
+                } else {
+                    k.to_string()
+                };
+                (k, line)
+            })
+            .collect();
+        if reverse {
+            lines.sort_by(|a, b| b.0.cmp(&a.0));
         } else {
-            self.row_offset -= 3;
+            lines.sort_by(|a, b| a.0.cmp(&b.0));
         }
+        let lines: Vec<String> = lines.into_iter().map(|(_, line)| line).collect();
+        self.core.replace_lines(first, last, lines.join("\n"));
     }
 
-    pub fn scroll_down(&mut self) {
-        self.row_offset = min(self.row_offset + 3, self.core.buffer().len_lines() - 1);
+    // Insert `text` as lines below the 1-based `line`
+    pub fn put_lines(&mut self, line: usize, text: &str) {
+        self.core.set_cursor(Cursor {
+            row: line.saturating_sub(1),
+            col: 0,
+        });
+        if line == 0 {
+            self.core.insert_newline_here();
+        } else {
+            self.core.insert_newline();
+        }
+        for c in text.trim_end_matches('\n').chars() {
+            self.core.insert(c);
+        }
+        let cursor = self.core.first_non_blank(self.core.cursor().row);
+        self.core.set_cursor(cursor);
     }
 
-    pub fn show_cursor_middle(&mut self) {
-        let (rows, _) = Self::windows_size();
-        if rows / 2 > self.core.cursor().row {
-            self.row_offset = 0;
-        } else {
-            self.row_offset = self.core.cursor().row - rows / 2;
+    // `J`. Join `n` lines below to the current line with a space and leave the cursor on the
+    // last join. Leading whitespace of the joined lines is removed.
+    pub fn join_lines(&mut self, n: usize) {
+        let first = self.core.cursor().row;
+        let last = min(first + n, self.core.buffer().len_lines() - 1);
+        if first == last {
+            return;
+        }
+        let mut lines = self.lines(first, last).into_iter();
+        let mut joined = lines.next().unwrap_or_default();
+        let mut col = 0;
+        for line in lines {
+            let line = line.trim_start();
+            col = joined.chars().count();
+            // No space after trailing whitespace, before `)` or for an empty line
+            if joined.ends_with(char::is_whitespace) || line.is_empty() {
+                col = col.saturating_sub(1);
+            } else if !line.starts_with(')') {
+                joined.push(' ');
+            }
+            joined.push_str(line);
         }
+        self.core.replace_lines(first, last, joined);
+        self.core.set_cursor(Cursor { row: first, col });
     }
 
-    pub fn format(&mut self) {
-        let src = self.core.get_string();
-        let formatter = self.config.get::<keys::Formatter>(self.path());
+    pub fn reverse_lines(&mut self, first: usize, last: usize) {
+        let mut lines = self.lines(first, last);
+        lines.reverse();
+        self.core.replace_lines(first, last, lines.join("\n"));
+        self.core.set_cursor(Cursor { row: first, col: 0 });
+    }
 
-        if let Some(formatter) = formatter {
-            if let Some(formatted) = formatter::system_format(formatter.command(), &src) {
-                if formatted != self.core.get_string() {
-                    self.core.set_string(formatted, false);
-                }
+    // Move rows below the 1-based `line`. Returns false if `line` is inside the rows.
+    pub fn move_lines(&mut self, first: usize, last: usize, line: usize) -> bool {
+        if line > first && line <= last {
+            return false;
+        }
+        let moved = self.lines(first, last);
+        let (span, lines, row) = if line <= first {
+            let mut lines = moved;
+            lines.extend(self.lines(line, first).into_iter().take(first - line));
+            ((line, last), lines, line + last - first)
+        } else {
+            let mut lines = self.lines(last + 1, line - 1);
+            lines.extend(moved);
+            ((first, line - 1), lines, line - 1)
+        };
+        self.core.replace_lines(span.0, span.1, lines.join("\n"));
+        let cursor = self.core.first_non_blank(row);
+        self.core.set_cursor(cursor);
+        true
+    }
+
+    // Copy rows below the 1-based `line` like `:t`
+    pub fn copy_lines(&mut self, first: usize, last: usize, line: usize) {
+        let text = self.lines(first, last).join("\n");
+        self.put_lines(line, &text);
+    }
+
+    // Filter lines through an external command like `:{range}!sort`
+    pub fn filter_lines(&mut self, first: usize, last: usize, command: process::Command) -> bool {
+        let mut src = self.lines(first, last).join("\n");
+        src.push('\n');
+        if let Some(mut out) = formatter::system_format(command, &src) {
+            if out.ends_with('\n') {
+                out.pop();
             }
+            self.core.replace_lines(first, last, out);
+            true
+        } else {
+            false
         }
     }
 
@@ -360,18 +2329,52 @@ impl<'a> Buffer<'a> {
         selected: Option<CursorRange>,
     ) -> Option<Cursor> {
         view.bg = self.syntax.theme.settings.background.map(|c| c.into());
+        self.update_folds();
+        if let Some((first, _)) = self.closed_fold(self.row_offset) {
+            self.row_offset = first;
+        }
+        if self.scrollbar {
+            view = view.scrollbar(self.row_offset, self.core.buffer().len_lines());
+        }
         let v = Vec::new();
         let compiler_outputs = self
             .last_compiler_result
             .as_ref()
             .map(|res| &res.messages)
             .unwrap_or_else(|| &v);
-        let mut view = LinenumView::new(
-            self.row_offset,
-            self.core.buffer().len_lines(),
-            &compiler_outputs,
-            view,
-        );
+        let mut view = if self.zen {
+            LinenumView::hidden(view.centered(self.zen_width()))
+        } else if self.line_numbers == LineNumbers::Off {
+            LinenumView::hidden(view)
+        } else {
+            let linenum_styles = if self.heatmap {
+                let last = min(
+                    self.row_offset + view.height(),
+                    self.core.buffer().len_lines(),
+                );
+                (self.row_offset..last)
+                    .map(|i| match self.core.line_time(i) {
+                        Some(t) => styles::HEAT[draw::heat_bucket(t.elapsed())],
+                        None => styles::UI,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let relative_to = if self.line_numbers == LineNumbers::Relative {
+                Some(self.core.cursor().row)
+            } else {
+                None
+            };
+            LinenumView::new(
+                self.row_offset,
+                self.core.buffer().len_lines(),
+                &compiler_outputs,
+                linenum_styles,
+                relative_to,
+                view,
+            )
+        };
         let mut cursor = None;
 
         if self.buffer_update != self.core.buffer_changed() {
@@ -379,13 +2382,109 @@ impl<'a> Buffer<'a> {
             self.cache.dirty_from(self.core.dirty_from);
         }
 
+        let tab_width = self.indent_width();
+        // Nesting from lines above the screen, only from the nearby ones for large files
+        let rainbow = self.is_rainbow_brackets();
+        let mut depth = 0;
+        if rainbow {
+            for i in self.row_offset.saturating_sub(RAINBOW_LOOKBACK)..self.row_offset {
+                draw::bracket_depths(self.core.buffer().visible_line(i).chars(), &mut depth);
+            }
+        }
+        // Rows in a closed fold after its first one are not drawn
+        let mut next_row = self.row_offset;
         'outer: for i in self.row_offset..self.core.buffer().len_lines() {
-            self.cache.cache_line(self.core.buffer(), i);
-            let line_ref = self.cache.get_line(i).unwrap();
-            let mut line = Cow::Borrowed(line_ref);
+            if i < next_row {
+                continue;
+            }
+            if let Some((first, last)) = self.closed_fold(i) {
+                let last = min(last, self.core.buffer().len_lines() - 1);
+                self.core.dirty_from = i;
+                if rainbow {
+                    for j in first..=last {
+                        draw::bracket_depths(
+                            self.core.buffer().visible_line(j).chars(),
+                            &mut depth,
+                        );
+                    }
+                }
+                let text = format!(
+                    "+--{:>3} lines: {}",
+                    last - first + 1,
+                    self.core.buffer().visible_line(first).to_string().trim()
+                );
+                let t = Cursor { row: first, col: 0 };
+                if (first..=last).contains(&self.core.cursor().row) {
+                    cursor = view.cursor();
+                }
+                for c in text.chars().map(|c| if c == '\t' { ' ' } else { c }) {
+                    if view.cause_newline(c) {
+                        break;
+                    }
+                    if view.put(c, styles::FOLDED, Some(t)).is_none() {
+                        break 'outer;
+                    }
+                }
+                while !view.cause_newline(' ') {
+                    view.put(' ', styles::FOLDED, Some(t));
+                }
+                if last + 1 < self.core.buffer().len_lines() {
+                    view.skip_lines(last - first);
+                    view.newline();
+                }
+                next_row = last + 1;
+                continue;
+            }
+            let settings = &self.syntax.theme.settings;
+            let style = CharStyle::fg_bg(
+                settings.foreground.map_or(styles::DEFAULT.fg, Into::into),
+                settings.background.map_or(styles::DEFAULT.bg, Into::into),
+            );
+            let cursor_col = self.cursor_col(i);
+            let (start, part) = self.core.buffer().visible_part(i, cursor_col);
+            let mut line = if start == 0 {
+                self.cache.cache_line(self.core.buffer(), i);
+                Cow::Borrowed(self.cache.get_line(i).unwrap())
+            } else {
+                // Only the start of a long line is highlighted
+                Cow::Owned(part.chars().map(|c| (c, style)).collect())
+            };
+
+            if let Some(preview) = self.preview.get(&i) {
+                line = Cow::Owned(
+                    preview
+                        .text
+                        .chars()
+                        .enumerate()
+                        .map(|(j, c)| {
+                            if preview.changed.iter().any(|&(l, r)| l <= j && j < r) {
+                                (c, styles::PREVIEW)
+                            } else {
+                                (c, style)
+                            }
+                        })
+                        .collect(),
+                );
+            }
 
             self.core.dirty_from = i;
 
+            if rainbow {
+                let chars: Vec<char> = line.iter().map(|&(c, _)| c).collect();
+                for (j, d) in draw::bracket_depths(chars, &mut depth) {
+                    line.to_mut()[j].1.fg = styles::RAINBOW[d % styles::RAINBOW.len()];
+                }
+            }
+
+            if let Some(dictionary) = self.dictionary.as_ref().filter(|_| self.spell) {
+                let text = self.core.buffer().visible_part(i, cursor_col).1.to_string();
+                for (l, r) in dictionary.misspellings(&text) {
+                    for k in l..min(r, line.len()) {
+                        line.to_mut()[k].1 = styles::SPELL;
+                    }
+                }
+            }
+
             if !self.search.is_empty() && line.len() >= self.search.len() {
                 for j in 0..=line.len() - self.search.len() {
                     let m = self
@@ -401,15 +2500,72 @@ impl<'a> Buffer<'a> {
                 }
             }
 
+            let is_cursorline = self.cursorline && i == self.core.cursor().row;
+            let fill = if is_cursorline {
+                styles::CURSORLINE
+            } else if let Some(col) = self.syntax.theme.settings.background {
+                CharStyle::bg(col.into())
+            } else {
+                styles::DEFAULT
+            };
+
+            if self.breakindent {
+                let indent: String = line
+                    .iter()
+                    .map(|&(c, _)| c)
+                    .take_while(|&c| c == ' ' || c == '\t')
+                    .collect();
+                view.set_break_indent(self.break_indent(&indent), fill);
+            }
+
+            let trailing = line.len()
+                - line
+                    .iter()
+                    .rev()
+                    .take_while(|(c, _)| *c == ' ' || *c == '\t')
+                    .count();
+            let mut width = 0;
             for (j, &c) in line.iter().enumerate() {
                 let (c, mut style) = c;
-                let t = Cursor { row: i, col: j };
+                let t = Cursor {
+                    row: i,
+                    col: start + j,
+                };
+
+                // Cells after the marker of a tab
+                let mut padding = 0;
+                if self.list {
+                    if c == '\t' {
+                        padding = tab_width - 1 - width % tab_width;
+                        style = styles::WHITESPACE;
+                    } else if c == ' ' && j >= trailing {
+                        style = styles::WHITESPACE;
+                    }
+                }
+                let control = draw::control_notation(c);
+                if control.is_some() {
+                    style = styles::CONTROL;
+                }
+                let c = match c {
+                    '\t' if self.list => '▸',
+                    ' ' if self.list && j >= trailing => '·',
+                    c => control.as_ref().and_then(|s| s.chars().next()).unwrap_or(c),
+                };
+                width += control
+                    .as_ref()
+                    .map_or_else(|| c.width().unwrap_or(0), String::len)
+                    + padding;
 
                 if self.is_annotate(t) {
                     style.modification = draw::CharModification::UnderLine;
                 }
+                if is_cursorline {
+                    style.bg = styles::CURSORLINE.bg;
+                }
 
-                let style = if selected.as_ref().map(|r| r.contains(t)) == Some(true) {
+                let style = if selected.as_ref().map(|r| r.contains(t)) == Some(true)
+                    || self.cursors.contains(&t)
+                {
                     styles::SELECTED
                 } else {
                     style
@@ -420,6 +2576,16 @@ impl<'a> Buffer<'a> {
                 } else if view.put(c, style, Some(t)).is_none() {
                     break 'outer;
                 }
+                for c in control.iter().flat_map(|s| s.chars().skip(1)) {
+                    if view.put(c, style, Some(t)).is_none() {
+                        break 'outer;
+                    }
+                }
+                for _ in 0..padding {
+                    if view.put(' ', style, Some(t)).is_none() {
+                        break 'outer;
+                    }
+                }
             }
             let t = Cursor {
                 row: i,
@@ -427,31 +2593,34 @@ impl<'a> Buffer<'a> {
             };
 
             if self.core.cursor() == t {
+                // `virtualedit=all` past the line end
+                for _ in t.col..self.core.virtual_col() {
+                    view.put(' ', fill, Some(t));
+                }
                 cursor = view.cursor();
             }
 
-            if self.core.buffer().l(i).len_chars() == 0 {
-                if let Some(col) = self.syntax.theme.settings.background {
-                    view.put(' ', CharStyle::bg(col.into()), Some(t));
-                } else {
-                    view.put(' ', styles::DEFAULT, Some(t));
-                }
+            if self.list {
+                view.put('$', styles::WHITESPACE, Some(t));
+            } else if self.core.buffer().l(i).len_chars() == 0 {
+                view.put(' ', fill, Some(t));
             }
 
-            if i != self.core.buffer().len_lines() - 1 {
-                if let Some(col) = self.syntax.theme.settings.background {
-                    while !view.cause_newline(' ') {
-                        view.put(' ', CharStyle::bg(col.into()), Some(t));
-                    }
-                } else {
-                    while !view.cause_newline(' ') {
-                        view.put(' ', styles::DEFAULT, Some(t));
-                    }
+            if i != self.core.buffer().len_lines() - 1 || is_cursorline {
+                while !view.cause_newline(' ') {
+                    view.put(' ', fill, Some(t));
                 }
+            }
+            if i != self.core.buffer().len_lines() - 1 {
                 view.newline();
             }
         }
 
+        let eob = self.end_of_buffer();
+        if eob != ' ' {
+            view.end_of_buffer(eob, styles::END_OF_BUFFER);
+        }
+
         cursor
     }
 }
@@ -0,0 +1,327 @@
+use std::cmp::{max, min};
+
+use termion::event::{Event, Key};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressBase {
+    // 1-based line number. 0 means "before the first line".
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub base: AddressBase,
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range(pub Address, pub Address);
+
+// Where `n` and `N` put the cursor relative to a match, like `/pat/e+1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOffset {
+    // Lines below the match start
+    Line(isize),
+    // Characters from the match start
+    Start(isize),
+    // Characters from the last character of the match
+    End(isize),
+}
+
+impl Default for SearchOffset {
+    fn default() -> Self {
+        SearchOffset::Start(0)
+    }
+}
+
+pub struct CommandLine<'a> {
+    pub range: Option<Range>,
+    pub name: &'a str,
+    pub bang: bool,
+    pub args: &'a str,
+    // `args` with trailing whitespace kept, for `:normal`
+    pub raw_args: &'a str,
+}
+
+impl Address {
+    fn new(base: AddressBase) -> Self {
+        Self { base, offset: 0 }
+    }
+
+    // Returns 1-based line number. `mark` returns 0-based row of the mark.
+    pub fn line<F: Fn(char) -> Option<usize>>(
+        &self,
+        current_row: usize,
+        len_lines: usize,
+        mark: F,
+    ) -> Option<usize> {
+        let line = match self.base {
+            AddressBase::Line(line) => line,
+            AddressBase::Current => current_row + 1,
+            AddressBase::Last => len_lines,
+            AddressBase::Mark(c) => mark(c)? + 1,
+        } as isize
+            + self.offset;
+
+        if line >= 0 && line <= len_lines as isize {
+            Some(line as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Range {
+    // Returns inclusive 0-based rows.
+    pub fn rows<F: Fn(char) -> Option<usize>>(
+        &self,
+        current_row: usize,
+        len_lines: usize,
+        mark: F,
+    ) -> Option<(usize, usize)> {
+        let l = self.0.line(current_row, len_lines, &mark)?;
+        let r = self.1.line(current_row, len_lines, &mark)?;
+        let l = max(1, min(l, r));
+        let r = max(1, max(l, r));
+        Some((l - 1, r - 1))
+    }
+
+    pub fn whole() -> Self {
+        Range(
+            Address::new(AddressBase::Line(1)),
+            Address::new(AddressBase::Last),
+        )
+    }
+}
+
+// `reg[isters]` style abbreviation
+pub fn is_abbrev(name: &str, min: &str, full: &str) -> bool {
+    name.starts_with(min) && full.starts_with(name)
+}
+
+fn parse_number(s: &str) -> (Option<usize>, &str) {
+    let len = s
+        .char_indices()
+        .find(|&(_, c)| !c.is_ascii_digit())
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| s.len());
+    (s[..len].parse().ok(), &s[len..])
+}
+
+pub fn parse_address(s: &str) -> (Option<Address>, &str) {
+    let mut chars = s.chars();
+    let (base, mut rest) = match chars.next() {
+        Some('.') => (Some(AddressBase::Current), chars.as_str()),
+        Some('$') => (Some(AddressBase::Last), chars.as_str()),
+        Some('\'') => {
+            if let Some(c) = chars.next() {
+                (Some(AddressBase::Mark(c)), chars.as_str())
+            } else {
+                (None, s)
+            }
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let (n, rest) = parse_number(s);
+            (n.map(AddressBase::Line), rest)
+        }
+        _ => (None, s),
+    };
+
+    let mut offset = 0;
+    let mut has_offset = false;
+    loop {
+        let sign = if rest.starts_with('+') {
+            1
+        } else if rest.starts_with('-') {
+            -1
+        } else {
+            break;
+        };
+        has_offset = true;
+        let (n, r) = parse_number(&rest[1..]);
+        offset += sign * n.unwrap_or(1) as isize;
+        rest = r;
+    }
+
+    match base {
+        Some(base) => (Some(Address { base, offset }), rest),
+        None if has_offset => (
+            Some(Address {
+                base: AddressBase::Current,
+                offset,
+            }),
+            rest,
+        ),
+        None => (None, s),
+    }
+}
+
+pub fn parse_range(s: &str) -> (Option<Range>, &str) {
+    if let Some(rest) = s.strip_prefix('%') {
+        return (Some(Range::whole()), rest);
+    }
+
+    let (l, rest) = parse_address(s);
+    if let Some(rest) = rest.strip_prefix(',') {
+        let l = l.unwrap_or_else(|| Address::new(AddressBase::Current));
+        let (r, rest) = parse_address(rest);
+        let r = r.unwrap_or(l);
+        (Some(Range(l, r)), rest)
+    } else {
+        (l.map(|l| Range(l, l)), rest)
+    }
+}
+
+pub fn parse(s: &str) -> CommandLine<'_> {
+    let (range, s) = parse_range(s.trim_start());
+    let s = s.trim_start();
+    let name_len = s
+        .char_indices()
+        .find(|&(_, c)| !c.is_alphabetic())
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| s.len());
+    // Non alphabetic commands like `!` are one character.
+    let name_len = if name_len == 0 {
+        s.chars().next().map(char::len_utf8).unwrap_or(0)
+    } else {
+        name_len
+    };
+    let (name, rest) = s.split_at(name_len);
+    let bang = name != "!" && rest.starts_with('!');
+    let rest = if bang { &rest[1..] } else { rest };
+
+    CommandLine {
+        range,
+        name,
+        bang,
+        args: rest.trim(),
+        raw_args: rest.trim_start(),
+    }
+}
+
+// Parse keys like `A;<Esc>` of `:normal`. Supports `<Esc>`, `<CR>`, `<BS>`, `<Tab>`, `<lt>` and `<C-x>`.
+pub fn parse_keys(s: &str) -> Vec<Event> {
+    let mut res = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = rest[1..end].to_lowercase();
+                let key = match name.as_str() {
+                    "esc" => Some(Key::Esc),
+                    "cr" | "enter" => Some(Key::Char('\n')),
+                    "bs" => Some(Key::Backspace),
+                    "tab" => Some(Key::Char('\t')),
+                    "lt" => Some(Key::Char('<')),
+                    _ if name.starts_with("c-") && name.chars().count() == 3 => {
+                        name.chars().last().map(Key::Ctrl)
+                    }
+                    _ => None,
+                };
+                if let Some(key) = key {
+                    res.push(Event::Key(key));
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        res.push(Event::Key(Key::Char(c)));
+        rest = &rest[c.len_utf8()..];
+    }
+    res
+}
+
+// Translate vim style `&` and `\1` into `regex` crate style.
+pub fn substitute_replacement(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => res.push_str("${0}"),
+            '$' => res.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    res.push_str("${");
+                    res.push(d);
+                    res.push('}');
+                }
+                Some('t') => res.push('\t'),
+                Some('r') => res.push('\n'),
+                Some(c) => res.push(c),
+                None => res.push('\\'),
+            },
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// `[+-]n`, `e[+-n]`, `s[+-n]` or `b[+-n]`
+pub fn parse_search_offset(s: &str) -> Option<SearchOffset> {
+    let (kind, rest) = match s.chars().next() {
+        Some(c) if c == 'e' || c == 's' || c == 'b' => (c, &s[1..]),
+        None => return Some(SearchOffset::default()),
+        _ => ('l', s),
+    };
+    let (n, rest) = if let Some(rest) = rest.strip_prefix('+') {
+        let (n, rest) = parse_number(rest);
+        (n.unwrap_or(1) as isize, rest)
+    } else if let Some(rest) = rest.strip_prefix('-') {
+        let (n, rest) = parse_number(rest);
+        (-(n.unwrap_or(1) as isize), rest)
+    } else if kind == 'l' {
+        let (n, rest) = parse_number(rest);
+        (n? as isize, rest)
+    } else {
+        (0, rest)
+    };
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(match kind {
+        'e' => SearchOffset::End(n),
+        'l' => SearchOffset::Line(n),
+        _ => SearchOffset::Start(n),
+    })
+}
+
+// `pattern/offset` typed after `/`. An invalid offset is ignored.
+pub fn split_search(s: &str) -> (String, SearchOffset) {
+    let parts = split_pattern(&format!("/{}", s)).unwrap_or_default();
+    let offset = parts
+        .get(1)
+        .and_then(|offset| parse_search_offset(offset))
+        .unwrap_or_default();
+    (parts.into_iter().next().unwrap_or_default(), offset)
+}
+
+// Split `/pat/rep/flags` by its first character.
+pub fn split_pattern(s: &str) -> Option<Vec<String>> {
+    let mut chars = s.chars();
+    let delimiter = chars.next()?;
+    if delimiter.is_alphanumeric() || delimiter == '\\' || delimiter.is_whitespace() {
+        return None;
+    }
+
+    let mut res = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(c) if c == delimiter => res.last_mut().unwrap().push(c),
+                Some(c) => {
+                    res.last_mut().unwrap().push('\\');
+                    res.last_mut().unwrap().push(c);
+                }
+                None => res.last_mut().unwrap().push('\\'),
+            }
+        } else if c == delimiter {
+            res.push(String::new());
+        } else {
+            res.last_mut().unwrap().push(c);
+        }
+    }
+    Some(res)
+}
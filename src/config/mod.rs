@@ -9,21 +9,86 @@ use crate::config::snippet::load_snippet;
 use crate::config::types::keys;
 use crate::config::types::Command;
 use crate::config::types::CompilerConfig;
+use crate::config::types::FinalNewline;
+use crate::draw::CursorShape;
 
 mod snippet;
 pub mod types;
 
 const DEFAULT_CONFIG: &str = include_str!("../../assets/default_config.toml");
 
+// File types of `[filetype.name]` by extension or file name. The first extension of a type is
+// used for its syntax.
+const FILETYPES: &[(&str, &[&str])] = &[
+    ("rust", &["rs"]),
+    ("python", &["py", "pyw"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cpp", "cc", "cxx", "hpp", "hh"]),
+    ("go", &["go"]),
+    ("javascript", &["js", "mjs"]),
+    ("typescript", &["ts", "tsx"]),
+    ("java", &["java"]),
+    ("ruby", &["rb"]),
+    ("sh", &["sh", "bash", "zsh"]),
+    ("toml", &["toml"]),
+    ("yaml", &["yaml", "yml"]),
+    ("json", &["json"]),
+    ("markdown", &["md", "markdown"]),
+    ("html", &["html", "htm"]),
+    ("css", &["css"]),
+    ("make", &["mk", "Makefile", "makefile"]),
+];
+
+pub fn detect_filetype(path: &path::Path) -> Option<String> {
+    let key = path.extension().or_else(|| path.file_name())?.to_str()?;
+    FILETYPES
+        .iter()
+        .find(|(_, keys)| keys.contains(&key))
+        .map(|(name, _)| String::from(*name))
+}
+
+// The extension of the syntax of a file type
+pub fn filetype_extension(filetype: &str) -> Option<&'static str> {
+    FILETYPES
+        .iter()
+        .find(|(name, _)| *name == filetype)
+        .and_then(|(_, keys)| keys.first().cloned())
+}
+
 #[derive(Deserialize, Debug)]
 struct ConfigToml {
     file: Option<HashMap<String, LanguageConfigToml>>,
+    filetype: Option<HashMap<String, LanguageConfigToml>>,
     file_default: Option<LanguageConfigToml>,
 }
 
 #[derive(Deserialize, Debug)]
 struct LanguageConfigToml {
     ansi_color: Option<bool>,
+    emacs_insert_keys: Option<bool>,
+    textwidth: Option<usize>,
+    spellfile: Option<String>,
+    undofile: Option<bool>,
+    expandtab: Option<bool>,
+    strip_ansi: Option<bool>,
+    undo_memory: Option<usize>,
+    comment: Option<String>,
+    zen_width: Option<usize>,
+    rainbow_brackets: Option<bool>,
+    smart_home: Option<bool>,
+    date_format: Option<String>,
+    time_format: Option<String>,
+    leader: Option<String>,
+    leader_mappings: Option<BTreeMap<String, String>>,
+    keymap: Option<BTreeMap<String, String>>,
+    timeoutlen: Option<u64>,
+    matchpairs: Option<String>,
+    end_of_buffer: Option<String>,
+    final_newline: Option<FinalNewline>,
+    cursor_normal: Option<String>,
+    cursor_insert: Option<String>,
+    cursor_replace: Option<String>,
+    cursor_visual: Option<String>,
     snippets: Option<Vec<String>>,
     indent_width: Option<usize>,
     lsp: Option<Vec<String>>,
@@ -51,6 +116,7 @@ impl LanguageConfig {
 #[derive(Default)]
 struct Config {
     file: HashMap<OsString, LanguageConfig>,
+    filetype: HashMap<String, LanguageConfig>,
     file_default: Option<LanguageConfig>,
 }
 
@@ -76,6 +142,37 @@ impl Into<LanguageConfig> for LanguageConfigToml {
         let mut language_config = LanguageConfig::default();
 
         language_config.insert_option::<keys::ANSIColor>(self.ansi_color);
+        language_config.insert_option::<keys::EmacsInsertKeys>(self.emacs_insert_keys);
+        language_config.insert_option::<keys::TextWidth>(self.textwidth);
+        language_config.insert_option::<keys::SpellFile>(
+            self.spellfile
+                .map(|s| shellexpand::full(&s).unwrap().into_owned()),
+        );
+        language_config.insert_option::<keys::UndoFile>(self.undofile);
+        language_config.insert_option::<keys::UndoMemory>(self.undo_memory);
+        language_config.insert_option::<keys::ExpandTab>(self.expandtab);
+        language_config.insert_option::<keys::StripAnsi>(self.strip_ansi);
+        language_config.insert_option::<keys::Comment>(self.comment);
+        language_config.insert_option::<keys::ZenWidth>(self.zen_width);
+        language_config.insert_option::<keys::RainbowBrackets>(self.rainbow_brackets);
+        language_config.insert_option::<keys::SmartHome>(self.smart_home);
+        language_config.insert_option::<keys::DateFormat>(self.date_format);
+        language_config.insert_option::<keys::TimeFormat>(self.time_format);
+        let leader = self.leader.and_then(|s| s.chars().next());
+        language_config.insert_option::<keys::Leader>(leader);
+        language_config.insert_option::<keys::LeaderMappings>(self.leader_mappings);
+        language_config.insert_option::<keys::Keymap>(self.keymap);
+        language_config.insert_option::<keys::TimeoutLen>(self.timeoutlen);
+        language_config.insert_option::<keys::MatchPairs>(self.matchpairs);
+        let end_of_buffer = self.end_of_buffer.and_then(|s| s.chars().next());
+        language_config.insert_option::<keys::EndOfBuffer>(end_of_buffer);
+        language_config.insert_option::<keys::FinalNewline>(self.final_newline);
+        // Unknown shapes fall back to the default config
+        let shape = |s: Option<String>| s.and_then(|s| CursorShape::from_name(&s));
+        language_config.insert_option::<keys::CursorNormal>(shape(self.cursor_normal));
+        language_config.insert_option::<keys::CursorInsert>(shape(self.cursor_insert));
+        language_config.insert_option::<keys::CursorReplace>(shape(self.cursor_replace));
+        language_config.insert_option::<keys::CursorVisual>(shape(self.cursor_visual));
         language_config.0.insert::<keys::Snippets>(snippets);
         language_config.insert_option::<keys::IndentWidth>(self.indent_width);
         language_config.insert_option::<keys::LSP>(
@@ -103,6 +200,12 @@ impl Into<Config> for ConfigToml {
                 .into_iter()
                 .map(|(k, v)| (OsString::from(k), v.into()))
                 .collect(),
+            filetype: self
+                .filetype
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect(),
             file_default: self.file_default.map(Into::into),
         }
     }
@@ -137,22 +240,48 @@ impl Default for ConfigWithDefault {
 }
 
 impl Config {
-    fn get<A: Key>(&self, path: Option<&path::Path>) -> Option<&A::Value> {
-        path.and_then(|path| path.extension().or_else(|| path.file_name()))
-            .and_then(|k| self.file.get(k))
-            .or_else(|| self.file_default.as_ref())
-            .and_then(|config| config.0.get::<A>())
+    // `[file.ext]` and then `[filetype.name]`
+    fn file_configs(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> Vec<&LanguageConfig> {
+        let file = path
+            .and_then(|path| path.extension().or_else(|| path.file_name()))
+            .and_then(|k| self.file.get(k));
+        let filetype = filetype.and_then(|k| self.filetype.get(k));
+        file.into_iter().chain(filetype).collect()
     }
 
-    fn snippets(&self, path: Option<&path::Path>) -> BTreeMap<String, String> {
-        if let Some(path) = path {
-            let key = path.extension().or_else(|| path.file_name());
-            let mut snippets = key
-                .and_then(|k| {
-                    self.file
-                        .get(k)
-                        .and_then(|config| config.0.get::<keys::Snippets>().cloned())
-                })
+    fn get<A: Key>(&self, path: Option<&path::Path>, filetype: Option<&str>) -> Option<&A::Value> {
+        self.get_file::<A>(path, filetype).or_else(|| {
+            self.file_default
+                .as_ref()
+                .and_then(|config| config.0.get::<A>())
+        })
+    }
+
+    // Only from `[file.ext]` and `[filetype.name]`, not from `[file_default]`
+    fn get_file<A: Key>(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> Option<&A::Value> {
+        self.file_configs(path, filetype)
+            .iter()
+            .find_map(|config| config.0.get::<A>())
+    }
+
+    fn snippets(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> BTreeMap<String, String> {
+        if path.is_some() || filetype.is_some() {
+            let mut snippets = self
+                .file_configs(path, filetype)
+                .iter()
+                .find_map(|config| config.0.get::<keys::Snippets>().cloned())
                 .unwrap_or_default();
 
             let mut snippets_default = self
@@ -175,13 +304,32 @@ impl Config {
 }
 
 impl ConfigWithDefault {
-    pub fn get<A: Key>(&self, path: Option<&path::Path>) -> Option<&A::Value> {
+    pub fn get<A: Key>(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> Option<&A::Value> {
+        self.config
+            .get::<A>(path, filetype)
+            .or_else(|| self.default.get::<A>(path, filetype))
+    }
+
+    // A value configured for the file type, ignoring `[file_default]`
+    pub fn get_file<A: Key>(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> Option<&A::Value> {
         self.config
-            .get::<A>(path)
-            .or_else(|| self.default.get::<A>(path))
+            .get_file::<A>(path, filetype)
+            .or_else(|| self.default.get_file::<A>(path, filetype))
     }
 
-    pub fn snippets(&self, path: Option<&path::Path>) -> BTreeMap<String, String> {
-        self.config.snippets(path)
+    pub fn snippets(
+        &self,
+        path: Option<&path::Path>,
+        filetype: Option<&str>,
+    ) -> BTreeMap<String, String> {
+        self.config.snippets(path, filetype)
     }
 }
@@ -31,6 +31,19 @@ pub enum CompilerType {
     Gcc,
 }
 
+// What `:w` does with newlines at the end of the file
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalNewline {
+    // Add a newline if there is none
+    #[serde(rename = "ensure")]
+    Ensure,
+    #[serde(rename = "preserve")]
+    Preserve,
+    // Remove trailing blank lines and end with one newline
+    #[serde(rename = "trim")]
+    Trim,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct CompilerConfig {
     pub command: Vec<String>,
@@ -48,6 +61,8 @@ pub mod keys {
 
     use crate::config::types::Command;
     use crate::config::types::CompilerConfig;
+    use crate::config::types::FinalNewline as FinalNewlineValue;
+    use crate::draw::CursorShape;
 
     pub struct ANSIColor;
 
@@ -55,6 +70,157 @@ pub mod keys {
         type Value = bool;
     }
 
+    pub struct EmacsInsertKeys;
+
+    impl Key for EmacsInsertKeys {
+        type Value = bool;
+    }
+
+    pub struct TextWidth;
+
+    impl Key for TextWidth {
+        type Value = usize;
+    }
+
+    pub struct SpellFile;
+
+    impl Key for SpellFile {
+        type Value = String;
+    }
+
+    pub struct CursorNormal;
+
+    impl Key for CursorNormal {
+        type Value = CursorShape;
+    }
+
+    pub struct CursorInsert;
+
+    impl Key for CursorInsert {
+        type Value = CursorShape;
+    }
+
+    pub struct CursorReplace;
+
+    impl Key for CursorReplace {
+        type Value = CursorShape;
+    }
+
+    pub struct CursorVisual;
+
+    impl Key for CursorVisual {
+        type Value = CursorShape;
+    }
+
+    pub struct FinalNewline;
+
+    impl Key for FinalNewline {
+        type Value = FinalNewlineValue;
+    }
+
+    pub struct RainbowBrackets;
+
+    impl Key for RainbowBrackets {
+        type Value = bool;
+    }
+
+    pub struct MatchPairs;
+
+    impl Key for MatchPairs {
+        type Value = String;
+    }
+
+    pub struct EndOfBuffer;
+
+    impl Key for EndOfBuffer {
+        type Value = char;
+    }
+
+    pub struct Leader;
+
+    impl Key for Leader {
+        type Value = char;
+    }
+
+    // Key sequences typed after the leader and the commands they run
+    pub struct LeaderMappings;
+
+    impl Key for LeaderMappings {
+        type Value = BTreeMap<String, String>;
+    }
+
+    // `strftime`-like formats of `:date` and `:time`
+    pub struct DateFormat;
+
+    impl Key for DateFormat {
+        type Value = String;
+    }
+
+    pub struct TimeFormat;
+
+    impl Key for TimeFormat {
+        type Value = String;
+    }
+
+    // Milliseconds to wait for the rest of a key sequence. 0 waits forever.
+    pub struct TimeoutLen;
+
+    impl Key for TimeoutLen {
+        type Value = u64;
+    }
+
+    // Action names of the Normal mode to keys
+    pub struct Keymap;
+
+    impl Key for Keymap {
+        type Value = BTreeMap<String, String>;
+    }
+
+    // Remove escape sequences from files when they are opened
+    pub struct StripAnsi;
+
+    impl Key for StripAnsi {
+        type Value = bool;
+    }
+
+    // Indent with spaces rather than tabs when the file does not tell
+    pub struct ExpandTab;
+
+    impl Key for ExpandTab {
+        type Value = bool;
+    }
+
+    // Kilobytes of undo history kept in memory
+    pub struct UndoMemory;
+
+    impl Key for UndoMemory {
+        type Value = usize;
+    }
+
+    pub struct SmartHome;
+
+    impl Key for SmartHome {
+        type Value = bool;
+    }
+
+    pub struct ZenWidth;
+
+    impl Key for ZenWidth {
+        type Value = usize;
+    }
+
+    pub struct Comment;
+
+    impl Key for Comment {
+        type Value = String;
+    }
+
+    pub struct UndoFile;
+
+    impl Key for UndoFile {
+        type Value = bool;
+    }
+
     pub struct Snippets;
 
     impl Key for Snippets {
@@ -5,6 +5,7 @@ use std::cmp::{max, min};
 use std::io;
 use std::io::Read;
 use std::num::Wrapping;
+use std::time::Instant;
 
 use ropey::{self, Rope, RopeSlice};
 
@@ -15,6 +16,8 @@ use self::operation::{Operation, OperationArg};
 
 pub mod operation;
 
+const MAX_CHANGES: usize = 100;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Id(Wrapping<usize>);
 
@@ -88,8 +91,20 @@ pub struct Core {
     history: Vec<Vec<Box<Operation>>>,
     history_tmp: Vec<Box<Operation>>,
     redo: Vec<Vec<Box<Operation>>>,
+    // Bytes of `history`. The oldest steps are dropped beyond `undo_memory`.
+    history_size: usize,
+    pub undo_memory: Option<usize>,
+    // Positions of edits for `g;` and `g,`, oldest first. One per line in a row.
+    changes: Vec<Cursor>,
+    change_index: Option<usize>,
     buffer_changed: Id,
     pub dirty_from: usize,
+    // `virtualedit=all`. The cursor may move past the end of a line.
+    pub virtual_edit: bool,
+    // Column past the end of the line while the cursor stays at the position
+    virtual_col: Option<(Cursor, usize)>,
+    // Last edit of each line in this session. Lines past the end are untouched.
+    line_times: Vec<Option<Instant>>,
 }
 
 impl Default for Core {
@@ -100,12 +115,23 @@ impl Default for Core {
             history: Vec::new(),
             history_tmp: Vec::new(),
             redo: Vec::new(),
+            history_size: 0,
+            undo_memory: None,
+            changes: Vec::new(),
+            change_index: None,
             buffer_changed: Id(Wrapping(1)),
             dirty_from: 0,
+            virtual_edit: false,
+            virtual_col: None,
+            line_times: Vec::new(),
         }
     }
 }
 
+fn step_size(ops: &[Box<dyn Operation>]) -> usize {
+    ops.iter().map(|op| op.size()).sum()
+}
+
 impl Core {
     pub fn from_reader<T: Read>(reader: T) -> io::Result<Self> {
         Ok(Self {
@@ -114,8 +140,15 @@ impl Core {
             history: Vec::new(),
             history_tmp: Vec::new(),
             redo: Vec::new(),
+            history_size: 0,
+            undo_memory: None,
+            changes: Vec::new(),
+            change_index: None,
             buffer_changed: Id(Wrapping(1)),
             dirty_from: 0,
+            virtual_edit: false,
+            virtual_col: None,
+            line_times: Vec::new(),
         })
     }
 
@@ -144,33 +177,87 @@ impl Core {
         self.buffer.l(self.cursor.row)
     }
 
+    // `^`-like position of `row`. The line end for a blank line.
+    pub fn first_non_blank(&self, row: usize) -> Cursor {
+        let col = self
+            .buffer
+            .l(row)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        Cursor { row, col }
+    }
+
+    // `g_`. The last non-blank character of `row`, or the start of a blank line.
+    pub fn last_non_blank(&self, row: usize) -> Cursor {
+        let line = self.buffer.l(row);
+        let mut col = line.len_chars();
+        while col > 0 && line.char(col - 1).is_whitespace() {
+            col -= 1;
+        }
+        Cursor {
+            row,
+            col: col.saturating_sub(1),
+        }
+    }
+
     pub fn current_line_after_cursor(&self) -> ropey::RopeSlice {
         self.current_line().slice(self.cursor.col..)
     }
 
+    // The column of the cursor on screen, which is past the line end with `virtualedit=all`
+    pub fn virtual_col(&self) -> usize {
+        match self.virtual_col {
+            Some((cursor, col)) if cursor == self.cursor => col,
+            _ => self.cursor.col,
+        }
+    }
+
+    fn set_virtual_col(&mut self, col: usize) {
+        self.virtual_col = if col > self.cursor.col {
+            Some((self.cursor, col))
+        } else {
+            None
+        };
+    }
+
     pub fn cursor_left(&mut self) {
-        if self.cursor.col != 0 {
+        let col = self.virtual_col();
+        if col > self.cursor.col {
+            self.set_virtual_col(col - 1);
+        } else if self.cursor.col != 0 {
             self.cursor.col -= 1;
         }
     }
 
     pub fn cursor_right(&mut self) {
-        self.cursor.col = min(
-            self.buffer.l(self.cursor.row).len_chars(),
-            self.cursor.col + 1,
-        );
+        let len = self.buffer.l(self.cursor.row).len_chars();
+        if self.virtual_edit && self.cursor.col == len {
+            let col = self.virtual_col() + 1;
+            self.set_virtual_col(col);
+        } else {
+            self.cursor.col = min(len, self.cursor.col + 1);
+        }
+    }
+
+    // Keep the column past the line end with `virtualedit=all`
+    fn move_row(&mut self, row: usize) {
+        let col = self.virtual_col();
+        self.cursor.row = row;
+        self.cursor.col = min(self.buffer.l(row).len_chars(), col);
+        if self.virtual_edit {
+            self.set_virtual_col(col);
+        }
     }
 
     pub fn cursor_up(&mut self) {
         if self.cursor.row != 0 {
-            self.cursor.row -= 1;
-            self.cursor.col = min(self.buffer.l(self.cursor.row).len_chars(), self.cursor.col);
+            self.move_row(self.cursor.row - 1);
         }
     }
 
     pub fn cursor_down(&mut self) {
-        self.cursor.row = min(self.buffer.len_lines() - 1, self.cursor.row + 1);
-        self.cursor.col = min(self.buffer.l(self.cursor.row).len_chars(), self.cursor.col);
+        self.move_row(min(self.buffer.len_lines() - 1, self.cursor.row + 1));
     }
 
     pub fn cursor_inc(&mut self) -> bool {
@@ -239,15 +326,16 @@ impl Core {
         })
     }
 
-    pub fn indent(&mut self, indent_width: usize) {
+    // `unit` is one level of indent, spaces or a tab
+    pub fn indent(&mut self, indent_width: usize, unit: &str) {
         self.cursor.col = 0;
         if self.cursor.row > 0 {
             let indent = indent::next_indent_level(
                 &Cow::from(self.buffer.l(self.cursor.row - 1)),
                 indent_width,
             );
-            for _ in 0..indent_width * indent {
-                self.insert(' ');
+            for c in unit.repeat(indent).chars() {
+                self.insert(c);
             }
         }
     }
@@ -325,34 +413,34 @@ impl Core {
     }
 
     pub fn insert(&mut self, c: char) {
-        let op = operation::Insert {
-            cursor: self.cursor,
-            c,
-        };
+        // Pad with spaces up to a column past the line end
+        if c != '\n' {
+            for _ in self.cursor.col..self.virtual_col() {
+                let op = operation::Insert::new(self.cursor, ' ');
+                self.perform(op);
+            }
+        }
+        let op = operation::Insert::new(self.cursor, c);
         self.perform(op);
     }
 
     // o
     pub fn insert_newline(&mut self) {
-        let op = operation::Insert {
-            cursor: Cursor {
-                row: self.cursor.row,
-                col: self.buffer.l(self.cursor.row).len_chars(),
-            },
-            c: '\n',
+        let cursor = Cursor {
+            row: self.cursor.row,
+            col: self.buffer.l(self.cursor.row).len_chars(),
         };
+        let op = operation::Insert::new(cursor, '\n');
         self.perform(op);
     }
 
     // O
     pub fn insert_newline_here(&mut self) {
-        let op = operation::Insert {
-            cursor: Cursor {
-                row: self.cursor.row,
-                col: 0,
-            },
-            c: '\n',
+        let cursor = Cursor {
+            row: self.cursor.row,
+            col: 0,
         };
+        let op = operation::Insert::new(cursor, '\n');
         self.perform(op);
         self.cursor_up();
     }
@@ -372,6 +460,12 @@ impl Core {
         self.perform(op);
     }
 
+    // Replace lines `first..=last` (without the last line break) by `s`.
+    pub fn replace_lines(&mut self, first: usize, last: usize, s: String) {
+        let op = operation::ReplaceLines::new(first, last, s);
+        self.perform(op);
+    }
+
     pub fn get_slice_by_range(&self, range: CursorRange) -> RopeSlice {
         let l = self.buffer.line_to_char(range.l().row) + range.l().col;
         let mut r = self.buffer.line_to_char(range.r().row) + range.r().col;
@@ -404,7 +498,11 @@ impl Core {
             self.dirty_from = 0;
             self.redo.clear();
             self.history.clear();
+            self.history_size = 0;
             self.history_tmp.clear();
+            self.changes.clear();
+            self.change_index = None;
+            self.line_times.clear();
         } else {
             let op = operation::Set::new(s);
             self.perform(op);
@@ -415,6 +513,12 @@ impl Core {
         &self.buffer
     }
 
+    // Text read after the buffer is opened. Neither undoable nor a modification.
+    pub fn append_loaded(&mut self, s: &str) {
+        let len = self.buffer.len_chars();
+        self.buffer.insert(len, s);
+    }
+
     pub fn cursor(&self) -> Cursor {
         self.cursor
     }
@@ -423,9 +527,18 @@ impl Core {
         assert!(cursor.row < self.buffer.len_lines());
         assert!(cursor.col <= self.buffer.l(cursor.row).len_chars());
         self.cursor = cursor;
+        self.virtual_col = None;
+    }
+
+    pub fn clamp_cursor(&self, cursor: Cursor) -> Cursor {
+        let row = min(cursor.row, self.buffer.len_lines() - 1);
+        let col = min(cursor.col, self.buffer.l(row).len_chars());
+        Cursor { row, col }
     }
 
     fn arg(&mut self) -> OperationArg {
+        // Edits move the text under a column past the line end
+        self.virtual_col = None;
         OperationArg {
             buffer: &mut self.buffer,
             cursor: &mut self.cursor,
@@ -433,30 +546,105 @@ impl Core {
     }
 
     fn perform<T: Operation + 'static>(&mut self, mut op: T) {
+        let len_lines = self.buffer.len_lines();
         if let Some(l) = op.perform(self.arg()) {
             self.dirty_from = min(self.dirty_from, l);
+            self.touch_lines(l, len_lines);
         }
         self.history_tmp.push(Box::new(op));
         self.redo.clear();
+        self.push_change();
         self.buffer_changed.inc();
     }
 
+    // Stamp the edited line `row` and lines inserted after it. `len_lines` is the count before the edit.
+    fn touch_lines(&mut self, row: usize, len_lines: usize) {
+        let now = Some(Instant::now());
+        let len = self.buffer.len_lines();
+        if self.line_times.len() <= row {
+            self.line_times.resize(row + 1, None);
+        }
+        if len > len_lines {
+            let i = row + 1;
+            self.line_times.splice(i..i, vec![now; len - len_lines]);
+        } else {
+            let end = min(row + 1 + len_lines - len, self.line_times.len());
+            self.line_times.drain(row + 1..end);
+        }
+        self.line_times[row] = now;
+    }
+
+    // When the line `row` was last edited in this session
+    pub fn line_time(&self, row: usize) -> Option<Instant> {
+        self.line_times.get(row).cloned().flatten()
+    }
+
+    fn push_change(&mut self) {
+        if self.changes.last().map(|c| c.row) == Some(self.cursor.row) {
+            self.changes.pop();
+        }
+        self.changes.push(self.cursor);
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.remove(0);
+        }
+        self.change_index = None;
+    }
+
+    // `g;` if `older` else `g,`. `None` at either end of the change list.
+    pub fn change_position(&mut self, older: bool) -> Option<Cursor> {
+        let index = match (self.change_index, older) {
+            (None, true) => self.changes.len().checked_sub(1)?,
+            (None, false) => return None,
+            (Some(i), true) => i.checked_sub(1)?,
+            (Some(i), false) if i + 1 < self.changes.len() => i + 1,
+            (Some(_), false) => return None,
+        };
+        self.change_index = Some(index);
+        Some(self.clamp_cursor(self.changes[index]))
+    }
+
     pub fn commit(&mut self) {
         if !self.history_tmp.is_empty() {
             let mut h = Vec::new();
             std::mem::swap(&mut self.history_tmp, &mut h);
+            self.history_size += step_size(&h);
             self.history.push(h);
+            self.drop_old_history();
+        }
+    }
+
+    // The latest step is kept even if it is larger than `undo_memory`
+    fn drop_old_history(&mut self) {
+        if let Some(limit) = self.undo_memory {
+            let mut n = 0;
+            while self.history_size > limit && n + 1 < self.history.len() {
+                self.history_size -= step_size(&self.history[n]);
+                n += 1;
+            }
+            self.history.drain(..n);
         }
     }
 
+    // Bytes used by the undo history
+    pub fn history_size(&self) -> usize {
+        self.history_size
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.history.len()
+    }
+
     pub fn undo(&mut self) {
         self.commit();
         if let Some(mut ops) = self.history.pop() {
             for op in ops.iter_mut().rev() {
+                let len_lines = self.buffer.len_lines();
                 if let Some(l) = op.undo(self.arg()) {
                     self.dirty_from = min(self.dirty_from, l);
+                    self.touch_lines(l, len_lines);
                 }
             }
+            self.history_size -= step_size(&ops);
             self.redo.push(ops);
             self.buffer_changed.inc();
         }
@@ -465,12 +653,36 @@ impl Core {
     pub fn redo(&mut self) {
         if let Some(mut ops) = self.redo.pop() {
             for op in &mut ops {
+                let len_lines = self.buffer.len_lines();
                 if let Some(l) = op.perform(self.arg()) {
                     self.dirty_from = min(self.dirty_from, l);
+                    self.touch_lines(l, len_lines);
                 }
             }
+            self.history_size += step_size(&ops);
             self.history.push(ops);
             self.buffer_changed.inc();
         }
     }
+
+    // Undo history as the splices of each step, oldest first. The operations record them.
+    pub fn undo_steps(&mut self) -> Vec<Vec<operation::Splice>> {
+        self.commit();
+        self.history
+            .iter()
+            .map(|ops| ops.iter().filter_map(|op| op.splice()).collect())
+            .collect()
+    }
+
+    // Replace the history with `steps` which end at the current text
+    pub fn set_undo_steps(&mut self, steps: Vec<Vec<operation::Splice>>) {
+        self.history = steps
+            .into_iter()
+            .map(|step| step.into_iter().map(|s| Box::new(s) as _).collect())
+            .collect();
+        self.history_size = self.history.iter().map(|ops| step_size(ops)).sum();
+        self.history_tmp.clear();
+        self.redo.clear();
+        self.drop_old_history();
+    }
 }
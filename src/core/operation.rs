@@ -2,6 +2,7 @@ use std::cmp::min;
 use std::fmt::Debug;
 
 use ropey::Rope;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::core::{Cursor, CursorRange};
 use crate::ropey_util::{is_line_end, RopeExt};
@@ -14,12 +15,33 @@ pub struct OperationArg<'a> {
 pub trait Operation: Debug {
     fn perform(&mut self, arg: OperationArg) -> Option<usize>;
     fn undo(&mut self, arg: OperationArg) -> Option<usize>;
+    // Approximate bytes kept in the undo history, including text saved for undo
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+    // The change of the last `perform` for the undo file. `None` if nothing was changed.
+    fn splice(&self) -> Option<Splice>;
+}
+
+fn text_size(s: &Option<String>) -> usize {
+    s.as_ref().map_or(0, String::len)
 }
 
 #[derive(Debug)]
 pub struct Insert {
     pub cursor: Cursor,
     pub c: char,
+    index: usize,
+}
+
+impl Insert {
+    pub fn new(cursor: Cursor, c: char) -> Self {
+        Self {
+            cursor,
+            c,
+            index: 0,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -27,6 +49,7 @@ pub struct Replace {
     pub cursor: Cursor,
     pub c: char,
     pub orig: Option<char>,
+    index: usize,
 }
 
 impl Replace {
@@ -35,6 +58,7 @@ impl Replace {
             cursor,
             c,
             orig: None,
+            index: 0,
         }
     }
 }
@@ -44,6 +68,7 @@ pub struct Delete {
     pub cursor: Cursor,
     pub orig: Option<char>,
     pub done: bool,
+    index: usize,
 }
 
 impl Delete {
@@ -52,6 +77,7 @@ impl Delete {
             cursor,
             orig: None,
             done: false,
+            index: 0,
         }
     }
 }
@@ -60,11 +86,16 @@ impl Delete {
 pub struct DeleteRange {
     pub range: CursorRange,
     orig: Option<String>,
+    index: usize,
 }
 
 impl DeleteRange {
     pub fn new(range: CursorRange) -> Self {
-        Self { range, orig: None }
+        Self {
+            range,
+            orig: None,
+            index: 0,
+        }
     }
 }
 
@@ -80,9 +111,70 @@ impl Set {
     }
 }
 
+#[derive(Debug)]
+pub struct ReplaceLines {
+    first: usize,
+    last: usize,
+    to: String,
+    from: Option<String>,
+    index: usize,
+}
+
+impl ReplaceLines {
+    pub fn new(first: usize, last: usize, to: String) -> Self {
+        Self {
+            first,
+            last,
+            to,
+            from: None,
+            index: 0,
+        }
+    }
+}
+
+// Replace `from` at char index `start` with `to`. Restored undo history is made of these.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Splice {
+    pub start: usize,
+    pub from: String,
+    pub to: String,
+}
+
+impl Splice {
+    // The smallest splice which turns `a` into `b`
+    pub fn diff(a: &str, b: &str) -> Self {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+        let suffix = a[prefix..]
+            .iter()
+            .rev()
+            .zip(b[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        Self {
+            start: prefix,
+            from: a[prefix..a.len() - suffix].iter().collect(),
+            to: b[prefix..b.len() - suffix].iter().collect(),
+        }
+    }
+
+    fn apply(arg: OperationArg, start: usize, from: &str, to: &str) -> Option<usize> {
+        arg.buffer.remove(start..start + from.chars().count());
+        arg.buffer.insert(start, to);
+        let row = arg.buffer.char_to_line(start);
+        *arg.cursor = Cursor {
+            row,
+            col: start - arg.buffer.line_to_char(row),
+        };
+        Some(row)
+    }
+}
+
 impl Operation for Insert {
     fn perform(&mut self, arg: OperationArg) -> Option<usize> {
         let i = arg.buffer.line_to_char(self.cursor.row) + self.cursor.col;
+        self.index = i;
         arg.buffer.insert_char(i, self.c);
         let mut cursor = self.cursor;
         if self.c == '\n' {
@@ -101,11 +193,20 @@ impl Operation for Insert {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(Splice {
+            start: self.index,
+            from: String::new(),
+            to: self.c.to_string(),
+        })
+    }
 }
 
 impl Operation for Replace {
     fn perform(&mut self, arg: OperationArg) -> Option<usize> {
         let i = arg.buffer.line_to_char(self.cursor.row) + self.cursor.col;
+        self.index = i;
         if self.cursor.col < arg.buffer.l(self.cursor.row).len_chars() {
             self.orig = Some(arg.buffer.l(self.cursor.row).char(self.cursor.col));
             arg.buffer.remove(i..=i);
@@ -124,11 +225,20 @@ impl Operation for Replace {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(Splice {
+            start: self.index,
+            from: self.orig.map(String::from).unwrap_or_default(),
+            to: self.c.to_string(),
+        })
+    }
 }
 
 impl Operation for Delete {
     fn perform(&mut self, arg: OperationArg) -> Option<usize> {
         let i = arg.buffer.line_to_char(self.cursor.row) + self.cursor.col;
+        self.index = i;
 
         if self.cursor.col < arg.buffer.l(self.cursor.row).len_chars() {
             self.orig = Some(arg.buffer.char(i));
@@ -165,9 +275,25 @@ impl Operation for Delete {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    // A line break is restored as `\n` like by `undo`
+    fn splice(&self) -> Option<Splice> {
+        if !self.done {
+            return None;
+        }
+        Some(Splice {
+            start: self.index,
+            from: self.orig.unwrap_or('\n').to_string(),
+            to: String::new(),
+        })
+    }
 }
 
 impl Operation for DeleteRange {
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + text_size(&self.orig)
+    }
+
     fn perform(&mut self, arg: OperationArg) -> Option<usize> {
         let l = arg.buffer.line_to_char(self.range.l().row) + self.range.l().col;
         let mut r = arg.buffer.line_to_char(self.range.r().row) + self.range.r().col;
@@ -187,6 +313,7 @@ impl Operation for DeleteRange {
         }
 
         self.orig = Some(String::from(arg.buffer.slice(l..r)));
+        self.index = l;
         arg.buffer.remove(l..r);
         *arg.cursor = self.range.l();
         Some(self.range.l().row)
@@ -199,9 +326,21 @@ impl Operation for DeleteRange {
         *arg.cursor = self.range.l();
         Some(self.range.l().row)
     }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(Splice {
+            start: self.index,
+            from: self.orig.clone()?,
+            to: String::new(),
+        })
+    }
 }
 
 impl Operation for Set {
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.to.len() + text_size(&self.from)
+    }
+
     fn perform(&mut self, arg: OperationArg) -> Option<usize> {
         if self.from.is_none() {
             self.from = Some(String::from(arg.buffer.slice(..)));
@@ -219,4 +358,64 @@ impl Operation for Set {
         arg.cursor.col = min(arg.buffer.l(arg.cursor.row).len_chars(), arg.cursor.col);
         Some(0)
     }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(Splice::diff(self.from.as_ref()?, &self.to))
+    }
+}
+
+impl Operation for ReplaceLines {
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.to.len() + text_size(&self.from)
+    }
+
+    fn perform(&mut self, arg: OperationArg) -> Option<usize> {
+        let l = arg.buffer.line_to_char(self.first);
+        let r = arg.buffer.line_to_char(self.last) + arg.buffer.l(self.last).len_chars();
+
+        self.from = Some(String::from(arg.buffer.slice(l..r)));
+        self.index = l;
+        arg.buffer.remove(l..r);
+        arg.buffer.insert(l, self.to.as_str());
+        arg.cursor.row = min(arg.buffer.len_lines() - 1, arg.cursor.row);
+        arg.cursor.col = min(arg.buffer.l(arg.cursor.row).len_chars(), arg.cursor.col);
+        Some(self.first)
+    }
+
+    fn undo(&mut self, arg: OperationArg) -> Option<usize> {
+        let l = arg.buffer.line_to_char(self.first);
+        let r = l + self.to.chars().count();
+
+        arg.buffer.remove(l..r);
+        arg.buffer.insert(l, self.from.as_ref().unwrap().as_str());
+        arg.cursor.row = min(arg.buffer.len_lines() - 1, arg.cursor.row);
+        arg.cursor.col = min(arg.buffer.l(arg.cursor.row).len_chars(), arg.cursor.col);
+        Some(self.first)
+    }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(Splice {
+            start: self.index,
+            from: self.from.clone()?,
+            to: self.to.clone(),
+        })
+    }
+}
+
+impl Operation for Splice {
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.from.len() + self.to.len()
+    }
+
+    fn perform(&mut self, arg: OperationArg) -> Option<usize> {
+        Self::apply(arg, self.start, &self.from, &self.to)
+    }
+
+    fn undo(&mut self, arg: OperationArg) -> Option<usize> {
+        Self::apply(arg, self.start, &self.to, &self.from)
+    }
+
+    fn splice(&self) -> Option<Splice> {
+        Some(self.clone())
+    }
 }
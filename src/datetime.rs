@@ -0,0 +1,138 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// A broken-down time for `:date` and `:time`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: i64,
+    // 1 to 12
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    // 0 is Sunday
+    pub weekday: u32,
+    // Seconds east of UTC
+    pub offset: i64,
+}
+
+impl DateTime {
+    // `secs` since the Unix epoch, shown in a time zone `offset` seconds east of UTC
+    pub fn from_unix(secs: i64, offset: i64) -> Self {
+        let local = secs + offset;
+        let days = local.div_euclid(86400);
+        let rest = local.rem_euclid(86400) as u32;
+
+        // Howard Hinnant's civil_from_days
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month,
+            day,
+            hour: rest / 3600,
+            minute: rest / 60 % 60,
+            second: rest % 60,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            offset,
+        }
+    }
+
+    // The current local time
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Self::from_unix(secs, local_offset(secs))
+    }
+
+    // `strftime`-like. Unknown conversions are left as they are.
+    pub fn format(&self, format: &str) -> String {
+        let mut res = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                res.push(c);
+                continue;
+            }
+            // 12, 1, ..., 11
+            let hour12 = (self.hour + 11) % 12 + 1;
+            let weekday = WEEKDAYS[self.weekday as usize];
+            let month = MONTHS[self.month as usize - 1];
+            match chars.next() {
+                Some('Y') => res.push_str(&self.year.to_string()),
+                Some('y') => res.push_str(&format!("{:02}", self.year.rem_euclid(100))),
+                Some('m') => res.push_str(&format!("{:02}", self.month)),
+                Some('d') => res.push_str(&format!("{:02}", self.day)),
+                Some('e') => res.push_str(&format!("{:2}", self.day)),
+                Some('H') => res.push_str(&format!("{:02}", self.hour)),
+                Some('I') => res.push_str(&format!("{:02}", hour12)),
+                Some('M') => res.push_str(&format!("{:02}", self.minute)),
+                Some('S') => res.push_str(&format!("{:02}", self.second)),
+                Some('p') => res.push_str(if self.hour < 12 { "AM" } else { "PM" }),
+                Some('A') => res.push_str(weekday),
+                Some('a') => res.push_str(&weekday[..3]),
+                Some('B') => res.push_str(month),
+                Some('b') => res.push_str(&month[..3]),
+                Some('z') => {
+                    let sign = if self.offset < 0 { '-' } else { '+' };
+                    let minutes = self.offset.abs() / 60;
+                    res.push_str(&format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60));
+                }
+                Some('%') => res.push('%'),
+                Some(c) => {
+                    res.push('%');
+                    res.push(c);
+                }
+                None => res.push('%'),
+            }
+        }
+        res
+    }
+}
+
+// Seconds east of UTC of the local time zone at `secs`. UTC if it cannot be found.
+fn local_offset(secs: i64) -> i64 {
+    let t = secs as libc::time_t;
+    // `localtime_r` only writes to `tm`, which is plain data
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        0
+    } else {
+        tm.tm_gmtoff as i64
+    }
+}
@@ -1,6 +1,8 @@
 use std;
+use std::cmp::{max, min};
 use std::fmt;
 use std::io::{self, Write};
+use std::time::Duration;
 
 use syntect;
 use termion;
@@ -150,6 +152,170 @@ pub mod styles {
         bg: Color { r: 0, g: 0, b: 0 },
         modification: CharModification::Default,
     };
+    pub const WHITESPACE: CharStyle = CharStyle {
+        fg: Color {
+            r: 80,
+            g: 120,
+            b: 160,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::Default,
+    };
+    pub const SPELL: CharStyle = CharStyle {
+        fg: Color {
+            r: 255,
+            g: 80,
+            b: 80,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::UnderLine,
+    };
+    pub const CURSORLINE: CharStyle = CharStyle {
+        fg: Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        },
+        bg: Color {
+            r: 40,
+            g: 40,
+            b: 48,
+        },
+        modification: CharModification::Default,
+    };
+    pub const PREVIEW: CharStyle = CharStyle {
+        fg: Color {
+            r: 255,
+            g: 200,
+            b: 0,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::UnderLine,
+    };
+    // `~` on rows past the end of the buffer
+    pub const END_OF_BUFFER: CharStyle = CharStyle {
+        fg: Color {
+            r: 80,
+            g: 80,
+            b: 200,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::Default,
+    };
+    // Control characters in caret notation
+    pub const CONTROL: CharStyle = CharStyle {
+        fg: Color {
+            r: 80,
+            g: 200,
+            b: 200,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::Default,
+    };
+    // Marks a Visual selection continuing above or below the screen
+    pub const SELECTION_EDGE: CharStyle = CharStyle {
+        fg: Color { r: 0, g: 0, b: 0 },
+        bg: Color {
+            r: 200,
+            g: 200,
+            b: 200,
+        },
+        modification: CharModification::Default,
+    };
+    // The line shown for a closed fold
+    pub const FOLDED: CharStyle = CharStyle {
+        fg: Color {
+            r: 160,
+            g: 160,
+            b: 200,
+        },
+        bg: Color {
+            r: 40,
+            g: 40,
+            b: 64,
+        },
+        modification: CharModification::Default,
+    };
+    // Line numbers of lines edited recently for `:set heatmap`, newest first
+    pub const HEAT: [CharStyle; 4] = [
+        CharStyle {
+            fg: Color { r: 0, g: 0, b: 0 },
+            bg: Color {
+                r: 255,
+                g: 170,
+                b: 60,
+            },
+            modification: CharModification::Default,
+        },
+        CharStyle {
+            fg: Color { r: 0, g: 0, b: 0 },
+            bg: Color {
+                r: 200,
+                g: 120,
+                b: 50,
+            },
+            modification: CharModification::Default,
+        },
+        CharStyle {
+            fg: Color {
+                r: 220,
+                g: 220,
+                b: 220,
+            },
+            bg: Color {
+                r: 130,
+                g: 80,
+                b: 40,
+            },
+            modification: CharModification::Default,
+        },
+        CharStyle {
+            fg: Color {
+                r: 200,
+                g: 200,
+                b: 200,
+            },
+            bg: Color {
+                r: 70,
+                g: 50,
+                b: 30,
+            },
+            modification: CharModification::Default,
+        },
+    ];
+    // Foregrounds of brackets by nesting depth
+    pub const RAINBOW: [Color; 6] = [
+        Color {
+            r: 255,
+            g: 215,
+            b: 0,
+        },
+        Color {
+            r: 218,
+            g: 112,
+            b: 214,
+        },
+        Color {
+            r: 23,
+            g: 159,
+            b: 255,
+        },
+        Color {
+            r: 80,
+            g: 200,
+            b: 120,
+        },
+        Color {
+            r: 255,
+            g: 140,
+            b: 60,
+        },
+        Color {
+            r: 0,
+            g: 200,
+            b: 200,
+        },
+    ];
 }
 
 pub struct StyleWithColorType {
@@ -214,13 +380,25 @@ enum Tile {
     Char(char, CharStyle, Option<Cursor>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CursorShape {
     Block,
     Bar,
     Underline,
 }
 
+impl CursorShape {
+    // Names in the config
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "block" => Some(CursorShape::Block),
+            "bar" => Some(CursorShape::Bar),
+            "underline" => Some(CursorShape::Underline),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Debug)]
 pub enum CursorState {
     Hide,
@@ -282,6 +460,13 @@ pub struct LinenumView<'a> {
     current_linenum: usize,
     width: usize,
     rustc_outputs: &'a [CompilerOutput],
+    // `:set breakindent`. Blank cells at the start of wrapped rows.
+    break_indent: (usize, CharStyle),
+    // `:set heatmap`. Styles of line numbers from the first line. `styles::UI` after them.
+    linenum_styles: Vec<CharStyle>,
+    first_linenum: usize,
+    // `:set relativenumber`. The cursor line.
+    relative_to: Option<usize>,
 }
 
 impl<'a> LinenumView<'a> {
@@ -289,6 +474,8 @@ impl<'a> LinenumView<'a> {
         current_linenum: usize,
         max_linenum: usize,
         rustc_outputs: &'a [CompilerOutput],
+        linenum_styles: Vec<CharStyle>,
+        relative_to: Option<usize>,
         view: View<'a>,
     ) -> Self {
         let width = format!("{}", max_linenum + 1).len() + 2;
@@ -297,20 +484,54 @@ impl<'a> LinenumView<'a> {
             width,
             current_linenum,
             rustc_outputs,
+            break_indent: (0, styles::DEFAULT),
+            linenum_styles,
+            first_linenum: current_linenum,
+            relative_to,
         };
         res.put_linenum();
         res
     }
 
+    // No line numbers for `:zen`
+    pub fn hidden(view: View<'a>) -> Self {
+        Self {
+            view,
+            width: 0,
+            current_linenum: 0,
+            rustc_outputs: &[],
+            break_indent: (0, styles::DEFAULT),
+            linenum_styles: Vec::new(),
+            first_linenum: 0,
+            relative_to: None,
+        }
+    }
+
     pub fn prefix_width(max_linenum: usize) -> usize {
         format!("{}", max_linenum + 1).len() + 2
     }
 
     fn put_linenum(&mut self) {
-        let s = format!("{}", self.current_linenum + 1);
+        if self.width == 0 {
+            return;
+        }
+        let s = match self.relative_to {
+            Some(row) if row != self.current_linenum => {
+                format!(
+                    "{}",
+                    max(row, self.current_linenum) - min(row, self.current_linenum)
+                )
+            }
+            _ => format!("{}", self.current_linenum + 1),
+        };
         let w = s.len();
+        let style = self
+            .linenum_styles
+            .get(self.current_linenum - self.first_linenum)
+            .cloned()
+            .unwrap_or(styles::UI);
         for c in s.chars() {
-            self.view.put(c, styles::UI, None);
+            self.view.put(c, style, None);
         }
 
         if let Some(o) = self
@@ -348,19 +569,40 @@ impl<'a> LinenumView<'a> {
         }
     }
 
+    // Indent wrapped rows of the current line. At most a half of the text width.
+    pub fn set_break_indent(&mut self, indent: usize, style: CharStyle) {
+        self.break_indent = (indent, style);
+    }
+
     pub fn put(&mut self, c: char, style: CharStyle, pos: Option<Cursor>) -> Option<Cursor> {
         if self.view.cause_newline(c) {
             self.view.newline();
             self.put_space();
+            let (indent, indent_style) = self.break_indent;
+            for _ in 0..min(indent, self.view.width.saturating_sub(self.width) / 2) {
+                self.view.put(' ', indent_style, None);
+            }
         }
         self.view.put(c, style, pos)
     }
 
+    // Lines hidden in a closed fold
+    pub fn skip_lines(&mut self, n: usize) {
+        self.current_linenum += n;
+    }
+
     pub fn newline(&mut self) {
         self.current_linenum += 1;
         self.view.newline();
         self.put_linenum();
     }
+
+    // Put `c` at the first column of the remaining rows instead of line numbers
+    pub fn end_of_buffer(&mut self, c: char, style: CharStyle) {
+        while self.view.newline().is_some() && !self.view.is_out() {
+            self.view.put(c, style, None);
+        }
+    }
 }
 
 impl Default for Term {
@@ -378,6 +620,51 @@ impl Default for Term {
 }
 
 impl Term {
+    pub fn new(height: usize, width: usize) -> Self {
+        Term {
+            height,
+            width,
+            cursor: CursorState::Hide,
+            buf: vec![vec![Tile::Char(' ', styles::DEFAULT, None); width]; height],
+        }
+    }
+
+    // Copy `other` to `orig`. Used to draw split windows.
+    pub fn blit(&mut self, orig: (usize, usize), other: Term) {
+        for (i, line) in other.buf.into_iter().enumerate() {
+            for (j, tile) in line.into_iter().enumerate() {
+                if orig.0 + i < self.height && orig.1 + j < self.width {
+                    self.buf[orig.0 + i][orig.1 + j] = tile;
+                }
+            }
+        }
+        if let CursorState::Show(cursor, shape) = other.cursor {
+            let cursor = Cursor {
+                row: orig.0 + cursor.row,
+                col: orig.1 + cursor.col,
+            };
+            self.cursor = CursorState::Show(cursor, shape);
+        }
+    }
+
+    // Characters drawn on `row`
+    pub fn row_string(&self, row: usize) -> String {
+        self.buf[row]
+            .iter()
+            .filter_map(|tile| match tile {
+                Tile::Char(c, _, _) => Some(*c),
+                Tile::Empty => None,
+            })
+            .collect()
+    }
+
+    pub fn style(&self, row: usize, col: usize) -> Option<CharStyle> {
+        match self.buf[row][col] {
+            Tile::Char(_, style, _) => Some(style),
+            Tile::Empty => None,
+        }
+    }
+
     pub fn pos(&self, cursor: Cursor) -> Option<Cursor> {
         for x in (0..=cursor.col).rev() {
             if let Tile::Char(_, _, Some(c)) = self.buf[cursor.row][x] {
@@ -532,11 +819,180 @@ impl DoubleBuffer {
     }
 }
 
+// Rows `[top, bottom)` of the scrollbar thumb in a bar of `height` rows
+pub fn scrollbar_thumb(height: usize, row_offset: usize, len_lines: usize) -> (usize, usize) {
+    let len_lines = max(len_lines, 1);
+    let size = min(height, max(1, height * height / len_lines));
+    let top = min(height - size, row_offset * height / len_lines);
+    (top, top + size)
+}
+
+// (row, col, height, width) of a popup of `size` (height, width) for the cursor at `(row, col)` in
+// a screen of `screen` size. It goes below the cursor or, if it does not fit there, above it where
+// there is more room. It is shifted left at the right edge.
+pub fn popup_rect(
+    cursor: (usize, usize),
+    size: (usize, usize),
+    screen: (usize, usize),
+) -> (usize, usize, usize, usize) {
+    let (row, col) = cursor;
+    let width = min(size.1, screen.1);
+    let below = screen.0.saturating_sub(row + 1);
+    let (row, height) = if size.0 <= below || below >= row {
+        (row + 1, min(size.0, below))
+    } else {
+        let height = min(size.0, row);
+        (row - height, height)
+    };
+    (row, min(col, screen.1 - width), height, width)
+}
+
+// Upper bounds of the ages of `styles::HEAT` except the last one
+const HEAT_AGES: [Duration; 3] = [
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(600),
+];
+
+// Control characters are drawn as `^[` (or `<9b>`) instead of being sent to the terminal
+pub fn control_notation(c: char) -> Option<String> {
+    match c as u32 {
+        0x09 | 0x0a => None,
+        n @ 0x00..=0x1f | n @ 0x7f => Some(format!("^{}", (n as u8 ^ 0x40) as char)),
+        n @ 0x80..=0x9f => Some(format!("<{:02x}>", n)),
+        _ => None,
+    }
+}
+
+// Cells taken by `c` on the screen
+pub fn char_width(c: char) -> usize {
+    control_notation(c).map_or_else(|| c.width().unwrap_or(0), |s| s.len())
+}
+
+// `:set showkeys`. A key like `<C-r>` or `<Esc>`. `None` for mouse events.
+pub fn key_notation(event: &termion::event::Event) -> Option<String> {
+    use termion::event::{Event, Key};
+    let key = match event {
+        Event::Key(key) => key,
+        _ => return None,
+    };
+    let s = match key {
+        Key::Char('\n') => "<CR>".into(),
+        Key::Char('\t') => "<Tab>".into(),
+        Key::Char(' ') => "<Space>".into(),
+        Key::Char('<') => "<lt>".into(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("<C-{}>", c),
+        Key::Alt(c) => format!("<M-{}>", c),
+        Key::F(n) => format!("<F{}>", n),
+        Key::Esc => "<Esc>".into(),
+        Key::Backspace => "<BS>".into(),
+        Key::Delete => "<Del>".into(),
+        Key::Insert => "<Insert>".into(),
+        Key::Left => "<Left>".into(),
+        Key::Right => "<Right>".into(),
+        Key::Up => "<Up>".into(),
+        Key::Down => "<Down>".into(),
+        Key::Home => "<Home>".into(),
+        Key::End => "<End>".into(),
+        Key::PageUp => "<PageUp>".into(),
+        Key::PageDown => "<PageDown>".into(),
+        _ => return None,
+    };
+    Some(s)
+}
+
+// Index of `styles::HEAT` for a line edited `age` ago
+pub fn heat_bucket(age: Duration) -> usize {
+    HEAT_AGES.iter().take_while(|&&a| age >= a).count()
+}
+
+// Unbalanced text does not nest deeper than this
+const MAX_BRACKET_DEPTH: usize = 64;
+
+// Columns of brackets in a line and their nesting depths. `depth` is carried over lines.
+pub fn bracket_depths<I: IntoIterator<Item = char>>(
+    line: I,
+    depth: &mut usize,
+) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    for (j, c) in line.into_iter().enumerate() {
+        match c {
+            '(' | '[' | '{' => {
+                res.push((j, *depth));
+                *depth = min(*depth + 1, MAX_BRACKET_DEPTH);
+            }
+            ')' | ']' | '}' => {
+                *depth = depth.saturating_sub(1);
+                res.push((j, *depth));
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+// Row offset to show by clicking the `y`th row of a scrollbar of `height` rows
+pub fn scrollbar_row_offset(height: usize, y: usize, len_lines: usize) -> usize {
+    min(y * len_lines / max(height, 1), len_lines.saturating_sub(1))
+}
+
 impl<'a> View<'a> {
+    // Draw a scrollbar on the last column. Returns the view of the other columns.
+    pub fn scrollbar(self, row_offset: usize, len_lines: usize) -> Self {
+        if self.width == 0 {
+            return self;
+        }
+        let (top, bottom) = scrollbar_thumb(self.height, row_offset, len_lines);
+        let col = self.orig.1 + self.width - 1;
+        for i in 0..self.height {
+            let c = if top <= i && i < bottom { '█' } else { '│' };
+            self.parent.buf[self.orig.0 + i][col] = Tile::Char(c, styles::UI, None);
+        }
+        Self {
+            width: self.width - 1,
+            ..self
+        }
+    }
+
+    // `▲` and `▼` on the last column of the first and last rows, which text never reaches
+    pub fn selection_edges(&mut self, above: bool, below: bool) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let col = self.orig.1 + self.width - 1;
+        if above {
+            self.parent.buf[self.orig.0][col] = Tile::Char('▲', styles::SELECTION_EDGE, None);
+        }
+        if below {
+            let row = self.orig.0 + self.height - 1;
+            self.parent.buf[row][col] = Tile::Char('▼', styles::SELECTION_EDGE, None);
+        }
+    }
+
     pub fn is_out(&self) -> bool {
         self.cursor.row >= self.orig.0 + self.height
     }
 
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Narrow to `width` columns in the middle
+    pub fn centered(self, width: usize) -> Self {
+        let width = min(width, self.width);
+        let col = self.orig.1 + (self.width - width) / 2;
+        Self {
+            orig: (self.orig.0, col),
+            width,
+            cursor: Cursor {
+                row: self.cursor.row,
+                col,
+            },
+            ..self
+        }
+    }
+
     pub fn newline(&mut self) -> Option<Cursor> {
         if self.is_out() {
             None
@@ -199,7 +199,7 @@ impl<'a> DrawCache<'a> {
                 ..(self.state_cache.len() + 1) * Self::CACHE_WIDTH
             {
                 state.next(
-                    &Cow::from(buffer.l(line)),
+                    &Cow::from(buffer.visible_line(line)),
                     self.syntax_set,
                     &self.highlighter,
                 );
@@ -231,7 +231,7 @@ impl<'a> DrawCache<'a> {
                     )
                 {
                     let draw = state.highlight(
-                        &Cow::from(buffer.l(i)),
+                        &Cow::from(buffer.visible_line(i)),
                         self.syntax_set,
                         &self.highlighter,
                         self.bg,
@@ -251,7 +251,7 @@ impl<'a> DrawCache<'a> {
                 )
             {
                 let draw = state.highlight(
-                    &Cow::from(buffer.l(i)),
+                    &Cow::from(buffer.visible_line(i)),
                     self.syntax_set,
                     &self.highlighter,
                     self.bg,
@@ -1,24 +1,32 @@
 use std::io::Write;
 use std::process;
 
-pub fn system_format(mut command: process::Command, src: &str) -> Option<String> {
+pub fn system_format(command: process::Command, src: &str) -> Option<String> {
+    system_format_with_error(command, src).ok()
+}
+
+// Returns stderr of the command on failure
+pub fn system_format_with_error(
+    mut command: process::Command,
+    src: &str,
+) -> Result<String, String> {
     let mut command = command
         .stdin(process::Stdio::piped())
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::piped())
         .spawn()
-        .ok()?;
+        .map_err(|e| e.to_string())?;
     {
-        let mut stdin = command.stdin.take()?;
-        write!(stdin, "{}", src).unwrap();
+        let mut stdin = command.stdin.take().ok_or("Failed to open stdin")?;
+        write!(stdin, "{}", src).map_err(|e| e.to_string())?;
     }
-    let out = command.wait_with_output().ok()?;
+    let out = command.wait_with_output().map_err(|e| e.to_string())?;
 
     if !out.status.success() {
-        return None;
+        return Err(String::from_utf8_lossy(&out.stderr).into_owned());
     }
 
     let stdout = out.stdout;
-    let out = String::from_utf8(stdout).ok()?;
-    Some(out.replace("\r\n", "\n"))
+    let out = String::from_utf8(stdout).map_err(|e| e.to_string())?;
+    Ok(out.replace("\r\n", "\n"))
 }
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::PathBuf;
+
+const MAX_HISTORY: usize = 100;
+
+// Entered commands or search queries, oldest first.
+#[derive(Default)]
+pub struct History {
+    // Where the history is persisted. In memory only if `None`.
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: Vec<String> = fs::read_to_string(&path)
+            .map(|s| {
+                s.lines()
+                    .filter(|l| !l.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if entries.len() > MAX_HISTORY {
+            entries.drain(..entries.len() - MAX_HISTORY);
+        }
+        Self {
+            path: Some(path),
+            entries,
+        }
+    }
+
+    // Consecutive duplicates are stored once
+    pub fn push(&mut self, entry: &str) {
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        if self.entries.last().map(String::as_str) == Some(entry) {
+            return;
+        }
+        self.entries.push(entry.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Some(path) = self.path.as_ref() {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let s: String = self.entries.iter().map(|e| format!("{}\n", e)).collect();
+            let _ = fs::write(path, s);
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // The newest entry older than `index` which starts with `prefix`
+    pub fn prev(&self, prefix: &str, index: Option<usize>) -> Option<usize> {
+        let end = index.unwrap_or(self.entries.len());
+        self.entries[..end]
+            .iter()
+            .rposition(|e| e.starts_with(prefix))
+    }
+
+    // The oldest entry newer than `index` which starts with `prefix`
+    pub fn next(&self, prefix: &str, index: usize) -> Option<usize> {
+        self.entries[index + 1..]
+            .iter()
+            .position(|e| e.starts_with(prefix))
+            .map(|i| index + 1 + i)
+    }
+}
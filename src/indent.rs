@@ -1,8 +1,141 @@
+use std::collections::BTreeMap;
+
+// Lines looked at by `detect`
+const DETECT_LINES: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces(usize),
+}
+
+// Guess the indentation of a file from lines indented by tabs or spaces. Space indents are
+// measured by the most common increase from the previous line. `None` if it is ambiguous.
+pub fn detect<I>(lines: I) -> Option<IndentStyle>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut tabs = 0;
+    let mut spaces = 0;
+    let mut increments = BTreeMap::new();
+    let mut prev = 0;
+    for line in lines.into_iter().take(DETECT_LINES) {
+        let line = line.as_ref();
+        let rest = line.trim_start_matches(&[' ', '\t'][..]);
+        if rest.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let width = line.len() - rest.len();
+        // A single space like ` * ` of a block comment
+        if width == 1 {
+            continue;
+        }
+        if width > 0 {
+            spaces += 1;
+        }
+        if width > prev {
+            *increments.entry(width - prev).or_insert(0) += 1;
+        }
+        prev = width;
+    }
+
+    if tabs > spaces {
+        Some(IndentStyle::Tabs)
+    } else if spaces > tabs {
+        increments
+            .into_iter()
+            .filter(|&(width, _)| width <= 8)
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map(|(width, _)| IndentStyle::Spaces(width))
+    } else {
+        None
+    }
+}
+
+// Columns of the leading whitespace of `line`
+pub fn indent_columns(line: &str, tab_width: usize) -> usize {
+    line.chars()
+        .take_while(|&c| c == ' ' || c == '\t')
+        .map(|c| if c == '\t' { tab_width } else { 1 })
+        .sum()
+}
+
+// `]p`. Shift `text` so that its first non-blank line is indented by `columns`, keeping the
+// relative indentation of the other lines. New indents use tabs if `tabs`.
+pub fn shift_to(text: &str, columns: usize, tab_width: usize, tabs: bool) -> String {
+    let first = text
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .map_or(0, |line| indent_columns(line, tab_width));
+    text.split('\n')
+        .map(|line| {
+            let rest = line.trim_start_matches(&[' ', '\t'][..]);
+            if rest.is_empty() {
+                return line.to_string();
+            }
+            let width = (indent_columns(line, tab_width) + columns).saturating_sub(first);
+            let indent = if tabs {
+                "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width)
+            } else {
+                " ".repeat(width)
+            };
+            indent + rest
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub fn next_indent_level(line: &str, indent_width: usize) -> usize {
-    let base = line.chars().take_while(|&c| c == ' ').count() / indent_width;
+    // A tab is one level
+    let base = indent_columns(line, indent_width) / indent_width;
     if ['{', '[', '('].iter().any(|&c| line.ends_with(c)) {
         base + 1
     } else {
         base
     }
 }
+
+// `foldmethod=indent`. Each run of lines indented deeper than the line above it is a fold,
+// nested ones included. Blank lines belong to a fold only when it continues after them.
+pub fn folds<I>(lines: I, tab_width: usize) -> Vec<(usize, usize)>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut folds = Vec::new();
+    // The first row and the indent of the header of the open folds
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut prev: Option<(usize, usize)> = None;
+    for (row, line) in lines.into_iter().enumerate() {
+        let line = line.as_ref();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let width = indent_columns(line, tab_width);
+        while let Some(&(first, header)) = open.last() {
+            if header < width {
+                break;
+            }
+            open.pop();
+            folds.push((first, prev.map(|p| p.0).unwrap_or(first)));
+        }
+        if let Some((_, prev_width)) = prev {
+            if width > prev_width {
+                open.push((row, prev_width));
+            }
+        }
+        prev = Some((row, width));
+    }
+    for (first, _) in open {
+        folds.push((first, prev.map(|p| p.0).unwrap_or(first)));
+    }
+    // A single line is not worth folding
+    folds.retain(|&(first, last)| first < last);
+    folds.sort();
+    folds
+}
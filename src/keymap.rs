@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use termion::event::{Event, Key};
+
+// Normal mode commands which `keymap` in the config can bind to other keys
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Left,
+    Down,
+    Up,
+    Right,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    LastLine,
+    MatchPair,
+    SentenceBackward,
+    SentenceForward,
+    NextLine,
+    PrevLine,
+    FindForward,
+    FindBackward,
+    SearchForward,
+    SearchNext,
+    SearchPrev,
+    Insert,
+    Append,
+    InsertLineStart,
+    AppendLineEnd,
+    OpenBelow,
+    OpenAbove,
+    SubstituteChar,
+    SubstituteLine,
+    ChangeToEnd,
+    Replace,
+    DeleteChar,
+    Join,
+    PasteAfter,
+    PasteBefore,
+    Undo,
+    Redo,
+    Repeat,
+    RepeatSubstitute,
+    Delete,
+    Change,
+    Yank,
+    Visual,
+    VisualLine,
+    Command,
+    Window,
+    ScrollDown,
+    ScrollUp,
+    JumpBack,
+    AlternateFile,
+    AddCursor,
+    CycleLineNumbers,
+}
+
+// Names in the config and the keys bound by default
+const ACTIONS: &[(Action, &str, Key)] = &[
+    (Action::Left, "left", Key::Char('h')),
+    (Action::Down, "down", Key::Char('j')),
+    (Action::Up, "up", Key::Char('k')),
+    (Action::Right, "right", Key::Char('l')),
+    (Action::WordForward, "word_forward", Key::Char('w')),
+    (Action::WordBackward, "word_backward", Key::Char('b')),
+    (Action::WordEnd, "word_end", Key::Char('e')),
+    (Action::LineStart, "line_start", Key::Char('0')),
+    (Action::LineEnd, "line_end", Key::Char('$')),
+    (Action::LastLine, "last_line", Key::Char('G')),
+    (Action::MatchPair, "match_pair", Key::Char('%')),
+    (
+        Action::SentenceBackward,
+        "sentence_backward",
+        Key::Char('('),
+    ),
+    (Action::SentenceForward, "sentence_forward", Key::Char(')')),
+    (Action::NextLine, "next_line", Key::Char('+')),
+    (Action::PrevLine, "prev_line", Key::Char('-')),
+    (Action::FindForward, "find_forward", Key::Char('f')),
+    (Action::FindBackward, "find_backward", Key::Char('F')),
+    (Action::SearchForward, "search_forward", Key::Char('/')),
+    (Action::SearchNext, "search_next", Key::Char('n')),
+    (Action::SearchPrev, "search_prev", Key::Char('N')),
+    (Action::Insert, "insert", Key::Char('i')),
+    (Action::Append, "append", Key::Char('a')),
+    (Action::InsertLineStart, "insert_line_start", Key::Char('I')),
+    (Action::AppendLineEnd, "append_line_end", Key::Char('A')),
+    (Action::OpenBelow, "open_below", Key::Char('o')),
+    (Action::OpenAbove, "open_above", Key::Char('O')),
+    (Action::SubstituteChar, "substitute_char", Key::Char('s')),
+    (Action::SubstituteLine, "substitute_line", Key::Char('S')),
+    (Action::ChangeToEnd, "change_to_end", Key::Char('C')),
+    (Action::Replace, "replace", Key::Char('r')),
+    (Action::DeleteChar, "delete_char", Key::Char('x')),
+    (Action::Join, "join", Key::Char('J')),
+    (Action::PasteAfter, "paste_after", Key::Char('p')),
+    (Action::PasteBefore, "paste_before", Key::Char('P')),
+    (Action::Undo, "undo", Key::Char('u')),
+    (Action::Redo, "redo", Key::Char('U')),
+    (Action::Repeat, "repeat", Key::Char('.')),
+    (
+        Action::RepeatSubstitute,
+        "repeat_substitute",
+        Key::Char('&'),
+    ),
+    (Action::Delete, "delete", Key::Char('d')),
+    (Action::Change, "change", Key::Char('c')),
+    (Action::Yank, "yank", Key::Char('y')),
+    (Action::Visual, "visual", Key::Char('v')),
+    (Action::VisualLine, "visual_line", Key::Char('V')),
+    (Action::Command, "command", Key::Char(':')),
+    (Action::Window, "window", Key::Ctrl('w')),
+    (Action::ScrollDown, "scroll_down", Key::Ctrl('e')),
+    (Action::ScrollUp, "scroll_up", Key::Ctrl('y')),
+    (Action::JumpBack, "jump_back", Key::Ctrl('o')),
+    (Action::AlternateFile, "alternate_file", Key::Ctrl('6')),
+    (Action::AddCursor, "add_cursor", Key::Ctrl('n')),
+    (
+        Action::CycleLineNumbers,
+        "cycle_line_numbers",
+        Key::Ctrl('k'),
+    ),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(action, _, _)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(a, _, _)| *a == self)
+            .map(|(_, name, _)| *name)
+            .unwrap()
+    }
+
+    // The key the Normal mode handles the action by
+    pub fn default_key(self) -> Key {
+        ACTIONS
+            .iter()
+            .find(|(a, _, _)| *a == self)
+            .map(|(_, _, key)| *key)
+            .unwrap()
+    }
+}
+
+// `a`, `<C-a>`, `<M-a>`, `<Space>`, `<CR>`, `<Tab>`, `<lt>` or `<F1>`
+pub fn parse_key(s: &str) -> Option<Key> {
+    let mut chars = s.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+    let name = s.strip_prefix('<')?.strip_suffix('>')?;
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    match name {
+        "Space" => Some(Key::Char(' ')),
+        "CR" => Some(Key::Char('\n')),
+        "Tab" => Some(Key::Char('\t')),
+        "lt" => Some(Key::Char('<')),
+        _ if name.starts_with("C-") => single(&name[2..]).map(Key::Ctrl),
+        _ if name.starts_with("M-") => single(&name[2..]).map(Key::Alt),
+        _ if name.starts_with('F') => name[1..].parse().ok().map(Key::F),
+        _ => None,
+    }
+}
+
+// Keys of the Normal mode bound to actions
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: ACTIONS
+                .iter()
+                .map(|(action, _, key)| (*key, *action))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    // `keymap` of the config maps action names to keys. Unknown names and keys are ignored.
+    pub fn new(overrides: &BTreeMap<String, String>) -> Self {
+        let mut keymap = Self::default();
+        for (name, key) in overrides {
+            if let (Some(action), Some(key)) = (Action::from_name(name), parse_key(key)) {
+                keymap.bindings.retain(|(_, a)| *a != action);
+                keymap.bindings.push((key, action));
+            }
+        }
+        keymap
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        // A later binding overrides a default one
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, action)| *action)
+    }
+
+    // The event Normal mode handles for `event`. `None` if the key was moved to another action.
+    pub fn translate(&self, event: Event) -> Option<Event> {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return Some(event),
+        };
+        if let Some(action) = self.action(key) {
+            return Some(Event::Key(action.default_key()));
+        }
+        if ACTIONS.iter().any(|(_, _, k)| *k == key) {
+            None
+        } else {
+            Some(event)
+        }
+    }
+}
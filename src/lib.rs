@@ -1,25 +1,84 @@
-pub use crate::buffer::Buffer;
-use crate::core::Core;
-use crate::mode::{Mode, Normal, Transition};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
 
+pub use crate::buffer::{Buffer, DiskChange, LineNumbers};
+use crate::core::{Core, Cursor};
+use crate::mode::{quit, DiskChanged, Mode, Normal, Transition, WindowCommand};
+use crate::window::{Layout, Windows};
+
+mod ansi;
+pub mod atomic_write;
 mod buffer;
 mod clipboard;
+mod command;
 mod compiler;
 pub mod config;
 mod core;
 mod cursor;
+pub mod datetime;
 pub mod draw;
 mod draw_cache;
 mod formatter;
-mod indent;
+pub mod history;
+pub mod indent;
 mod job_queue;
+pub mod keymap;
+mod loader;
 mod lsp;
 mod mode;
+mod path_completion;
+pub mod recent;
 mod ropey_util;
 mod rustc;
+pub mod spell;
 pub mod syntax;
 mod text_object;
 pub mod theme;
+pub mod undo_file;
+mod window;
+
+fn split_number(s: &str) -> Option<(&str, usize)> {
+    let i = s.rfind(':')?;
+    let n = s[i + 1..].parse().ok()?;
+    Some((&s[..i], n))
+}
+
+// Split `path:line` or `path:line:col` (1-based). A file which exists takes precedence.
+pub fn parse_path_arg(arg: &str) -> (PathBuf, Option<(usize, usize)>) {
+    if Path::new(arg).exists() {
+        return (PathBuf::from(arg), None);
+    }
+
+    if let Some((rest, n)) = split_number(arg) {
+        match split_number(rest) {
+            Some((path, line)) if !path.is_empty() => {
+                return (PathBuf::from(path), Some((line, n)));
+            }
+            _ if !rest.is_empty() => {
+                return (PathBuf::from(rest), Some((n, 1)));
+            }
+            _ => {}
+        }
+    }
+    (PathBuf::from(arg), None)
+}
+
+// Keys which run while a large file is loaded. They stay near the cursor.
+fn is_local_motion(event: &termion::event::Event) -> bool {
+    use termion::event::{Event, Key};
+    match event {
+        Event::Key(Key::Char(c)) => "hjklwbe0123456789".contains(*c),
+        Event::Key(Key::Up)
+        | Event::Key(Key::Down)
+        | Event::Key(Key::Left)
+        | Event::Key(Key::Right)
+        | Event::Key(Key::Ctrl('d'))
+        | Event::Key(Key::Ctrl('u'))
+        | Event::Key(Key::Ctrl('e'))
+        | Event::Key(Key::Ctrl('y')) => true,
+        _ => false,
+    }
+}
 
 pub struct BufferMode<'a> {
     pub buf: Buffer<'a>,
@@ -27,6 +86,8 @@ pub struct BufferMode<'a> {
     is_recording: bool,
     dot_macro: Vec<termion::event::Event>,
     recording_macro: Vec<termion::event::Event>,
+    windows: Windows,
+    last_event: Instant,
 }
 
 impl<'a> BufferMode<'a> {
@@ -37,14 +98,77 @@ impl<'a> BufferMode<'a> {
             is_recording: false,
             dot_macro: Vec::new(),
             recording_macro: Vec::new(),
+            windows: Windows::default(),
+            last_event: Instant::now(),
         }
     }
 
     pub fn event(&mut self, event: termion::event::Event) -> bool {
+        self.last_event = Instant::now();
+        if self.buf.showkeys {
+            self.buf.record_key(&event);
+        }
+        self.dispatch(event)
+    }
+
+    // Keys replayed by macros are not shown by `:set showkeys`
+    fn dispatch(&mut self, event: termion::event::Event) -> bool {
+        // Other keys may look at the whole text
+        if self.buf.is_loading() && !is_local_motion(&event) {
+            self.buf.finish_load();
+        }
+        let exit = self.handle_event(event);
+        // Reaching the end of the text read so far waits for the rest
+        if self.buf.is_loading()
+            && self.buf.core.cursor().row + 1 >= self.buf.core.buffer().len_lines()
+        {
+            self.buf.finish_load();
+        }
+        exit
+    }
+
+    fn handle_event(&mut self, event: termion::event::Event) -> bool {
         if self.is_recording {
             self.recording_macro.push(event.clone());
         }
-        match self.mode.event(&mut self.buf, event.clone()) {
+        let window_event = self.window_event(event.clone());
+        let transition = self.mode.event(&mut self.buf, window_event);
+        self.apply(transition, event)
+    }
+
+    // Mouse positions are made relative to the window under them. A press focuses the window
+    // and dragging stays in the focused one.
+    fn window_event(&mut self, event: termion::event::Event) -> termion::event::Event {
+        use termion::event::{Event, MouseEvent};
+        let (x, y) = match event {
+            Event::Mouse(MouseEvent::Press(_, x, y))
+            | Event::Mouse(MouseEvent::Hold(x, y))
+            | Event::Mouse(MouseEvent::Release(x, y)) => (x as usize - 1, y as usize - 1),
+            _ => return event,
+        };
+        let rect = if let Event::Mouse(MouseEvent::Press(..)) = event {
+            self.windows.focus_at(&mut self.buf, y, x)
+        } else {
+            self.windows.current_rect()
+        };
+        let (row, col, height, width) = match rect {
+            Some(rect) => rect,
+            None => return event,
+        };
+        self.buf.window_size = Some((height, width));
+        let x = (x.saturating_sub(col).min(width - 1) + 1) as u16;
+        let y = (y.saturating_sub(row).min(height - 1) + 1) as u16;
+        match event {
+            Event::Mouse(MouseEvent::Press(button, _, _)) => {
+                Event::Mouse(MouseEvent::Press(button, x, y))
+            }
+            Event::Mouse(MouseEvent::Hold(_, _)) => Event::Mouse(MouseEvent::Hold(x, y)),
+            _ => Event::Mouse(MouseEvent::Release(x, y)),
+        }
+    }
+
+    fn apply(&mut self, transition: Transition, event: termion::event::Event) -> bool {
+        match transition {
             Transition::Exit => {
                 return true;
             }
@@ -54,7 +178,7 @@ impl<'a> BufferMode<'a> {
             }
             Transition::DoMacro => {
                 for event in self.dot_macro.clone() {
-                    self.event(event);
+                    self.dispatch(event);
                 }
             }
             Transition::Return(s, is_commit_macro) => {
@@ -78,12 +202,164 @@ impl<'a> BufferMode<'a> {
                 t.init(&mut self.buf);
                 self.mode = t;
             }
+            // The other windows keep showing the buffer. Closing the last one quits.
+            Transition::Window(WindowCommand::Quit) if self.windows.len() == 1 => {
+                let transition = quit(&self.buf);
+                return self.apply(transition, event);
+            }
+            Transition::Window(command) => {
+                let message = self.window_command(command);
+                let mut t = Box::new(Normal::with_message(message.unwrap_or_default()));
+                t.init(&mut self.buf);
+                self.mode = t;
+            }
+            Transition::Keys((first, last), events) => self.run_keys(first, last, &events),
             Transition::Nothing => {}
         }
         false
     }
 
+    fn set_normal(&mut self) {
+        let mut t = Box::new(Normal::default());
+        t.init(&mut self.buf);
+        self.mode = t;
+    }
+
+    fn run_keys(&mut self, first: usize, mut last: usize, events: &[termion::event::Event]) {
+        let mut row = first;
+        while row <= last && row < self.buf.core.buffer().len_lines() {
+            let len_lines = self.buf.core.buffer().len_lines();
+            self.set_normal();
+            self.buf.core.set_cursor(Cursor { row, col: 0 });
+            for event in events {
+                self.dispatch(event.clone());
+            }
+            // Finish an incomplete command
+            self.dispatch(termion::event::Event::Key(termion::event::Key::Esc));
+
+            // Follow lines inserted or deleted by the keys
+            let delta = self.buf.core.buffer().len_lines() as isize - len_lines as isize;
+            let next = (row as isize + 1 + delta).max(row as isize);
+            let next_last = last as isize + delta;
+            if next_last < next {
+                break;
+            }
+            row = next as usize;
+            last = next_last as usize;
+        }
+        self.set_normal();
+    }
+
+    fn window_command(&mut self, command: WindowCommand) -> Option<String> {
+        match command {
+            WindowCommand::Split => self.windows.split(&mut self.buf, Layout::Horizontal),
+            WindowCommand::VSplit => self.windows.split(&mut self.buf, Layout::Vertical),
+            WindowCommand::Close | WindowCommand::Quit => {
+                if !self.windows.close(&mut self.buf) {
+                    return Some("Cannot close last window".into());
+                }
+            }
+            WindowCommand::Only => {
+                if !self.windows.only(&mut self.buf) {
+                    return Some("Already only one window".into());
+                }
+            }
+            WindowCommand::Focus(direction) => {
+                self.windows.focus(&mut self.buf, direction);
+            }
+            WindowCommand::Goto(n) => {
+                if !self.windows.goto(&mut self.buf, n) {
+                    return Some(format!("No window {}", n));
+                }
+            }
+            WindowCommand::Equalize => self.windows.equalize(),
+            WindowCommand::Resize(delta) => {
+                if !self.windows.resize(Layout::Horizontal, delta) {
+                    return Some("Cannot resize window height".into());
+                }
+            }
+            WindowCommand::VResize(delta) => {
+                if !self.windows.resize(Layout::Vertical, delta) {
+                    return Some("Cannot resize window width".into());
+                }
+            }
+        }
+        None
+    }
+
+    // Cancel an incomplete key sequence like `g` or `d` typed `timeoutlen` before `now`.
+    // Returns true if it was cancelled.
+    pub fn check_timeout(&mut self, now: Instant) -> bool {
+        match self.buf.timeoutlen() {
+            Some(timeout) if self.mode.is_pending() && now >= self.last_event + timeout => {
+                self.is_recording = false;
+                self.set_normal();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // `:set autoread`. `mtime` is of the file on disk. Only checked while idle in Normal mode.
+    pub fn check_disk(&mut self, mtime: Option<SystemTime>) {
+        if !self.mode.is_idle() {
+            return;
+        }
+        match self.buf.disk_change(mtime) {
+            DiskChange::Unchanged => {}
+            DiskChange::Reload => {
+                self.buf.reload();
+                let message = self
+                    .buf
+                    .path()
+                    .map(|p| format!("Reloaded {}", p.display()))
+                    .unwrap_or_default();
+                let mut t = Box::new(Normal::with_message(message));
+                t.init(&mut self.buf);
+                self.mode = t;
+            }
+            DiskChange::Conflict => {
+                self.mode = Box::new(DiskChanged { mtime });
+            }
+        }
+    }
+
+    // The terminal is resized. Scroll the focused window to keep the cursor visible.
+    pub fn resize(&mut self, height: usize, width: usize) {
+        let (_, _, height, width) = self.windows.rects(height, width)[self.windows.current()];
+        self.buf.window_size = Some((height, width));
+        self.buf.show_cursor();
+    }
+
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn current_window(&self) -> usize {
+        self.windows.current()
+    }
+
+    // (row, col, height, width) of each window in a terminal of the size
+    pub fn window_rects(&self, height: usize, width: usize) -> Vec<(usize, usize, usize, usize)> {
+        self.windows.rects(height, width)
+    }
+
     pub fn draw(&mut self, term: &mut draw::Term) {
-        self.mode.draw(&mut self.buf, term)
+        let mode = &mut self.mode;
+        self.windows
+            .draw(&mut self.buf, term, |buf, term| mode.draw(buf, term));
+        if self.buf.showkeys {
+            let keys: String = self.buf.recent_keys.iter().map(String::as_str).collect();
+            let len = keys.chars().count();
+            // The last column of a view is left for wrapping
+            if len > 0 && len < term.width {
+                term.view((0, term.width - len - 1), 1, len + 1)
+                    .puts(&keys, draw::styles::FOOTER);
+            }
+        }
+    }
+
+    pub fn showcmd(&self) -> String {
+        self.mode.showcmd()
     }
 }
@@ -0,0 +1,92 @@
+use std::io::{self, Read};
+use std::str;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Files larger than this are read on a background thread
+pub const LARGE_FILE: u64 = 4 * 1024 * 1024;
+const CHUNK_SIZE: usize = 256 * 1024;
+
+// Reads text in chunks on a background thread so the first part can be shown early
+pub struct Loader {
+    rx: Receiver<io::Result<String>>,
+    loaded: u64,
+    total: u64,
+}
+
+// Split off a trailing incomplete UTF-8 sequence to be completed by the next chunk
+fn take_valid(pending: &mut Vec<u8>) -> String {
+    let valid = match str::from_utf8(pending) {
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        _ => pending.len(),
+    };
+    let rest = pending.split_off(valid);
+    let s = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    s
+}
+
+impl Loader {
+    // `total` is the expected size in bytes for the progress
+    pub fn spawn<R: Read + Send + 'static>(mut reader: R, total: u64) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let mut pending = Vec::new();
+            loop {
+                match reader.read(&mut chunk) {
+                    Ok(0) => {
+                        if !pending.is_empty() {
+                            let _ = tx.send(Ok(String::from_utf8_lossy(&pending).into_owned()));
+                        }
+                        return;
+                    }
+                    Ok(n) => {
+                        pending.extend_from_slice(&chunk[..n]);
+                        if tx.send(Ok(take_valid(&mut pending))).is_err() {
+                            return;
+                        }
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            rx,
+            loaded: 0,
+            total,
+        }
+    }
+
+    // `None` when nothing is read yet. `Some(None)` when the whole text is read.
+    pub fn try_recv(&mut self) -> Option<Option<io::Result<String>>> {
+        match self.rx.try_recv() {
+            Ok(chunk) => Some(Some(self.count(chunk))),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+
+    // Wait for the next chunk. `None` when the whole text is read.
+    pub fn recv(&mut self) -> Option<io::Result<String>> {
+        self.rx.recv().ok().map(|chunk| self.count(chunk))
+    }
+
+    fn count(&mut self, chunk: io::Result<String>) -> io::Result<String> {
+        if let Ok(s) = chunk.as_ref() {
+            self.loaded += s.len() as u64;
+        }
+        chunk
+    }
+
+    // Percentage of bytes read so far
+    pub fn progress(&self) -> usize {
+        (self.loaded.min(self.total) * 100)
+            .checked_div(self.total)
+            .unwrap_or(0) as usize
+    }
+}
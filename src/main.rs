@@ -15,6 +15,8 @@ use termion::screen::AlternateScreen;
 
 use accepted::config;
 use accepted::draw::DoubleBuffer;
+use accepted::history::History;
+use accepted::recent::RecentFiles;
 use accepted::{Buffer, BufferMode};
 
 #[derive(BuildInfo)]
@@ -75,6 +77,8 @@ fn main() {
     let stdin = stdin();
     let mut stdout = MouseTerminal::from(AlternateScreen::from(stdout()).into_raw_mode().unwrap());
     // let mut stdout = MouseTerminal::from(stdout().into_raw_mode().unwrap());
+    // Bracketed paste
+    write!(stdout, "\x1b[?2004h").unwrap();
 
     let (tx, rx) = channel();
 
@@ -89,8 +93,23 @@ fn main() {
     let syntax_parent = accepted::syntax::SyntaxParent::default();
 
     let mut buf = Buffer::new(&syntax_parent, &config);
+    if let Some(mut config_dir) = dirs::config_dir() {
+        config_dir.push("acc");
+        buf.recent = RecentFiles::load(config_dir.join("recent"));
+        buf.command_history = History::load(config_dir.join("command_history"));
+        buf.search_history = History::load(config_dir.join("search_history"));
+        buf.undo_dir = Some(config_dir.join("undo"));
+    }
     if let Some(path) = file {
-        buf.open(path);
+        if let Some(arg) = path.to_str() {
+            let (path, position) = accepted::parse_path_arg(arg);
+            buf.open(path);
+            if let Some((line, col)) = position {
+                buf.goto_position(line, col);
+            }
+        } else {
+            buf.open(path);
+        }
     }
 
     let mut state = BufferMode::new(buf);
@@ -98,9 +117,12 @@ fn main() {
     let mut draw = DoubleBuffer::default();
 
     let frame = Duration::from_secs(1) / 60;
+    let mut last_disk_check = Instant::now();
+    let mut term_size = termion::terminal_size().unwrap();
 
     loop {
         let start_frame = Instant::now();
+        state.buf.poll_load();
         state.buf.extend_cache_duration(frame);
         let now = Instant::now();
 
@@ -115,8 +137,22 @@ fn main() {
                 draw.redraw();
             }
             if state.event(evt) {
+                write!(stdout, "\x1b[?2004l").unwrap();
                 return;
             }
+        } else {
+            state.check_timeout(Instant::now());
+            if state.buf.autoread && last_disk_check.elapsed() > Duration::from_secs(1) {
+                last_disk_check = Instant::now();
+                let mtime = state.buf.disk_mtime();
+                state.check_disk(mtime);
+            }
+        }
+
+        let size = termion::terminal_size().unwrap();
+        if size != term_size {
+            term_size = size;
+            state.resize(size.1 as usize, size.0 as usize);
         }
 
         state.draw(&mut draw.back);
@@ -1,6 +1,8 @@
 use std;
 use std::borrow::Cow;
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::env;
 use std::ffi::OsString;
 use std::io::BufRead;
 use std::io::BufReader;
@@ -9,7 +11,7 @@ use std::path::PathBuf;
 use std::process;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 use aho_corasick::Automaton;
 use ropey::Rope;
@@ -18,14 +20,20 @@ use termion;
 use termion::event::{Event, Key, MouseButton, MouseEvent};
 
 use crate::buffer::Buffer;
+use crate::buffer::LineNumbers;
+use crate::buffer::VisualSelection;
 use crate::buffer::Yank;
 use crate::clipboard;
+use crate::command;
 use crate::core::Core;
 use crate::core::Cursor;
 use crate::core::CursorRange;
 use crate::core::Id;
+use crate::datetime::DateTime;
 use crate::draw;
-use crate::indent;
+use crate::history::History;
+use crate::indent::{self, IndentStyle};
+use crate::path_completion::{self, PathCompletion};
 use crate::ropey_util::RopeExt;
 use crate::ropey_util::RopeSliceExt;
 use crate::text_object::{self, Action};
@@ -37,9 +45,31 @@ pub enum Transition {
     DoMacro,
     // Message, is commit dot macro?
     Return(Option<String>, bool),
+    Window(WindowCommand),
+    // `:normal`. Run keys on each row of the range.
+    Keys((usize, usize), Vec<Event>),
     Exit,
 }
 
+pub enum WindowCommand {
+    Split,
+    VSplit,
+    Close,
+    // Like `Close`, but the last window quits the editor
+    Quit,
+    // Close the other windows
+    Only,
+    // Move focus by `h`, `j`, `k`, `l` or `w`
+    Focus(char),
+    // Focus the window of the number, counted from 1 in the layout order
+    Goto(usize),
+    Equalize,
+    // Grow the height by rows
+    Resize(isize),
+    // Grow the width by columns
+    VResize(isize),
+}
+
 impl<T: Mode + 'static> From<T> for Transition {
     fn from(mode: T) -> Transition {
         Transition::Trans(Box::new(mode))
@@ -50,11 +80,87 @@ pub trait Mode {
     fn init(&mut self, _buf: &mut Buffer) {}
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition;
     fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term);
+    // Pending keys like a count or an operator
+    fn showcmd(&self) -> String {
+        String::new()
+    }
+    // Nothing is pending and the mode can be replaced, e.g. by `:set autoread`
+    fn is_idle(&self) -> bool {
+        false
+    }
+    // An incomplete key sequence which `timeoutlen` cancels
+    fn is_pending(&self) -> bool {
+        false
+    }
+}
+
+// Text position drawn under the mouse in the window of `buf`, which has `footer` rows below
+// the text. `None` before the window is drawn.
+fn mouse_position(buf: &mut Buffer, x: u16, y: u16, footer: usize) -> Option<Cursor> {
+    let (height, width) = buf.window_size?;
+    let mut term = draw::Term::new(height, width);
+    buf.draw(term.view((0, 0), height.saturating_sub(footer), width));
+    term.pos(Cursor {
+        row: y as usize - 1,
+        col: x as usize - 1,
+    })
+}
+
+// Show pending keys at the right end of the footer
+fn draw_showcmd(term: &mut draw::Term, showcmd: &str) {
+    let len = showcmd.chars().count();
+    if len == 0 || len >= term.width {
+        return;
+    }
+    let mut view = term.view((term.height - 1, term.width - len), 1, len);
+    view.puts(showcmd, draw::styles::FOOTER);
+}
+
+// `&`, `g&` and `:&`. The `g` flag is kept only if `keep_flags`.
+fn repeat_substitute(buf: &mut Buffer, (first, last): (usize, usize), keep_flags: bool) -> String {
+    if let Some((re, replacement, global)) = buf.last_substitute.clone() {
+        let count = buf.substitute(first, last, &re, &replacement, global && keep_flags);
+        buf.core.commit();
+        buf.show_cursor();
+        if count == 0 {
+            format!("Pattern not found: {}", re.as_str())
+        } else {
+            format!("{} substitutions", count)
+        }
+    } else {
+        "No previous substitute regular expression".into()
+    }
+}
+
+// `Ctrl-r {register}`. A linewise register ends with a newline.
+fn register_text(buf: &Buffer, register: char) -> Option<String> {
+    let yank = buf.get_yank(Some(register))?;
+    let mut text = yank.content.clone();
+    if yank.insert_newline {
+        text.push('\n');
+    }
+    Some(text)
+}
+
+// `Ctrl-r {register}` in `:` and `/`. Line breaks become spaces.
+fn register_line(buf: &Buffer, register: char) -> Option<String> {
+    register_text(buf, register).map(|s| s.trim_end_matches('\n').replace('\n', " "))
+}
+
+// Bracketed paste wraps pasted text in these
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+fn register_showcmd(register: Option<char>) -> String {
+    register.map(|r| format!("\"{}", r)).unwrap_or_default()
 }
 
 pub struct Normal {
     message: String,
     frame: usize,
+    register: Option<char>,
+    count: Option<usize>,
+    prefix: Option<char>,
 }
 
 pub struct Completion {
@@ -62,13 +168,38 @@ pub struct Completion {
     pub doc: String,
 }
 
-struct Prefix;
+#[derive(Default)]
+struct Prefix {
+    // Keys of a leader mapping typed so far
+    keys: String,
+}
+
+struct WindowPrefix {
+    count: Option<usize>,
+}
+
+// Ctrl-n / Ctrl-p completion from words in the buffer
+struct WordCompletion {
+    prefix: String,
+    candidates: Vec<String>,
+    // `None` shows the original prefix
+    index: Option<usize>,
+}
 
 struct Insert {
     completion_index: Option<usize>,
     buf_update: Id,
     completions: Vec<Completion>,
     snippet_completions: Vec<String>,
+    word_completion: Option<WordCompletion>,
+    // `Ctrl-r` waits for a register name
+    register_pending: bool,
+    // Where the text inserted in this session starts. It goes to `".` at the end.
+    start: Cursor,
+    // Inside a bracketed paste. Keys are inserted as they are.
+    pasting: bool,
+    // Started by a paste in Normal mode. The end of the paste goes back to Normal mode.
+    paste_only: bool,
 }
 
 impl Default for Insert {
@@ -77,7 +208,12 @@ impl Default for Insert {
             completion_index: None,
             completions: Vec::new(),
             snippet_completions: Vec::new(),
+            word_completion: None,
             buf_update: Id::default(),
+            register_pending: false,
+            start: Cursor { row: 0, col: 0 },
+            pasting: false,
+            paste_only: false,
         }
     }
 }
@@ -92,25 +228,279 @@ struct Find {
 
 struct TextObjectOperation {
     parser: text_object::TextObjectParser,
+    register: Option<char>,
+    count: Option<usize>,
+    // Keys typed after the operator
+    keys: String,
+    // `'` or `` ` `` waiting for a mark name
+    mark: Option<char>,
 }
 
 impl TextObjectOperation {
-    fn new(action: Action) -> Self {
+    fn new(action: Action, register: Option<char>, count: Option<usize>) -> Self {
         Self {
             parser: text_object::TextObjectParser::new(action),
+            register,
+            count,
+            keys: String::new(),
+            mark: None,
+        }
+    }
+
+    // Deleted and changed text also goes to the numbered or small delete registers
+    fn save_yank(&self, buf: &mut Buffer, yank: Yank) {
+        if self.parser.action == Action::Yank {
+            buf.set_yank(self.register, yank);
+        } else {
+            buf.set_delete(self.register, yank);
+        }
+    }
+
+    // `dd`, `d'a` and so on. Whole lines from `row` to `last`.
+    fn operate_lines(&self, buf: &mut Buffer, row: usize, last: usize) -> Transition {
+        let lines = CursorRange(
+            Cursor { row, col: 0 },
+            Cursor {
+                row: last,
+                col: buf.core.buffer().l(last).len_chars(),
+            },
+        );
+        let mut content = String::from(buf.core.get_slice_by_range(lines));
+        if content.ends_with('\n') {
+            content.pop();
+        }
+        let yank = Yank {
+            insert_newline: true,
+            content,
+        };
+        self.save_yank(buf, yank);
+        match self.parser.action {
+            Action::Delete => {
+                let lines = if last == buf.core.buffer().len_lines() - 1 && row > 0 {
+                    // Take the line break before the last lines instead of leaving an empty line
+                    let col = buf.core.buffer().l(row - 1).len_chars();
+                    CursorRange(Cursor { row: row - 1, col }, lines.r())
+                } else {
+                    lines
+                };
+                buf.core.delete_range(lines);
+                let row = min(row, buf.core.buffer().len_lines() - 1);
+                let cursor = buf.core.first_non_blank(row);
+                buf.core.set_cursor(cursor);
+                buf.core.commit();
+                Transition::Return(None, true)
+            }
+            Action::Yank => Transition::Return(None, false),
+            Action::Change => {
+                buf.core.set_cursor(Cursor { row, col: 0 });
+                let len = buf.core.buffer().line_to_char(last)
+                    + buf.core.buffer().l(last).len_chars()
+                    - buf.core.buffer().line_to_char(row);
+                for _ in 0..len {
+                    buf.core.delete();
+                }
+                buf.core.commit();
+                buf.indent();
+                Insert::default().into()
+            }
+        }
+    }
+
+    // Characters in `range` including both ends
+    fn operate_range(&self, buf: &mut Buffer, range: CursorRange) -> Transition {
+        let yank = Yank {
+            insert_newline: false,
+            content: String::from(buf.core.get_slice_by_range(range)),
+        };
+        self.save_yank(buf, yank);
+        match self.parser.action {
+            Action::Delete => {
+                buf.core.delete_range(range);
+                buf.core.commit();
+                Transition::Return(None, true)
+            }
+            Action::Change => {
+                buf.core.delete_range(range);
+                buf.core.commit();
+                Insert::default().into()
+            }
+            Action::Yank => Transition::Return(None, false),
+        }
+    }
+
+    // `'a` covers the lines to the mark and `` `a `` the characters up to the mark
+    fn operate_to_mark(&self, buf: &mut Buffer, linewise: bool, name: char) -> Transition {
+        let target = match buf.marks.get(&name) {
+            Some(&mark) => buf.core.clamp_cursor(mark),
+            None => return Transition::Return(Some(format!("Mark not set: {}", name)), false),
+        };
+        let cursor = buf.core.cursor();
+        if linewise {
+            return self.operate_lines(
+                buf,
+                min(cursor.row, target.row),
+                max(cursor.row, target.row),
+            );
+        }
+        let (l, r) = (min(cursor, target), max(cursor, target));
+        match buf.core.prev_cursor(r).filter(|_| l < r) {
+            Some(r) => self.operate_range(buf, CursorRange(l, r)),
+            None => Transition::Return(None, false),
+        }
+    }
+}
+
+// `gq` and `gw`. `gw` keeps the cursor in place.
+struct Reflow {
+    keep_cursor: bool,
+    count: Option<usize>,
+    keys: String,
+}
+
+impl Reflow {
+    fn new(keep_cursor: bool, count: Option<usize>) -> Self {
+        Self {
+            keep_cursor,
+            count,
+            keys: String::new(),
+        }
+    }
+
+    fn operator(&self) -> char {
+        if self.keep_cursor {
+            'w'
+        } else {
+            'q'
+        }
+    }
+}
+
+// `gc{motion}`. Toggle line comments.
+struct Comment {
+    count: Option<usize>,
+    keys: String,
+}
+
+// Up/Down recall of `History` entries starting with the typed text
+#[derive(Default)]
+struct HistoryRecall {
+    prefix: String,
+    index: Option<usize>,
+}
+
+impl HistoryRecall {
+    // The entry to show, or `None` if there is no more entry
+    fn recall(&mut self, history: &History, current: &str, up: bool) -> Option<String> {
+        if self.index.is_none() {
+            self.prefix = current.to_string();
+        }
+        if up {
+            let i = history.prev(&self.prefix, self.index)?;
+            self.index = Some(i);
+            Some(history.entries()[i].clone())
+        } else {
+            let index = self.index?;
+            if let Some(i) = history.next(&self.prefix, index) {
+                self.index = Some(i);
+                Some(history.entries()[i].clone())
+            } else {
+                // Back to the typed text
+                self.index = None;
+                Some(self.prefix.clone())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Search {
+    recall: HistoryRecall,
+    register_pending: bool,
+}
+
+#[derive(Default)]
+struct Command {
+    command: String,
+    recall: HistoryRecall,
+    register_pending: bool,
+    completion: PathCompletion,
+}
+
+// Quickfix list shown at the bottom
+struct QuickFix;
+
+// `z=`. Pick a replacement of the word at `start..end` of the current line.
+struct SpellSuggest {
+    start: usize,
+    end: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+struct Recent {
+    files: Vec<PathBuf>,
+    index: usize,
+}
+
+struct ViewText {
+    row_offset: usize,
+    lines: Vec<String>,
+}
+
+impl ViewText {
+    fn new(lines: Vec<String>) -> Self {
+        Self {
+            row_offset: 0,
+            lines,
         }
     }
 }
 
-struct Search;
+// `:xxd`. Read-only hex dump of the buffer.
+struct HexView {
+    row_offset: usize,
+    lines: Vec<String>,
+}
 
+#[derive(Default)]
 struct Save {
     path: String,
+    completion: PathCompletion,
+}
+
+// `:set autoread` found the file changed on disk while the buffer is modified
+pub struct DiskChanged {
+    pub mtime: Option<SystemTime>,
+}
+
+// Asks before quitting with unsaved changes
+struct QuitConfirm;
+
+// Quit the editor. Unsaved changes are confirmed first.
+pub fn quit(buf: &Buffer) -> Transition {
+    if buf.is_modified() {
+        QuitConfirm.into()
+    } else {
+        Transition::Exit
+    }
 }
 
 struct Visual {
     cursor: Cursor,
     line_mode: bool,
+    register: Option<char>,
+    prefix: Option<char>,
+}
+
+impl Visual {
+    fn new(cursor: Cursor, line_mode: bool) -> Self {
+        Self {
+            cursor,
+            line_mode,
+            register: None,
+            prefix: None,
+        }
+    }
 }
 
 struct ViewProcess {
@@ -186,147 +576,531 @@ impl Default for Normal {
         Self {
             message: String::new(),
             frame: 0,
+            register: None,
+            count: None,
+            prefix: None,
         }
     }
 }
 
 impl Normal {
     pub fn with_message(message: String) -> Self {
-        Self { message, frame: 0 }
+        Self {
+            message,
+            ..Default::default()
+        }
     }
 }
 
-impl Mode for Normal {
-    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
-        match event {
-            Event::Key(Key::Char('.')) => {
-                return Transition::DoMacro;
-            }
-            Event::Key(Key::Char('u')) => {
-                buf.core.undo();
-                buf.show_cursor();
-            }
-            Event::Key(Key::Char('U')) => {
-                buf.core.redo();
-                buf.show_cursor();
+impl Normal {
+    fn set_message(&mut self, buf: &mut Buffer, message: String) {
+        buf.log_message(&message);
+        self.message = message;
+    }
+
+    // `zj`, `zk`, `[z` and `]z`
+    fn fold_jump(&mut self, buf: &mut Buffer, count: Option<usize>, motion: char) {
+        buf.update_folds();
+        let mut row = buf.core.cursor().row;
+        for _ in 0..count.unwrap_or(1) {
+            match buf.fold_motion(row, motion) {
+                Some(next) => row = next,
+                None => break,
             }
-            Event::Key(Key::Char('i')) => {
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+        }
+        if row == buf.core.cursor().row {
+            self.set_message(buf, "No fold found".into());
+        } else {
+            let cursor = buf.core.first_non_blank(row);
+            buf.core.set_cursor(cursor);
+            buf.show_cursor();
+        }
+    }
+
+    fn prefixed_event(
+        &mut self,
+        buf: &mut Buffer,
+        prefix: char,
+        register: Option<char>,
+        count: Option<usize>,
+        event: termion::event::Event,
+    ) -> Transition {
+        match (prefix, event) {
+            ('g', Event::Key(Key::Char('&'))) => {
+                let last = buf.core.buffer().len_lines() - 1;
+                let message = repeat_substitute(buf, (0, last), true);
+                self.set_message(buf, message);
             }
-            Event::Key(Key::Char('I')) => {
-                let mut i = 0;
-                {
-                    let line = buf.core.current_line();
-                    while i < line.len_chars() && line.char(i) == ' ' {
-                        i += 1;
+            // Paste and leave the cursor after the pasted text
+            ('g', Event::Key(Key::Char(c))) if c == 'p' || c == 'P' => {
+                if let Some(yank) = buf.get_yank(register).cloned() {
+                    let end = buf.paste(&yank, c == 'p');
+                    if yank.insert_newline && end.row + 1 < buf.core.buffer().len_lines() {
+                        buf.core.set_cursor(Cursor {
+                            row: end.row + 1,
+                            col: 0,
+                        });
                     }
+                    buf.core.commit();
+                    buf.show_cursor();
                 }
-                let mut c = buf.core.cursor();
-                c.col = i;
-                buf.core.set_cursor(c);
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
             }
-            Event::Key(Key::Char('S')) => {
-                let mut c = buf.core.cursor();
-                c.col = 0;
-                buf.core.set_cursor(c);
-                for _ in 0..buf.core.current_line().len_chars() {
-                    buf.core.delete()
+            // Paste lines indented like the current line
+            ('[', Event::Key(Key::Char('p'))) | (']', Event::Key(Key::Char('p'))) => {
+                if let Some(yank) = buf.get_yank(register).cloned() {
+                    let after = prefix == ']';
+                    let row = buf.core.cursor().row + if after { 1 } else { 0 };
+                    let yank = buf.indented_yank(&yank);
+                    buf.paste(&yank, after);
+                    let cursor = buf.core.first_non_blank(row);
+                    buf.core.set_cursor(cursor);
+                    buf.core.commit();
+                    buf.show_cursor();
                 }
-                buf.indent();
-                return Transition::RecordMacro(Box::new(Insert::default()));
             }
-            Event::Key(Key::Char('C')) => {
-                while buf.core.char_at_cursor().is_some() {
-                    buf.core.delete()
+            ('g', Event::Key(Key::Char('_'))) => {
+                let row = min(
+                    buf.core.cursor().row + count.unwrap_or(1) - 1,
+                    buf.core.buffer().len_lines() - 1,
+                );
+                let cursor = buf.core.last_non_blank(row);
+                buf.core.set_cursor(cursor);
+                buf.show_cursor();
+            }
+            ('m', Event::Key(Key::Char(c))) if c.is_ascii_lowercase() => {
+                buf.marks.insert(c, buf.core.cursor());
+            }
+            ('\'', Event::Key(Key::Char(c))) | ('`', Event::Key(Key::Char(c))) => {
+                if let Some(cursor) = buf.mark_position(c, prefix == '\'') {
+                    buf.push_jump();
+                    buf.core.set_cursor(cursor);
+                    buf.show_cursor();
+                } else {
+                    self.set_message(buf, format!("Mark not set: {}", c));
                 }
-                return Transition::RecordMacro(Box::new(Insert::default()));
             }
-            Event::Key(Key::Char('a')) => {
-                buf.core.cursor_right();
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+            ('z', Event::Key(Key::Char('z'))) | ('z', Event::Key(Key::Char('.'))) => {
+                buf.show_cursor_middle();
             }
-            Event::Key(Key::Char('A')) => {
-                let mut c = buf.core.cursor();
-                c.col = buf.core.current_line().len_chars();
-                buf.core.set_cursor(c);
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+            ('z', Event::Key(Key::Char(c))) if c == 'j' || c == 'k' => {
+                self.fold_jump(buf, count, c);
             }
-            Event::Key(Key::Char('r')) => {
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(R));
+            ('[', Event::Key(Key::Char('z'))) | (']', Event::Key(Key::Char('z'))) => {
+                self.fold_jump(buf, count, prefix);
             }
-            Event::Key(Key::Char('s')) => {
-                buf.core.delete();
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+            ('z', Event::Key(Key::Char('d'))) => {
+                buf.update_folds();
+                let row = buf.core.cursor().row;
+                if !buf.delete_fold(row) {
+                    self.set_message(buf, "No fold found".into());
+                }
             }
-            Event::Key(Key::Char('o')) => {
-                buf.core.insert_newline();
-                buf.indent();
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+            ('z', Event::Key(Key::Char('E'))) => {
+                buf.folds.clear();
+                buf.closed_folds.clear();
             }
-            Event::Key(Key::Char('O')) => {
-                buf.core.insert_newline_here();
-                buf.indent();
-                buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+            ('z', Event::Key(Key::Char('a'))) => {
+                buf.update_folds();
+                let row = buf.core.cursor().row;
+                if !buf.toggle_fold(row) {
+                    self.set_message(buf, "No fold found".into());
+                }
             }
-            Event::Key(Key::Char('h')) => {
-                buf.core.cursor_left();
-                buf.show_cursor();
+            ('z', Event::Key(Key::Char('M'))) => {
+                buf.update_folds();
+                buf.close_all_folds();
             }
-            Event::Key(Key::Char('j')) => {
-                buf.core.cursor_down();
-                buf.show_cursor();
+            ('z', Event::Key(Key::Char('R'))) => {
+                buf.update_folds();
+                buf.closed_folds.clear();
             }
-            Event::Key(Key::Char('k')) => {
-                buf.core.cursor_up();
-                buf.show_cursor();
+            ('z', Event::Key(Key::Char('='))) => {
+                if !buf.spell {
+                    self.set_message(buf, "Spell checking is off".into());
+                } else if let Some((start, end, word)) = buf.misspelling_under_cursor() {
+                    let candidates = buf
+                        .dictionary
+                        .as_ref()
+                        .map(|d| d.suggest(&word))
+                        .unwrap_or_default();
+                    if candidates.is_empty() {
+                        self.set_message(buf, format!("No suggestions for {}", word));
+                    } else {
+                        return SpellSuggest {
+                            start,
+                            end,
+                            candidates,
+                            index: 0,
+                        }
+                        .into();
+                    }
+                } else {
+                    self.set_message(buf, "No misspelled word under cursor".into());
+                }
             }
-            Event::Key(Key::Char('l')) => {
-                buf.core.cursor_right();
-                buf.show_cursor();
+            ('[', Event::Key(Key::Char('['))) | (']', Event::Key(Key::Char(']'))) => {
+                let forward = prefix == ']';
+                if !repeat_jump(buf, count, |core| text_object::lower_indent(core, forward)) {
+                    self.set_message(buf, "No less indented line".into());
+                }
             }
-            Event::Key(Key::Char('w')) => {
-                buf.core.w();
-                buf.show_cursor();
+            ('[', Event::Key(Key::Char('{'))) | (']', Event::Key(Key::Char('}'))) => {
+                let forward = prefix == ']';
+                if !repeat_jump(buf, count, |core| {
+                    text_object::enclosing_brace(core, forward)
+                }) {
+                    self.set_message(buf, "No enclosing brace".into());
+                }
             }
-            Event::Key(Key::Char('b')) => {
-                buf.core.b();
+            ('[', Event::Key(Key::Char('w'))) | (']', Event::Key(Key::Char('w'))) => {
+                let forward = prefix == ']';
+                for _ in 0..count.unwrap_or(1) {
+                    match text_object::subword(&buf.core, forward) {
+                        Some(cursor) => buf.core.set_cursor(cursor),
+                        None => break,
+                    }
+                }
                 buf.show_cursor();
             }
-            Event::Key(Key::Char('e')) => {
-                buf.core.e();
-                buf.show_cursor();
+            ('[', Event::Key(Key::Char('s'))) | (']', Event::Key(Key::Char('s'))) => {
+                if !buf.spell {
+                    self.set_message(buf, "Spell checking is off".into());
+                } else if let Some(cursor) = buf.find_misspelling(prefix == ']') {
+                    buf.core.set_cursor(cursor);
+                    buf.show_cursor();
+                } else {
+                    self.set_message(buf, "No misspelled words".into());
+                }
             }
-            Event::Key(Key::Char('f')) => {
-                return Find { to_right: true }.into();
+            ('g', Event::Key(Key::Char('f'))) => {
+                if let Some(word) = buf.path_under_cursor() {
+                    if let Some(path) = buf.resolve_path(&word) {
+                        if buf.is_modified() {
+                            self.set_message(buf, "No write since last change".into());
+                            return Transition::Nothing;
+                        }
+                        buf.push_jump();
+                        buf.open(&path);
+                        self.set_message(buf, format!("Opened {}", path.display()));
+                    } else {
+                        self.set_message(buf, format!("File not found: {}", word));
+                    }
+                } else {
+                    self.set_message(buf, "No file name under cursor".into());
+                }
             }
-            Event::Key(Key::Char('F')) => {
+            ('g', Event::Key(Key::Char(c))) if c == 'q' || c == 'w' => {
+                return Reflow::new(c == 'w', count).into();
+            }
+            ('g', Event::Key(Key::Char(c))) if c == ';' || c == ',' => {
+                let mut cursor = None;
+                for _ in 0..count.unwrap_or(1) {
+                    match buf.core.change_position(c == ';') {
+                        Some(c) => cursor = Some(c),
+                        None => break,
+                    }
+                }
+                if let Some(cursor) = cursor {
+                    buf.core.set_cursor(cursor);
+                    buf.show_cursor();
+                } else if c == ';' {
+                    self.set_message(buf, "At start of change list".into());
+                } else {
+                    self.set_message(buf, "At end of change list".into());
+                }
+            }
+            ('g', Event::Key(Key::Char('c'))) => {
+                return Comment {
+                    count,
+                    keys: String::new(),
+                }
+                .into();
+            }
+            ('g', Event::Key(Key::Char('g'))) => {
+                buf.core.set_cursor(Cursor { row: 0, col: 0 });
+                buf.show_cursor();
+            }
+            ('g', Event::Key(Key::Char('v'))) => {
+                if let Some(selection) = buf.last_visual {
+                    let anchor = buf.core.clamp_cursor(selection.anchor);
+                    let cursor = buf.core.clamp_cursor(selection.cursor);
+                    buf.core.set_cursor(cursor);
+                    buf.show_cursor();
+                    return Visual::new(anchor, selection.line_mode).into();
+                } else {
+                    self.set_message(buf, "No previous Visual selection".into());
+                }
+            }
+            ('g', Event::Key(Key::Char('d'))) => {
+                if let Some(word) = buf.word_under_cursor() {
+                    if let Some(cursor) = buf.find_definition(&word) {
+                        buf.push_jump();
+                        buf.core.set_cursor(cursor);
+                        buf.show_cursor();
+                    } else {
+                        self.set_message(buf, format!("Definition not found: {}", word));
+                    }
+                } else {
+                    self.set_message(buf, "No identifier under cursor".into());
+                }
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+}
+
+impl Mode for Normal {
+    fn init(&mut self, buf: &mut Buffer) {
+        if !self.message.is_empty() {
+            buf.log_message(&self.message);
+        }
+    }
+
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let prefix = self.prefix.take();
+        if prefix == Some('"') {
+            if let Event::Key(Key::Char(c)) = event {
+                if c.is_alphanumeric() || c == '"' || c == '-' || c == '.' {
+                    self.register = Some(c);
+                }
+            }
+            return Transition::Nothing;
+        }
+        if prefix.is_none() {
+            if let Event::Key(Key::Char(c)) = event {
+                // `0` is a motion unless it continues a count
+                if let Some(d) = c.to_digit(10).filter(|&d| d > 0 || self.count.is_some()) {
+                    self.count = Some(self.count.unwrap_or(0) * 10 + d as usize);
+                    return Transition::Nothing;
+                }
+            }
+        }
+        let register = self.register.take();
+        let count = self.count.take();
+        if let Some(prefix) = prefix {
+            return self.prefixed_event(buf, prefix, register, count, event);
+        }
+        // The first key of a Normal mode command is looked up in `keymap` of the config. The
+        // rest of the keys and the other modes are not remapped.
+        let raw = event.clone();
+        let event = match buf.keymap().translate(event) {
+            Some(event) => event,
+            None => return Transition::Nothing,
+        };
+        if let (Some(count), Event::Key(Key::Char(c))) = (count, &event) {
+            if "hjklwbe".contains(*c) {
+                for _ in 0..count {
+                    self.event(buf, raw.clone());
+                }
+                return Transition::Nothing;
+            }
+        }
+        if event == Event::Key(Key::Char(buf.leader())) {
+            return Prefix::default().into();
+        }
+        match event {
+            Event::Key(Key::Char('"')) => {
+                self.prefix = Some('"');
+                self.count = count;
+            }
+            Event::Key(Key::Char('g')) => {
+                self.prefix = Some('g');
+                self.register = register;
+                self.count = count;
+            }
+            Event::Key(Key::Char(':')) => {
+                return Command::default().into();
+            }
+            Event::Key(Key::Ctrl('w')) => {
+                return WindowPrefix { count }.into();
+            }
+            Event::Key(Key::Ctrl('n')) => {
+                if !buf.add_cursor_below() {
+                    self.set_message(buf, "No next line".into());
+                }
+            }
+            Event::Key(Key::Esc) => {
+                buf.cursors.clear();
+            }
+            Event::Key(Key::Ctrl('e')) => {
+                buf.scroll_lines(count.unwrap_or(1), true);
+            }
+            Event::Key(Key::Ctrl('k')) => {
+                buf.line_numbers = buf.line_numbers.next();
+                self.set_message(buf, buf.line_numbers.name().into());
+            }
+            Event::Key(Key::Ctrl('y')) => {
+                buf.scroll_lines(count.unwrap_or(1), false);
+            }
+            Event::Key(Key::Ctrl('6')) => {
+                if !buf.has_alternate() {
+                    self.set_message(buf, "No alternate file".into());
+                } else if buf.is_modified() {
+                    self.set_message(buf, "No write since last change".into());
+                } else {
+                    buf.open_alternate();
+                }
+            }
+            Event::Key(Key::Ctrl('o')) => {
+                if let Err(message) = buf.jump_back() {
+                    self.set_message(buf, message);
+                }
+            }
+            Event::Key(Key::Char('.')) => {
+                return Transition::DoMacro;
+            }
+            Event::Key(Key::Char('&')) => {
+                let row = buf.core.cursor().row;
+                let message = repeat_substitute(buf, (row, row), false);
+                self.set_message(buf, message);
+            }
+            Event::Key(Key::Char('u')) => {
+                buf.core.undo();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('U')) => {
+                buf.core.redo();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('i')) => {
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            // Insert pasted text at the cursor
+            Event::Unsupported(ref v) if v.as_slice() == PASTE_START => {
+                let insert = Insert {
+                    pasting: true,
+                    paste_only: true,
+                    ..Insert::default()
+                };
+                return Transition::RecordMacro(Box::new(insert));
+            }
+            Event::Key(Key::Char('I')) => {
+                let c = buf.core.first_non_blank(buf.core.cursor().row);
+                buf.core.set_cursor(c);
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('S')) => {
+                let mut c = buf.core.cursor();
+                c.col = 0;
+                buf.core.set_cursor(c);
+                for _ in 0..buf.core.current_line().len_chars() {
+                    buf.core.delete()
+                }
+                buf.indent();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('C')) => {
+                while buf.core.char_at_cursor().is_some() {
+                    buf.core.delete()
+                }
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('a')) => {
+                buf.core.cursor_right();
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('A')) => {
+                let mut c = buf.core.cursor();
+                c.col = buf.core.current_line().len_chars();
+                buf.core.set_cursor(c);
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('r')) => {
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(R));
+            }
+            Event::Key(Key::Char('s')) => {
+                buf.core.delete();
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('o')) => {
+                buf.core.insert_newline();
+                buf.indent();
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('O')) => {
+                buf.core.insert_newline_here();
+                buf.indent();
+                buf.show_cursor();
+                return Transition::RecordMacro(Box::new(Insert::default()));
+            }
+            Event::Key(Key::Char('h')) => {
+                buf.core.cursor_left();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('j')) => {
+                buf.core.cursor_down();
+                buf.skip_closed_fold(true);
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('k')) => {
+                buf.core.cursor_up();
+                buf.skip_closed_fold(false);
+                buf.show_cursor();
+            }
+            // First non-blank of the next or previous line
+            Event::Key(Key::Char(c)) if c == '+' || c == '\n' || c == '-' => {
+                let row = buf.core.cursor().row;
+                let n = count.unwrap_or(1);
+                let row = if c == '-' {
+                    row.saturating_sub(n)
+                } else {
+                    min(row + n, buf.core.buffer().len_lines() - 1)
+                };
+                let cursor = buf.core.first_non_blank(row);
+                buf.core.set_cursor(cursor);
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('l')) => {
+                buf.core.cursor_right();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('w')) => {
+                buf.core.w();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('b')) => {
+                buf.core.b();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('e')) => {
+                buf.core.e();
+                buf.show_cursor();
+            }
+            Event::Key(Key::Char('f')) => {
+                return Find { to_right: true }.into();
+            }
+            Event::Key(Key::Char('F')) => {
                 return Find { to_right: false }.into();
             }
-            Event::Key(Key::Char('0')) => {
+            Event::Key(Key::Char('0')) if !buf.is_smart_home() => {
                 buf.core.set_cursor(Cursor {
                     row: buf.core.cursor().row,
                     col: 0,
                 });
             }
+            // Toggle between the first non-blank character and column 0
+            Event::Key(Key::Home) | Event::Key(Key::Char('0')) => {
+                let cursor = buf.core.first_non_blank(buf.core.cursor().row);
+                if buf.core.cursor() == cursor {
+                    buf.core.set_cursor(Cursor { col: 0, ..cursor });
+                } else {
+                    buf.core.set_cursor(cursor);
+                }
+            }
             Event::Key(Key::Char('$')) => {
                 buf.core.set_cursor(Cursor {
                     row: buf.core.cursor().row,
                     col: buf.core.current_line().len_chars(),
                 });
             }
-            Event::Key(Key::Char('g')) => {
-                buf.core.set_cursor(Cursor { row: 0, col: 0 });
-                buf.show_cursor();
-            }
             Event::Key(Key::Char('G')) => {
                 let row = buf.core.buffer().len_lines() - 1;
                 let col = buf.core.buffer().l(row).len_chars();
@@ -335,10 +1109,9 @@ impl Mode for Normal {
             }
             Event::Key(Key::Char('n')) => {
                 if !buf.search.is_empty() {
-                    let mut pos = buf.core.cursor();
+                    let mut pos = buf.search_origin(true);
 
-                    let search = buf.search.iter().collect::<String>();
-                    let ac = aho_corasick::AcAutomaton::new(vec![search]);
+                    let ac = aho_corasick::AcAutomaton::new(vec![buf.search_text()]);
 
                     if let Some(p) = buf.core.next_cursor(pos) {
                         pos = p;
@@ -371,7 +1144,8 @@ impl Mode for Normal {
                         let col = buf.core.buffer().byte_to_char(start)
                             - buf.core.buffer().line_to_char(row);
 
-                        buf.core.set_cursor(Cursor { row, col });
+                        let cursor = buf.search_offset_cursor(Cursor { row, col });
+                        buf.core.set_cursor(cursor);
                         buf.show_cursor();
                     }
                 }
@@ -379,7 +1153,8 @@ impl Mode for Normal {
             Event::Key(Key::Char('N')) => {
                 // TODO: Use aho-corasick. Waiting reverse iterator of ropey.
                 if !buf.search.is_empty() {
-                    let search: String = buf.search.iter().collect();
+                    let search = buf.search_text();
+                    let search_len = search.chars().count();
                     let last_pos = Cursor {
                         row: buf.core.buffer().len_lines() - 1,
                         col: buf
@@ -388,20 +1163,28 @@ impl Mode for Normal {
                             .l(buf.core.buffer().len_lines() - 1)
                             .len_chars(),
                     };
+                    let cursor = buf.core.cursor();
+                    let origin = buf.search_origin(false);
+                    buf.core.set_cursor(origin);
                     let orig_pos = buf.core.cursor();
                     if !buf.core.cursor_dec() {
                         buf.core.set_cursor(last_pos);
                     }
 
                     loop {
-                        let matched = buf.core.current_line_after_cursor().len_chars()
-                            >= buf.search.len()
-                            && buf
-                                .core
-                                .current_line_after_cursor()
-                                .slice(..buf.search.len())
-                                == search;
-                        if matched || buf.core.cursor() == orig_pos {
+                        // The match may span lines with `:set multiline`
+                        let idx = buf.core.buffer().line_to_char(buf.core.cursor().row)
+                            + buf.core.cursor().col;
+                        let matched = idx + search_len <= buf.core.buffer().len_chars()
+                            && buf.core.buffer().slice(idx..idx + search_len) == search;
+                        if matched {
+                            let cursor = buf.search_offset_cursor(buf.core.cursor());
+                            buf.core.set_cursor(cursor);
+                            buf.show_cursor();
+                            break;
+                        }
+                        if buf.core.cursor() == orig_pos {
+                            buf.core.set_cursor(cursor);
                             buf.show_cursor();
                             break;
                         }
@@ -412,49 +1195,42 @@ impl Mode for Normal {
                     }
                 }
             }
+            Event::Key(Key::Char('J')) => {
+                buf.join_lines(max(2, count.unwrap_or(2)) - 1);
+                buf.core.commit();
+                buf.show_cursor();
+            }
             Event::Key(Key::Char('x')) => {
-                buf.core.delete();
+                let rest = buf.core.current_line_after_cursor().len_chars();
+                for _ in 0..min(max(1, count.unwrap_or(1)), max(1, rest)) {
+                    buf.core.delete();
+                }
                 buf.core.commit();
                 buf.show_cursor();
             }
-            Event::Key(Key::Char('/')) => return Search.into(),
+            Event::Key(Key::Char('/')) => return Search::default().into(),
             Event::Key(Key::Char('v')) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: false,
-                }
-                .into();
+                return Visual::new(buf.core.cursor(), false).into();
             }
             Event::Key(Key::Char('V')) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: true,
-                }
-                .into();
-            }
-            Event::Key(Key::Char('p')) => {
-                if buf.yank.insert_newline {
-                    buf.core.insert_newline();
-                } else {
-                    buf.core.cursor_right();
-                }
-
-                for c in buf.yank.content.chars() {
-                    buf.core.insert(c);
-                }
-                buf.core.commit();
-                buf.show_cursor();
+                return Visual::new(buf.core.cursor(), true).into();
             }
-            Event::Key(Key::Char('P')) => {
-                if buf.yank.insert_newline {
-                    buf.core.insert_newline_here();
-                }
-
-                for c in buf.yank.content.chars() {
-                    buf.core.insert(c);
+            Event::Key(Key::Char(c)) if c == 'p' || c == 'P' => {
+                if let Some(yank) = buf.get_yank(register).cloned() {
+                    let row = buf.core.cursor().row + if c == 'p' { 1 } else { 0 };
+                    let end = buf.paste(&yank, c == 'p');
+                    // On the first non-blank of the first pasted line or the last pasted character
+                    if yank.insert_newline {
+                        let cursor = buf.core.first_non_blank(row);
+                        buf.core.set_cursor(cursor);
+                    } else {
+                        if let Some(cursor) = buf.core.prev_cursor(end) {
+                            buf.core.set_cursor(cursor);
+                        }
+                    }
+                    buf.core.commit();
+                    buf.show_cursor();
                 }
-                buf.core.commit();
-                buf.show_cursor();
             }
             Event::Key(Key::Ctrl('p')) => {
                 if let Some(s) = clipboard::clipboard_paste() {
@@ -463,36 +1239,59 @@ impl Mode for Normal {
                     }
                     buf.core.commit();
                 } else {
-                    self.message = "Failed to paste from clipboard".into();
+                    self.set_message(buf, "Failed to paste from clipboard".into());
                 }
                 buf.show_cursor();
             }
-            Event::Key(Key::Char(' ')) => {
-                return Prefix.into();
+            Event::Key(Key::Char(c)) if c == ')' || c == '(' => {
+                let forward = c == ')';
+                repeat_jump(buf, count, |core| text_object::sentence(core, forward));
             }
-            Event::Key(Key::Char('z')) => {
-                buf.show_cursor_middle();
+            Event::Key(Key::Char('%')) => {
+                // `N%` goes to N percent of the lines
+                if let Some(percent) = count {
+                    if percent > 100 {
+                        self.set_message(buf, "Count must be at most 100".into());
+                    } else {
+                        let len_lines = buf.core.buffer().len_lines();
+                        let row = (percent * len_lines).div_ceil(100).max(1) - 1;
+                        buf.push_jump();
+                        let cursor = buf.core.first_non_blank(row);
+                        buf.core.set_cursor(cursor);
+                        buf.show_cursor();
+                    }
+                    return Transition::Nothing;
+                }
+                let pairs = buf.match_pairs();
+                if let Some(cursor) = text_object::matching_tag(&buf.core)
+                    .or_else(|| text_object::matching_paren(&buf.core, &pairs))
+                {
+                    buf.push_jump();
+                    buf.core.set_cursor(cursor);
+                    buf.show_cursor();
+                }
+            }
+            Event::Key(Key::Char(c)) if "z[]m'`".contains(c) => {
+                self.prefix = Some(c);
+                self.count = count;
             }
             Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
-                let col = x as usize - 1;
-                let row = y as usize - 1;
-                let cursor = Cursor { row, col };
-
-                let mut term = draw::Term::default();
-                let height = term.height;
-                let width = term.width;
-                buf.draw(term.view((0, 0), height, width));
-
-                if let Some(c) = term.pos(cursor) {
+                if let Some((height, width)) = buf.window_size {
+                    if buf.click_scrollbar(y as usize - 1, x as usize - 1, height - 1, width) {
+                        return Transition::Nothing;
+                    }
+                }
+                if let Some(c) = mouse_position(buf, x, y, 1) {
                     buf.core.set_cursor(c);
                 }
             }
-            Event::Mouse(MouseEvent::Hold(_, _)) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: false,
+            Event::Mouse(MouseEvent::Hold(x, y)) => {
+                let on_scrollbar = buf.window_size.is_some_and(|(height, width)| {
+                    buf.click_scrollbar(y as usize - 1, x as usize - 1, height - 1, width)
+                });
+                if !on_scrollbar {
+                    return Visual::new(buf.core.cursor(), false).into();
                 }
-                .into();
             }
             Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
                 buf.scroll_up();
@@ -503,7 +1302,9 @@ impl Mode for Normal {
             _ => {
                 if let Event::Key(Key::Char(c)) = event {
                     if let Some(action) = Action::from_char(c) {
-                        return Transition::RecordMacro(Box::new(TextObjectOperation::new(action)));
+                        return Transition::RecordMacro(Box::new(TextObjectOperation::new(
+                            action, register, count,
+                        )));
                     }
                 }
             }
@@ -516,26 +1317,39 @@ impl Mode for Normal {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height - 1, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height - 1, 0), 1, width);
         if let Some(message) = buf.compiler_message_on_cursor() {
             footer.puts(message, draw::styles::FOOTER);
+        } else if buf.zen {
+            // Only messages in `:zen`
+            footer.puts(&self.message, draw::styles::FOOTER);
         } else {
+            let indent_style = match buf.indent_style {
+                Some(IndentStyle::Tabs) => " [tabs]".to_string(),
+                Some(IndentStyle::Spaces(width)) => format!(" [spaces:{}]", width),
+                None => String::new(),
+            };
             footer.puts(
                 &format!(
-                    "[Normal] ({} {}) [{}] {}",
+                    "[Normal] ({} {}) [{}]{} {}",
                     buf.core.cursor().row + 1,
                     buf.core.cursor().col + 1,
                     buf.path()
                         .map(|p| p.to_string_lossy())
-                        .unwrap_or_else(|| "*".into()),
+                        .unwrap_or_else(|| "No Name".into()),
+                    indent_style,
                     &self.message,
                 ),
                 draw::styles::FOOTER,
             );
 
+            if let Some(progress) = buf.load_progress() {
+                footer.puts(&format!(" Loading… {}%", progress), draw::styles::FOOTER);
+            }
+
             if buf.is_compiling() {
                 let animation = [
                     '⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏',
@@ -551,11 +1365,45 @@ impl Mode for Normal {
                 footer.puts(msg, draw::styles::FOOTER);
             }
         }
+        draw_showcmd(term, &self.showcmd());
         self.frame = (std::num::Wrapping(self.frame) + std::num::Wrapping(1)).0;
     }
+
+    fn showcmd(&self) -> String {
+        format!(
+            "{}{}{}",
+            register_showcmd(self.register),
+            self.count.map(|c| c.to_string()).unwrap_or_default(),
+            self.prefix.map(|c| c.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn is_idle(&self) -> bool {
+        self.showcmd().is_empty()
+    }
+
+    fn is_pending(&self) -> bool {
+        !self.is_idle()
+    }
 }
 
 impl Insert {
+    // Back to Normal mode. The inserted text goes to `".`.
+    fn leave(&mut self, buf: &mut Buffer) -> Transition {
+        buf.core.commit();
+        let buffer = buf.core.buffer();
+        let index = |c: Cursor| min(buffer.line_to_char(c.row) + c.col, buffer.len_chars());
+        let (start, end) = (index(self.start), index(buf.core.cursor()));
+        if start < end {
+            let yank = Yank {
+                insert_newline: false,
+                content: buffer.slice(start..end).to_string(),
+            };
+            buf.registers.insert('.', yank);
+        }
+        Transition::Return(None, true)
+    }
+
     fn token(core: &Core) -> String {
         let line = core.current_line();
         let mut i = core.cursor().col;
@@ -579,13 +1427,87 @@ impl Insert {
         }
     }
 
-    fn completion_len(&self) -> usize {
-        self.completions.len() + self.snippet_completions.len()
+    fn complete_word(&mut self, buf: &mut Buffer, forward: bool) {
+        let mut completion = self.word_completion.take().unwrap_or_else(|| {
+            let prefix = Self::token(&buf.core);
+            WordCompletion {
+                candidates: buf.complete_words(&prefix),
+                prefix,
+                index: None,
+            }
+        });
+        let len = completion.candidates.len();
+        if len == 0 {
+            return;
+        }
+        // Cycle through the candidates and the original prefix
+        completion.index = match (completion.index, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(len - 1),
+            (Some(i), true) => Some(i + 1).filter(|&i| i < len),
+            (Some(i), false) => i.checked_sub(1),
+        };
+        let word = completion
+            .index
+            .map(|i| completion.candidates[i].as_str())
+            .unwrap_or(&completion.prefix);
+        Self::remove_token(&mut buf.core);
+        for c in word.chars() {
+            buf.core.insert(c);
+        }
+        buf.show_cursor();
+        self.word_completion = Some(completion);
     }
 
-    fn get_completion(&self, buf: &Buffer) -> Option<String> {
-        let index = self.completion_index?;
-        if index < self.completions.len() {
+    // The menu of `Ctrl-n` and `Ctrl-p` candidates. `cursor` is at the end of the completed word.
+    fn draw_word_completion(&self, term: &mut draw::Term, cursor: Cursor) {
+        let completion = match self.word_completion.as_ref() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let word = completion
+            .index
+            .map(|i| completion.candidates[i].as_str())
+            .unwrap_or(&completion.prefix);
+        let word_width: usize = word.chars().map(draw::char_width).sum();
+        let item_width = |s: &String| s.chars().map(draw::char_width).sum::<usize>();
+        let candidates = &completion.candidates;
+        let width = candidates.iter().map(item_width).max().unwrap_or(0) + 2;
+        let (row, col, height, width) = draw::popup_rect(
+            (cursor.row, cursor.col.saturating_sub(word_width + 1)),
+            (min(candidates.len(), 10), width),
+            (term.height, term.width),
+        );
+        if height == 0 {
+            return;
+        }
+        // Scroll to show the selection
+        let top = completion
+            .index
+            .map(|i| (i + 1).saturating_sub(height))
+            .unwrap_or(0);
+        let mut view = term.view((row, col), height, width);
+        for (i, candidate) in candidates.iter().enumerate().skip(top).take(height) {
+            let style = if Some(i) == completion.index {
+                draw::styles::HIGHLIGHT
+            } else {
+                draw::styles::UI
+            };
+            let padding = " ".repeat(width.saturating_sub(item_width(candidate) + 1));
+            for c in format!(" {}{}", candidate, padding).chars() {
+                view.put_inline(c, style, None);
+            }
+            view.newline();
+        }
+    }
+
+    fn completion_len(&self) -> usize {
+        self.completions.len() + self.snippet_completions.len()
+    }
+
+    fn get_completion(&self, buf: &Buffer) -> Option<String> {
+        let index = self.completion_index?;
+        if index < self.completions.len() {
             Some(self.completions[index].keyword.clone())
         } else {
             Some(buf.snippet[&self.snippet_completions[index - self.completions.len()]].clone())
@@ -646,6 +1568,7 @@ impl Insert {
 
 impl Mode for Insert {
     fn init(&mut self, buf: &mut Buffer) {
+        self.start = buf.core.cursor();
         // Flush completion
         if let Some(lsp) = buf.lsp.as_ref() {
             lsp.poll();
@@ -653,16 +1576,96 @@ impl Mode for Insert {
         self.build_completion(buf);
     }
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        if self.pasting {
+            match event {
+                Event::Unsupported(ref v) if v.as_slice() == PASTE_END => {
+                    self.pasting = false;
+                    if self.paste_only {
+                        return self.leave(buf);
+                    }
+                }
+                Event::Key(Key::Char(c)) => {
+                    buf.core.insert(c);
+                }
+                _ => {}
+            }
+            buf.show_cursor();
+            return Transition::Nothing;
+        }
+        if let Event::Unsupported(ref v) = event {
+            if v.as_slice() == PASTE_START {
+                self.pasting = true;
+                return Transition::Nothing;
+            }
+        }
+        if !buf.cursors.is_empty() {
+            match event {
+                Event::Key(Key::Char(c)) if !c.is_control() => {
+                    buf.edit_at_cursors(|core| core.insert(c));
+                    buf.show_cursor();
+                    return Transition::Nothing;
+                }
+                Event::Key(Key::Backspace) => {
+                    buf.edit_at_cursors(|core| {
+                        if core.cursor().col > 0 {
+                            core.cursor_dec();
+                            core.delete();
+                        }
+                    });
+                    buf.show_cursor();
+                    return Transition::Nothing;
+                }
+                _ => buf.cursors.clear(),
+            }
+        }
+        if self.register_pending {
+            self.register_pending = false;
+            if let Some(text) = match event {
+                Event::Key(Key::Char(c)) => register_text(buf, c),
+                _ => None,
+            } {
+                for c in text.chars() {
+                    buf.core.insert(c);
+                }
+                buf.show_cursor();
+            }
+            return Transition::Nothing;
+        }
         match event {
-            Event::Key(Key::Esc) => {
-                buf.core.commit();
-                return Transition::Return(None, true);
+            Event::Key(Key::Ctrl('n')) | Event::Key(Key::Ctrl('p')) => {
+                self.complete_word(buf, event == Event::Key(Key::Ctrl('n')));
+                return Transition::Nothing;
+            }
+            // Enter and Tab accept the candidate without inserting themselves
+            Event::Key(Key::Char('\n')) | Event::Key(Key::Char('\t'))
+                if self.word_completion.is_some() =>
+            {
+                self.word_completion = None;
+                return Transition::Nothing;
+            }
+            // Esc closes the menu and restores the typed prefix
+            Event::Key(Key::Esc) if self.word_completion.is_some() => {
+                if let Some(completion) = self.word_completion.take() {
+                    Self::remove_token(&mut buf.core);
+                    for c in completion.prefix.chars() {
+                        buf.core.insert(c);
+                    }
+                    buf.show_cursor();
+                }
+                return Transition::Nothing;
             }
+            // Any other key commits the completion
+            _ => self.word_completion = None,
+        }
+        match event {
+            Event::Key(Key::Esc) => return self.leave(buf),
             Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
                 buf.scroll_up();
+                self.start = buf.core.cursor();
             }
             Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
                 buf.scroll_down();
+                self.start = buf.core.cursor();
             }
             Event::Key(Key::Backspace) => {
                 let parens = [('{', '}'), ('(', ')'), ('[', ']')];
@@ -681,6 +1684,76 @@ impl Mode for Insert {
                 buf.core.delete();
                 buf.show_cursor();
             }
+            Event::Key(Key::Ctrl('r')) => {
+                self.register_pending = true;
+            }
+            Event::Key(Key::Ctrl('t')) | Event::Key(Key::Ctrl('d')) => {
+                let before = buf.core.cursor();
+                buf.shift_line(event == Event::Key(Key::Ctrl('t')));
+                // The inserted text moves with the indent
+                if self.start.row == before.row {
+                    let col = self.start.col + buf.core.cursor().col;
+                    self.start.col = col.saturating_sub(before.col);
+                }
+                buf.show_cursor();
+            }
+            // Emacs style motions. The inserted text starts again from there.
+            Event::Key(Key::Ctrl('a')) if buf.is_emacs_insert_keys() => {
+                let row = buf.core.cursor().row;
+                buf.core.set_cursor(Cursor { row, col: 0 });
+                self.start = buf.core.cursor();
+            }
+            Event::Key(Key::Ctrl('e')) if buf.is_emacs_insert_keys() => {
+                let row = buf.core.cursor().row;
+                let col = buf.core.current_line().len_chars();
+                buf.core.set_cursor(Cursor { row, col });
+                self.start = buf.core.cursor();
+            }
+            Event::Key(Key::Ctrl('f')) if buf.is_emacs_insert_keys() => {
+                buf.core.cursor_right();
+                self.start = buf.core.cursor();
+            }
+            Event::Key(Key::Ctrl('b')) if buf.is_emacs_insert_keys() => {
+                buf.core.cursor_left();
+                self.start = buf.core.cursor();
+            }
+            // The text of the last Insert session
+            Event::Key(Key::Ctrl('a')) => {
+                if let Some(text) = register_text(buf, '.') {
+                    for c in text.chars() {
+                        buf.core.insert(c);
+                    }
+                    buf.show_cursor();
+                }
+            }
+            Event::Key(Key::Alt('d')) => {
+                let format = buf.date_format();
+                buf.insert_time(&DateTime::now(), &format);
+                buf.show_cursor();
+            }
+            Event::Key(Key::Ctrl('w')) | Event::Key(Key::Ctrl('u')) => {
+                let end = buf.core.cursor();
+                let mut start = Cursor {
+                    row: end.row,
+                    col: 0,
+                };
+                if event == Event::Key(Key::Ctrl('w')) {
+                    buf.core.b();
+                    // Do not go beyond the line
+                    if buf.core.cursor().row == end.row {
+                        start = buf.core.cursor();
+                    }
+                }
+                buf.core.set_cursor(start);
+                if start < end {
+                    let r = Cursor {
+                        row: end.row,
+                        col: end.col - 1,
+                    };
+                    buf.core.delete_range(CursorRange(start, r));
+                }
+                buf.show_cursor();
+            }
             Event::Key(Key::Char('\t')) => {
                 if self.completion_len() > 0 {
                     if let Some(index) = self.completion_index {
@@ -688,6 +1761,8 @@ impl Mode for Insert {
                     } else {
                         self.completion_index = Some(0);
                     }
+                } else if buf.indent_style == Some(IndentStyle::Tabs) {
+                    buf.core.insert('\t');
                 } else {
                     buf.core.insert(' ');
                     while buf.core.cursor().col % buf.indent_width() != 0 {
@@ -720,14 +1795,16 @@ impl Mode for Insert {
                     buf.show_cursor();
                     self.completion_index = None;
                 } else {
+                    buf.expand_abbreviation();
                     let indent_width = buf.indent_width();
+                    let unit = buf.indent_unit();
                     buf.core.insert('\n');
                     let indent = indent::next_indent_level(
                         &Cow::from(buf.core.buffer().l(buf.core.cursor().row - 1)),
                         indent_width,
                     );
-                    for _ in 0..indent_width * indent {
-                        buf.core.insert(' ');
+                    for c in unit.repeat(indent).chars() {
+                        buf.core.insert(c);
                     }
                     let pos = buf.core.cursor();
                     if ['}', ']', ')']
@@ -736,14 +1813,17 @@ impl Mode for Insert {
                     {
                         buf.core.insert('\n');
                         let i = if indent == 0 { 0 } else { indent - 1 };
-                        for _ in 0..indent_width * i {
-                            buf.core.insert(' ');
+                        for c in unit.repeat(i).chars() {
+                            buf.core.insert(c);
                         }
                     }
                     buf.core.set_cursor(pos);
                 }
             }
             Event::Key(Key::Char(c)) if !c.is_control() => {
+                if !c.is_alphanumeric() && c != '_' {
+                    buf.expand_abbreviation();
+                }
                 // Auto pair
                 let pairs = [('(', ')'), ('{', '}'), ('[', ']'), ('"', '"')];
 
@@ -760,6 +1840,8 @@ impl Mode for Insert {
             }
             _ => {}
         }
+        // Deleted back over the start
+        self.start = min(self.start, buf.core.cursor());
         self.build_completion(buf);
         buf.show_cursor();
         Transition::Nothing
@@ -771,8 +1853,11 @@ impl Mode for Insert {
         let width = term.width;
         let mut cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Bar))
+            .map(|c| draw::CursorState::Show(c, buf.insert_cursor()))
             .unwrap_or(draw::CursorState::Hide);
+        if let Some(cursor) = cursor {
+            self.draw_word_completion(term, cursor);
+        }
 
         if let Some(cursor) = cursor.as_mut() {
             cursor.row += 1;
@@ -816,6 +1901,10 @@ impl Mode for Insert {
 }
 
 impl Mode for R {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         let core = &mut buf.core;
         match event {
@@ -836,98 +1925,1224 @@ impl Mode for R {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Underline))
+            .map(|c| draw::CursorState::Show(c, buf.replace_cursor()))
             .unwrap_or(draw::CursorState::Hide);
     }
 }
 
 impl Mode for Search {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Up)
+            | Event::Key(Key::Down)
+            | Event::Key(Key::Ctrl('p'))
+            | Event::Key(Key::Ctrl('n')) => {
+                let up = event == Event::Key(Key::Up) || event == Event::Key(Key::Ctrl('p'));
+                let current: String = buf.search.iter().collect();
+                if let Some(entry) = self.recall.recall(&buf.search_history, &current, up) {
+                    buf.search = entry.chars().collect();
+                }
+                return Transition::Nothing;
+            }
+            _ => self.recall.index = None,
+        }
+        if self.register_pending {
+            self.register_pending = false;
+            if let Event::Key(Key::Char(c)) = event {
+                if let Some(text) = register_line(buf, c) {
+                    buf.search.extend(text.chars());
+                }
+            }
+            return Transition::Nothing;
+        }
         match event {
             Event::Key(Key::Esc) => {
                 return Transition::Return(None, false);
             }
+            Event::Key(Key::Ctrl('r')) => {
+                self.register_pending = true;
+            }
             Event::Key(Key::Backspace) => {
                 buf.search.pop();
             }
             Event::Key(Key::Char(c)) => {
                 if c == '\n' {
+                    let search: String = buf.search.iter().collect();
+                    buf.search_history.push(&search);
+                    let (pattern, offset) = command::split_search(&search);
+                    buf.search = pattern.chars().collect();
+                    buf.search_offset = offset;
                     return Transition::Return(None, false);
                 }
                 buf.search.push(c);
             }
-            _ => {}
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height - 1;
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let mut footer = term.view((height, 0), 1, width);
+        footer.put('/', draw::styles::DEFAULT, None);
+        for &c in &buf.search {
+            footer.put(c, draw::styles::DEFAULT, None);
+        }
+    }
+}
+
+impl Command {
+    fn with_range() -> Self {
+        Self {
+            command: "'<,'>".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Returns regex, replacement and whether `g` flag is given
+    fn substitute_args(buf: &Buffer, args: &str) -> Result<(regex::Regex, String, bool), String> {
+        let parts = command::split_pattern(args).unwrap_or_default();
+        let pattern = parts
+            .first()
+            .cloned()
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| regex::escape(&buf.search_text()));
+        let replacement = command::substitute_replacement(parts.get(1).map_or("", String::as_str));
+        let flags = parts.get(2).map_or("", String::as_str);
+
+        if pattern.is_empty() {
+            return Err("No previous regular expression".into());
+        }
+
+        let re = regex::RegexBuilder::new(&pattern)
+            .case_insensitive(flags.contains('i'))
+            .multi_line(buf.multiline)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok((re, replacement, flags.contains('g')))
+    }
+
+    fn substitute(buf: &mut Buffer, (first, last): (usize, usize), args: &str) -> Transition {
+        match Self::substitute_args(buf, args) {
+            Ok((re, replacement, global)) => {
+                buf.last_substitute = Some((re.clone(), replacement.clone(), global));
+                let count = buf.substitute(first, last, &re, &replacement, global);
+                buf.core.commit();
+                buf.show_cursor();
+                let message = if count == 0 {
+                    format!("Pattern not found: {}", re.as_str())
+                } else {
+                    format!("{} substitutions", count)
+                };
+                Transition::Return(Some(message), false)
+            }
+            Err(err) => Transition::Return(Some(err), false),
+        }
+    }
+
+    // Live preview of `:s` like `inccommand` of Neovim
+    fn update_preview(&self, buf: &mut Buffer) {
+        buf.preview.clear();
+        let command = command::parse(&self.command);
+        if !command::is_abbrev(command.name, "s", "substitute") {
+            return;
+        }
+        let current_line = (buf.core.cursor().row, buf.core.cursor().row);
+        let rows = match command.range {
+            Some(range) => buf.range_rows(range),
+            None => Some(current_line),
+        };
+        if let Some((first, last)) = rows {
+            if let Ok((re, replacement, global)) = Self::substitute_args(buf, command.args) {
+                buf.preview_substitute(first, last, &re, &replacement, global);
+            }
+        }
+    }
+
+    fn run(&self, buf: &mut Buffer) -> Transition {
+        let command = command::parse(&self.command);
+        let rows = command.range.map(|range| buf.range_rows(range));
+        if rows == Some(None) {
+            return Transition::Return(Some("Invalid range".into()), false);
+        }
+        let rows = rows.and_then(|r| r);
+        let current_line = (buf.core.cursor().row, buf.core.cursor().row);
+        let whole = (0, buf.core.buffer().len_lines() - 1);
+
+        match command.name {
+            "" => {
+                if let Some((_, row)) = rows {
+                    buf.core.set_cursor(Cursor { row, col: 0 });
+                    buf.show_cursor();
+                }
+                Transition::Return(None, false)
+            }
+            name if command::is_abbrev(name, "reg", "registers") => {
+                ViewText::new(buf.register_list()).into()
+            }
+            name if command::is_abbrev(name, "ia", "iabbrev") => {
+                let mut args = command.args.splitn(2, char::is_whitespace);
+                match (args.next().filter(|s| !s.is_empty()), args.next()) {
+                    (None, _) => ViewText::new(buf.abbreviation_list()).into(),
+                    (Some(lhs), Some(rhs)) if !rhs.trim().is_empty() => {
+                        let rhs = rhs.trim().to_string();
+                        buf.abbreviations.insert(lhs.to_string(), rhs);
+                        Transition::Return(None, false)
+                    }
+                    (Some(lhs), _) => match buf.abbreviations.get(lhs) {
+                        Some(rhs) => Transition::Return(Some(format!("{}  {}", lhs, rhs)), false),
+                        None => Transition::Return(Some("No abbreviation found".into()), false),
+                    },
+                }
+            }
+            name if command::is_abbrev(name, "una", "unabbreviate") => {
+                if buf.abbreviations.remove(command.args).is_some() {
+                    Transition::Return(None, false)
+                } else {
+                    Transition::Return(Some("No such abbreviation".into()), false)
+                }
+            }
+            // The windows share the buffer, so a file cannot be opened in one of them
+            name if command::is_abbrev(name, "sp", "split")
+                || command::is_abbrev(name, "vs", "vsplit") =>
+            {
+                if !command.args.is_empty() {
+                    Transition::Return(
+                        Some("Windows share the buffer. Open a file with :e".into()),
+                        false,
+                    )
+                } else if name.starts_with('s') {
+                    Transition::Window(WindowCommand::Split)
+                } else {
+                    Transition::Window(WindowCommand::VSplit)
+                }
+            }
+            name if command::is_abbrev(name, "clo", "close") => {
+                Transition::Window(WindowCommand::Close)
+            }
+            name if command::is_abbrev(name, "on", "only") => {
+                Transition::Window(WindowCommand::Only)
+            }
+            name if command::is_abbrev(name, "bd", "bdelete") => {
+                if buf.is_modified() && !command.bang {
+                    return Transition::Return(
+                        Some("No write since last change (add ! to override)".into()),
+                        false,
+                    );
+                }
+                let deleted = buf
+                    .path()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "No Name".into());
+                let message = if buf.delete_buffer() {
+                    let path = buf.path().map(|p| p.display().to_string());
+                    format!("Deleted {}, editing {}", deleted, path.unwrap_or_default())
+                } else {
+                    format!("Deleted {}, no buffers left", deleted)
+                };
+                Transition::Return(Some(message), false)
+            }
+            name if command::is_abbrev(name, "gr", "grep") => {
+                match regex::Regex::new(command.args) {
+                    Ok(re) => {
+                        buf.quickfix = buf.grep(&re);
+                        if buf.quickfix.is_empty() {
+                            Transition::Return(
+                                Some(format!("Pattern not found: {}", command.args)),
+                                false,
+                            )
+                        } else {
+                            buf.quickfix_jump(0);
+                            QuickFix.into()
+                        }
+                    }
+                    Err(err) => Transition::Return(Some(err.to_string()), false),
+                }
+            }
+            name if command::is_abbrev(name, "cope", "copen") => {
+                if buf.quickfix.is_empty() {
+                    Transition::Return(Some("No quickfix list".into()), false)
+                } else {
+                    buf.push_jump();
+                    QuickFix.into()
+                }
+            }
+            name if command::is_abbrev(name, "cn", "cnext")
+                || command::is_abbrev(name, "cp", "cprevious") =>
+            {
+                let i = if name.starts_with("cn") {
+                    buf.quickfix_index + 1
+                } else {
+                    buf.quickfix_index.wrapping_sub(1)
+                };
+                let message = if buf.quickfix.is_empty() {
+                    "No quickfix list".into()
+                } else {
+                    buf.quickfix_jump(i)
+                        .unwrap_or_else(|| "No more items".into())
+                };
+                Transition::Return(Some(message), false)
+            }
+            name if command::is_abbrev(name, "se", "set") => match command.args {
+                "list" => {
+                    buf.list = true;
+                    Transition::Return(None, false)
+                }
+                "nolist" => {
+                    buf.list = false;
+                    Transition::Return(None, false)
+                }
+                "scrollbar" => {
+                    buf.scrollbar = true;
+                    Transition::Return(None, false)
+                }
+                "noscrollbar" => {
+                    buf.scrollbar = false;
+                    Transition::Return(None, false)
+                }
+                "nu" | "number" | "nornu" | "norelativenumber" => {
+                    buf.line_numbers = LineNumbers::Absolute;
+                    Transition::Return(None, false)
+                }
+                "rnu" | "relativenumber" => {
+                    buf.line_numbers = LineNumbers::Relative;
+                    Transition::Return(None, false)
+                }
+                "nonu" | "nonumber" => {
+                    buf.line_numbers = LineNumbers::Off;
+                    Transition::Return(None, false)
+                }
+                "cursorline" => {
+                    buf.cursorline = true;
+                    Transition::Return(None, false)
+                }
+                "nocursorline" => {
+                    buf.cursorline = false;
+                    Transition::Return(None, false)
+                }
+                "heatmap" => {
+                    buf.heatmap = true;
+                    Transition::Return(None, false)
+                }
+                "noheatmap" => {
+                    buf.heatmap = false;
+                    Transition::Return(None, false)
+                }
+                "showkeys" => {
+                    buf.showkeys = true;
+                    Transition::Return(None, false)
+                }
+                "noshowkeys" => {
+                    buf.showkeys = false;
+                    buf.recent_keys.clear();
+                    Transition::Return(None, false)
+                }
+                "autoread" => {
+                    buf.autoread = true;
+                    Transition::Return(None, false)
+                }
+                "noautoread" => {
+                    buf.autoread = false;
+                    Transition::Return(None, false)
+                }
+                "breakindent" => {
+                    buf.breakindent = true;
+                    Transition::Return(None, false)
+                }
+                "nobreakindent" => {
+                    buf.breakindent = false;
+                    Transition::Return(None, false)
+                }
+                "buftype=nofile" => {
+                    buf.scratch = true;
+                    Transition::Return(None, false)
+                }
+                "buftype=" => {
+                    buf.scratch = false;
+                    Transition::Return(None, false)
+                }
+                "multiline" => {
+                    buf.multiline = true;
+                    Transition::Return(None, false)
+                }
+                "nomultiline" => {
+                    buf.multiline = false;
+                    Transition::Return(None, false)
+                }
+                "virtualedit=all" => {
+                    buf.core.virtual_edit = true;
+                    Transition::Return(None, false)
+                }
+                "virtualedit=" | "novirtualedit" => {
+                    buf.core.virtual_edit = false;
+                    Transition::Return(None, false)
+                }
+                "spell" => {
+                    if buf.load_dictionary() {
+                        buf.spell = true;
+                        Transition::Return(None, false)
+                    } else {
+                        Transition::Return(Some("No word list found".into()), false)
+                    }
+                }
+                "nospell" => {
+                    buf.spell = false;
+                    Transition::Return(None, false)
+                }
+                "commentstring=" => {
+                    buf.commentstring = None;
+                    Transition::Return(None, false)
+                }
+                option if option.starts_with("commentstring=") => {
+                    let value = option["commentstring=".len()..].replace("\\ ", " ");
+                    if value.contains("%s") {
+                        buf.commentstring = Some(value);
+                        Transition::Return(None, false)
+                    } else {
+                        Transition::Return(Some("commentstring must contain %s".into()), false)
+                    }
+                }
+                option if option.starts_with("matchpairs=") => {
+                    let value = &option["matchpairs=".len()..];
+                    // An empty value resets it
+                    buf.matchpairs = if value.is_empty() {
+                        None
+                    } else {
+                        Some(value.into())
+                    };
+                    Transition::Return(None, false)
+                }
+                "foldmethod=indent" | "fdm=indent" => {
+                    buf.set_fold_indent(true);
+                    Transition::Return(None, false)
+                }
+                "foldmethod=manual" | "fdm=manual" => {
+                    buf.set_fold_indent(false);
+                    Transition::Return(None, false)
+                }
+                option => Transition::Return(Some(format!("Unknown option: {}", option)), false),
+            },
+            "pwd" => match env::current_dir() {
+                Ok(dir) => Transition::Return(Some(dir.display().to_string()), false),
+                Err(err) => Transition::Return(Some(err.to_string()), false),
+            },
+            "cd" => {
+                let dir = if command.args.is_empty() {
+                    dirs::home_dir()
+                } else {
+                    Some(PathBuf::from(shellexpand::tilde(command.args).as_ref()))
+                };
+                let message = match dir.map(env::set_current_dir) {
+                    Some(Ok(())) => env::current_dir()
+                        .map(|dir| dir.display().to_string())
+                        .unwrap_or_default(),
+                    Some(Err(err)) => format!("{}: {}", command.args, err),
+                    None => "No home directory".into(),
+                };
+                Transition::Return(Some(message), false)
+            }
+            "w" => {
+                if !command.args.is_empty() {
+                    let path: String = shellexpand::tilde(command.args).into();
+                    let message = if buf.save_as(PathBuf::from(&path)) {
+                        format!("Saved to {}", path)
+                    } else {
+                        format!("Failed to save {}", path)
+                    };
+                    return Transition::Return(Some(message), false);
+                }
+                let message = match buf.path().map(|p| p.display().to_string()) {
+                    Some(path) => {
+                        if buf.save(false) {
+                            format!("Saved to {}", path)
+                        } else {
+                            format!("Failed to save {}", path)
+                        }
+                    }
+                    None => {
+                        return Save::default().into();
+                    }
+                };
+                Transition::Return(Some(message), false)
+            }
+            name if command::is_abbrev(name, "ret", "retab") => {
+                let (first, last) = rows.unwrap_or(whole);
+                let changed = buf.retab(first, last, command.bang);
+                buf.core.commit();
+                Transition::Return(Some(format!("{} lines changed", changed)), false)
+            }
+            "xxd" => HexView {
+                row_offset: 0,
+                lines: buf.hex_dump(),
+            }
+            .into(),
+            // The format in the argument or in the config
+            "date" | "time" => {
+                let format = if !command.raw_args.is_empty() {
+                    command.raw_args.to_string()
+                } else if command.name == "date" {
+                    buf.date_format()
+                } else {
+                    buf.time_format()
+                };
+                buf.insert_time(&DateTime::now(), &format);
+                buf.core.commit();
+                buf.show_cursor();
+                Transition::Return(None, false)
+            }
+            name if command::is_abbrev(name, "setf", "setfiletype") => {
+                let filetype = command.raw_args.trim();
+                if filetype.is_empty() {
+                    let message = buf.filetype().unwrap_or("No file type").to_string();
+                    return Transition::Return(Some(message), false);
+                }
+                buf.set_filetype(filetype);
+                Transition::Return(None, false)
+            }
+            "zen" => {
+                buf.zen = !buf.zen;
+                buf.show_cursor();
+                Transition::Return(None, false)
+            }
+            "wa" | "wqa" => match save_all(buf) {
+                Ok(_) if command.name == "wqa" => Transition::Exit,
+                Ok(message) | Err(message) => Transition::Return(Some(message), false),
+            },
+            name if (name == "b" || name == "e") && command.args == "#" => {
+                let message = if !buf.has_alternate() {
+                    "No alternate file".into()
+                } else if buf.is_modified() && !command.bang {
+                    "No write since last change (add ! to override)".into()
+                } else {
+                    buf.open_alternate();
+                    let path = buf.path().map(|p| p.display().to_string());
+                    format!("Opened {}", path.unwrap_or_default())
+                };
+                Transition::Return(Some(message), false)
+            }
+            "e" => {
+                if command.args.is_empty() {
+                    return Transition::Return(Some("No file name".into()), false);
+                }
+                if buf.is_modified() && !command.bang {
+                    return Transition::Return(
+                        Some("No write since last change (add ! to override)".into()),
+                        false,
+                    );
+                }
+                let path: String = shellexpand::tilde(command.args).into();
+                buf.open(&path);
+                Transition::Return(Some(format!("Opened {}", path)), false)
+            }
+            name if name == "new" || command::is_abbrev(name, "ene", "enew") => {
+                if buf.is_modified() && !command.bang {
+                    return Transition::Return(
+                        Some("No write since last change (add ! to override)".into()),
+                        false,
+                    );
+                }
+                buf.new_file();
+                Transition::Return(None, false)
+            }
+            "recent" => {
+                let files = buf.recent.existing();
+                if files.is_empty() {
+                    Transition::Return(Some("No recent files".into()), false)
+                } else {
+                    Recent { files, index: 0 }.into()
+                }
+            }
+            name if command::is_abbrev(name, "mes", "messages") => {
+                ViewText::new(buf.messages.iter().cloned().collect()).into()
+            }
+            name if command::is_abbrev(name, "s", "substitute") => {
+                Self::substitute(buf, rows.unwrap_or(current_line), command.args)
+            }
+            name if command::is_abbrev(name, "norm", "normal") => Transition::Keys(
+                rows.unwrap_or(current_line),
+                command::parse_keys(command.raw_args),
+            ),
+            // `:&&` keeps the flags
+            "&" => {
+                let message =
+                    repeat_substitute(buf, rows.unwrap_or(current_line), command.args == "&");
+                Transition::Return(Some(message), false)
+            }
+            name if command::is_abbrev(name, "sor", "sort") => {
+                let (first, last) = rows.unwrap_or(whole);
+                // `:sort [i] [/pattern/]`
+                let (flags, pattern) = match command.args.find('/') {
+                    Some(i) => (
+                        &command.args[..i],
+                        command::split_pattern(&command.args[i..])
+                            .and_then(|parts| parts.into_iter().next()),
+                    ),
+                    None => (command.args, None),
+                };
+                let pattern = pattern.filter(|p| !p.is_empty());
+                let key = match pattern.map(|p| regex::Regex::new(&p)) {
+                    Some(Ok(re)) => Some(re),
+                    Some(Err(err)) => return Transition::Return(Some(err.to_string()), false),
+                    None => None,
+                };
+                buf.sort_lines(first, last, command.bang, flags.contains('i'), key.as_ref());
+                buf.core.commit();
+                Transition::Return(None, false)
+            }
+            name if command::is_abbrev(name, "pu", "put") => {
+                let register = command.args.chars().next().unwrap_or('"');
+                let line = command
+                    .range
+                    .and_then(|range| buf.range_line(range))
+                    .unwrap_or(current_line.0 + 1);
+                if let Some(yank) = buf.get_yank(Some(register)).cloned() {
+                    buf.put_lines(line, &yank.content);
+                    buf.core.commit();
+                    buf.show_cursor();
+                    Transition::Return(None, false)
+                } else {
+                    Transition::Return(Some(format!("Nothing in register {}", register)), false)
+                }
+            }
+            name if command::is_abbrev(name, "fo", "fold") => {
+                let (first, last) = rows.unwrap_or(current_line);
+                buf.add_fold(first, last);
+                Transition::Return(None, false)
+            }
+            "AnsiStrip" => {
+                let (first, last) = rows.unwrap_or(whole);
+                let changed = buf.strip_ansi(first, last);
+                buf.core.commit();
+                Transition::Return(Some(format!("{} lines changed", changed)), false)
+            }
+            "Align" => {
+                if command.raw_args.is_empty() {
+                    return Transition::Return(Some("No delimiter".into()), false);
+                }
+                let (first, last) = rows.unwrap_or(whole);
+                let changed = buf.align(first, last, command.raw_args, command.bang);
+                buf.core.commit();
+                Transition::Return(Some(format!("{} lines changed", changed)), false)
+            }
+            name if command::is_abbrev(name, "rev", "reverse") => {
+                let (first, last) = rows.unwrap_or(whole);
+                buf.reverse_lines(first, last);
+                buf.core.commit();
+                Transition::Return(None, false)
+            }
+            name if name == "t"
+                || command::is_abbrev(name, "m", "move")
+                || command::is_abbrev(name, "co", "copy") =>
+            {
+                let (first, last) = rows.unwrap_or(current_line);
+                let line = match command::parse_address(command.args) {
+                    (Some(address), "") => buf.address_line(address),
+                    _ => None,
+                };
+                let line = if let Some(line) = line {
+                    line
+                } else {
+                    return Transition::Return(Some("Invalid address".into()), false);
+                };
+                if name.starts_with('m') {
+                    if !buf.move_lines(first, last, line) {
+                        let message = "Cannot move a range of lines into itself";
+                        return Transition::Return(Some(message.into()), false);
+                    }
+                } else {
+                    buf.copy_lines(first, last, line);
+                }
+                buf.core.commit();
+                buf.show_cursor();
+                Transition::Return(None, false)
+            }
+            "fmt" => match buf.format_with_error() {
+                Ok(true) => {
+                    buf.core.commit();
+                    buf.show_cursor();
+                    Transition::Return(Some("Formatted".into()), false)
+                }
+                Ok(false) => Transition::Return(Some("No formatter for this file".into()), false),
+                Err(err) => {
+                    let mut lines: Vec<String> = err.lines().map(String::from).collect();
+                    if lines.is_empty() {
+                        lines.push("Formatter failed".into());
+                    }
+                    ViewText::new(lines).into()
+                }
+            },
+            "!" => {
+                let mut shell = process::Command::new("sh");
+                shell.arg("-c").arg(command.args);
+                if let Some((first, last)) = rows {
+                    let message = if buf.filter_lines(first, last, shell) {
+                        buf.core.commit();
+                        None
+                    } else {
+                        Some(format!("Failed to run {}", command.args))
+                    };
+                    Transition::Return(message, false)
+                } else if let Some(view) = shell
+                    .stdout(process::Stdio::piped())
+                    .stderr(process::Stdio::piped())
+                    .spawn()
+                    .ok()
+                    .and_then(ViewProcess::with_process)
+                {
+                    view.into()
+                } else {
+                    Transition::Return(Some(format!("Failed to run {}", command.args)), false)
+                }
+            }
+            name => Transition::Return(Some(format!("Not an editor command: {}", name)), false),
+        }
+    }
+}
+
+impl Mode for Command {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Up)
+            | Event::Key(Key::Down)
+            | Event::Key(Key::Ctrl('p'))
+            | Event::Key(Key::Ctrl('n')) => {
+                let up = event == Event::Key(Key::Up) || event == Event::Key(Key::Ctrl('p'));
+                if let Some(entry) = self.recall.recall(&buf.command_history, &self.command, up) {
+                    self.command = entry;
+                    self.update_preview(buf);
+                }
+                return Transition::Nothing;
+            }
+            _ => self.recall.index = None,
+        }
+        if self.register_pending {
+            self.register_pending = false;
+            if let Event::Key(Key::Char(c)) = event {
+                if let Some(text) = register_line(buf, c) {
+                    self.command.push_str(&text);
+                }
+            }
+            self.update_preview(buf);
+            return Transition::Nothing;
+        }
+        match event {
+            Event::Key(Key::Esc) => {
+                buf.preview.clear();
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Ctrl('r')) => {
+                self.register_pending = true;
+            }
+            Event::Key(Key::Backspace) => {
+                if self.command.is_empty() {
+                    buf.preview.clear();
+                    return Transition::Return(None, false);
+                }
+                self.command.pop();
+            }
+            Event::Key(Key::Char('\n')) => {
+                buf.preview.clear();
+                buf.command_history.push(&self.command);
+                return self.run(buf);
+            }
+            Event::Key(Key::Ctrl('w')) => {
+                path_completion::delete_component(&mut self.command);
+            }
+            // Complete the path of `:e` and `:w`
+            Event::Key(Key::Char('\t')) => {
+                let command = command::parse(&self.command);
+                if command.name == "e" || command.name == "w" {
+                    let len = self.command.len() - command.raw_args.len();
+                    let (name, path) = self.command.split_at(len);
+                    let space = if name.ends_with(' ') { "" } else { " " };
+                    if let Some(path) = self.completion.complete(path) {
+                        self.command = format!("{}{}{}", name, space, path);
+                    }
+                }
+            }
+            Event::Key(Key::Char(c)) => {
+                self.command.push(c);
+            }
+            _ => {}
+        }
+        self.update_preview(buf);
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height - 1;
+        let width = term.width;
+        buf.draw(term.view((0, 0), height, width));
+
+        let mut footer = term.view((height, 0), 1, width);
+        footer.put(':', draw::styles::DEFAULT, None);
+        footer.puts(&self.command, draw::styles::DEFAULT);
+        term.cursor = draw::CursorState::Show(
+            Cursor {
+                row: height,
+                col: min(width - 1, self.command.chars().count() + 1),
+            },
+            draw::CursorShape::Bar,
+        );
+    }
+}
+
+impl Mode for ViewText {
+    fn event(&mut self, _buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(_) => Transition::Return(None, false),
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+                if self.row_offset <= 3 {
+                    self.row_offset = 0;
+                } else {
+                    self.row_offset -= 3;
+                }
+                Transition::Nothing
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+                if !self.lines.is_empty() {
+                    self.row_offset = min(self.lines.len() - 1, self.row_offset + 3);
+                }
+                Transition::Nothing
+            }
+            _ => Transition::Nothing,
+        }
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height;
+        let width = term.width;
+        term.cursor = draw::CursorState::Hide;
+        {
+            let mut view = term.view((0, 0), height - 1, width);
+            for line in &self.lines[self.row_offset..] {
+                for c in line.chars() {
+                    view.put_inline(c, draw::styles::DEFAULT, None);
+                }
+                view.newline();
+            }
+        }
+        {
+            let mut view = term.view((height - 1, 0), 1, width);
+            view.puts("Press any key to return", draw::styles::FOOTER);
+        }
+    }
+}
+
+impl Mode for HexView {
+    fn event(&mut self, _buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let last = self.lines.len().saturating_sub(1);
+        match event {
+            Event::Key(Key::Esc) | Event::Key(Key::Char('q')) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Char('j'))
+            | Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+                self.row_offset = min(last, self.row_offset + 1);
+            }
+            Event::Key(Key::Char('k'))
+            | Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+                self.row_offset = self.row_offset.saturating_sub(1);
+            }
+            Event::Key(Key::Ctrl('d')) => {
+                self.row_offset = min(last, self.row_offset + 16);
+            }
+            Event::Key(Key::Ctrl('u')) => {
+                self.row_offset = self.row_offset.saturating_sub(16);
+            }
+            Event::Key(Key::Char('g')) => {
+                self.row_offset = 0;
+            }
+            Event::Key(Key::Char('G')) => {
+                self.row_offset = last;
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height;
+        let width = term.width;
+        term.cursor = draw::CursorState::Hide;
+        {
+            let mut view = term.view((0, 0), height - 1, width);
+            for line in self.lines.iter().skip(self.row_offset) {
+                for c in line.chars() {
+                    view.put_inline(c, draw::styles::DEFAULT, None);
+                }
+                view.newline();
+            }
+        }
+        term.view((height - 1, 0), 1, width).puts(
+            "[Hex] j/k, Ctrl-d/u, g/G to scroll, Esc or q to return",
+            draw::styles::FOOTER,
+        );
+    }
+}
+
+impl QuickFix {
+    const HEIGHT: usize = 10;
+}
+
+impl Mode for QuickFix {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Char('j')) | Event::Key(Key::Down) => {
+                let i = min(buf.quickfix_index + 1, buf.quickfix.len() - 1);
+                buf.quickfix_select(i);
+            }
+            Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                let i = buf.quickfix_index.saturating_sub(1);
+                buf.quickfix_select(i);
+            }
+            Event::Key(Key::Char('\n')) => {
+                let message = buf.quickfix_select(buf.quickfix_index);
+                return Transition::Return(message, false);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let list_height = min(Self::HEIGHT, buf.quickfix.len());
+        let height = term.height.saturating_sub(list_height + 1);
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let mut view = term.view((height, 0), term.height - height, width);
+        let row_offset = (buf.quickfix_index + 1).saturating_sub(list_height);
+        for (i, (row, col, line)) in buf
+            .quickfix
+            .iter()
+            .enumerate()
+            .skip(row_offset)
+            .take(list_height)
+        {
+            let style = if i == buf.quickfix_index {
+                draw::styles::HIGHLIGHT
+            } else {
+                draw::styles::DEFAULT
+            };
+            for c in format!("{}:{}: {}", row + 1, col + 1, line.trim()).chars() {
+                view.put_inline(c, style, None);
+            }
+            view.newline();
+        }
+        view.puts(
+            "Quickfix ... [j/k: Select] [Enter: Jump] [Esc: Close]",
+            draw::styles::FOOTER,
+        );
+    }
+}
+
+impl Mode for SpellSuggest {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Char('j')) | Event::Key(Key::Down) => {
+                self.index = min(self.index + 1, self.candidates.len() - 1);
+            }
+            Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                self.index = self.index.saturating_sub(1);
+            }
+            Event::Key(Key::Char('\n')) => {
+                let row = buf.core.cursor().row;
+                buf.core.delete_range(CursorRange(
+                    Cursor {
+                        row,
+                        col: self.start,
+                    },
+                    Cursor {
+                        row,
+                        col: self.end - 1,
+                    },
+                ));
+                buf.core.set_cursor(Cursor {
+                    row,
+                    col: self.start,
+                });
+                for c in self.candidates[self.index].chars() {
+                    buf.core.insert(c);
+                }
+                buf.core.set_cursor(Cursor {
+                    row,
+                    col: self.start,
+                });
+                buf.core.commit();
+                return Transition::Return(None, true);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height;
+        let width = term.width;
+        term.cursor = draw::CursorState::Hide;
+        {
+            let mut view = term.view((0, 0), height - 1, width);
+            let row_offset = (self.index + 2).saturating_sub(height);
+            for (i, word) in self.candidates.iter().enumerate().skip(row_offset) {
+                let style = if i == self.index {
+                    draw::styles::HIGHLIGHT
+                } else {
+                    draw::styles::DEFAULT
+                };
+                for c in format!("{} {}", i + 1, word).chars() {
+                    view.put_inline(c, style, None);
+                }
+                view.newline();
+            }
+        }
+        {
+            let mut view = term.view((height - 1, 0), 1, width);
+            view.puts(
+                "Suggestions: j/k to select, Enter to replace",
+                draw::styles::FOOTER,
+            );
+        }
+    }
+}
+
+impl Mode for Recent {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Char('j')) | Event::Key(Key::Down) => {
+                self.index = min(self.index + 1, self.files.len() - 1);
+            }
+            Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                self.index = self.index.saturating_sub(1);
+            }
+            Event::Key(Key::Char('\n')) => {
+                if buf.is_modified() {
+                    return Transition::Return(Some("No write since last change".into()), false);
+                }
+                let path = self.files[self.index].clone();
+                buf.open(&path);
+                return Transition::Return(Some(format!("Opened {}", path.display())), false);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height;
+        let width = term.width;
+        term.cursor = draw::CursorState::Hide;
+        {
+            let mut view = term.view((0, 0), height - 1, width);
+            let row_offset = (self.index + 2).saturating_sub(height);
+            for (i, file) in self.files.iter().enumerate().skip(row_offset) {
+                let style = if i == self.index {
+                    draw::styles::HIGHLIGHT
+                } else {
+                    draw::styles::DEFAULT
+                };
+                for c in file.display().to_string().chars() {
+                    view.put_inline(c, style, None);
+                }
+                view.newline();
+            }
+        }
+        {
+            let mut view = term.view((height - 1, 0), 1, width);
+            view.puts(
+                "Recent files: j/k to select, Enter to open",
+                draw::styles::FOOTER,
+            );
+        }
+    }
+}
+
+impl Mode for Save {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Backspace) => {
+                self.path.pop();
+            }
+            Event::Key(Key::Ctrl('w')) => {
+                path_completion::delete_component(&mut self.path);
+            }
+            Event::Key(Key::Char('\t')) => {
+                if let Some(path) = self.completion.complete(&self.path) {
+                    self.path = path;
+                }
+            }
+            Event::Key(Key::Char(c)) => {
+                if c == '\n' {
+                    let path: String = shellexpand::tilde(&self.path).into();
+                    let message = if buf.save_as(PathBuf::from(&path)) {
+                        format!("Saved to {}", path)
+                    } else {
+                        format!("Failed to save {}", path)
+                    };
+                    return Normal::with_message(message).into();
+                }
+                self.path.push(c);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        if term.height < 2 {
+            return;
+        }
+        let height = term.height - 2;
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let mut footer = term.view((height, 0), 2, width);
+        footer.puts(
+            &std::env::current_dir().unwrap().to_string_lossy(),
+            draw::styles::UI,
+        );
+        footer.newline();
+        footer.puts("> ", draw::styles::UI);
+        footer.puts(&self.path, draw::styles::UI);
+    }
+}
+
+impl Mode for DiskChanged {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Char('y')) => {
+                buf.reload();
+                let path = buf.path().map(|p| p.display().to_string());
+                Transition::Return(path.map(|p| format!("Reloaded {}", p)), false)
+            }
+            Event::Key(Key::Char('n')) | Event::Key(Key::Esc) => {
+                buf.ignore_disk_change(self.mtime);
+                Transition::Return(None, false)
+            }
+            _ => Transition::Nothing,
         }
-        Transition::Nothing
     }
 
     fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        if term.height < 2 {
+            return;
+        }
         let height = term.height - 1;
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height, 0), 1, width);
-        footer.put('/', draw::styles::DEFAULT, None);
-        for &c in &buf.search {
-            footer.put(c, draw::styles::DEFAULT, None);
-        }
+        footer.puts(
+            "File changed on disk. Reload and discard changes? (y/n)",
+            draw::styles::FOOTER,
+        );
     }
 }
 
-impl Mode for Save {
-    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+impl Mode for QuitConfirm {
+    fn event(&mut self, _buf: &mut Buffer, event: termion::event::Event) -> Transition {
         match event {
-            Event::Key(Key::Esc) => {
-                return Transition::Return(None, false);
-            }
-            Event::Key(Key::Backspace) => {
-                self.path.pop();
-            }
-            Event::Key(Key::Char(c)) => {
-                if c == '\n' {
-                    let path: String = shellexpand::tilde(&self.path).into();
-                    buf.set_path(PathBuf::from(path.clone()));
-                    let message = if buf.save(false) {
-                        format!("Saved to {}", path)
-                    } else {
-                        format!("Failed to save {}", path)
-                    };
-                    return Normal::with_message(message).into();
-                }
-                self.path.push(c);
-            }
-            _ => {}
+            Event::Key(Key::Char('y')) => Transition::Exit,
+            Event::Key(Key::Char('n')) | Event::Key(Key::Esc) => Transition::Return(None, false),
+            _ => Transition::Nothing,
         }
-        Transition::Nothing
     }
 
     fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
         if term.height < 2 {
             return;
         }
-        let height = term.height - 2;
+        let height = term.height - 1;
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
-        let mut footer = term.view((height, 0), 2, width);
+        let mut footer = term.view((height, 0), 1, width);
         footer.puts(
-            &std::env::current_dir().unwrap().to_string_lossy(),
-            draw::styles::UI,
+            "No write since last change. Quit anyway? (y/n)",
+            draw::styles::FOOTER,
         );
-        footer.newline();
-        footer.puts("> ", draw::styles::UI);
-        footer.puts(&self.path, draw::styles::UI);
     }
 }
 
+// Next keys after `keys` and what they do, for the footer of `Prefix`
+fn leader_hints(mappings: &BTreeMap<String, String>, keys: &str) -> String {
+    let mut hints = BTreeMap::new();
+    for (sequence, command) in mappings {
+        let mut rest = match sequence.strip_prefix(keys) {
+            Some(rest) => rest.chars(),
+            None => continue,
+        };
+        if let Some(c) = rest.next() {
+            let hint = if rest.next().is_none() {
+                command.as_str()
+            } else {
+                "..."
+            };
+            hints.entry(c).or_insert(hint);
+        }
+    }
+    hints
+        .into_iter()
+        .map(|(c, hint)| format!(" [{}: {}]", c, hint))
+        .collect()
+}
+
 impl Mode for Prefix {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        // Leader mappings take precedence over the keys below
+        if let Event::Key(Key::Char(c)) = event {
+            let mappings = buf.leader_mappings();
+            let mut keys = self.keys.clone();
+            keys.push(c);
+            if let Some(command) = mappings.get(&keys) {
+                let command = Command {
+                    command: command.trim_start_matches(':').into(),
+                    ..Default::default()
+                };
+                return command.run(buf);
+            }
+            if mappings.keys().any(|sequence| sequence.starts_with(&keys)) {
+                self.keys = keys;
+                return Transition::Nothing;
+            }
+            if !self.keys.is_empty() {
+                return Transition::Return(None, false);
+            }
+        }
         match event {
             Event::Key(Key::Esc) => {
                 return Transition::Return(None, false);
@@ -937,7 +3152,7 @@ impl Mode for Prefix {
                 return Transition::Return(None, false);
             }
             Event::Key(Key::Char('q')) => {
-                return Transition::Exit;
+                return quit(buf);
             }
             Event::Key(Key::Char('g')) => {
                 return Goto::default().into();
@@ -952,23 +3167,18 @@ impl Mode for Prefix {
                     };
                     return Transition::Return(Some(message), false);
                 } else {
-                    return Save {
-                        path: String::new(),
-                    }
-                    .into();
+                    return Save::default().into();
                 }
             }
             Event::Key(Key::Char('a')) => {
                 if let Some(path) = buf.path() {
                     return Save {
                         path: path.to_string_lossy().into(),
+                        ..Default::default()
                     }
                     .into();
                 } else {
-                    return Save {
-                        path: String::new(),
-                    }
-                    .into();
+                    return Save::default().into();
                 }
             }
             Event::Key(Key::Char('y')) => {
@@ -1038,7 +3248,62 @@ impl Mode for Prefix {
             }
             _ => {}
         }
-        Transition::Nothing
+        // Unknown keys
+        Transition::Return(None, false)
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height - 1;
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let hints = leader_hints(&buf.leader_mappings(), &self.keys);
+        let mut footer = term.view((height, 0), 1, width);
+        if self.keys.is_empty() {
+            footer.puts(
+                &format!(
+                    "Prefix ... [Esc: Return] [q: Quit] [s: Save] [a: save As ...] [<Space> Format]{}",
+                    hints
+                ),
+                draw::styles::FOOTER,
+            );
+        } else {
+            footer.puts(
+                &format!("Prefix {} ...{}", self.keys, hints),
+                draw::styles::FOOTER,
+            );
+        }
+    }
+}
+
+impl Mode for WindowPrefix {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
+    fn event(&mut self, _buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let count = self.count.unwrap_or(1) as isize;
+        match event {
+            Event::Key(Key::Char(c)) | Event::Key(Key::Ctrl(c)) => match c {
+                'h' | 'j' | 'k' | 'l' | 'w' => Transition::Window(WindowCommand::Focus(c)),
+                '1'..='9' => Transition::Window(WindowCommand::Goto(c as usize - '0' as usize)),
+                's' => Transition::Window(WindowCommand::Split),
+                'v' => Transition::Window(WindowCommand::VSplit),
+                'c' => Transition::Window(WindowCommand::Close),
+                'q' => Transition::Window(WindowCommand::Quit),
+                'o' => Transition::Window(WindowCommand::Only),
+                '=' => Transition::Window(WindowCommand::Equalize),
+                '+' => Transition::Window(WindowCommand::Resize(count)),
+                '-' => Transition::Window(WindowCommand::Resize(-count)),
+                '>' => Transition::Window(WindowCommand::VResize(count)),
+                '<' => Transition::Window(WindowCommand::VResize(-count)),
+                _ => Transition::Return(None, false),
+            },
+            _ => Transition::Return(None, false),
+        }
     }
 
     fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
@@ -1046,15 +3311,22 @@ impl Mode for Prefix {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height, 0), 1, width);
         footer.puts(
-            "Prefix ... [Esc: Return] [q: Quit] [s: Save] [a: save As ...] [<Space> Format]",
+            "Window ... [hjkl: Focus] [1-9: Go to] [s: Split] [v: Vertical split] [c: Close] [q: Quit] [=+-<>: Resize]",
             draw::styles::FOOTER,
         );
     }
+
+    fn showcmd(&self) -> String {
+        format!(
+            "{}^W",
+            self.count.map(|c| c.to_string()).unwrap_or_default()
+        )
+    }
 }
 
 impl Visual {
@@ -1075,10 +3347,82 @@ impl Visual {
 
 impl Mode for Visual {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        // Keep `'<` and `'>` marks and `gv` selection up to date
+        let range = self.get_range(buf.core.cursor(), buf.core.buffer());
+        buf.marks.insert('<', range.l());
+        buf.marks.insert('>', range.r());
+        buf.last_visual = Some(VisualSelection {
+            anchor: self.cursor,
+            cursor: buf.core.cursor(),
+            line_mode: self.line_mode,
+        });
+
+        match (self.prefix.take(), event.clone()) {
+            (Some('"'), Event::Key(Key::Char(c))) => {
+                if c.is_alphanumeric() || c == '"' || c == '-' || c == '.' {
+                    self.register = Some(c);
+                }
+                return Transition::Nothing;
+            }
+            (Some('g'), Event::Key(Key::Char('g'))) => {
+                buf.core.set_cursor(Cursor { row: 0, col: 0 });
+                buf.show_cursor();
+                return Transition::Nothing;
+            }
+            // `vip` and `vap` select the lines
+            (Some(c), Event::Key(Key::Char('p'))) if c == 'i' || c == 'a' => {
+                if let Some((first, last)) = text_object::paragraph(&buf.core, c == 'a') {
+                    self.cursor = Cursor { row: first, col: 0 };
+                    self.line_mode = true;
+                    buf.core.set_cursor(Cursor { row: last, col: 0 });
+                    buf.show_cursor();
+                }
+                return Transition::Nothing;
+            }
+            (Some('z'), Event::Key(Key::Char('f'))) => {
+                let range = self.get_range(buf.core.cursor(), buf.core.buffer());
+                buf.add_fold(range.l().row, range.r().row);
+                buf.core.set_cursor(range.l());
+                buf.show_cursor();
+                return Transition::Return(None, false);
+            }
+            (Some('g'), Event::Key(Key::Char('c'))) => {
+                let range = self.get_range(buf.core.cursor(), buf.core.buffer());
+                buf.toggle_comment(range.l().row, range.r().row);
+                buf.core.commit();
+                let cursor = buf.core.clamp_cursor(Cursor {
+                    row: range.l().row,
+                    col: 0,
+                });
+                buf.core.set_cursor(cursor);
+                buf.show_cursor();
+                return Transition::Return(None, false);
+            }
+            (Some(_), _) => {
+                return Transition::Nothing;
+            }
+            (None, _) => {}
+        }
+
         match event {
             Event::Key(Key::Esc) => {
                 return Transition::Return(None, false);
             }
+            Event::Key(Key::Char('"')) => {
+                self.prefix = Some('"');
+            }
+            Event::Key(Key::Char(c)) if c == 'g' || c == 'z' || c == 'i' || c == 'a' => {
+                self.prefix = Some(c);
+            }
+            Event::Key(Key::Char(':')) => {
+                return Command::with_range().into();
+            }
+            Event::Key(Key::Char('o')) => {
+                let anchor = self.cursor;
+                self.cursor = buf.core.cursor();
+                buf.core.set_cursor(anchor);
+                buf.show_cursor();
+            }
             Event::Key(Key::Char('h')) => {
                 buf.core.cursor_left();
                 buf.show_cursor();
@@ -1107,10 +3451,6 @@ impl Mode for Visual {
                 buf.core.e();
                 buf.show_cursor();
             }
-            Event::Key(Key::Char('g')) => {
-                buf.core.set_cursor(Cursor { row: 0, col: 0 });
-                buf.show_cursor();
-            }
             Event::Key(Key::Char('G')) => {
                 let row = buf.core.buffer().len_lines() - 1;
                 let col = buf.core.buffer().l(row).len_chars();
@@ -1136,8 +3476,13 @@ impl Mode for Visual {
                     buf.indent();
                 }
                 buf.core.commit();
-                buf.yank.insert_newline = self.line_mode;
-                buf.yank.content = s;
+                buf.set_delete(
+                    self.register,
+                    Yank {
+                        insert_newline: self.line_mode,
+                        content: s,
+                    },
+                );
 
                 buf.show_cursor();
                 return if to_insert {
@@ -1156,8 +3501,8 @@ impl Mode for Visual {
                             buf.core.insert(c);
                         }
                     }
-                } else {
-                    for c in buf.yank.content.chars() {
+                } else if let Some(yank) = buf.get_yank(self.register).cloned() {
+                    for c in yank.content.chars() {
                         buf.core.insert(c);
                     }
                 }
@@ -1177,8 +3522,13 @@ impl Mode for Visual {
                 if is_clipboard {
                     clipboard::clipboard_copy(&s);
                 } else {
-                    buf.yank.insert_newline = self.line_mode;
-                    buf.yank.content = s;
+                    buf.set_yank(
+                        self.register,
+                        Yank {
+                            insert_newline: self.line_mode,
+                            content: s,
+                        },
+                    );
                 }
                 return Transition::Return(Some("Yanked".into()), false);
             }
@@ -1187,31 +3537,13 @@ impl Mode for Visual {
                 return S(range).into();
             }
             Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
-                let col = x as usize - 1;
-                let row = y as usize - 1;
-                let cursor = Cursor { row, col };
-
-                let mut term = draw::Term::default();
-                let height = term.height;
-                let width = term.width;
-                buf.draw(term.view((0, 0), height, width));
-
-                if let Some(c) = term.pos(cursor) {
+                if let Some(c) = mouse_position(buf, x, y, 0) {
                     buf.core.set_cursor(c);
                 }
                 return Normal::default().into();
             }
             Event::Mouse(MouseEvent::Hold(x, y)) => {
-                let col = x as usize - 1;
-                let row = y as usize - 1;
-                let cursor = Cursor { row, col };
-
-                let mut term = draw::Term::default();
-                let height = term.height;
-                let width = term.width;
-                buf.draw(term.view((0, 0), height, width));
-
-                if let Some(c) = term.pos(cursor) {
+                if let Some(c) = mouse_position(buf, x, y, 0) {
                     buf.core.set_cursor(c);
                 }
             }
@@ -1226,8 +3558,27 @@ impl Mode for Visual {
         let range = self.get_range(buf.core.cursor(), buf.core.buffer());
         let cursor = buf.draw_with_selected(term.view((0, 0), height, width), Some(range));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.visual_cursor()))
             .unwrap_or(draw::CursorState::Hide);
+
+        // Show where the selection goes off the screen. The marks are left of the scrollbar.
+        let width = if buf.scrollbar {
+            width.saturating_sub(1)
+        } else {
+            width
+        };
+        if height > 0 && width > 0 {
+            // `range` is not ordered in charwise Visual
+            let above = range.l().row < buf.row_offset();
+            let below = term
+                .pos(Cursor {
+                    row: height - 1,
+                    col: width - 1,
+                })
+                .is_some_and(|last| range.r() > last);
+            term.view((0, 0), height, width)
+                .selection_edges(above, below);
+        }
     }
 }
 
@@ -1286,52 +3637,215 @@ impl Mode for ViewProcess {
     }
 }
 
+// Move by `motion` `count` times and push a jump. Returns false if it does not move.
+fn repeat_jump<F: Fn(&Core) -> Option<Cursor>>(
+    buf: &mut Buffer,
+    count: Option<usize>,
+    motion: F,
+) -> bool {
+    let orig = buf.core.cursor();
+    let mut moved = false;
+    for _ in 0..count.unwrap_or(1) {
+        match motion(&buf.core) {
+            Some(cursor) => {
+                buf.core.set_cursor(cursor);
+                moved = true;
+            }
+            None => break,
+        }
+    }
+    if moved {
+        let target = buf.core.cursor();
+        buf.core.set_cursor(orig);
+        buf.push_jump();
+        buf.core.set_cursor(target);
+        buf.show_cursor();
+    }
+    moved
+}
+
+// `:wa`. The editor has one buffer, which all windows share, so this is `:w` if it is
+// modified.
+fn save_all(buf: &mut Buffer) -> Result<String, String> {
+    if !buf.is_modified() {
+        return Ok("No changes to save".into());
+    }
+    match buf.path().map(|p| p.display().to_string()) {
+        Some(path) => {
+            if buf.save(false) {
+                Ok(format!("Saved to {}", path))
+            } else {
+                Err(format!("Failed to save {}", path))
+            }
+        }
+        None => Err("No file name".into()),
+    }
+}
+
+// Rows of a linewise motion typed after an operator like `gq`. `Err` while it is incomplete.
+fn motion_rows(
+    buf: &Buffer,
+    keys: &str,
+    operator: char,
+    count: Option<usize>,
+) -> Result<Option<(usize, usize)>, ()> {
+    let row = buf.core.cursor().row;
+    let len_lines = buf.core.buffer().len_lines();
+    let count = count.unwrap_or(1);
+    Ok(match keys {
+        "i" | "a" => return Err(()),
+        "ip" | "ap" => text_object::paragraph(&buf.core, keys == "ap"),
+        "j" => Some((row, min(row + count, len_lines - 1))),
+        "k" => Some((row.saturating_sub(count), row)),
+        _ if keys.len() == 1 && keys.starts_with(operator) => {
+            Some((row, min(row + max(1, count) - 1, len_lines - 1)))
+        }
+        _ => None,
+    })
+}
+
+impl Mode for Comment {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let c = match event {
+            Event::Key(Key::Char(c)) => c,
+            _ => return Transition::Return(None, false),
+        };
+        self.keys.push(c);
+        let rows = match motion_rows(buf, &self.keys, 'c', self.count) {
+            Ok(rows) => rows,
+            Err(()) => return Transition::Nothing,
+        };
+        if let Some((first, last)) = rows {
+            buf.toggle_comment(first, last);
+            buf.core.commit();
+            let cursor = buf.core.clamp_cursor(Cursor { row: first, col: 0 });
+            buf.core.set_cursor(cursor);
+            buf.show_cursor();
+        }
+        Transition::Return(None, false)
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height - 1;
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+        term.view((height, 0), 1, width)
+            .puts("Comment ", draw::styles::FOOTER);
+        draw_showcmd(term, &self.showcmd());
+    }
+
+    fn showcmd(&self) -> String {
+        format!(
+            "{}gc{}",
+            self.count.map(|c| c.to_string()).unwrap_or_default(),
+            self.keys
+        )
+    }
+}
+
+impl Mode for Reflow {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let c = match event {
+            Event::Key(Key::Char(c)) => c,
+            _ => return Transition::Return(None, false),
+        };
+        self.keys.push(c);
+        let rows = match motion_rows(buf, &self.keys, self.operator(), self.count) {
+            Ok(rows) => rows,
+            Err(()) => return Transition::Nothing,
+        };
+        if let Some((first, last)) = rows {
+            let cursor = buf.core.cursor();
+            let len = buf.reflow_lines(first, last);
+            buf.core.commit();
+            let cursor = if self.keep_cursor {
+                cursor
+            } else {
+                Cursor {
+                    row: first + len - 1,
+                    col: 0,
+                }
+            };
+            let cursor = buf.core.clamp_cursor(cursor);
+            buf.core.set_cursor(cursor);
+            buf.show_cursor();
+        }
+        Transition::Return(None, false)
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
+        let height = term.height - 1;
+        let width = term.width;
+        let cursor = buf.draw(term.view((0, 0), height, width));
+        term.cursor = cursor
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
+            .unwrap_or(draw::CursorState::Hide);
+        term.view((height, 0), 1, width)
+            .puts("Reflow ", draw::styles::FOOTER);
+        draw_showcmd(term, &self.showcmd());
+    }
+
+    fn showcmd(&self) -> String {
+        format!(
+            "{}g{}{}",
+            self.count.map(|c| c.to_string()).unwrap_or_default(),
+            self.operator(),
+            self.keys
+        )
+    }
+}
+
 impl Mode for TextObjectOperation {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         if event == Event::Key(Key::Esc) {
             return Transition::Return(None, false);
         }
         if let Event::Key(Key::Char(c)) = event {
+            self.keys.push(c);
+            if let Some(kind) = self.mark.take() {
+                return self.operate_to_mark(buf, kind == '\'', c);
+            }
+            // Not `di'` or `dt'`
+            if self.keys.len() == 1 && (c == '\'' || c == '`') {
+                self.mark = Some(c);
+                return Transition::Nothing;
+            }
             if c == self.parser.action.to_char() {
-                // Yank current line
-                buf.yank = Yank {
-                    insert_newline: true,
-                    content: String::from(buf.core.current_line()),
-                };
-                match self.parser.action {
-                    // dd
-                    Action::Delete => {
-                        let range = CursorRange(
-                            Cursor {
-                                row: buf.core.cursor().row,
-                                col: 0,
-                            },
-                            Cursor {
-                                row: buf.core.cursor().row,
-                                col: buf.core.current_line().len_chars(),
-                            },
-                        );
-                        buf.core.delete_range(range);
-                        buf.core.commit();
-                        return Transition::Return(None, true);
-                    }
-                    Action::Yank => {
-                        return Transition::Return(None, false);
-                    }
-                    Action::Change => {
-                        let pos = buf.core.cursor();
-                        buf.core.set_cursor(Cursor {
-                            row: pos.row,
-                            col: 0,
-                        });
-                        for _ in 0..buf.core.current_line().len_chars() {
-                            buf.core.delete();
-                        }
-                        buf.core.commit();
-                        buf.indent();
-                        return Insert::default().into();
-                    }
+                // dd, yy and cc
+                let row = buf.core.cursor().row;
+                let last = min(
+                    row + max(1, self.count.unwrap_or(1)) - 1,
+                    buf.core.buffer().len_lines() - 1,
+                );
+                return self.operate_lines(buf, row, last);
+            }
+            // d+ and d- are linewise like dj and dk
+            if self.keys.len() == 1 && (c == '+' || c == '\n' || c == '-') {
+                let row = buf.core.cursor().row;
+                let n = max(1, self.count.unwrap_or(1));
+                let last = buf.core.buffer().len_lines() - 1;
+                if c == '-' && row > 0 {
+                    return self.operate_lines(buf, row.saturating_sub(n), row);
+                }
+                if c != '-' && row < last {
+                    return self.operate_lines(buf, row, min(row + n, last));
                 }
+                return Transition::Return(None, false);
             }
 
             if c == 'j' || c == 'k' {
@@ -1366,10 +3880,11 @@ impl Mode for TextObjectOperation {
                     )
                 };
 
-                buf.yank = Yank {
+                let yank = Yank {
                     insert_newline: true,
                     content: String::from(buf.core.get_slice_by_range(range).trim_end()),
                 };
+                self.save_yank(buf, yank);
                 match self.parser.action {
                     // dj or dk
                     Action::Delete => {
@@ -1390,33 +3905,30 @@ impl Mode for TextObjectOperation {
                 }
             }
 
-            if let Some(half_range) = self.parser.parse(c, &buf.core) {
-                if let Some(range) = half_range {
-                    buf.yank = Yank {
-                        insert_newline: false,
-                        content: String::from(buf.core.get_slice_by_range(range)),
-                    };
-                    match self.parser.action {
-                        Action::Delete => {
-                            buf.core.delete_range(range);
-                            buf.core.commit();
-                            return Transition::Return(None, true);
-                        }
-                        Action::Change => {
-                            buf.core.delete_range(range);
-                            buf.core.commit();
-                            return Insert::default().into();
-                        }
-                        Action::Yank => {
-                            return Transition::Return(None, false);
-                        }
-                    }
-                } else {
-                    return Transition::Return(None, true);
-                }
+            // `dip` and `dap` are linewise
+            if self.keys == "ip" || self.keys == "ap" {
+                return match text_object::paragraph(&buf.core, self.keys == "ap") {
+                    Some((first, last)) => self.operate_lines(buf, first, last),
+                    None => Transition::Return(None, false),
+                };
+            }
+
+            // A count after the operator is pending as before
+            if c.is_ascii_digit()
+                && self.parser.prefix
+                    == text_object::Prefix::TextObjectPrefix(text_object::TextObjectPrefix::None)
+            {
+                return Transition::Nothing;
             }
+
+            // An unknown key or a text object not found cancels the operator
+            return match self.parser.parse(c, &buf.core) {
+                Some(Some(range)) => self.operate_range(buf, range),
+                Some(None) => Transition::Return(None, false),
+                None => Transition::Nothing,
+            };
         }
-        Transition::Nothing
+        Transition::Return(None, false)
     }
 
     fn draw(&mut self, buf: &mut Buffer, term: &mut draw::Term) {
@@ -1424,7 +3936,7 @@ impl Mode for TextObjectOperation {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height, 0), 1, width);
@@ -1440,10 +3952,25 @@ impl Mode for TextObjectOperation {
                 footer.puts("Yank ", draw::styles::FOOTER);
             }
         }
+        draw_showcmd(term, &self.showcmd());
+    }
+
+    fn showcmd(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            register_showcmd(self.register),
+            self.count.map(|c| c.to_string()).unwrap_or_default(),
+            self.parser.action.to_char(),
+            self.keys
+        )
     }
 }
 
 impl Mode for S {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         match event {
             Event::Key(Key::Esc) => {
@@ -1480,12 +4007,16 @@ impl Mode for S {
         let range = self.0;
         let cursor = buf.draw_with_selected(term.view((0, 0), height, width), Some(range));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
     }
 }
 
 impl Mode for Find {
+    fn is_pending(&self) -> bool {
+        true
+    }
+
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         match event {
             Event::Key(Key::Esc) => {
@@ -1519,7 +4050,7 @@ impl Mode for Find {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height - 1, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height - 1, 0), 1, width);
@@ -1568,7 +4099,7 @@ impl Mode for Goto {
         let width = term.width;
         let cursor = buf.draw(term.view((0, 0), height, width));
         term.cursor = cursor
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .map(|c| draw::CursorState::Show(c, buf.normal_cursor()))
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = term.view((height, 0), 1, width);
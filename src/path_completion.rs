@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+// Tab completion of file paths in prompts. Repeated Tab cycles through the matches.
+#[derive(Default)]
+pub struct PathCompletion {
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl PathCompletion {
+    // Files in the directory of `input` whose names start with the rest of it.
+    // Directories end with `/` so that the next Tab descends into them.
+    fn candidates(input: &str) -> Vec<String> {
+        let (dir, name) = match input.rfind('/') {
+            Some(i) => input.split_at(i + 1),
+            None => ("", input),
+        };
+        let expanded = shellexpand::tilde(if dir.is_empty() { "." } else { dir });
+        let entries = match fs::read_dir(Path::new(expanded.as_ref())) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut res: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().into_string().ok()?;
+                // Hidden files only when asked for
+                if !file_name.starts_with(name) || (file_name.starts_with('.') && name.is_empty()) {
+                    return None;
+                }
+                let is_dir = entry.path().is_dir();
+                Some(format!(
+                    "{}{}{}",
+                    dir,
+                    file_name,
+                    if is_dir { "/" } else { "" }
+                ))
+            })
+            .collect();
+        res.sort();
+        res
+    }
+
+    // The completed `input`, or `None` if nothing matches
+    pub fn complete(&mut self, input: &str) -> Option<String> {
+        // A single match is completed again to descend into a directory
+        if self.candidates.len() > 1 && self.candidates[self.index] == input {
+            self.index = (self.index + 1) % self.candidates.len();
+        } else {
+            self.candidates = Self::candidates(input);
+            self.index = 0;
+        }
+        self.candidates.get(self.index).cloned()
+    }
+}
+
+// `Ctrl-w`. Delete the last component of a path, or the last argument of a command.
+pub fn delete_component(s: &mut String) {
+    let separators = &['/', ' '][..];
+    if s.ends_with(separators) {
+        s.pop();
+    }
+    let len = s.rfind(separators).map(|i| i + 1).unwrap_or(0);
+    s.truncate(len);
+}
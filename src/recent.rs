@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT_FILES: usize = 50;
+
+// Recently opened or saved files, newest first.
+#[derive(Default)]
+pub struct RecentFiles {
+    // Where the list is persisted. In memory only if `None`.
+    path: Option<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn load(path: PathBuf) -> Self {
+        let files = fs::read_to_string(&path)
+            .map(|s| {
+                s.lines()
+                    .filter(|l| !l.is_empty())
+                    .map(PathBuf::from)
+                    .take(MAX_RECENT_FILES)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            files,
+        }
+    }
+
+    pub fn push(&mut self, file: &Path) {
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        self.files.retain(|f| f != &file);
+        self.files.insert(0, file);
+        self.files.truncate(MAX_RECENT_FILES);
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Some(path) = self.path.as_ref() {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let s: String = self
+                .files
+                .iter()
+                .map(|f| format!("{}\n", f.display()))
+                .collect();
+            let _ = fs::write(path, s);
+        }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    // Files which still exist
+    pub fn existing(&self) -> Vec<PathBuf> {
+        self.files.iter().filter(|f| f.is_file()).cloned().collect()
+    }
+}
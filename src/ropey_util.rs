@@ -1,11 +1,23 @@
 use ropey::{Rope, RopeSlice};
+use std::cmp::min;
 
 pub trait RopeSliceExt {
     fn trim_end(self) -> Self;
 }
 
+// Lines longer than this are highlighted only up to here and drawn only for this many characters
+// from near the cursor
+pub const LONG_LINE: usize = 30_000;
+// The drawn part of a long line starts at a multiple of this before the cursor
+const LONG_LINE_STEP: usize = 1_000;
+
 pub trait RopeExt {
     fn l(&self, line_idx: usize) -> RopeSlice;
+    // `l` up to `LONG_LINE` characters
+    fn visible_line(&self, line_idx: usize) -> RopeSlice<'_>;
+    // Up to `LONG_LINE` characters of `l` drawn with the cursor at `col`, and the column they
+    // start at
+    fn visible_part(&self, line_idx: usize, col: usize) -> (usize, RopeSlice<'_>);
 }
 
 pub fn is_line_end(c: char) -> bool {
@@ -30,4 +42,19 @@ impl RopeExt for Rope {
     fn l(&self, line_idx: usize) -> RopeSlice {
         self.line(line_idx).trim_end()
     }
+
+    fn visible_line(&self, line_idx: usize) -> RopeSlice<'_> {
+        self.visible_part(line_idx, 0).1
+    }
+
+    fn visible_part(&self, line_idx: usize, col: usize) -> (usize, RopeSlice<'_>) {
+        let line = self.l(line_idx);
+        let len = line.len_chars();
+        if len > LONG_LINE {
+            let start = min(col, len) / LONG_LINE_STEP * LONG_LINE_STEP;
+            (start, line.slice(start..min(start + LONG_LINE, len)))
+        } else {
+            (0, line)
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+// Word list for `:set spell`. Only lowercase ASCII words are checked.
+#[derive(Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    pub fn from_words<I: IntoIterator<Item = S>, S: Into<String>>(words: I) -> Self {
+        Self {
+            words: words.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    // One word per line like `/usr/share/dict/words`
+    pub fn load(path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(path).ok()?;
+        Some(Self::from_words(
+            s.lines().map(str::trim).filter(|w| !w.is_empty()),
+        ))
+    }
+
+    fn is_checked(word: &str) -> bool {
+        word.chars().all(|c| c.is_ascii_lowercase())
+    }
+
+    pub fn is_misspelled(&self, word: &str) -> bool {
+        Self::is_checked(word) && !self.words.contains(word)
+    }
+
+    // Char ranges of misspelled words in `line`
+    pub fn misspellings(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut res = Vec::new();
+        let mut start = 0;
+        let mut word = String::new();
+        for (i, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+            if c.is_alphanumeric() || c == '_' {
+                if word.is_empty() {
+                    start = i;
+                }
+                word.push(c);
+            } else if !word.is_empty() {
+                if self.is_misspelled(&word) {
+                    res.push((start, i));
+                }
+                word.clear();
+            }
+        }
+        res
+    }
+
+    // Words within an edit distance of 2, nearest first
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let mut res: Vec<(usize, &String)> = self
+            .words
+            .iter()
+            .filter(|w| (w.len() as isize - word.len() as isize).abs() <= 2)
+            .map(|w| (edit_distance(word, w), w))
+            .filter(|&(d, _)| d <= 2)
+            .collect();
+        res.sort();
+        res.into_iter().map(|(_, w)| w.clone()).collect()
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
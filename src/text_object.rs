@@ -1,4 +1,5 @@
 use crate::core::{Core, Cursor, CursorRange};
+use crate::ropey_util::RopeExt;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Action {
@@ -37,6 +38,10 @@ pub enum TextObjectPrefix {
 pub enum Prefix {
     TextObjectPrefix(TextObjectPrefix),
     Find { inclusive: bool },
+    // `[` or `]` of `[[`, `]]`, `[{` and `]}`
+    Bracket(char),
+    // `g_`
+    G,
 }
 
 pub trait TextObject {
@@ -54,6 +59,90 @@ struct Quote(char);
 
 struct Parens(char, char);
 
+struct Tag;
+
+// A `<name ...>` or `</name>` as char offsets. Self-closing tags and `<!...>` are skipped.
+struct TagPos {
+    start: usize,
+    end: usize,
+    name: String,
+    is_close: bool,
+}
+
+fn scan_tags(chars: &[char]) -> Vec<TagPos> {
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '<' {
+            i += 1;
+            continue;
+        }
+        let end = match chars[i..].iter().position(|&c| c == '>') {
+            Some(len) => i + len,
+            None => break,
+        };
+        let is_close = chars.get(i + 1) == Some(&'/');
+        let name: String = chars[i + if is_close { 2 } else { 1 }..end]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '-' || **c == ':' || **c == '_')
+            .collect();
+        if !name.is_empty() && chars[end - 1] != '/' {
+            res.push(TagPos {
+                start: i,
+                end,
+                name,
+                is_close,
+            });
+        }
+        i = end + 1;
+    }
+    res
+}
+
+// Pairs of matching open and close tags. Nested tags of the same name are matched by a stack.
+fn tag_pairs(chars: &[char]) -> Vec<(TagPos, TagPos)> {
+    let mut res = Vec::new();
+    let mut stack: Vec<TagPos> = Vec::new();
+    for tag in scan_tags(chars) {
+        if !tag.is_close {
+            stack.push(tag);
+        } else if let Some(i) = stack.iter().rposition(|t| t.name == tag.name) {
+            let open = stack.remove(i);
+            // Unclosed tags inside like `<br>` are dropped
+            stack.truncate(i);
+            res.push((open, tag));
+        }
+    }
+    res
+}
+
+fn char_to_cursor(core: &Core, i: usize) -> Cursor {
+    let row = core.buffer().char_to_line(i);
+    Cursor {
+        row,
+        col: i - core.buffer().line_to_char(row),
+    }
+}
+
+fn cursor_to_char(core: &Core, cursor: Cursor) -> usize {
+    core.buffer().line_to_char(cursor.row) + cursor.col
+}
+
+// `%` on a tag jumps to the start of the matching tag
+pub fn matching_tag(core: &Core) -> Option<Cursor> {
+    let chars: Vec<char> = core.buffer().chars().collect();
+    let pos = cursor_to_char(core, core.cursor());
+    tag_pairs(&chars).into_iter().find_map(|(open, close)| {
+        if open.start <= pos && pos <= open.end {
+            Some(char_to_cursor(core, close.start))
+        } else if close.start <= pos && pos <= close.end {
+            Some(char_to_cursor(core, open.start))
+        } else {
+            None
+        }
+    })
+}
+
 impl TextObject for Quote {
     fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
         match prefix {
@@ -94,7 +183,12 @@ impl TextObject for Quote {
 }
 
 impl TextObject for Parens {
-    fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
+    fn get_range(
+        &self,
+        action: Action,
+        prefix: TextObjectPrefix,
+        core: &Core,
+    ) -> Option<CursorRange> {
         match prefix {
             TextObjectPrefix::A | TextObjectPrefix::Inner => {
                 let mut stack = Vec::new();
@@ -107,9 +201,35 @@ impl TextObject for Parens {
                         if let Some(l) = stack.pop() {
                             if l <= core.cursor() && t >= core.cursor() {
                                 if prefix == TextObjectPrefix::Inner {
+                                    // Whole lines of a block whose brackets end and start lines
+                                    if l.col + 1 == core.buffer().l(l.row).len_chars()
+                                        && t == core.first_non_blank(t.row)
+                                        && l.row < t.row
+                                    {
+                                        if l.row + 1 == t.row {
+                                            return None;
+                                        }
+                                        let row = t.row - 1;
+                                        let mut col = core.buffer().l(row).len_chars();
+                                        // `ciB` keeps the line break to type on
+                                        if action == Action::Change && col > 0 {
+                                            col -= 1;
+                                        }
+                                        return Some(CursorRange(
+                                            Cursor {
+                                                row: l.row + 1,
+                                                col: 0,
+                                            },
+                                            Cursor { row, col },
+                                        ));
+                                    }
                                     let l = core.next_cursor(l)?;
                                     let r = core.prev_cursor(t)?;
-                                    return if l < r { Some(CursorRange(l, r)) } else { None };
+                                    return if l <= r {
+                                        Some(CursorRange(l, r))
+                                    } else {
+                                        None
+                                    };
                                 } else {
                                     return Some(CursorRange(l, t));
                                 }
@@ -129,6 +249,257 @@ impl TextObject for Parens {
     }
 }
 
+// `(:),{:},<:>` as in `matchpairs`. Entries other than two different characters are ignored.
+pub fn parse_match_pairs(s: &str) -> Vec<(char, char)> {
+    s.split(',')
+        .filter_map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some(l), Some(':'), Some(r), None) if l != r => Some((l, r)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// `%` on a bracket jumps to the matching one
+pub fn matching_paren(core: &Core, pairs: &[(char, char)]) -> Option<Cursor> {
+    let c = core.char_at_cursor()?;
+    let chars: Vec<char> = core.buffer().chars().collect();
+    let pos = cursor_to_char(core, core.cursor());
+    let mut depth = 0;
+    if let Some(&(l, r)) = pairs.iter().find(|p| p.0 == c) {
+        for (i, &d) in chars.iter().enumerate().skip(pos) {
+            if d == l {
+                depth += 1;
+            } else if d == r {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(char_to_cursor(core, i));
+                }
+            }
+        }
+    } else if let Some(&(l, r)) = pairs.iter().find(|p| p.1 == c) {
+        for (i, &d) in chars.iter().enumerate().take(pos + 1).rev() {
+            if d == r {
+                depth += 1;
+            } else if d == l {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(char_to_cursor(core, i));
+                }
+            }
+        }
+    }
+    None
+}
+
+// Width of the leading whitespace, or `None` for a blank line.
+// A tab counts as one column, which is enough to compare lines indented alike.
+fn indent_of(core: &Core, row: usize) -> Option<usize> {
+    let line = core.buffer().line(row);
+    let width = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    if line.chars().skip(width).all(char::is_whitespace) {
+        None
+    } else {
+        Some(width)
+    }
+}
+
+// `[[` and `]]`. The nearest line before or after the cursor indented less than the cursor line.
+pub fn lower_indent(core: &Core, forward: bool) -> Option<Cursor> {
+    let row = core.cursor().row;
+    let indent = indent_of(core, row)?;
+    let is_lower = |&r: &usize| indent_of(core, r).map(|i| i < indent) == Some(true);
+    let row = if forward {
+        (row + 1..core.buffer().len_lines()).find(is_lower)?
+    } else {
+        (0..row).rev().find(is_lower)?
+    };
+    Some(Cursor {
+        row,
+        col: indent_of(core, row)?,
+    })
+}
+
+// `[{` and `]}`. The unmatched brace enclosing the cursor.
+pub fn enclosing_brace(core: &Core, forward: bool) -> Option<Cursor> {
+    let chars: Vec<char> = core.buffer().chars().collect();
+    let pos = cursor_to_char(core, core.cursor());
+    let (open, close) = if forward { ('{', '}') } else { ('}', '{') };
+    let mut depth = 0;
+    let mut check = |i: usize| {
+        if chars[i] == open {
+            depth += 1;
+        } else if chars[i] == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    };
+    let i = if forward {
+        (pos + 1..chars.len()).find(|&i| check(i))?
+    } else {
+        (0..pos).rev().find(|&i| check(i))?
+    };
+    Some(char_to_cursor(core, i))
+}
+
+// Char offsets where sentences start. A sentence ends at `.`, `!` or `?` followed by
+// whitespace, or at an empty line. Abbreviations like `e.g. this` are not special.
+fn is_blank_row(core: &Core, row: usize) -> bool {
+    core.buffer().l(row).chars().all(char::is_whitespace)
+}
+
+// Rows of `ip`, the non-blank lines around the cursor. `ap` also takes the blank lines after
+// them, or before them at the end of the text.
+pub fn paragraph(core: &Core, around: bool) -> Option<(usize, usize)> {
+    let len_lines = core.buffer().len_lines();
+    let row = core.cursor().row;
+    if is_blank_row(core, row) {
+        return None;
+    }
+    let mut first = row;
+    while first > 0 && !is_blank_row(core, first - 1) {
+        first -= 1;
+    }
+    let mut last = row;
+    while last + 1 < len_lines && !is_blank_row(core, last + 1) {
+        last += 1;
+    }
+    if around {
+        if last + 1 < len_lines {
+            while last + 1 < len_lines && is_blank_row(core, last + 1) {
+                last += 1;
+            }
+        } else {
+            while first > 0 && is_blank_row(core, first - 1) {
+                first -= 1;
+            }
+        }
+    }
+    Some((first, last))
+}
+
+fn sentence_starts(chars: &[char]) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut boundary = true;
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).cloned();
+        if boundary && !c.is_whitespace() {
+            res.push(i);
+            boundary = false;
+        }
+        if ['.', '!', '?'].contains(&c) && next.map(char::is_whitespace) != Some(false) {
+            boundary = true;
+        }
+        if c == '\n' && next == Some('\n') {
+            boundary = true;
+        }
+    }
+    res
+}
+
+// `)` and `(`. The start of the next or previous sentence.
+pub fn sentence(core: &Core, forward: bool) -> Option<Cursor> {
+    let chars: Vec<char> = core.buffer().chars().collect();
+    let pos = cursor_to_char(core, core.cursor());
+    let starts = sentence_starts(&chars);
+    let i = if forward {
+        starts.into_iter().find(|&i| i > pos)?
+    } else {
+        starts.into_iter().rev().find(|&i| i < pos)?
+    };
+    Some(char_to_cursor(core, i))
+}
+
+// Starts of subwords in identifiers (`foo|Bar|Baz`, `foo_|bar`, `HTTP|Server`) and of runs
+// of other symbols
+fn subword_starts(chars: &[char]) -> Vec<usize> {
+    let is_word = |c: char| c.is_alphanumeric();
+    let is_symbol = |c: char| !c.is_alphanumeric() && !c.is_whitespace() && c != '_';
+    let mut res = Vec::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        let next = chars.get(i + 1).cloned();
+        let start = if is_word(c) {
+            match prev {
+                Some(p) if is_word(p) => {
+                    (c.is_uppercase() && !p.is_uppercase())
+                        || (c.is_uppercase() && next.map(char::is_lowercase) == Some(true))
+                }
+                _ => true,
+            }
+        } else {
+            is_symbol(c) && prev.map(is_symbol) != Some(true)
+        };
+        if start {
+            res.push(i);
+        }
+    }
+    res
+}
+
+// `]w` and `[w`. The start of the next or previous subword. Subwords do not span lines, so
+// lines are scanned one by one from the cursor.
+pub fn subword(core: &Core, forward: bool) -> Option<Cursor> {
+    let cursor = core.cursor();
+    let mut row = cursor.row;
+    loop {
+        let chars: Vec<char> = core.buffer().l(row).chars().collect();
+        let starts = subword_starts(&chars);
+        let col = if forward {
+            starts
+                .into_iter()
+                .find(|&col| row != cursor.row || col > cursor.col)
+        } else {
+            starts
+                .into_iter()
+                .rev()
+                .find(|&col| row != cursor.row || col < cursor.col)
+        };
+        if let Some(col) = col {
+            return Some(Cursor { row, col });
+        }
+        row = if forward {
+            Some(row + 1).filter(|&row| row < core.buffer().len_lines())?
+        } else {
+            row.checked_sub(1)?
+        };
+    }
+}
+
+impl TextObject for Tag {
+    fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
+        let chars: Vec<char> = core.buffer().chars().collect();
+        let pos = cursor_to_char(core, core.cursor());
+        // The innermost element around the cursor
+        let (open, close) = tag_pairs(&chars)
+            .into_iter()
+            .filter(|(open, close)| open.start <= pos && pos <= close.end)
+            .max_by_key(|(open, _)| open.start)?;
+        match prefix {
+            TextObjectPrefix::Inner => {
+                if open.end + 1 < close.start {
+                    Some(CursorRange(
+                        char_to_cursor(core, open.end + 1),
+                        char_to_cursor(core, close.start - 1),
+                    ))
+                } else {
+                    None
+                }
+            }
+            TextObjectPrefix::A => Some(CursorRange(
+                char_to_cursor(core, open.start),
+                char_to_cursor(core, close.end),
+            )),
+            TextObjectPrefix::None => None,
+        }
+    }
+}
+
 impl TextObject for Word {
     fn get_range(
         &self,
@@ -206,17 +577,26 @@ impl TextObjectParser {
             match c {
                 'a' => {
                     self.prefix = Prefix::TextObjectPrefix(TextObjectPrefix::A);
+                    return None;
                 }
                 'i' => {
                     self.prefix = Prefix::TextObjectPrefix(TextObjectPrefix::Inner);
+                    return None;
                 }
-                'f' | 't' => {
-                    if let Prefix::TextObjectPrefix { .. } = self.prefix {
-                        self.prefix = Prefix::Find {
-                            inclusive: c == 'f',
-                        };
-                        return None;
-                    }
+                // `it` and `at` are tags
+                'f' | 't' if self.prefix == Prefix::TextObjectPrefix(TextObjectPrefix::None) => {
+                    self.prefix = Prefix::Find {
+                        inclusive: c == 'f',
+                    };
+                    return None;
+                }
+                '[' | ']' if self.prefix == Prefix::TextObjectPrefix(TextObjectPrefix::None) => {
+                    self.prefix = Prefix::Bracket(c);
+                    return None;
+                }
+                'g' if self.prefix == Prefix::TextObjectPrefix(TextObjectPrefix::None) => {
+                    self.prefix = Prefix::G;
+                    return None;
                 }
                 _ => (),
             }
@@ -250,19 +630,83 @@ impl TextObjectParser {
                     Some(None)
                 }
             }
+            // `[[` and `]]` cover whole lines up to the target line. Brace motions are exclusive.
+            Prefix::Bracket(b) => Some(match (b, c) {
+                ('[', '[') | (']', ']') => lower_indent(core, b == ']').map(|target| {
+                    let (first, last) = if b == ']' {
+                        (core.cursor().row, target.row - 1)
+                    } else {
+                        (target.row + 1, core.cursor().row)
+                    };
+                    CursorRange(
+                        Cursor { row: first, col: 0 },
+                        Cursor {
+                            row: last,
+                            col: core.buffer().l(last).len_chars(),
+                        },
+                    )
+                }),
+                ('[', '{') => enclosing_brace(core, false)
+                    .and_then(|t| Some(CursorRange(t, core.prev_cursor(core.cursor())?))),
+                (']', '}') => enclosing_brace(core, true)
+                    .and_then(|t| Some(CursorRange(core.cursor(), core.prev_cursor(t)?))),
+                // Like `dw`, `d]w` stops at the end of the line
+                (']', 'w') => {
+                    let l = core.cursor();
+                    let len = core.current_line().len_chars();
+                    match subword(core, true) {
+                        Some(t) if t.row == l.row => core.prev_cursor(t).map(|r| CursorRange(l, r)),
+                        _ if l.col < len => Some(CursorRange(
+                            l,
+                            Cursor {
+                                row: l.row,
+                                col: len - 1,
+                            },
+                        )),
+                        _ => None,
+                    }
+                }
+                ('[', 'w') => subword(core, false)
+                    .and_then(|t| Some(CursorRange(t, core.prev_cursor(core.cursor())?))),
+                _ => None,
+            }),
+            // `g_` is inclusive up to the last non-blank character
+            Prefix::G => Some(match c {
+                '_' => {
+                    let l = core.cursor();
+                    let r = core.last_non_blank(l.row);
+                    if l <= r && r.col < core.current_line().len_chars() {
+                        Some(CursorRange(l, r))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }),
             Prefix::TextObjectPrefix(text_object_prefix) => match c {
                 'w' => Some(Word.get_range(self.action, text_object_prefix, core)),
+                't' => Some(Tag.get_range(self.action, text_object_prefix, core)),
                 '\'' | '"' => Some(Quote(c).get_range(self.action, text_object_prefix, core)),
-                '{' | '}' => {
+                '{' | '}' | 'B' => {
                     Some(Parens('{', '}').get_range(self.action, text_object_prefix, core))
                 }
-                '(' | ')' => {
+                // `d)` and `d(` are exclusive sentence motions
+                ')' if text_object_prefix == TextObjectPrefix::None => Some(
+                    sentence(core, true)
+                        .and_then(|t| Some(CursorRange(core.cursor(), core.prev_cursor(t)?))),
+                ),
+                '(' if text_object_prefix == TextObjectPrefix::None => Some(
+                    sentence(core, false)
+                        .and_then(|t| Some(CursorRange(t, core.prev_cursor(core.cursor())?))),
+                ),
+                '(' | ')' | 'b' => {
                     Some(Parens('(', ')').get_range(self.action, text_object_prefix, core))
                 }
                 '[' | ']' => {
                     Some(Parens('[', ']').get_range(self.action, text_object_prefix, core))
                 }
-                _ => None,
+                // Not a motion or a text object
+                _ => Some(None),
             },
         }
     }
@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::operation::Splice;
+use crate::core::Core;
+
+// Undo history of a file with the hash of the text it ends at.
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    hash: u64,
+    steps: Vec<Vec<Splice>>,
+}
+
+// 64-bit FNV-1a. Unlike `DefaultHasher`, it stays the same across Rust versions.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// `dir/<hash of the absolute path>`
+pub fn undo_path(dir: &Path, path: &Path) -> PathBuf {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    dir.join(format!("{:016x}", hash(path.to_string_lossy().as_bytes())))
+}
+
+// `text` is what was written. It may differ from the buffer by final newlines.
+pub fn save(dir: &Path, path: &Path, core: &mut Core, text: &str) -> bool {
+    let mut steps = core.undo_steps();
+    let current = core.get_string();
+    if current != text {
+        steps.push(vec![Splice::diff(&current, text)]);
+    }
+    let undo_file = UndoFile {
+        hash: hash(text.as_bytes()),
+        steps,
+    };
+    fs::create_dir_all(dir).is_ok()
+        && serde_json::to_string(&undo_file)
+            .ok()
+            .and_then(|s| fs::write(undo_path(dir, path), s).ok())
+            .is_some()
+}
+
+// Restore the history of `path`. It is discarded if the file was changed outside.
+pub fn load(dir: &Path, path: &Path, core: &mut Core) -> bool {
+    let undo_path = undo_path(dir, path);
+    let undo_file = fs::read_to_string(&undo_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<UndoFile>(&s).ok());
+    match undo_file {
+        Some(undo_file) if undo_file.hash == hash(core.get_string().as_bytes()) => {
+            core.set_undo_steps(undo_file.steps);
+            true
+        }
+        Some(_) => {
+            let _ = fs::remove_file(undo_path);
+            false
+        }
+        None => false,
+    }
+}
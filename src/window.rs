@@ -0,0 +1,319 @@
+use std::cmp::{max, min};
+
+use crate::buffer::Buffer;
+use crate::core::Cursor;
+use crate::draw;
+
+// Rows of a pane including its footer
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    // Windows are stacked from top to bottom
+    Horizontal,
+    // Windows are placed from left to right
+    Vertical,
+}
+
+// View state of a window. The focused window's state lives in `Buffer`.
+#[derive(Clone, Copy)]
+struct Window {
+    cursor: Cursor,
+    row_offset: usize,
+    // Height or width along the layout set by resizing. Shares the rest if `None`.
+    size: Option<usize>,
+}
+
+pub struct Windows {
+    windows: Vec<Window>,
+    current: usize,
+    layout: Layout,
+    // Terminal size of the last draw
+    term_size: (usize, usize),
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self {
+            windows: vec![Window {
+                cursor: Cursor { row: 0, col: 0 },
+                row_offset: 0,
+                size: None,
+            }],
+            current: 0,
+            layout: Layout::Horizontal,
+            term_size: (0, 0),
+        }
+    }
+}
+
+impl Windows {
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    fn save(&mut self, buf: &Buffer) {
+        let window = &mut self.windows[self.current];
+        window.cursor = buf.core.cursor();
+        window.row_offset = buf.row_offset();
+    }
+
+    fn load(&self, buf: &mut Buffer, i: usize) {
+        let window = self.windows[i];
+        let cursor = buf.core.clamp_cursor(window.cursor);
+        buf.core.set_cursor(cursor);
+        buf.set_row_offset(window.row_offset);
+    }
+
+    fn set_current(&mut self, buf: &mut Buffer, i: usize) {
+        self.save(buf);
+        self.current = i;
+        self.load(buf, i);
+    }
+
+    // All windows are laid out by the latest split direction.
+    pub fn split(&mut self, buf: &mut Buffer, layout: Layout) {
+        self.save(buf);
+        if self.layout != layout {
+            self.equalize();
+        }
+        self.layout = layout;
+        let mut window = self.windows[self.current];
+        window.size = None;
+        self.windows.insert(self.current, window);
+    }
+
+    pub fn equalize(&mut self) {
+        for window in &mut self.windows {
+            window.size = None;
+        }
+    }
+
+    // Grow the focused window by `delta` rows (`Horizontal`) or columns (`Vertical`).
+    // Returns false if the windows are not laid out along `layout`.
+    pub fn resize(&mut self, layout: Layout, delta: isize) -> bool {
+        let n = self.windows.len();
+        if n == 1 || layout != self.layout {
+            return false;
+        }
+        let total = self.total(self.term_size.0, self.term_size.1);
+        let min_size = self.min_size();
+        let size = self.sizes(total)[self.current] as isize + delta;
+        let max_size = total.saturating_sub(min_size * (n - 1));
+        let size = (size.max(0) as usize).max(min_size).min(max_size);
+        self.windows[self.current].size = Some(size);
+        // Keep a window which takes the rest
+        if self
+            .windows
+            .iter()
+            .enumerate()
+            .all(|(i, w)| i == self.current || w.size.is_some())
+        {
+            let neighbor = if self.current + 1 < n {
+                self.current + 1
+            } else {
+                self.current - 1
+            };
+            self.windows[neighbor].size = None;
+        }
+        true
+    }
+
+    // Length along the layout
+    fn total(&self, height: usize, width: usize) -> usize {
+        match self.layout {
+            Layout::Horizontal => height,
+            Layout::Vertical => width,
+        }
+    }
+
+    fn min_size(&self) -> usize {
+        match self.layout {
+            Layout::Horizontal => MIN_HEIGHT,
+            Layout::Vertical => MIN_WIDTH,
+        }
+    }
+
+    // Lengths of windows along the layout which sum up to `total`
+    fn sizes(&self, total: usize) -> Vec<usize> {
+        let n = self.windows.len();
+        let fixed: usize = self.windows.iter().filter_map(|w| w.size).sum();
+        let free = self.windows.iter().filter(|w| w.size.is_none()).count();
+        let rest = total.saturating_sub(fixed);
+        let mut k = 0;
+        let mut sizes: Vec<usize> = self
+            .windows
+            .iter()
+            .map(|w| {
+                w.size.unwrap_or_else(|| {
+                    k += 1;
+                    // The last one takes the remainder
+                    if k == free {
+                        rest - rest / free * (free - 1)
+                    } else {
+                        rest / free
+                    }
+                })
+            })
+            .collect();
+
+        let min_size = min(self.min_size(), total / n);
+        for size in sizes.iter_mut() {
+            *size = max(*size, min_size);
+        }
+        while sizes.iter().sum::<usize>() > total {
+            let j = (0..n).max_by_key(|&j| sizes[j]).unwrap();
+            sizes[j] -= 1;
+        }
+        let sum: usize = sizes.iter().sum();
+        let last = self
+            .windows
+            .iter()
+            .rposition(|w| w.size.is_none())
+            .unwrap_or(n - 1);
+        sizes[last] += total - sum;
+        sizes
+    }
+
+    pub fn close(&mut self, buf: &mut Buffer) -> bool {
+        if self.windows.len() == 1 {
+            return false;
+        }
+        self.windows.remove(self.current);
+        if self.current == self.windows.len() {
+            self.current -= 1;
+        }
+        self.load(buf, self.current);
+        if self.windows.len() == 1 {
+            buf.window_size = None;
+        }
+        true
+    }
+
+    // `:only`. The focused window takes the whole screen.
+    pub fn only(&mut self, buf: &mut Buffer) -> bool {
+        if self.windows.len() == 1 {
+            return false;
+        }
+        self.save(buf);
+        let mut window = self.windows[self.current];
+        window.size = None;
+        self.windows = vec![window];
+        self.current = 0;
+        buf.window_size = None;
+        true
+    }
+
+    pub fn focus(&mut self, buf: &mut Buffer, direction: char) -> bool {
+        let next = match (self.layout, direction) {
+            (_, 'w') => Some((self.current + 1) % self.windows.len()),
+            (Layout::Horizontal, 'j') | (Layout::Vertical, 'l') => {
+                Some(self.current + 1).filter(|&i| i < self.windows.len())
+            }
+            (Layout::Horizontal, 'k') | (Layout::Vertical, 'h') => self.current.checked_sub(1),
+            _ => None,
+        };
+        if let Some(next) = next {
+            self.set_current(buf, next);
+            true
+        } else {
+            false
+        }
+    }
+
+    // `Ctrl-w 2`. `n` counts from 1.
+    pub fn goto(&mut self, buf: &mut Buffer, n: usize) -> bool {
+        if n == 0 || n > self.windows.len() {
+            return false;
+        }
+        self.set_current(buf, n - 1);
+        true
+    }
+
+    // Focus the window at the position of the last drawn terminal and return its
+    // (row, col, height, width)
+    pub fn focus_at(
+        &mut self,
+        buf: &mut Buffer,
+        row: usize,
+        col: usize,
+    ) -> Option<(usize, usize, usize, usize)> {
+        let rects = self.rects(self.term_size.0, self.term_size.1);
+        let i = rects
+            .iter()
+            .position(|&(r, c, h, w)| r <= row && row < r + h && c <= col && col < c + w)?;
+        if i != self.current {
+            self.set_current(buf, i);
+        }
+        Some(rects[i])
+    }
+
+    // (row, col, height, width) of the focused window in the last drawn terminal
+    pub fn current_rect(&self) -> Option<(usize, usize, usize, usize)> {
+        let rect = self.rects(self.term_size.0, self.term_size.1)[self.current];
+        Some(rect).filter(|&(_, _, h, w)| h > 0 && w > 0)
+    }
+
+    // (row, col, height, width) of each window
+    pub fn rects(&self, height: usize, width: usize) -> Vec<(usize, usize, usize, usize)> {
+        let total = self.total(height, width);
+        let mut offset = 0;
+        self.sizes(total)
+            .into_iter()
+            .map(|size| {
+                let rect = match self.layout {
+                    Layout::Horizontal => (offset, 0, size, width),
+                    Layout::Vertical => (0, offset, height, size),
+                };
+                offset += size;
+                rect
+            })
+            .collect()
+    }
+
+    // `mode` draws the focused window
+    pub fn draw<F: FnMut(&mut Buffer, &mut draw::Term)>(
+        &mut self,
+        buf: &mut Buffer,
+        term: &mut draw::Term,
+        mut mode: F,
+    ) {
+        self.term_size = (term.height, term.width);
+        if self.windows.len() == 1 {
+            buf.window_size = None;
+            mode(buf, term);
+            return;
+        }
+
+        self.save(buf);
+        term.cursor = draw::CursorState::Hide;
+        let rects = self.rects(term.height, term.width);
+        for (i, &(row, col, height, width)) in rects.iter().enumerate() {
+            if height < 2 || width == 0 {
+                continue;
+            }
+            let mut pane = draw::Term::new(height, width);
+            if i == self.current {
+                buf.window_size = Some((height, width));
+                mode(buf, &mut pane);
+            } else {
+                self.load(buf, i);
+                buf.draw(pane.view((0, 0), height - 1, width));
+                pane.cursor = draw::CursorState::Hide;
+                let name = buf
+                    .path()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "No Name".into());
+                pane.view((height - 1, 0), 1, width)
+                    .puts(&format!("{} [{}]", i + 1, name), draw::styles::UI);
+                self.load(buf, self.current);
+            }
+            term.blit((row, col), pane);
+        }
+    }
+}
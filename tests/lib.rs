@@ -1,6 +1,6 @@
 use termion::event::{Event, Key};
 
-use accepted::{config, Buffer, BufferMode};
+use accepted::{config, Buffer, BufferMode, LineNumbers};
 
 trait BufferModeExt {
     fn command(&mut self, command: &str);
@@ -105,3 +105,3892 @@ fn test_simples() {
     simple_test("123 456 789", "ww0iabc ", "abc 123 456 789");
     simple_test("123 456 789", "$i abc", "123 456 789 abc");
 }
+
+#[test]
+fn test_registers() {
+    with_buffer_mode_from("123\n456 789", |mut state| {
+        state.command("\"ayyj\"byw");
+        let list = state.buf.register_list();
+        assert!(list.contains(&"\"a  l  123".to_string()));
+        assert!(list.contains(&"\"b  c  456 ".to_string()));
+
+        // `:registers` is read-only
+        state.command(":reg\nx");
+        assert_eq!(state.buf.core.get_string(), "123\n456 789");
+
+        state.command("\"aP");
+        assert_eq!(state.buf.core.get_string(), "123\n123\n456 789");
+    });
+}
+
+#[test]
+fn test_command_range() {
+    // Visual selection seeds `'<,'>`
+    simple_test("a\na\na\na", "jVj:s/a/b/\n", "a\nb\nb\na");
+    simple_test("aa\naa\naa", "jvl:s/a/b/g\n", "aa\nbb\naa");
+
+    simple_test("a\na\na\na", ":2,3s/a/b/\n", "a\nb\nb\na");
+    simple_test("a\na\na\na", ":%s/a/b/\n", "b\nb\nb\nb");
+    simple_test("a\na\na\na", "j:.,+1s/a/b/\n", "a\nb\nb\na");
+    simple_test("abab", ":s/(a)b/\\1c/g\n", "acac");
+    simple_test("ABab", ":s/a/x/gi\n", "xBxb");
+
+    simple_test("c\nb\na", ":sort\n", "a\nb\nc");
+    simple_test("a\nb\nc", ":sort!\n", "c\nb\na");
+    simple_test("b\nB\na\nA", ":sort\n", "A\nB\na\nb");
+    simple_test("b\nB\na\nA", ":sort i\n", "a\nA\nb\nB");
+    simple_test("b\nB\na\nA", ":sort! i\n", "b\nB\na\nA");
+    simple_test(
+        "x=3\ny=1\nnone\nz=2\nw=1",
+        ":sort /=/\n",
+        "none\ny=1\nw=1\nz=2\nx=3",
+    );
+    simple_test("a,B\nb,a\nc,b", ":sort i /,/\n", "b,a\na,B\nc,b");
+    simple_test("b\na", ":sort /(/\n", "b\na");
+    simple_test("d\nc\nb\na", "jVj:sort\n", "d\nb\nc\na");
+    simple_test("a\nb\nc", ":reverse\n", "c\nb\na");
+    simple_test("ab\ncd", "vy:put\n", "ab\na\ncd");
+    simple_test("ab\ncd", "\"ayyj:put a\n", "ab\ncd\nab");
+    simple_test("ab\ncd", "j\"ayy:0put a\n", "cd\nab\ncd");
+    simple_test("ab\ncd", "yy:$pu\n", "ab\ncd\nab");
+    simple_test("ab", ":put b\n", "ab");
+    simple_test("a\nb\nc\nd", ":2,3rev\n", "a\nc\nb\nd");
+    simple_test("a\nb\nc\nd", "jVj:reverse\n", "a\nc\nb\nd");
+    simple_test("a\nb\nc\nd", "G:2,3rev\nx", "a\n\nb\nd");
+    simple_test("c\nb\na\nd", ":1,3!sort\n", "a\nb\nc\nd");
+
+    simple_test("a\na\na\na", ":3\nx", "a\na\n\na");
+}
+
+#[test]
+fn test_move_copy() {
+    // The cursor follows the moved line
+    simple_test("a\nb\nc", "jj:m 0\nx", "\na\nb");
+    simple_test("a\nb\nc\nd", ":1,2m $\nx", "c\nd\na\n");
+    simple_test("a\nb\nc\nd", ":4m1\n", "a\nd\nb\nc");
+    simple_test("a\nb\nc", ":m 1\n", "a\nb\nc");
+    simple_test("a\nb\nc", ":1,3m2\n", "a\nb\nc");
+    simple_test("a\nb\nc", ":m\n", "a\nb\nc");
+
+    simple_test("a\nb\nc", ":1,2t $\nx", "a\nb\nc\na\n");
+    simple_test("a\nb", "j:co 0\n", "b\na\nb");
+    simple_test("a\nb", ":t.\n", "a\na\nb");
+}
+
+#[test]
+fn test_gv() {
+    simple_test("123\n456\n789", "jjggx", "23\n456\n789");
+    // No previous selection
+    simple_test("abc", "gvx", "bc");
+
+    // Reselect after editing elsewhere
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.command_esc("lvl");
+        state.command("jxgvd");
+        assert_eq!(state.buf.core.get_string(), "a\nde\nghi");
+    });
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.command_esc("jV");
+        state.command("ggxgvd");
+        assert_eq!(state.buf.core.get_string(), "bc\nghi");
+    });
+    // Clamped to the buffer
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.command_esc("jjvl");
+        state.command("ggdjgvd");
+        assert_eq!(state.buf.core.get_string(), "i");
+    });
+}
+
+#[test]
+fn test_visual_swap_end() {
+    simple_test("abcdef", "llvlohd", "aef");
+    simple_test("abc\ndef\nghi", "jVokd", "ghi");
+    simple_test("abc\ndef\nghi\njkl", "jVoojd", "abc\njkl");
+}
+
+#[test]
+fn test_goto_definition() {
+    let src = "fn main() {\n    let x = foo(1);\n    x\n}\n\nfn foo(a: i32) -> i32 {\n    a\n}";
+    with_buffer_mode_from(src, |mut state| {
+        state.command("jffgdi@");
+        state.event(Event::Key(Key::Esc));
+        assert!(state.buf.core.get_string().contains("fn @foo(a"));
+
+        state.event(Event::Key(Key::Ctrl('o')));
+        state.command_esc("i#");
+        assert!(state.buf.core.get_string().contains("= #foo(1)"));
+    });
+
+    // `let mut` definition
+    simple_test("let mut y = 1;\ny + 1", "jgdi@", "let mut @y = 1;\ny + 1");
+    // Falls back to the first occurrence
+    simple_test("bar();\nbar();", "jgdi@", "@bar();\nbar();");
+    // Not an identifier
+    simple_test("a + b", "llgdi@", "a @+ b");
+}
+
+#[test]
+fn test_multi_cursor() {
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command_esc("i> ");
+        assert_eq!(state.buf.core.get_string(), "> abc\n> def\n> ghi");
+
+        // Esc collapsed the cursors
+        state.command_esc("iZ");
+        assert_eq!(state.buf.core.get_string(), "> abc\n> def\n> Zghi");
+    });
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.command("ll");
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command("i");
+        state.event(Event::Key(Key::Backspace));
+        state.command_esc("X");
+        assert_eq!(state.buf.core.get_string(), "aXc\ndXf\nghi");
+    });
+}
+
+#[test]
+fn test_format_command() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default(
+        r#"
+[file.txt]
+formatter = ["tr", "a-z", "A-Z"]
+
+[file.fail]
+formatter = ["sh", "-c", "echo syntax error >&2; exit 1"]
+"#,
+    )
+    .unwrap();
+
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.set_path("a.txt".into());
+    buf.core.set_string("abc\ndef\nghi".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command("jjl:fmt\n");
+    assert_eq!(state.buf.core.get_string(), "ABC\nDEF\nGHI");
+    assert_eq!(state.buf.core.cursor().row, 2);
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "abc\ndef\nghi");
+
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.set_path("a.fail".into());
+    buf.core.set_string("abc".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command(":fmt\n");
+    assert_eq!(state.buf.core.get_string(), "abc");
+}
+
+#[test]
+fn test_messages() {
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(":foo\n");
+        state.command(":s/x/y/\n");
+        state.command("gv");
+        assert_eq!(
+            state.buf.messages.iter().collect::<Vec<_>>(),
+            vec![
+                "Not an editor command: foo",
+                "Pattern not found: x",
+                "No previous Visual selection",
+            ]
+        );
+
+        // Any key closes the log
+        state.command(":messages\nx");
+        assert_eq!(state.buf.core.get_string(), "abc");
+        assert_eq!(state.buf.messages.len(), 3);
+    });
+}
+
+#[test]
+fn test_substitute_preview() {
+    with_buffer_mode_from("foo bar\nbar foo foo\nbaz", |mut state| {
+        // Only the lines in the window are previewed
+        state.resize(10, 80);
+        state.command(":%s/fo");
+        assert_eq!(state.buf.preview[&0].text, "o bar");
+        state.command("o/X");
+        assert_eq!(state.buf.core.get_string(), "foo bar\nbar foo foo\nbaz");
+        let preview = state
+            .buf
+            .preview
+            .iter()
+            .map(|(&row, line)| (row, line.text.as_str(), line.changed.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            preview,
+            vec![(0, "X bar", vec![(0, 1)]), (1, "bar X foo", vec![(4, 5)])]
+        );
+
+        state.command("/g");
+        assert_eq!(state.buf.preview[&1].text, "bar X X");
+
+        state.command("\n");
+        assert!(state.buf.preview.is_empty());
+        assert_eq!(state.buf.core.get_string(), "X bar\nbar X X\nbaz");
+    });
+
+    // Esc discards the preview
+    with_buffer_mode_from("foo", |mut state| {
+        state.command_esc(":s/foo/bar/");
+        assert!(state.buf.preview.is_empty());
+        assert_eq!(state.buf.core.get_string(), "foo");
+    });
+}
+
+#[test]
+fn test_insert_delete_word() {
+    with_buffer_mode_from("abc", |mut state| {
+        state.command("Afoo bar");
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert_eq!(state.buf.core.get_string(), "abcfoo ");
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert_eq!(state.buf.core.get_string(), "");
+        state.command_esc("x");
+        assert_eq!(state.buf.core.get_string(), "x");
+        // One undo unit
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "abc");
+    });
+    with_buffer_mode_from("abc\ndef", |mut state| {
+        state.command("jA gh");
+        state.event(Event::Key(Key::Ctrl('u')));
+        state.command_esc("x");
+        assert_eq!(state.buf.core.get_string(), "abc\nx");
+    });
+    // Stop at the start of the line
+    with_buffer_mode_from("abc\n  def", |mut state| {
+        state.command("jA");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert_eq!(state.buf.core.get_string(), "abc\n");
+    });
+}
+
+#[test]
+fn test_emacs_insert_keys() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config =
+        config::parse_config_with_default("[file_default]\nemacs_insert_keys = true").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("abc def".into(), true);
+    let mut state = BufferMode::new(buf);
+
+    state.command("lli");
+    state.event(Event::Key(Key::Ctrl('a')));
+    assert_eq!(state.buf.core.cursor().col, 0);
+    state.event(Event::Key(Key::Ctrl('e')));
+    assert_eq!(state.buf.core.cursor().col, 7);
+    state.event(Event::Key(Key::Ctrl('b')));
+    state.event(Event::Key(Key::Ctrl('b')));
+    assert_eq!(state.buf.core.cursor().col, 5);
+    state.event(Event::Key(Key::Ctrl('f')));
+    assert_eq!(state.buf.core.cursor().col, 6);
+    state.command_esc("x");
+    assert_eq!(state.buf.core.get_string(), "abc dexf");
+
+    // Disabled by default
+    with_buffer_mode_from("abc", |mut state| {
+        state.command("lli");
+        state.event(Event::Key(Key::Ctrl('a')));
+        assert_eq!(state.buf.core.cursor().col, 2);
+    });
+}
+
+#[test]
+fn test_recent_files() {
+    use accepted::recent::RecentFiles;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_recent_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dir = fs::canonicalize(dir).unwrap();
+    let list = dir.join("acc").join("recent");
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    fs::write(&a, "aaa").unwrap();
+    fs::write(&b, "bbb").unwrap();
+
+    let mut recent = RecentFiles::load(list.clone());
+    recent.push(&a);
+    recent.push(&b);
+    recent.push(&a);
+    assert_eq!(
+        RecentFiles::load(list.clone()).files(),
+        &[a.clone(), b.clone()]
+    );
+
+    // Select the second one
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.recent = RecentFiles::load(list.clone());
+    let mut state = BufferMode::new(buf);
+    state.command(":recent\njk");
+    state.event(Event::Key(Key::Down));
+    state.command("\n");
+    assert_eq!(state.buf.core.get_string(), "bbb");
+    assert_eq!(state.buf.path(), Some(b.as_path()));
+    assert_eq!(
+        RecentFiles::load(list.clone()).files(),
+        &[b.clone(), a.clone()]
+    );
+
+    // Not over unsaved changes
+    state.command("x:recent\nj\n");
+    assert_eq!(state.buf.path(), Some(b.as_path()));
+    assert_eq!(
+        state.buf.messages.back().map(String::as_str),
+        Some("No write since last change")
+    );
+
+    // Missing files are skipped
+    fs::remove_file(&a).unwrap();
+    assert_eq!(RecentFiles::load(list).existing(), vec![b]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parse_path_arg() {
+    use accepted::parse_path_arg;
+    use std::path::PathBuf;
+
+    assert_eq!(parse_path_arg("foo.rs"), (PathBuf::from("foo.rs"), None));
+    assert_eq!(
+        parse_path_arg("foo.rs:42"),
+        (PathBuf::from("foo.rs"), Some((42, 1)))
+    );
+    assert_eq!(
+        parse_path_arg("src/foo.rs:42:5"),
+        (PathBuf::from("src/foo.rs"), Some((42, 5)))
+    );
+    assert_eq!(
+        parse_path_arg("a:b.rs:3"),
+        (PathBuf::from("a:b.rs"), Some((3, 1)))
+    );
+    assert_eq!(
+        parse_path_arg("foo.rs:x"),
+        (PathBuf::from("foo.rs:x"), None)
+    );
+    assert_eq!(parse_path_arg(":42"), (PathBuf::from(":42"), None));
+
+    // An existing file named with a colon wins
+    let dir = std::env::temp_dir().join(format!("acc_test_path_arg_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("foo.rs:42");
+    std::fs::write(&file, "").unwrap();
+    let arg = file.to_str().unwrap();
+    assert_eq!(parse_path_arg(arg), (file.clone(), None));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.buf.goto_position(2, 3);
+        state.command_esc("ix");
+        assert_eq!(state.buf.core.get_string(), "abc\ndexf\nghi");
+        state.buf.goto_position(100, 100);
+        state.command_esc("iy");
+        assert_eq!(state.buf.core.get_string(), "abc\ndexf\nghiy");
+    });
+}
+
+#[test]
+fn test_change_directory() {
+    use std::env;
+    use std::fs;
+
+    let cwd = env::current_dir().unwrap();
+    let dir = env::temp_dir().join(format!("acc_test_cd_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dir = fs::canonicalize(dir).unwrap();
+
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(&format!(":cd {}\n", dir.display()));
+        assert_eq!(env::current_dir().unwrap(), dir);
+        state.command(":pwd\n");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some(dir.to_str().unwrap())
+        );
+
+        // Relative to the new directory
+        state.command(":w out.txt\n");
+        assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "abc\n");
+
+        state.command(":cd no_such_directory\n");
+        assert_eq!(env::current_dir().unwrap(), dir);
+        assert!(state
+            .buf
+            .messages
+            .back()
+            .unwrap()
+            .starts_with("no_such_directory: "));
+    });
+
+    env::set_current_dir(cwd).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count() {
+    simple_test("abcdef", "3x", "def");
+    simple_test("abcdef", "10x", "");
+    simple_test("abc\ndef\nghi\njkl", "2dd", "ghi\njkl");
+    simple_test(
+        "abc\ndef\nghi\njkl",
+        "j2yyGp",
+        "abc\ndef\nghi\njkl\ndef\nghi",
+    );
+    simple_test("abc\ndef\nghi\njkl", "j2ccx", "abc\nx\njkl");
+    simple_test("abc\ndef\nghi", "2jx", "abc\ndef\nhi");
+    simple_test("abcdefghijkl", "10lx", "abcdefghijl");
+}
+
+#[test]
+fn test_showcmd() {
+    with_buffer_mode_from("abc", |mut state| {
+        assert_eq!(state.showcmd(), "");
+        state.command("2");
+        assert_eq!(state.showcmd(), "2");
+        state.command("d");
+        assert_eq!(state.showcmd(), "2d");
+        state.command("i");
+        assert_eq!(state.showcmd(), "2di");
+        state.event(Event::Key(Key::Esc));
+        assert_eq!(state.showcmd(), "");
+
+        state.command("\"a1");
+        assert_eq!(state.showcmd(), "\"a1");
+        state.command("2g");
+        assert_eq!(state.showcmd(), "\"a12g");
+        state.command("g");
+        assert_eq!(state.showcmd(), "");
+    });
+}
+
+#[test]
+fn test_split_window() {
+    use termion::event::{MouseButton, MouseEvent};
+
+    with_buffer_mode_from("1\n2\n3\n4\n5\n6\n7\n8\n9", |mut state| {
+        state.command(":split\n");
+        assert_eq!(state.window_count(), 2);
+        assert_eq!(state.current_window(), 0);
+
+        // Each window has its own cursor and scroll position
+        state.command("jj");
+        state.event(Event::Mouse(MouseEvent::Press(
+            MouseButton::WheelDown,
+            1,
+            1,
+        )));
+        assert_eq!(state.buf.row_offset(), 3);
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("j");
+        assert_eq!(state.current_window(), 1);
+        assert_eq!(state.buf.core.cursor().row, 0);
+        assert_eq!(state.buf.row_offset(), 0);
+        state.command("G");
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("k");
+        assert_eq!(state.current_window(), 0);
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert_eq!(state.buf.row_offset(), 3);
+
+        // No window above
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("k");
+        assert_eq!(state.current_window(), 0);
+
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+
+        state.command(":close\n");
+        assert_eq!(state.window_count(), 1);
+        assert_eq!(state.buf.core.cursor().row, 8);
+
+        state.command(":close\n");
+        assert_eq!(state.window_count(), 1);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Cannot close last window")
+        );
+    });
+
+    // Focus moves by h and l in vertical splits
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(":vsplit\n");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("j");
+        assert_eq!(state.current_window(), 0);
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("l");
+        assert_eq!(state.current_window(), 1);
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+    });
+}
+
+#[test]
+fn test_grep() {
+    with_buffer_mode_from("foo\nbar\n  foo foo\nbaz", |mut state| {
+        state.command(":grep fo+\n");
+        assert_eq!(
+            state.buf.quickfix,
+            vec![
+                (0, 0, "foo".to_string()),
+                (2, 2, "  foo foo".to_string()),
+                (2, 6, "  foo foo".to_string()),
+            ]
+        );
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (0, 0)
+        );
+
+        // Enter on a list item jumps there
+        state.command("j\n");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (2, 2)
+        );
+
+        state.command(":cn\n");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (2, 6)
+        );
+        state.command(":cn\n");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (2, 6)
+        );
+        state.command(":cp\n");
+        state.command(":cp\n");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (0, 0)
+        );
+
+        state.command(":grep qux\n");
+        assert!(state.buf.quickfix.is_empty());
+
+        // Moving in the list does not add jumps
+        state.command("G0:grep fo+\njjk\n");
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (3, 0)
+        );
+    });
+}
+
+#[test]
+fn test_reflow() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\ntextwidth = 40").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string(
+        "    // The quick brown fox jumps over the lazy dog and keeps\n    // running until it reaches the river.\n\nnext"
+            .into(),
+        true,
+    );
+    let mut state = BufferMode::new(buf);
+
+    state.command("jgwip");
+    assert_eq!(
+        state.buf.core.get_string(),
+        "    // The quick brown fox jumps over\n    // the lazy dog and keeps running\n    // until it reaches the river.\n\nnext"
+    );
+    assert_eq!(state.buf.core.cursor().row, 1);
+
+    // Words longer than textwidth stay on their own line
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string(
+        "a supercalifragilisticexpialidocious_and_then_some word".into(),
+        true,
+    );
+    let mut state = BufferMode::new(buf);
+    state.command("gqq");
+    assert_eq!(
+        state.buf.core.get_string(),
+        "a\nsupercalifragilisticexpialidocious_and_then_some\nword"
+    );
+    assert_eq!(state.buf.core.cursor().row, 2);
+}
+
+#[test]
+fn test_word_completion() {
+    with_buffer_mode_from("foobar fizz\nfoo\n\nfood", |mut state| {
+        assert_eq!(
+            state.buf.complete_words("fo"),
+            vec!["foobar".to_string(), "foo".to_string(), "food".to_string()]
+        );
+
+        state.command("jjifo");
+        state.event(Event::Key(Key::Ctrl('n')));
+        assert_eq!(state.buf.core.current_line().to_string(), "foo");
+        state.event(Event::Key(Key::Ctrl('n')));
+        assert_eq!(state.buf.core.current_line().to_string(), "food");
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.event(Event::Key(Key::Ctrl('n')));
+        // Back to the typed prefix
+        assert_eq!(state.buf.core.current_line().to_string(), "fo");
+        state.event(Event::Key(Key::Ctrl('p')));
+        assert_eq!(state.buf.core.current_line().to_string(), "foobar");
+
+        // Typing commits the completion
+        state.command_esc(" x");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "foobar fizz\nfoo\nfoobar x\nfood"
+        );
+    });
+}
+
+#[test]
+fn test_set_list() {
+    with_buffer_mode_from("\tab  \nc", |mut state| {
+        state.command(":set list\n");
+        assert!(state.buf.list);
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        assert!(term.row_string(0).trim_end().ends_with("▸   ab··$"));
+        assert!(term.row_string(1).trim_end().ends_with("c$"));
+        // The buffer itself is unchanged
+        assert_eq!(state.buf.core.get_string(), "\tab  \nc");
+
+        // Cursor still moves by characters
+        state.command("l");
+        assert_eq!(state.buf.core.cursor().col, 1);
+
+        state.command(":set nolist\n");
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        assert!(term.row_string(0).trim_end().ends_with("ab"));
+    });
+}
+
+#[test]
+fn test_repeat_substitute() {
+    with_buffer_mode_from("a a\nb\na a\na a", |mut state| {
+        state.command("&");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No previous substitute regular expression")
+        );
+
+        state.command(":s/a/x/g\n");
+        assert_eq!(state.buf.core.get_string(), "x x\nb\na a\na a");
+
+        // `&` drops the flags
+        state.command("jj&");
+        assert_eq!(state.buf.core.get_string(), "x x\nb\nx a\na a");
+
+        state.command("g&");
+        assert_eq!(state.buf.core.get_string(), "x x\nb\nx x\nx x");
+
+        state.command(":s/x/y/g\n:4&&\n");
+        assert_eq!(state.buf.core.get_string(), "x x\nb\ny y\ny y");
+    });
+}
+
+#[test]
+fn test_normal_command() {
+    simple_test("a\nb\nc", ":%normal I// \n", "// a\n// b\n// c");
+    simple_test("a\nb\nc", ":2,3norm A;\n", "a\nb;\nc;");
+    simple_test("a\nb\nc\nd", ":%normal dd\n", "");
+    simple_test("a\nb", ":%normal o-<Esc>\n", "a\n-\nb\n-");
+}
+
+#[test]
+fn test_history() {
+    use accepted::history::History;
+    use std::fs;
+
+    with_buffer_mode_from("aaa", |mut state| {
+        state.command(":s/a/x/\n:sort\n:s/a/y/\n");
+        assert_eq!(
+            state.buf.command_history.entries(),
+            &["s/a/x/", "sort", "s/a/y/"]
+        );
+
+        // Only entries starting with the typed text
+        state.command(":s/");
+        state.event(Event::Key(Key::Up));
+        state.event(Event::Key(Key::Up));
+        state.event(Event::Key(Key::Up));
+        state.event(Event::Key(Key::Down));
+        state.command("\n");
+        assert_eq!(state.buf.core.get_string(), "xyy");
+
+        // Down from the newest entry restores the typed text
+        state.command(":s");
+        state.event(Event::Key(Key::Ctrl('p')));
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command("ort\n");
+        assert_eq!(state.buf.command_history.entries().last().unwrap(), "sort");
+
+        // `/` edits the last query
+        state.command("/ab\n/");
+        state.event(Event::Key(Key::Backspace));
+        state.event(Event::Key(Key::Backspace));
+        state.command("xy\n/");
+        state.event(Event::Key(Key::Backspace));
+        state.event(Event::Key(Key::Backspace));
+        state.command("a");
+        state.event(Event::Key(Key::Up));
+        state.command("\n");
+        assert_eq!(state.buf.search.iter().collect::<String>(), "ab");
+        assert_eq!(state.buf.search_history.entries(), &["ab", "xy", "ab"]);
+    });
+
+    let path = std::env::temp_dir()
+        .join(format!("acc_test_history_{}", std::process::id()))
+        .join("history");
+    let mut history = History::load(path.clone());
+    history.push("w");
+    history.push("w");
+    history.push("e foo");
+    history.push("w");
+    assert_eq!(History::load(path.clone()).entries(), &["w", "e foo", "w"]);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_spell() {
+    use accepted::spell::Dictionary;
+
+    let dictionary = Dictionary::from_words(vec!["the", "quick", "brown", "fox"]);
+    // Words with capitals or digits are not checked
+    assert_eq!(
+        dictionary.misspellings("The quikc brown fx2 fox jumsp"),
+        vec![(4, 9), (24, 29)]
+    );
+    assert_eq!(dictionary.suggest("quikc"), vec!["quick".to_string()]);
+    assert_eq!(dictionary.suggest("fx"), vec!["fox".to_string()]);
+
+    with_buffer_mode_from("the quikc\nbrown\nfox jumsp", |mut state| {
+        state.buf.dictionary = Some(Dictionary::from_words(vec![
+            "the", "quick", "brown", "fox", "jumps",
+        ]));
+        state.command("]s");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Spell checking is off")
+        );
+
+        state.command(":set spell\n]s");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (0, 4)
+        );
+        state.command("]s");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (2, 4)
+        );
+        // Wraps around
+        state.command("]s[s");
+        assert_eq!(
+            (state.buf.core.cursor().row, state.buf.core.cursor().col),
+            (2, 4)
+        );
+
+        state.command("z=\n");
+        assert_eq!(state.buf.core.get_string(), "the quikc\nbrown\nfox jumps");
+    });
+}
+
+#[test]
+fn test_tag_text_object() {
+    let src = "<div id=\"a\">\n  <div>inner <b>x</b></div>\n  <br/>\n</div>";
+    // Innermost element around the cursor
+    simple_test(
+        src,
+        "jfncitnew",
+        "<div id=\"a\">\n  <div>new</div>\n  <br/>\n</div>",
+    );
+    simple_test(src, "jfndat", "<div id=\"a\">\n  \n  <br/>\n</div>");
+    simple_test(src, "jjdat", "");
+    simple_test(
+        src,
+        "jfbdit",
+        "<div id=\"a\">\n  <div>inner <b></b></div>\n  <br/>\n</div>",
+    );
+    // No inner object of an empty element
+    simple_test("<p></p>", "dit", "<p></p>");
+
+    // `%` between tags and brackets
+    simple_test(
+        src,
+        "%i@",
+        "<div id=\"a\">\n  <div>inner <b>x</b></div>\n  <br/>\n@</div>",
+    );
+    simple_test("f(a[1])", "f(%i@", "f(a[1]@)");
+    simple_test("f(a[1])", "f)%i@", "f@(a[1])");
+}
+
+#[test]
+fn test_resize_window() {
+    with_buffer_mode_from("abc", |mut state| {
+        let heights = |state: &BufferMode| -> Vec<usize> {
+            state
+                .window_rects(24, 80)
+                .iter()
+                .map(|&(_, _, h, _)| h)
+                .collect()
+        };
+        state.command(":split\n:split\n");
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        assert_eq!(heights(&state), vec![8, 8, 8]);
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("+");
+        assert_eq!(heights(&state), vec![9, 7, 8]);
+        state.command("3");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("-");
+        assert_eq!(heights(&state), vec![6, 9, 9]);
+
+        // Other windows keep the minimum height
+        state.command("30");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("+");
+        assert_eq!(heights(&state), vec![20, 2, 2]);
+        state.command("30");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("-");
+        assert_eq!(heights(&state), vec![2, 11, 11]);
+
+        // Width can not change in horizontal splits
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command(">");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Cannot resize window width")
+        );
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("=");
+        assert_eq!(heights(&state), vec![8, 8, 8]);
+    });
+
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(":vsplit\n");
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("l");
+        state.command("10");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command(">");
+        let widths: Vec<usize> = state
+            .window_rects(24, 80)
+            .iter()
+            .map(|&(_, _, _, w)| w)
+            .collect();
+        assert_eq!(widths, vec![30, 50]);
+        state.draw(&mut term);
+    });
+}
+
+#[test]
+fn test_goto_file() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_gf_{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let a = dir.join("a.txt");
+    fs::write(&a, "see sub/b.txt.\nmissing.txt").unwrap();
+    fs::write(dir.join("sub").join("b.txt"), "bbb").unwrap();
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&a);
+    let mut state = BufferMode::new(buf);
+
+    state.command("w");
+    assert_eq!(state.buf.path_under_cursor().as_deref(), Some("sub/b.txt"));
+    assert_eq!(
+        state.buf.resolve_path("sub/b.txt"),
+        Some(dir.join("sub").join("b.txt"))
+    );
+
+    // Relative to the directory of the current file
+    state.command("gf");
+    assert_eq!(state.buf.core.get_string(), "bbb");
+
+    state.event(Event::Key(Key::Ctrl('o')));
+    assert_eq!(state.buf.path(), Some(a.as_path()));
+    assert_eq!(state.buf.core.cursor().col, 4);
+
+    state.command("jgf");
+    assert_eq!(state.buf.path(), Some(a.as_path()));
+    assert_eq!(
+        state.buf.messages.back().map(String::as_str),
+        Some("File not found: missing.txt")
+    );
+    assert!(!dir.join("missing.txt").exists());
+
+    // Neither leaves unsaved changes
+    let b = dir.join("sub").join("b.txt");
+    state.command("ggwgfx");
+    assert_eq!(state.buf.path(), Some(b.as_path()));
+    state.event(Event::Key(Key::Ctrl('o')));
+    assert_eq!(state.buf.path(), Some(b.as_path()));
+    assert_eq!(
+        state.buf.messages.back().map(String::as_str),
+        Some("No write since last change")
+    );
+    state.command(&format!(":e! {}\njxk0wgf", a.display()));
+    assert_eq!(state.buf.path(), Some(a.as_path()));
+    assert_eq!(
+        state.buf.messages.back().map(String::as_str),
+        Some("No write since last change")
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_undo_file() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_undo_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    fs::write(&file, "abc\n").unwrap();
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nundofile = true").unwrap();
+    let open = || {
+        let mut buf = Buffer::new(&syntax_parent, &config);
+        buf.undo_dir = Some(dir.join("undo"));
+        buf.open(&file);
+        BufferMode::new(buf)
+    };
+
+    let mut state = open();
+    state.command_esc("Ix");
+    state.command_esc("oyz");
+    assert!(state.buf.save(false));
+
+    // Round-trip
+    let mut state = open();
+    assert_eq!(state.buf.core.get_string(), "xabc\nyz\n");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "xabc\n");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "abc\n");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "abc\n");
+    state.command("UU");
+    assert_eq!(state.buf.core.get_string(), "xabc\nyz\n");
+
+    // Deleting and replacing
+    state.command("ggxrqjdd");
+    assert_eq!(state.buf.core.get_string(), "qbc\n");
+    assert!(state.buf.save(false));
+    let mut state = open();
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "abc\nyz\n");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "xabc\nyz\n");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "xabc\n");
+
+    // Changed outside of the editor
+    fs::write(&file, "changed\n").unwrap();
+    let mut state = open();
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "changed\n");
+    assert!(fs::read_dir(dir.join("undo")).unwrap().next().is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_toggle_comment() {
+    simple_test("x\ny\n", "gcc", "# x\ny\n");
+    simple_test(
+        "fn f() {\n    a;\n\n        b;\n}\n",
+        "j2gcj",
+        "fn f() {\n    # a;\n\n    #     b;\n}\n",
+    );
+    simple_test("  # a;\n  #b;\n\nc\n", "gcip", "  a;\n  b;\n\nc\n");
+    // Mixed selections are commented
+    simple_test("# a\nb\n", "gcj", "# # a\n# b\n");
+    simple_test("a\nb\nc\n", "Vjgc", "# a\n# b\nc\n");
+    // The indent is counted in characters
+    simple_test("\u{3000}a\n b\n", "gcj", "\u{3000}# a\n # b\n");
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\ncomment = \"//\"").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("    let a = 1;\n".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command("gcc");
+    assert_eq!(state.buf.core.get_string(), "    // let a = 1;\n");
+    state.command("gcc");
+    assert_eq!(state.buf.core.get_string(), "    let a = 1;\n");
+}
+
+#[test]
+fn test_comment_leader_guess() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\ncomment = \"//\"").unwrap();
+    let state_from = |s: &str| {
+        let mut buf = Buffer::new(&syntax_parent, &config);
+        buf.core.set_string(s.into(), true);
+        BufferMode::new(buf)
+    };
+
+    // From a shebang
+    let mut state = state_from("#!/bin/sh\necho hi\n");
+    state.command("jgcc");
+    assert_eq!(state.buf.core.get_string(), "#!/bin/sh\n# echo hi\n");
+    let mut state = state_from("#!/usr/bin/env node\nf()\n");
+    state.command("jgcc");
+    assert_eq!(state.buf.core.get_string(), "#!/usr/bin/env node\n// f()\n");
+
+    // From the first non-blank line
+    let mut state = state_from("\n-- lua\nx = 1\n");
+    state.command("jjgcc");
+    assert_eq!(state.buf.core.get_string(), "\n-- lua\n-- x = 1\n");
+
+    // The configured default
+    let mut state = state_from("x\n");
+    state.command("gcc");
+    assert_eq!(state.buf.core.get_string(), "// x\n");
+
+    // `commentstring` wins
+    let mut state = state_from("#!/bin/sh\necho hi\n");
+    state.command(":set commentstring=;\\ %s\njgcc");
+    assert_eq!(state.buf.core.get_string(), "#!/bin/sh\n; echo hi\n");
+    state.command(":set commentstring=x\n");
+    assert_eq!(
+        state.buf.messages.back().map(String::as_str),
+        Some("commentstring must contain %s")
+    );
+    state.command(":set commentstring=\ngcc");
+    assert_eq!(state.buf.core.get_string(), "#!/bin/sh\n# ; echo hi\n");
+
+    // Text after `%s` closes the comment
+    let mut state = state_from("int a;\n  b();\n");
+    state.command(":set commentstring=/*\\ %s\\ */\ngcj");
+    assert_eq!(state.buf.core.get_string(), "/* int a; */\n/*   b(); */\n");
+    state.command("gcj");
+    assert_eq!(state.buf.core.get_string(), "int a;\n  b();\n");
+    // Only the leader is not a comment
+    let mut state = state_from("/* a\n");
+    state.command(":set commentstring=/*\\ %s\\ */\ngcc");
+    assert_eq!(state.buf.core.get_string(), "/* /* a */\n");
+}
+
+#[test]
+fn test_cursorline() {
+    use accepted::draw::{styles, Term};
+
+    with_buffer_mode_from("ab\ncd\nef", |mut state| {
+        state.command(":set cursorline\nj");
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        let col = term.row_string(1).find('c').unwrap();
+        assert_eq!(term.style(1, col).unwrap().bg, styles::CURSORLINE.bg);
+        assert_eq!(term.style(1, 78), Some(styles::CURSORLINE));
+        assert_ne!(term.style(0, col).unwrap().bg, styles::CURSORLINE.bg);
+        assert_ne!(term.style(2, 78), Some(styles::CURSORLINE));
+
+        // It moves with the cursor
+        state.command("j");
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        assert_eq!(term.style(2, 78), Some(styles::CURSORLINE));
+        assert_ne!(term.style(1, 78), Some(styles::CURSORLINE));
+
+        // Selection wins
+        state.command("vl");
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        assert_eq!(term.style(2, col), Some(styles::SELECTED));
+        state.command_esc("");
+
+        state.command(":set nocursorline\n");
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        assert_ne!(term.style(2, 78), Some(styles::CURSORLINE));
+    });
+}
+
+#[test]
+fn test_change_list() {
+    with_buffer_mode_from("aa\nbb\ncc\ndd\nee", |mut state| {
+        state.command("g;");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("At start of change list")
+        );
+
+        state.command("x");
+        state.command("jjx");
+        state.command_esc("jjAz");
+        state.command("gg");
+        let pos = |state: &BufferMode| (state.buf.core.cursor().row, state.buf.core.cursor().col);
+
+        state.command("g;");
+        assert_eq!(pos(&state).0, 4);
+        state.command("g;");
+        assert_eq!(pos(&state), (2, 0));
+        state.command("g;");
+        assert_eq!(pos(&state).0, 0);
+        state.command("g;");
+        assert_eq!(pos(&state).0, 0);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("At start of change list")
+        );
+
+        state.command("g,");
+        assert_eq!(pos(&state), (2, 0));
+        state.command("g,g,");
+        assert_eq!(pos(&state).0, 4);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("At end of change list")
+        );
+
+        state.command("2g;");
+        assert_eq!(pos(&state).0, 0);
+
+        // A new change starts from the latest again
+        state.command("jx");
+        state.command("gg");
+        state.command("g;");
+        assert_eq!(pos(&state).0, 1);
+        state.command("g;");
+        assert_eq!(pos(&state).0, 4);
+    });
+}
+
+#[test]
+fn test_write_path() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_write_path_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    let copy = dir.join("b.txt");
+    fs::write(&file, "abc\n").unwrap();
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&file);
+    let mut state = BufferMode::new(buf);
+
+    // A copy. The buffer keeps its path and changes.
+    state.command("x");
+    state.command(&format!(":w {}\n", copy.display()));
+    assert_eq!(fs::read_to_string(&copy).unwrap(), "bc\n");
+    assert_eq!(state.buf.path(), Some(file.as_path()));
+    assert!(state.buf.is_modified());
+    state.command(":w\n");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "bc\n");
+
+    // A buffer without a path takes it only when it is written
+    with_buffer_mode_from("abc", |mut state| {
+        let missing = dir.join("no_such_directory").join("c.txt");
+        state.command(&format!(":w {}\n", missing.display()));
+        assert_eq!(state.buf.path(), None);
+        assert!(state.buf.is_modified());
+
+        let path = dir.join("c.txt");
+        state.command(&format!(":w {}\n", path.display()));
+        assert_eq!(state.buf.path(), Some(path.as_path()));
+        assert!(!state.buf.is_modified());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\n");
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_save_all() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_wa_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    fs::write(&file, "abc\n").unwrap();
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&file);
+    let mut state = BufferMode::new(buf);
+    let last_message = |state: &BufferMode| state.buf.messages.back().cloned();
+
+    assert!(!state.buf.is_modified());
+    state.command(":wa\n");
+    assert_eq!(last_message(&state).as_deref(), Some("No changes to save"));
+
+    state.command("x");
+    assert!(state.buf.is_modified());
+    state.command(":wa\n");
+    assert_eq!(
+        last_message(&state),
+        Some(format!("Saved to {}", file.display()))
+    );
+    assert!(!state.buf.is_modified());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "bc\n");
+
+    // `:wqa` quits after saving
+    state.command("x:wqa");
+    assert!(state.event(Event::Key(Key::Char('\n'))));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "c\n");
+
+    // and quits without saving if nothing has changed
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&file);
+    let mut state = BufferMode::new(buf);
+    state.command(":wqa");
+    assert!(state.event(Event::Key(Key::Char('\n'))));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "c\n");
+
+    // A modified buffer without a path is reported and `:wqa` does not quit
+    with_buffer_mode_from("abc", |mut state| {
+        assert!(state.buf.is_modified());
+        state.command(":wqa");
+        assert!(!state.event(Event::Key(Key::Char('\n'))));
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No file name")
+        );
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scrollbar() {
+    use accepted::draw::Term;
+
+    let text: String = (0..100).map(|i| format!("{}\n", i)).collect();
+    with_buffer_mode_from(&text, |mut state| {
+        // Disabled by default
+        assert!(!state.buf.click_scrollbar(5, 79, 10, 80));
+
+        state.command(":set scrollbar\n");
+        assert!(state.buf.click_scrollbar(5, 79, 10, 80));
+        assert_eq!(state.buf.row_offset(), 50);
+        assert!(state.buf.click_scrollbar(0, 79, 10, 80));
+        assert_eq!(state.buf.row_offset(), 0);
+        assert!(state.buf.click_scrollbar(9, 79, 10, 80));
+        assert_eq!(state.buf.row_offset(), 90);
+
+        // Clicks in the text area are not taken
+        assert!(!state.buf.click_scrollbar(5, 10, 10, 80));
+        assert!(!state.buf.click_scrollbar(10, 79, 10, 80));
+        assert_eq!(state.buf.row_offset(), 90);
+
+        let mut term = Term::new(11, 80);
+        state.draw(&mut term);
+        assert!(term.row_string(7).ends_with('│'));
+        assert!(term.row_string(8).ends_with('█'));
+        assert!(term.row_string(9).ends_with('│'));
+    });
+}
+
+#[test]
+fn test_scrollbar_column() {
+    use accepted::draw::Term;
+
+    with_buffer_mode_from(&"a".repeat(40), |mut state| {
+        state.command(
+            ":set scrollbar
+$",
+        );
+        let mut term = Term::new(6, 20);
+        state.draw(&mut term);
+        // Text wraps before the scrollbar
+        assert_eq!(term.row_string(0), format!("1  {} █", "a".repeat(15)));
+        assert_eq!(term.row_string(1), format!("   {} █", "a".repeat(15)));
+        assert_eq!(term.row_string(2), format!("   {}      █", "a".repeat(10)));
+    });
+}
+
+#[test]
+fn test_mouse_split_window() {
+    use accepted::draw::Term;
+    use termion::event::{MouseButton, MouseEvent};
+
+    with_buffer_mode_from("abc\ndef\nghi", |mut state| {
+        state.command(":vsplit\n");
+        let mut term = Term::new(10, 40);
+        state.draw(&mut term);
+        assert_eq!(state.window_rects(10, 40)[1], (0, 20, 10, 20));
+
+        // Clicking the right window focuses it and moves the cursor there
+        state.event(Event::Mouse(MouseEvent::Press(MouseButton::Left, 25, 2)));
+        assert_eq!(state.current_window(), 1);
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 1);
+
+        state.event(Event::Mouse(MouseEvent::Press(MouseButton::Left, 6, 3)));
+        assert_eq!(state.current_window(), 0);
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert_eq!(state.buf.core.cursor().col, 2);
+    });
+}
+
+#[test]
+fn test_retab() {
+    with_buffer_mode_from("fn f() {\n\tif x {\n\t\ty; // a\tb\n\t}\n}", |mut state| {
+        state.command(":retab\n");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        y; // a\tb\n    }\n}"
+        );
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("3 lines changed")
+        );
+
+        state.command(":retab!\n");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n\tif x {\n\t\ty; // a\tb\n\t}\n}"
+        );
+
+        state.command("u");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        y; // a\tb\n    }\n}"
+        );
+    });
+
+    // Mixed indentation and ranges
+    with_buffer_mode_from("  \tx\n      y\n  z", |mut state| {
+        state.command(":1,2retab!\n");
+        assert_eq!(state.buf.core.get_string(), "\tx\n\t  y\n  z");
+    });
+}
+
+#[test]
+fn test_indent_motion() {
+    let text = "fn f() {\n    if x {\n        a;\n        b;\n    }\n\n    c;\n}";
+    with_buffer_mode_from(text, |mut state| {
+        let pos = |state: &BufferMode| (state.buf.core.cursor().row, state.buf.core.cursor().col);
+
+        state.command("jjj[[");
+        assert_eq!(pos(&state), (1, 4));
+        state.command("[[");
+        assert_eq!(pos(&state), (0, 0));
+        state.command("]]");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No less indented line")
+        );
+
+        // Blank lines are skipped
+        state.command("4j]]");
+        assert_eq!(pos(&state), (7, 0));
+
+        state.command("gg3j2[[");
+        assert_eq!(pos(&state), (0, 0));
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(pos(&state).0, 3);
+
+        state.command("k]]");
+        assert_eq!(pos(&state), (4, 4));
+
+        // Enclosing braces
+        state.command("kkfa[{");
+        assert_eq!(pos(&state), (1, 9));
+        state.command("j]}");
+        assert_eq!(pos(&state), (4, 4));
+        state.command("2[{");
+        assert_eq!(pos(&state), (0, 7));
+    });
+
+    // As operator motions
+    simple_test(text, "jjd]]", "fn f() {\n    if x {\n    }\n\n    c;\n}");
+    simple_test(text, "jjjd[[", "fn f() {\n    if x {\n    }\n\n    c;\n}");
+    simple_test(
+        text,
+        "jjfad]}",
+        "fn f() {\n    if x {\n        }\n\n    c;\n}",
+    );
+}
+
+#[test]
+fn test_zen() {
+    use accepted::draw::{CursorState, Term};
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nzen_width = 40").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core
+        .set_string(format!("{}\nabc", "x".repeat(50)), true);
+    let mut state = BufferMode::new(buf);
+
+    let mut term = Term::new(24, 80);
+    state.draw(&mut term);
+    assert!(term.row_string(0).starts_with("1  xxx"));
+
+    state.command(":zen\nj");
+    assert!(state.buf.zen);
+    let mut term = Term::new(24, 80);
+    state.draw(&mut term);
+    // 40 columns in the middle without line numbers. The last column is left for wrapping.
+    assert_eq!(
+        term.row_string(0),
+        format!("{}{}{}", " ".repeat(20), "x".repeat(39), " ".repeat(21))
+    );
+    assert_eq!(
+        term.row_string(1).trim_end(),
+        format!("{}{}", " ".repeat(20), "x".repeat(11))
+    );
+    assert_eq!(term.row_string(2).trim(), "abc");
+    assert!(term.row_string(2).starts_with(&" ".repeat(20)));
+    match term.cursor {
+        CursorState::Show(c, _) => assert_eq!((c.row, c.col), (2, 20)),
+        CursorState::Hide => panic!("cursor is hidden"),
+    }
+
+    state.command(":zen\n");
+    assert!(!state.buf.zen);
+    let mut term = Term::new(24, 80);
+    state.draw(&mut term);
+    assert!(term.row_string(0).starts_with("1  xxx"));
+    assert!(term.row_string(1).starts_with("2  abc"));
+}
+
+#[test]
+fn test_cursor_shape() {
+    use accepted::draw::{CursorShape, CursorState, Term};
+
+    let shape = |state: &mut BufferMode| {
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        match term.cursor {
+            CursorState::Show(_, shape) => shape,
+            CursorState::Hide => panic!("cursor is hidden"),
+        }
+    };
+
+    with_buffer_mode(|mut state| {
+        assert_eq!(shape(&mut state), CursorShape::Block);
+        state.command("i");
+        assert_eq!(shape(&mut state), CursorShape::Bar);
+    });
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default(
+        "[file_default]\ncursor_normal = \"underline\"\ncursor_insert = \"block\"\ncursor_replace = \"triangle\"\ncursor_visual = \"bar\"",
+    )
+    .unwrap();
+    let buf = Buffer::new(&syntax_parent, &config);
+    let mut state = BufferMode::new(buf);
+    assert_eq!(shape(&mut state), CursorShape::Underline);
+    state.command("i");
+    assert_eq!(shape(&mut state), CursorShape::Block);
+    state.event(Event::Key(Key::Esc));
+    state.command("v");
+    assert_eq!(shape(&mut state), CursorShape::Bar);
+    state.event(Event::Key(Key::Esc));
+    // An unknown shape falls back to the default
+    state.command("R");
+    assert_eq!(shape(&mut state), CursorShape::Underline);
+}
+
+#[test]
+fn test_hex_view() {
+    use accepted::draw::Term;
+
+    with_buffer_mode_from("hello\tworld!\nabcdefgh", |mut state| {
+        assert_eq!(
+            state.buf.hex_dump(),
+            vec![
+                "00000000  68 65 6c 6c 6f 09 77 6f  72 6c 64 21 0a 61 62 63  |hello.world!.abc|",
+                "00000010  64 65 66 67 68                                    |defgh|",
+            ]
+        );
+
+        state.command(":xxd\n");
+        let mut term = Term::new(24, 100);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("00000000  68 65 6c"));
+        assert!(term.row_string(1).trim_end().ends_with("|defgh|"));
+
+        // Read-only and back to the text
+        state.command("xj");
+        state.event(Event::Key(Key::Esc));
+        assert_eq!(state.buf.core.get_string(), "hello\tworld!\nabcdefgh");
+        let mut term = Term::new(24, 100);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("1  hello"));
+    });
+}
+
+#[test]
+fn test_final_newline() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_eol_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+
+    let saved = |policy: Option<&str>, text: &str| {
+        let config = match policy {
+            Some(policy) => config::parse_config_with_default(&format!(
+                "[file_default]\nfinal_newline = \"{}\"",
+                policy
+            ))
+            .unwrap(),
+            None => config::ConfigWithDefault::default(),
+        };
+        let mut buf = Buffer::new(&syntax_parent, &config);
+        buf.core.set_string(text.into(), true);
+        buf.set_path(file.clone());
+        assert!(buf.save(false));
+        // The buffer is not touched
+        assert_eq!(buf.core.get_string(), text);
+        fs::read_to_string(&file).unwrap()
+    };
+
+    // `ensure` is the default
+    assert_eq!(saved(None, "a\nb"), "a\nb\n");
+    assert_eq!(saved(Some("ensure"), "a\nb"), "a\nb\n");
+    assert_eq!(saved(Some("ensure"), "a\nb\n"), "a\nb\n");
+    assert_eq!(saved(Some("ensure"), "a\nb\n\n\n"), "a\nb\n\n\n");
+    assert_eq!(saved(Some("ensure"), ""), "");
+
+    assert_eq!(saved(Some("preserve"), "a\nb"), "a\nb");
+    assert_eq!(saved(Some("preserve"), "a\nb\n"), "a\nb\n");
+    assert_eq!(saved(Some("preserve"), "a\nb\n\n\n"), "a\nb\n\n\n");
+
+    assert_eq!(saved(Some("trim"), "a\nb"), "a\nb\n");
+    assert_eq!(saved(Some("trim"), "a\nb\n"), "a\nb\n");
+    assert_eq!(saved(Some("trim"), "a\n\nb\n\n  \n\n"), "a\n\nb\n");
+    assert_eq!(saved(Some("trim"), "\n\n"), "");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_atomic_write() {
+    use accepted::atomic_write;
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_atomic_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    fs::write(&file, "original\n").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+
+    // Interrupted before the rename
+    let result = atomic_write::write_with(&file, b"new\n", |temp| {
+        assert_eq!(fs::read_to_string(temp).unwrap(), "new\n");
+        Err(io::Error::other("interrupted"))
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "original\n");
+    // The temporary file is removed
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    atomic_write::write(&file, b"new\n").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "new\n");
+    let mode = fs::metadata(&file).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    // A failed save reports why
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.set_path(dir.join("missing").join("b.txt"));
+    assert!(!buf.save(false));
+    assert!(buf
+        .messages
+        .back()
+        .is_some_and(|m| m.starts_with("Failed to save")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_autoread() {
+    use accepted::DiskChange;
+    use std::fs;
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_autoread_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("a.txt");
+    fs::write(&file, "abc\ndef\n").unwrap();
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&file);
+    let opened = buf.disk_mtime();
+    let later = opened.map(|t| t + Duration::from_secs(10));
+
+    // Off by default
+    assert_eq!(buf.disk_change(later), DiskChange::Unchanged);
+
+    let mut state = BufferMode::new(buf);
+    for c in ":set autoread\n".chars() {
+        state.event(Event::Key(Key::Char(c)));
+    }
+    assert!(state.buf.autoread);
+    assert_eq!(state.buf.disk_change(opened), DiskChange::Unchanged);
+    assert_eq!(state.buf.disk_change(None), DiskChange::Unchanged);
+    assert_eq!(state.buf.disk_change(later), DiskChange::Reload);
+
+    // Unmodified: reload keeping the cursor
+    fs::write(&file, "xyz\ndef\nghi\n").unwrap();
+    for c in "jl".chars() {
+        state.event(Event::Key(Key::Char(c)));
+    }
+    state.check_disk(later);
+    assert_eq!(state.buf.core.get_string(), "xyz\ndef\nghi\n");
+    assert_eq!(state.buf.core.cursor().row, 1);
+    assert_eq!(state.buf.core.cursor().col, 1);
+    assert!(state.buf.messages.back().unwrap().starts_with("Reloaded"));
+
+    // Modified: ask. `n` keeps the buffer and stops asking for the same file.
+    let latest = later.map(|t| t + Duration::from_secs(10));
+    state.event(Event::Key(Key::Char('x')));
+    assert!(state.buf.is_modified());
+    assert_eq!(state.buf.disk_change(latest), DiskChange::Conflict);
+    state.check_disk(latest);
+    state.event(Event::Key(Key::Char('n')));
+    assert_eq!(state.buf.core.get_string(), "xyz\ndf\nghi\n");
+    assert_eq!(state.buf.disk_change(latest), DiskChange::Unchanged);
+
+    // `y` discards the changes
+    let newest = latest.map(|t| t + Duration::from_secs(10));
+    state.check_disk(newest);
+    state.event(Event::Key(Key::Char('y')));
+    assert_eq!(state.buf.core.get_string(), "xyz\ndef\nghi\n");
+    assert!(!state.buf.is_modified());
+
+    // Not while an operator is pending
+    state.event(Event::Key(Key::Char('d')));
+    state.check_disk(newest.map(|t| t + Duration::from_secs(10)));
+    state.event(Event::Key(Key::Char('d')));
+    assert_eq!(state.buf.core.get_string(), "xyz\nghi\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sentence_motion() {
+    let text = "One two. Three! Four\nfive? Six (e.g. seven).\n\nNew paragraph";
+    with_buffer_mode_from(text, |mut state| {
+        let pos = |state: &BufferMode| (state.buf.core.cursor().row, state.buf.core.cursor().col);
+
+        state.command("ll)");
+        assert_eq!(pos(&state), (0, 9));
+        state.command(")");
+        assert_eq!(pos(&state), (0, 16));
+        // Across a line end
+        state.command(")");
+        assert_eq!(pos(&state), (1, 6));
+        // Abbreviations end a sentence
+        state.command(")");
+        assert_eq!(pos(&state), (1, 16));
+        // An empty line ends a paragraph
+        state.command(")");
+        assert_eq!(pos(&state), (3, 0));
+        state.command(")");
+        assert_eq!(pos(&state), (3, 0));
+
+        state.command("(");
+        assert_eq!(pos(&state), (1, 16));
+        state.command("2(");
+        assert_eq!(pos(&state), (0, 16));
+        state.command("ll(");
+        assert_eq!(pos(&state), (0, 16));
+        state.command("3(");
+        assert_eq!(pos(&state), (0, 0));
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(pos(&state), (0, 16));
+    });
+
+    // As operator motions
+    simple_test(
+        text,
+        "d)",
+        "Three! Four\nfive? Six (e.g. seven).\n\nNew paragraph",
+    );
+    simple_test(
+        text,
+        "fTd)",
+        "One two. Four\nfive? Six (e.g. seven).\n\nNew paragraph",
+    );
+    simple_test(
+        text,
+        "fFd(",
+        "One two. Four\nfive? Six (e.g. seven).\n\nNew paragraph",
+    );
+    simple_test(
+        text,
+        "jfSc)X. ",
+        "One two. Three! Four\nfive? X. seven).\n\nNew paragraph",
+    );
+    // Parens text objects are kept
+    simple_test(
+        text,
+        "jf(ldi)",
+        "One two. Three! Four\nfive? Six ().\n\nNew paragraph",
+    );
+}
+
+#[test]
+fn test_background_load() {
+    use accepted::indent::IndentStyle;
+    use std::io::Read;
+    use std::sync::mpsc::{channel, Receiver};
+
+    // Returns one chunk per read and blocks until the next is sent
+    struct SlowReader(Receiver<&'static [u8]>);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.recv() {
+                Ok(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+
+    let (tx, rx) = channel::<&[u8]>();
+    tx.send(b"abc\ndef\ngh").unwrap();
+    buf.load_from(SlowReader(rx), 20);
+    // The first chunk is there before the rest is read
+    assert!(buf.is_loading());
+    assert_eq!(buf.load_progress(), Some(50));
+    assert_eq!(buf.core.get_string(), "abc\ndef\ngh");
+    assert_eq!(buf.core.buffer().len_lines(), 3);
+
+    // A multi-byte char split across chunks is kept whole
+    tx.send(b"i\n\xc3").unwrap();
+    tx.send(b"\xa9x\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    buf.poll_load();
+    assert!(buf.is_loading());
+    assert_eq!(buf.core.get_string(), "abc\ndef\nghi\néx\n");
+    assert!(!buf.is_modified());
+
+    // Moves near the cursor don't wait for the rest
+    let mut state = BufferMode::new(buf);
+    state.command("jl");
+    assert!(state.buf.is_loading());
+    assert_eq!(state.buf.core.cursor().row, 1);
+
+    // `G` needs the whole text
+    tx.send(b"\tend\n").unwrap();
+    drop(tx);
+    assert_eq!(state.buf.indent_style, None);
+    state.command("G");
+    assert!(!state.buf.is_loading());
+    assert_eq!(state.buf.load_progress(), None);
+    assert_eq!(state.buf.core.get_string(), "abc\ndef\nghi\néx\n\tend\n");
+    // The indent is detected from the loaded text
+    assert_eq!(state.buf.indent_style, Some(IndentStyle::Tabs));
+    assert_eq!(state.buf.core.cursor().row, 5);
+    assert!(!state.buf.is_modified());
+}
+
+#[test]
+fn test_virtual_edit() {
+    use accepted::draw::{CursorState, Term};
+
+    // Off by default
+    simple_test("ab\nabcdef", "lllllix", "abx\nabcdef");
+
+    with_buffer_mode_from("ab\nabcdef\n", |mut state| {
+        let pos = |state: &BufferMode| (state.buf.core.cursor().col, state.buf.core.virtual_col());
+        state.command(":set virtualedit=all\n");
+        state.command("lllll");
+        assert_eq!(pos(&state), (2, 5));
+
+        // Drawn past the line end
+        let mut term = Term::new(24, 80);
+        state.draw(&mut term);
+        match term.cursor {
+            CursorState::Show(c, _) => assert_eq!((c.row, c.col), (0, 8)),
+            CursorState::Hide => panic!("cursor is hidden"),
+        }
+
+        // The column is kept across shorter lines
+        state.command("j");
+        assert_eq!(pos(&state), (5, 5));
+        state.command("jk");
+        assert_eq!(pos(&state), (5, 5));
+        state.command("k");
+        assert_eq!(pos(&state), (2, 5));
+        state.command("hh");
+        assert_eq!(pos(&state), (2, 3));
+
+        // Typing pads with spaces
+        state.command_esc("iXY");
+        assert_eq!(state.buf.core.get_string(), "ab XY\nabcdef\n");
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "ab\nabcdef\n");
+
+        state.command("jj$llllax");
+        assert_eq!(state.buf.core.get_string(), "ab\nabcdef\n     x");
+
+        // Other moves drop the column
+        state.event(Event::Key(Key::Esc));
+        state.command("gg$lll0");
+        assert_eq!(pos(&state), (0, 0));
+
+        state.command(":set novirtualedit\n");
+        state.command("$lll");
+        assert_eq!(pos(&state), (2, 2));
+    });
+}
+
+#[test]
+fn test_breakindent() {
+    use accepted::draw::{CursorState, Term};
+
+    let cursor = |term: &Term| match term.cursor {
+        CursorState::Show(c, _) => (c.row, c.col),
+        CursorState::Hide => panic!("cursor is hidden"),
+    };
+    let text = format!("    {}", "a".repeat(30));
+    with_buffer_mode_from(&text, |mut state| {
+        state.command("$");
+
+        // 3 columns of line numbers and 16 of text
+        let mut term = Term::new(5, 20);
+        state.draw(&mut term);
+        assert_eq!(term.row_string(0), format!("1      {} ", "a".repeat(12)));
+        assert_eq!(term.row_string(1), format!("   {} ", "a".repeat(16)));
+        assert_eq!(cursor(&term), (2, 5));
+
+        state.command(":set breakindent\n");
+        let mut term = Term::new(5, 20);
+        state.draw(&mut term);
+        assert_eq!(term.row_string(0), format!("1      {} ", "a".repeat(12)));
+        assert_eq!(term.row_string(1), format!("       {} ", "a".repeat(12)));
+        assert_eq!(
+            term.row_string(2).trim_end(),
+            format!("       {}", "a".repeat(6))
+        );
+        assert_eq!(cursor(&term), (2, 13));
+
+        state.command(":set nobreakindent\n");
+        assert!(!state.buf.breakindent);
+    });
+
+    // Scrolling counts the rows of tab indents as drawn
+    let text = format!("\t\t{}\nb", "a".repeat(38));
+    with_buffer_mode_from(&text, |mut state| {
+        state.resize(6, 20);
+        state.command(":set list\n:set breakindent\nj");
+        let mut term = Term::new(6, 20);
+        state.draw(&mut term);
+        assert_eq!(state.buf.row_offset(), 1);
+        assert_eq!(cursor(&term), (0, 3));
+    });
+}
+
+#[test]
+fn test_insert_register() {
+    with_buffer_mode_from("foo bar\nbaz\nfoo", |mut state| {
+        let ctrl_r = |state: &mut BufferMode, register: char| {
+            state.event(Event::Key(Key::Ctrl('r')));
+            state.event(Event::Key(Key::Char(register)));
+        };
+
+        // Unnamed and named registers in Insert mode
+        state.command("\"ayyywjA ");
+        ctrl_r(&mut state, '"');
+        state.command("-");
+        ctrl_r(&mut state, 'a');
+        state.command("!");
+        // An empty register inserts nothing
+        ctrl_r(&mut state, 'z');
+        state.event(Event::Key(Key::Esc));
+        assert_eq!(
+            state.buf.core.get_string(),
+            "foo bar\nbaz foo -foo bar\n!\nfoo"
+        );
+
+        // Undone with the rest of the insertion
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "foo bar\nbaz\nfoo");
+
+        // Search prompt
+        state.command("gg\"bywjj0/");
+        ctrl_r(&mut state, 'b');
+        assert_eq!(state.buf.search.iter().collect::<String>(), "foo ");
+        state.command("\n");
+
+        // Command prompt. Line breaks become spaces.
+        state.command("gg:s/");
+        ctrl_r(&mut state, 'b');
+        state.command("/x/\n");
+        assert_eq!(state.buf.core.get_string(), "xbar\nbaz\nfoo");
+        state.command("jj:s/foo/");
+        ctrl_r(&mut state, 'a');
+        state.command("/\n");
+        assert_eq!(state.buf.core.get_string(), "xbar\nbaz\nfoo bar");
+    });
+}
+
+#[test]
+fn test_mark_operator() {
+    let text = "one\n  two\nthree\nfour\nfive";
+    // Down and up
+    simple_test(text, "jmajjggd'a", "three\nfour\nfive");
+    simple_test(text, "jjjmakkd'a", "one\nfive");
+    simple_test(text, "jjmad'a", "one\n  two\nfour\nfive");
+    simple_test(text, "jjjmakkd'ap", "one\nfive\n  two\nthree\nfour");
+
+    // Characterwise up to the mark
+    simple_test(text, "jlllmajjd`a", "one\n  tr\nfive");
+    simple_test(text, "jjlmakkd`a", "ohree\nfour\nfive");
+
+    // Quote text objects are kept
+    simple_test("say 'hi' now", "fhdi'", "say '' now");
+
+    with_buffer_mode_from(text, |mut state| {
+        state.command("d'b");
+        assert_eq!(state.buf.core.get_string(), text);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Mark not set: b")
+        );
+
+        state.command("jlllmajjy`a");
+        assert_eq!(state.buf.yank.content, "wo\nthree\nfou");
+        assert!(!state.buf.yank.insert_newline);
+        state.command("y'a");
+        assert_eq!(state.buf.yank.content, "  two\nthree\nfour");
+        assert!(state.buf.yank.insert_newline);
+        state.command("gg");
+
+        // Jumps
+        state.command("jmbG'b");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("G`b");
+        assert_eq!(state.buf.core.cursor().col, 0);
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(state.buf.core.cursor().row, 4);
+    });
+}
+
+#[test]
+fn test_resize() {
+    use accepted::draw::{CursorState, Term};
+
+    let text = (1..=40)
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    with_buffer_mode_from(&text, |mut state| {
+        let cursor_row = |state: &mut BufferMode, height: usize| {
+            let mut term = Term::new(height, 80);
+            state.draw(&mut term);
+            match term.cursor {
+                CursorState::Show(c, _) => Some(c.row),
+                CursorState::Hide => None,
+            }
+        };
+
+        state.resize(24, 80);
+        state.command("20j");
+        assert_eq!(cursor_row(&mut state, 24), Some(20));
+
+        // Shrink below the cursor
+        state.resize(10, 80);
+        let row = cursor_row(&mut state, 10).expect("cursor is hidden");
+        assert!(row < 9);
+        assert!(state.buf.row_offset() > 0);
+
+        // Split windows keep the cursor in the focused one
+        state.command(":sp\n");
+        state.resize(12, 80);
+        let rects = state.window_rects(12, 80);
+        let (top, _, height, _) = rects[state.current_window()];
+        let row = cursor_row(&mut state, 12).expect("cursor is hidden");
+        assert!(top <= row && row < top + height - 1);
+    });
+}
+
+#[test]
+fn test_line_motions() {
+    let text = "one  \n  two\t \n\tthree\nfour";
+    with_buffer_mode_from(text, |mut state| {
+        // g_ skips trailing whitespace
+        state.command("g_");
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("jg_");
+        assert_eq!(state.buf.core.cursor().col, 4);
+        state.command("gg2g_");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 4);
+
+        // + and Enter skip leading spaces and tabs
+        state.command("gg+");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("\n");
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert_eq!(state.buf.core.cursor().col, 1);
+        state.command("-");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("gg3+");
+        assert_eq!(state.buf.core.cursor().row, 3);
+        state.command("9+");
+        assert_eq!(state.buf.core.cursor().row, 3);
+        state.command("2-");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+    });
+
+    // Linewise operators
+    simple_test(text, "d+", "\tthree\nfour");
+    simple_test(text, "jd\n", "one  \nfour");
+    simple_test(text, "jjd-", "one  \nfour");
+    simple_test(text, "2d+", "four");
+    simple_test(text, "Gd+", text);
+    simple_test(text, "d-", text);
+    simple_test(text, "jlldg_", "one  \n  \t \n\tthree\nfour");
+    simple_test("a b", "dt-", "a b");
+}
+
+#[test]
+fn test_scroll_line() {
+    let text = (1..=40)
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    with_buffer_mode_from(&text, |mut state| {
+        state.resize(10, 80);
+        let ctrl = |state: &mut BufferMode, c: char, n: usize| {
+            for _ in 0..n {
+                state.event(Event::Key(Key::Ctrl(c)));
+            }
+        };
+
+        // The cursor stays until it reaches the top
+        state.command("5j");
+        ctrl(&mut state, 'e', 3);
+        assert_eq!(state.buf.row_offset(), 3);
+        assert_eq!(state.buf.core.cursor().row, 5);
+        ctrl(&mut state, 'e', 3);
+        assert_eq!(state.buf.row_offset(), 6);
+        assert_eq!(state.buf.core.cursor().row, 6);
+        state.command("3");
+        ctrl(&mut state, 'e', 1);
+        assert_eq!(state.buf.row_offset(), 9);
+        assert_eq!(state.buf.core.cursor().row, 9);
+
+        // and the bottom
+        state.command("j");
+        ctrl(&mut state, 'y', 1);
+        assert_eq!(state.buf.row_offset(), 8);
+        assert_eq!(state.buf.core.cursor().row, 10);
+        ctrl(&mut state, 'y', 8);
+        assert_eq!(state.buf.row_offset(), 0);
+        assert_eq!(state.buf.core.cursor().row, 8);
+        ctrl(&mut state, 'y', 1);
+        assert_eq!(state.buf.row_offset(), 0);
+
+        // Not past the last line
+        state.command("G");
+        ctrl(&mut state, 'e', 50);
+        assert_eq!(state.buf.row_offset(), 39);
+        assert_eq!(state.buf.core.cursor().row, 39);
+    });
+}
+
+#[test]
+fn test_new_buffer() {
+    use accepted::draw::Term;
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_new_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("new.txt");
+
+    with_buffer_mode_from("abc\ndef", |mut state| {
+        // Unsaved changes are kept without `!`
+        state.command("x:enew\n");
+        assert_eq!(state.buf.core.get_string(), "bc\ndef");
+        state.command(":enew!\n");
+        assert_eq!(state.buf.core.get_string(), "");
+        assert_eq!(state.buf.core.buffer().len_lines(), 1);
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 0));
+        assert!(state.buf.path().is_none());
+        assert!(!state.buf.is_modified());
+
+        let mut term = Term::new(10, 40);
+        state.draw(&mut term);
+        assert!(term.row_string(9).contains("[No Name]"));
+
+        // Saving asks for a path
+        state.command_esc("ihello");
+        state.command(":w\n");
+        let mut term = Term::new(10, 40);
+        state.draw(&mut term);
+        assert!(term.row_string(9).starts_with("> "));
+        state.command(&format!("{}\n", file.display()));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+        assert_eq!(state.buf.path(), Some(file.as_path()));
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_search_offset() {
+    with_buffer_mode_from("xfoo bar\nbaz foobar\nx\ny\nz", |mut state| {
+        let cursor = |state: &BufferMode| state.buf.core.cursor().into_tuple();
+        // `/` edits the last query
+        let search = |state: &mut BufferMode, s: &str| {
+            state.command("/");
+            for _ in 0..state.buf.search.len() {
+                state.event(Event::Key(Key::Backspace));
+            }
+            state.command(s);
+        };
+
+        // End of the match
+        search(&mut state, "foo/e\n");
+        state.command("n");
+        assert_eq!(cursor(&state), (0, 3));
+        state.command("n");
+        assert_eq!(cursor(&state), (1, 6));
+        state.command("N");
+        assert_eq!(cursor(&state), (0, 3));
+        search(&mut state, "foo/e+1\n");
+        state.command("ggn");
+        assert_eq!(cursor(&state), (0, 4));
+        assert_eq!(state.buf.search.iter().collect::<String>(), "foo");
+
+        // Lines below the match
+        search(&mut state, "foo/+2\n");
+        state.command("ggn");
+        assert_eq!(cursor(&state), (2, 0));
+        state.command("n");
+        assert_eq!(cursor(&state), (3, 0));
+        state.command("N");
+        assert_eq!(cursor(&state), (2, 0));
+
+        // Invalid offsets are ignored
+        search(&mut state, "foo/x\n");
+        state.command("ggn");
+        assert_eq!(cursor(&state), (0, 1));
+        assert_eq!(state.buf.search.iter().collect::<String>(), "foo");
+    });
+
+    // An escaped slash is a part of the pattern
+    with_buffer_mode_from("a b/c", |mut state| {
+        state.command("/b\\/c\nn");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 2));
+    });
+}
+
+#[test]
+fn test_insert_shift() {
+    let shift = |state: &mut BufferMode, c: char| state.event(Event::Key(Key::Ctrl(c)));
+    with_buffer_mode_from("foo bar\n  baz\n\tqux", |mut state| {
+        // The cursor keeps its place in the text
+        state.command("wi");
+        shift(&mut state, 't');
+        assert_eq!(state.buf.core.get_string(), "    foo bar\n  baz\n\tqux");
+        state.command("x");
+        assert_eq!(state.buf.core.get_string(), "    foo xbar\n  baz\n\tqux");
+        shift(&mut state, 't');
+        shift(&mut state, 'd');
+        shift(&mut state, 'd');
+        shift(&mut state, 'd');
+        state.command("y");
+        state.command_esc("");
+        assert_eq!(state.buf.core.get_string(), "foo xybar\n  baz\n\tqux");
+
+        // Rounded to `indent_width`
+        state.command("jA");
+        shift(&mut state, 't');
+        assert_eq!(state.buf.core.get_string(), "foo xybar\n    baz\n\tqux");
+        state.command_esc("");
+        state.command("jI");
+        shift(&mut state, 'd');
+        state.command("!");
+        state.command_esc("");
+        assert_eq!(state.buf.core.get_string(), "foo xybar\n    baz\n!qux");
+
+        // One undo step with the insertion
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "foo xybar\n    baz\n\tqux");
+    });
+}
+
+#[test]
+fn test_rainbow_brackets() {
+    use accepted::draw::{bracket_depths, styles, Term};
+
+    let mut depth = 0;
+    assert_eq!(
+        bracket_depths("f(a[b{c}d]e)g)".chars(), &mut depth),
+        vec![(1, 0), (3, 1), (5, 2), (7, 2), (9, 1), (11, 0), (13, 0)]
+    );
+    assert_eq!(depth, 0);
+
+    // Carried to the next line
+    assert_eq!(
+        bracket_depths("({".chars(), &mut depth),
+        vec![(0, 0), (1, 1)]
+    );
+    assert_eq!(
+        bracket_depths("x})".chars(), &mut depth),
+        vec![(1, 1), (2, 0)]
+    );
+
+    // Capped
+    bracket_depths("(".repeat(1000).chars(), &mut depth);
+    assert_eq!(depth, 64);
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config =
+        config::parse_config_with_default("[file_default]\nrainbow_brackets = true").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a(b[c]d\n)".into(), true);
+    let mut state = BufferMode::new(buf);
+    let mut term = Term::new(5, 20);
+    state.draw(&mut term);
+    let offset = term.row_string(0).find('a').unwrap();
+    let fg = |row: usize, col: usize| term.style(row, offset + col).unwrap().fg;
+    assert_eq!(fg(0, 1), styles::RAINBOW[0]);
+    assert_eq!(fg(0, 3), styles::RAINBOW[1]);
+    assert_eq!(fg(0, 5), styles::RAINBOW[1]);
+    assert_eq!(fg(1, 0), styles::RAINBOW[0]);
+    assert_ne!(fg(0, 2), styles::RAINBOW[0]);
+}
+
+#[test]
+fn test_alternate_file() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_alternate_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    fs::write(&a, "a1\na2\na3\n").unwrap();
+    fs::write(&b, "b1\n").unwrap();
+
+    with_buffer_mode(|mut state| {
+        state.event(Event::Key(Key::Ctrl('6')));
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No alternate file")
+        );
+
+        state.command(&format!(":e {}\njj", a.display()));
+        state.command(&format!(":e {}\n", b.display()));
+        assert_eq!(state.buf.path(), Some(b.as_path()));
+
+        // Back and forth with the cursor kept
+        state.event(Event::Key(Key::Ctrl('6')));
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+        assert_eq!(state.buf.core.cursor().row, 2);
+        state.command(":b#\n");
+        assert_eq!(state.buf.path(), Some(b.as_path()));
+        state.command(":e#\n");
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+
+        // Unsaved changes are kept
+        state.command("x");
+        state.event(Event::Key(Key::Ctrl('6')));
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+        state.command(":b#\n");
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+        state.command(":b!#\n");
+        assert_eq!(state.buf.path(), Some(b.as_path()));
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_edit_modified() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_edit_modified_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.txt");
+    fs::write(&a, "a1\n").unwrap();
+
+    with_buffer_mode(|mut state| {
+        state.command("ix");
+        state.event(Event::Key(Key::Esc));
+        state.command(&format!(":e {}\n", a.display()));
+        assert_eq!(state.buf.path(), None);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No write since last change (add ! to override)")
+        );
+        state.command(&format!(":e! {}\n", a.display()));
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+        assert_eq!(state.buf.core.get_string(), "a1\n");
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_only_and_bdelete() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_bdelete_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    fs::write(&a, "a1\na2\na3\n").unwrap();
+    fs::write(&b, "b1\n").unwrap();
+
+    with_buffer_mode(|mut state| {
+        state.command(":split\n:vsplit\n");
+        assert_eq!(state.window_count(), 3);
+        state.command("G");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("o");
+        assert_eq!(state.window_count(), 1);
+        state.command(":split\n:only\n");
+        assert_eq!(state.window_count(), 1);
+        state.command(":on\n");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Already only one window")
+        );
+
+        state.command(&format!(":e {}\njj", a.display()));
+        state.command(&format!(":e {}\n", b.display()));
+        // Not deleted with unsaved changes
+        state.command("x:bd\n");
+        assert_eq!(state.buf.path(), Some(b.as_path()));
+        state.command(":bd!\n");
+        assert_eq!(state.buf.path(), Some(a.as_path()));
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert!(!state.buf.has_alternate());
+
+        // The last buffer leaves an empty one
+        state.command(":bdelete\n");
+        assert_eq!(state.buf.path(), None);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some(format!("Deleted {}, no buffers left", a.display()).as_str())
+        );
+    });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_heatmap() {
+    use accepted::draw::{heat_bucket, styles, Term};
+    use std::time::Duration;
+
+    assert_eq!(heat_bucket(Duration::from_secs(0)), 0);
+    assert_eq!(heat_bucket(Duration::from_secs(30)), 1);
+    assert_eq!(heat_bucket(Duration::from_secs(300)), 2);
+    assert_eq!(heat_bucket(Duration::from_secs(3600)), 3);
+
+    with_buffer_mode_from("a\nb\nc\nd", |mut state| {
+        let touched = |state: &BufferMode| -> Vec<bool> {
+            (0..state.buf.core.buffer().len_lines())
+                .map(|i| state.buf.core.line_time(i).is_some())
+                .collect()
+        };
+        assert_eq!(touched(&state), vec![false, false, false, false]);
+        state.command("jx");
+        assert_eq!(touched(&state), vec![false, true, false, false]);
+
+        // Moved with inserted and deleted lines
+        state.command_esc("jjoe");
+        assert_eq!(touched(&state), vec![false, true, false, true, true]);
+        state.command("ggjjdd");
+        assert_eq!(touched(&state), vec![false, true, true, true]);
+        state.command("u");
+        assert_eq!(touched(&state).len(), 5);
+        assert!(!touched(&state)[0]);
+
+        // Line numbers are tinted
+        state.command(":set heatmap\n");
+        let mut term = Term::new(10, 20);
+        state.draw(&mut term);
+        assert_eq!(term.style(0, 0), Some(styles::UI));
+        assert_eq!(term.style(1, 0), Some(styles::HEAT[0]));
+        state.command(":set noheatmap\n");
+        state.draw(&mut term);
+        assert_eq!(term.style(1, 0), Some(styles::UI));
+    });
+}
+
+#[test]
+fn test_leader_mappings() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default(
+        "[file_default]\nleader = \",\"\n[file_default.leader_mappings]\nr = \":reverse\"\nfr = \"s/a/b/\"\n",
+    )
+    .unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a1\na2\na3".into(), true);
+    let mut state = BufferMode::new(buf);
+
+    state.command(",r");
+    assert_eq!(state.buf.core.get_string(), "a3\na2\na1");
+    // Through a sub-prefix
+    state.command(",f");
+    let mut term = accepted::draw::Term::new(10, 80);
+    state.draw(&mut term);
+    assert!(term.row_string(9).contains("Prefix f ... [r: s/a/b/]"));
+    state.command("r");
+    assert_eq!(state.buf.core.get_string(), "b3\na2\na1");
+
+    // Unknown keys return to Normal mode
+    state.command(",fxj");
+    assert_eq!(state.buf.core.cursor().row, 1);
+    state.command(",zj");
+    assert_eq!(state.buf.core.cursor().row, 2);
+    // Space is not the leader any more
+    state.command(" x");
+    assert_eq!(state.buf.core.get_string(), "b3\na2\n1");
+}
+
+#[test]
+fn test_insert_date() {
+    use accepted::datetime::DateTime;
+
+    let time = DateTime::from_unix(1_700_000_000, 0);
+    assert_eq!(time.format("%Y-%m-%d %H:%M:%S"), "2023-11-14 22:13:20");
+    assert_eq!(
+        time.format("%a %b %e %I%p %z %%"),
+        "Tue Nov 14 10PM +0000 %"
+    );
+    // The date moves with the time zone
+    let time = DateTime::from_unix(1_700_000_000, 9 * 3600);
+    assert_eq!(
+        time.format("%A %d %B %y %z"),
+        "Wednesday 15 November 23 +0900"
+    );
+    assert_eq!(
+        DateTime::from_unix(-1, 0).format("%Y-%m-%d %H:%M:%S %a"),
+        "1969-12-31 23:59:59 Wed"
+    );
+    assert_eq!(
+        DateTime::from_unix(951_782_400, 0).format("%Y-%m-%d"),
+        "2000-02-29"
+    );
+    assert_eq!(
+        DateTime::from_unix(0, -5400).format("%H:%M %z"),
+        "22:30 -0130"
+    );
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config =
+        config::parse_config_with_default("[file_default]\ndate_format = \"%d/%m/%Y\"").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("x".into(), true);
+    let format = buf.date_format();
+    buf.insert_time(&DateTime::from_unix(1_700_000_000, 0), &format);
+    assert_eq!(buf.core.get_string(), "14/11/2023x");
+
+    let is_date = |s: &str| {
+        s.len() == 10
+            && s.chars().enumerate().all(|(i, c)| {
+                if i == 4 || i == 7 {
+                    c == '-'
+                } else {
+                    c.is_ascii_digit()
+                }
+            })
+    };
+    with_buffer_mode_from("", |mut state| {
+        state.command(":date\n");
+        assert!(is_date(&state.buf.core.get_string()));
+        state.command("u:time %Y\n");
+        assert_eq!(state.buf.core.get_string().len(), 4);
+        state.command("ui");
+        state.event(Event::Key(Key::Alt('d')));
+        state.event(Event::Key(Key::Esc));
+        assert!(is_date(&state.buf.core.get_string()));
+    });
+}
+
+#[test]
+fn test_smart_home() {
+    with_buffer_mode_from("    abc", |mut state| {
+        state.command("$");
+        state.event(Event::Key(Key::Home));
+        assert_eq!(state.buf.core.cursor().col, 4);
+        state.event(Event::Key(Key::Home));
+        assert_eq!(state.buf.core.cursor().col, 0);
+        state.event(Event::Key(Key::Home));
+        assert_eq!(state.buf.core.cursor().col, 4);
+
+        // `0` stays literal by default
+        state.command("0");
+        assert_eq!(state.buf.core.cursor().col, 0);
+        state.command("0");
+        assert_eq!(state.buf.core.cursor().col, 0);
+    });
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nsmart_home = true").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("\tabc".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command("$0");
+    assert_eq!(state.buf.core.cursor().col, 1);
+    state.command("0");
+    assert_eq!(state.buf.core.cursor().col, 0);
+    state.command("0");
+    assert_eq!(state.buf.core.cursor().col, 1);
+}
+
+#[test]
+fn test_matchpairs() {
+    // `<` is not a pair by default
+    simple_test("Vec<Option<u8>>", "f<%i@", "Vec@<Option<u8>>");
+    simple_test(
+        "Vec<Option<u8>>",
+        ":set matchpairs=(:),<:>\nf<%i@",
+        "Vec<Option<u8>@>",
+    );
+    simple_test("a<b>c", ":set matchpairs=<:>\nf>%i@", "a@<b>c");
+    // Brackets not listed are ignored as well as invalid entries
+    simple_test("(a<b>)", ":set matchpairs=<:>,x,{:{\n%i@", "@(a<b>)");
+    simple_test("(a)", ":set matchpairs=<:>\n:set matchpairs=\n%i@", "(a@)");
+
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nmatchpairs = \"<:>\"").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("f<T>()".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command("l%");
+    assert_eq!(state.buf.core.cursor().col, 3);
+}
+
+#[test]
+fn test_iabbrev() {
+    with_buffer_mode_from("", |mut state| {
+        state.command(":iabbrev teh the\n");
+        state.command_esc("iteh teh. tehx xteh\n");
+        assert_eq!(state.buf.core.get_string(), "the the. tehx xteh\n");
+        // The expansion is undone with the insertion
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "");
+
+        state.command(":una teh\n");
+        state.command_esc("iteh ");
+        assert_eq!(state.buf.core.get_string(), "teh ");
+    });
+    with_buffer_mode_from("", |mut state| {
+        state.command(":ia fn fn main() {}\n");
+        state.command_esc("ifn\n");
+        assert_eq!(state.buf.core.get_string(), "fn main() {}\n");
+    });
+}
+
+#[test]
+fn test_indented_paste() {
+    with_buffer_mode_from("a\n  b\n\n    c\nd\n        e", |mut state| {
+        state.command("jVjjy");
+        // Into a deeper place, blank lines kept empty
+        state.command("G]p");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "a\n  b\n\n    c\nd\n        e\n        b\n\n          c"
+        );
+        assert_eq!(state.buf.core.cursor().into_tuple(), (6, 8));
+        // Above a line without indent
+        state.command(":5\n[p");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "a\n  b\n\n    c\nb\n\n  c\nd\n        e\n        b\n\n          c"
+        );
+        assert_eq!(state.buf.core.cursor().into_tuple(), (4, 0));
+    });
+    // Characterwise text is pasted as a line
+    with_buffer_mode_from("\tab\ncd", |mut state| {
+        state.command("jvly");
+        state.command("k]p");
+        assert_eq!(state.buf.core.get_string(), "\tab\n\tcd\ncd");
+    });
+}
+
+#[test]
+fn test_align() {
+    with_buffer_mode_from(
+        "let a = 1;\nlet bcd = 2;\nfoo();\nlet ef = 3;",
+        |mut state| {
+            state.command(":Align =\n");
+            assert_eq!(
+                state.buf.core.get_string(),
+                "let a   = 1;\nlet bcd = 2;\nfoo();\nlet ef  = 3;"
+            );
+            assert_eq!(
+                state.buf.messages.back().map(String::as_str),
+                Some("2 lines changed")
+            );
+        },
+    );
+    // Every `:` or only the first one, over a selection
+    let text = "x\na: 1: one\nbbb: 22: two\nc: 333: three";
+    with_buffer_mode_from(text, |mut state| {
+        state.command("jVjj:Align :\n");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "x\na  : 1  : one\nbbb: 22 : two\nc  : 333: three"
+        );
+    });
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":2,4Align! :\n");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "x\na  : 1: one\nbbb: 22: two\nc  : 333: three"
+        );
+    });
+}
+
+#[test]
+fn test_gp() {
+    with_buffer_mode_from("ab\ncd", |mut state| {
+        state.command("yygp");
+        assert_eq!(state.buf.core.get_string(), "ab\nab\ncd");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (2, 0));
+        state.command("gP");
+        assert_eq!(state.buf.core.get_string(), "ab\nab\nab\ncd");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (3, 0));
+        // Stays on the last line at the end of the buffer
+        state.command("gp");
+        assert_eq!(state.buf.core.get_string(), "ab\nab\nab\ncd\nab");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (4, 2));
+    });
+    with_buffer_mode_from("abc", |mut state| {
+        state.command("vly");
+        state.command("gp");
+        assert_eq!(state.buf.core.get_string(), "aabbc");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 3));
+        state.command("gP");
+        assert_eq!(state.buf.core.get_string(), "aababbc");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 5));
+        // `p` leaves the cursor on the last pasted character
+        state.command("p");
+        assert_eq!(state.buf.core.get_string(), "aababbabc");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 7));
+        state.command("\"_x");
+        assert_eq!(state.buf.core.get_string(), "aababbac");
+    });
+}
+
+#[test]
+fn test_control_characters() {
+    use accepted::draw::{styles, CursorState, Term};
+
+    with_buffer_mode_from("a\x1bb\0c\u{9b}d", |mut state| {
+        state.command("$h");
+        let mut term = Term::new(4, 20);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("1  a^[b^@c<9b>d"));
+        assert_eq!(term.style(0, 4), Some(styles::CONTROL));
+        assert_eq!(term.style(0, 5), Some(styles::CONTROL));
+        // The buffer keeps the characters
+        assert_eq!(state.buf.core.get_string(), "a\x1bb\0c\u{9b}d");
+        // `d` after the notations
+        match term.cursor {
+            CursorState::Show(c, _) => assert_eq!((c.row, c.col), (0, 14)),
+            CursorState::Hide => panic!("cursor is hidden"),
+        }
+    });
+}
+
+#[test]
+fn test_showkeys() {
+    use accepted::draw::{key_notation, Term};
+    use termion::event::MouseEvent;
+
+    let notation = |key| key_notation(&Event::Key(key));
+    assert_eq!(notation(Key::Char('d')).as_deref(), Some("d"));
+    assert_eq!(notation(Key::Char('\n')).as_deref(), Some("<CR>"));
+    assert_eq!(notation(Key::Char(' ')).as_deref(), Some("<Space>"));
+    assert_eq!(notation(Key::Char('<')).as_deref(), Some("<lt>"));
+    assert_eq!(notation(Key::Ctrl('r')).as_deref(), Some("<C-r>"));
+    assert_eq!(notation(Key::Alt('x')).as_deref(), Some("<M-x>"));
+    assert_eq!(notation(Key::Esc).as_deref(), Some("<Esc>"));
+    assert_eq!(notation(Key::F(5)).as_deref(), Some("<F5>"));
+    assert_eq!(key_notation(&Event::Mouse(MouseEvent::Release(1, 1))), None);
+
+    with_buffer_mode_from("a\nb\nc\nd", |mut state| {
+        state.command(":set showkeys\n");
+        state.command("dd");
+        state.event(Event::Key(Key::Esc));
+        // Keys replayed by `.` are not shown again
+        state.command(".");
+        assert_eq!(state.buf.core.get_string(), "c\nd");
+        let mut term = Term::new(6, 30);
+        state.draw(&mut term);
+        assert!(term.row_string(0).ends_with(" dd<Esc>. "));
+
+        state.command(":set noshowkeys\n");
+        state.draw(&mut term);
+        assert!(!term.row_string(0).contains("<Esc>"));
+    });
+}
+
+#[test]
+fn test_end_of_buffer() {
+    use accepted::draw::{styles, Term};
+
+    with_buffer_mode_from("a\nb", |mut state| {
+        let mut term = Term::new(6, 20);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("1  a"));
+        assert!(term.row_string(1).starts_with("2  b"));
+        for row in 2..5 {
+            assert_eq!(term.row_string(row).trim_end(), "~");
+            assert_eq!(term.style(row, 0), Some(styles::END_OF_BUFFER));
+        }
+    });
+
+    // A space disables it
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config =
+        config::parse_config_with_default("[file_default]\nend_of_buffer = \" \"").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a".into(), true);
+    let mut state = BufferMode::new(buf);
+    let mut term = Term::new(6, 20);
+    state.draw(&mut term);
+    assert_eq!(term.row_string(1).trim_end(), "");
+}
+
+#[test]
+fn test_cursor_after_edit() {
+    let cursor = |init: &str, commands: &str, expected: &str| {
+        let mut res = None;
+        with_buffer_mode_from(init, |mut state| {
+            state.command(commands);
+            assert_eq!(state.buf.core.get_string(), expected);
+            res = Some(state.buf.core.cursor().into_tuple());
+        });
+        res.unwrap()
+    };
+
+    // `dd` goes to the first non-blank of the line below, or above at the end
+    assert_eq!(cursor("a\n  b\nc", "dd", "  b\nc"), (0, 2));
+    assert_eq!(cursor("x\n  a\n  b", "Gdd", "x\n  a"), (1, 2));
+    assert_eq!(cursor("x\n\ta\nb\nc", "jjl2dd", "x\n\ta"), (1, 1));
+    assert_eq!(cursor("abc\ndef", "ll2dd", ""), (0, 0));
+
+    // `J` sits on the join space
+    assert_eq!(cursor("ab\n  cd", "J", "ab cd"), (0, 2));
+    assert_eq!(cursor("a\nb\nc\nd", "3J", "a b c\nd"), (0, 3));
+    assert_eq!(cursor("ab \ncd", "J", "ab cd"), (0, 2));
+    assert_eq!(cursor("f(\n)", "J", "f()"), (0, 2));
+    assert_eq!(cursor("ab\n", "J", "ab"), (0, 1));
+    assert_eq!(cursor("ab", "J", "ab"), (0, 0));
+
+    // Linewise `p` and `P` land on the first non-blank of the first pasted line
+    assert_eq!(cursor("  ab\ncd", "yyp", "  ab\n  ab\ncd"), (1, 2));
+    assert_eq!(
+        cursor("  ab\n\tx\ncd", "yjGp", "  ab\n\tx\ncd\n  ab\n\tx"),
+        (3, 2)
+    );
+    assert_eq!(cursor("cd\n  ab", "jyykP", "  ab\ncd\n  ab"), (0, 2));
+}
+
+#[test]
+fn test_detect_indent() {
+    use accepted::draw::Term;
+    use accepted::indent::{detect, IndentStyle};
+
+    let tabs = "fn main() {\n\tif x {\n\t\ty();\n\t}\n}";
+    assert_eq!(detect(tabs.lines()), Some(IndentStyle::Tabs));
+    let two = "a:\n  b:\n    c: 1\n    d: 2\n  e: 3\n";
+    assert_eq!(detect(two.lines()), Some(IndentStyle::Spaces(2)));
+    let four = "def f():\n    if x:\n        return 1\n    /**\n     * doc\n     */\n    return 2";
+    assert_eq!(detect(four.lines()), Some(IndentStyle::Spaces(4)));
+    // Ambiguous or no indent at all
+    assert_eq!(detect("a\n\tb\n  c".lines()), None);
+    assert_eq!(detect("a\nb".lines()), None);
+
+    with_buffer_mode_from(tabs, |mut state| {
+        state.buf.detect_indent();
+        assert_eq!(state.buf.indent_style, Some(IndentStyle::Tabs));
+        state.command_esc("o");
+        assert_eq!(state.buf.core.current_line().to_string(), "\t");
+        state.command_esc("ccx\tx");
+        assert_eq!(state.buf.core.current_line().to_string(), "\tx\tx");
+
+        let mut term = Term::new(6, 40);
+        state.draw(&mut term);
+        assert!(term.row_string(5).contains("[No Name] [tabs]"));
+    });
+    with_buffer_mode_from(two, |mut state| {
+        state.buf.detect_indent();
+        assert_eq!(state.buf.indent_width(), 2);
+        state.command_esc("jA\t");
+        assert_eq!(state.buf.core.current_line().to_string(), "  b:  ");
+    });
+}
+
+#[test]
+fn test_path_completion() {
+    use accepted::draw::Term;
+
+    let dir = std::env::temp_dir().join(format!("acc_test_complete_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub").join("inner.txt"), "").unwrap();
+    std::fs::write(dir.join("alpha.rs"), "").unwrap();
+    std::fs::write(dir.join("beta1.txt"), "").unwrap();
+    std::fs::write(dir.join("beta2.txt"), "").unwrap();
+    let d = dir.to_string_lossy().to_string();
+
+    let footer = |state: &mut BufferMode| {
+        let mut term = Term::new(5, 120);
+        state.draw(&mut term);
+        term.row_string(4).trim_end().to_string()
+    };
+    let ctrl_w = |state: &mut BufferMode| state.event(Event::Key(Key::Ctrl('w')));
+
+    // `Save` prompt of a buffer without a path
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(":w\n");
+        state.command(&format!("{}/al\t", d));
+        assert_eq!(footer(&mut state), format!("> {}/alpha.rs", d));
+
+        // Ambiguous names cycle
+        ctrl_w(&mut state);
+        state.command("be\t");
+        assert_eq!(footer(&mut state), format!("> {}/beta1.txt", d));
+        state.command("\t");
+        assert_eq!(footer(&mut state), format!("> {}/beta2.txt", d));
+        state.command("\t");
+        assert_eq!(footer(&mut state), format!("> {}/beta1.txt", d));
+
+        // Into a directory
+        ctrl_w(&mut state);
+        state.command("su\t\t");
+        assert_eq!(footer(&mut state), format!("> {}/sub/inner.txt", d));
+        ctrl_w(&mut state);
+        ctrl_w(&mut state);
+        assert_eq!(footer(&mut state), format!("> {}/", d));
+    });
+
+    with_buffer_mode_from("abc", |mut state| {
+        state.command(&format!(":e {}/alp\t", d));
+        assert_eq!(footer(&mut state), format!(":e {}/alpha.rs", d));
+        ctrl_w(&mut state);
+        assert_eq!(footer(&mut state), format!(":e {}/", d));
+        // Not for other commands
+        state.command_esc(":s/a\t");
+        assert_eq!(state.buf.core.get_string(), "abc");
+    });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_long_line() {
+    use accepted::draw::{CursorState, Term};
+
+    let long = "ab ".repeat(20_000);
+    with_buffer_mode_from(&format!("first\n{}\nlast", long), |mut state| {
+        state.resize(40, 120);
+        let mut term = Term::new(40, 120);
+        state.command("jx");
+        state.draw(&mut term);
+        // Shown from the top as it is taller than the window
+        assert_eq!(state.buf.row_offset(), 1);
+        assert!(term.row_string(0).starts_with("2  b ab"));
+        assert_eq!(state.buf.core.current_line().len_chars(), long.len() - 1);
+
+        // The end of the line is drawn with the cursor there
+        state.command("$");
+        state.draw(&mut term);
+        let cursor = match term.cursor {
+            CursorState::Show(c, _) => c,
+            CursorState::Hide => panic!("cursor is hidden"),
+        };
+        assert!(term.row_string(cursor.row).trim_end().ends_with("ab ab"));
+    });
+}
+
+#[test]
+fn test_fold_motion() {
+    let text = (1..=12)
+        .map(|i| format!("  {}", i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    with_buffer_mode_from(&text, |mut state| {
+        // Rows 1-5 with 2-3 nested in it and rows 7-9
+        state.command(":2,6fold\n:7,9fo\n");
+        state.command("jjVjzf");
+        assert_eq!(state.buf.folds, vec![(1, 5), (2, 3), (6, 8)]);
+        let row = |state: &BufferMode| state.buf.core.cursor().row;
+
+        state.command("gg");
+        state.command("zj");
+        assert_eq!(row(&state), 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("zj");
+        assert_eq!(row(&state), 2);
+        state.command("zj");
+        assert_eq!(row(&state), 6);
+        state.command("zj");
+        assert_eq!(row(&state), 6);
+        state.command("Gzk");
+        assert_eq!(row(&state), 8);
+        state.command("2zk");
+        assert_eq!(row(&state), 3);
+
+        // Start and end of the current fold, then of the enclosing one
+        state.command("]z");
+        assert_eq!(row(&state), 5);
+        state.command(":4\n[z");
+        assert_eq!(row(&state), 2);
+        state.command("[z");
+        assert_eq!(row(&state), 1);
+        state.command(":4\n2]z");
+        assert_eq!(row(&state), 5);
+
+        state.command(":4\nzd");
+        assert_eq!(state.buf.folds, vec![(1, 5), (6, 8)]);
+        state.command("zE");
+        assert!(state.buf.folds.is_empty());
+        state.command("ggzj");
+        assert_eq!(row(&state), 0);
+
+        // Folds past the end of the text are dropped
+        state.command(":5,6fold\nzM");
+        state.command("gg20dd");
+        state.command("zj");
+        assert_eq!(row(&state), 0);
+        state.command("zk]z[z");
+        assert_eq!(row(&state), 0);
+        assert!(state.buf.folds.is_empty());
+        assert!(state.buf.closed_folds.is_empty());
+        let mut term = accepted::draw::Term::new(10, 40);
+        state.draw(&mut term);
+    });
+}
+
+#[test]
+fn test_fold_indent() {
+    let text = "fn main() {\n    if x {\n        a();\n        b();\n    }\n\n    c();\n}\nd";
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":set foldmethod=indent\n");
+        state.command("zR");
+        // The body of `main` with the body of `if` in it. A single line is not folded.
+        assert_eq!(state.buf.folds, vec![(1, 6), (2, 3)]);
+
+        state.command("zM");
+        assert_eq!(state.buf.closed_folds.len(), 2);
+        let mut term = accepted::draw::Term::new(10, 40);
+        state.draw(&mut term);
+        assert!(term.row_string(0).contains("fn main() {"));
+        assert!(term.row_string(1).contains("+--  6 lines: if x {"));
+        assert!(term.row_string(2).contains('}'));
+        assert!(term.row_string(3).contains('d'));
+        // `j` moves over the closed fold
+        state.command("ggjj");
+        assert_eq!(state.buf.core.cursor().row, 7);
+
+        state.command("zR");
+        assert!(state.buf.closed_folds.is_empty());
+        state.draw(&mut term);
+        assert!(term.row_string(2).contains("a();"));
+
+        // `za` closes the innermost fold and opens it again
+        state.command(":4\nza");
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert!(state.buf.closed_folds.contains(&(2, 3)));
+        state.command("za");
+        assert!(state.buf.closed_folds.is_empty());
+
+        // Computed again after an edit. Indenting the closing brace of `if` joins the fold.
+        state.command_esc(":5\nI    ");
+        assert_eq!(
+            state.buf.core.get_string().lines().nth(4),
+            Some("        }")
+        );
+        state.draw(&mut term);
+        assert_eq!(state.buf.folds, vec![(1, 6), (2, 4)]);
+        state.command("ggdd");
+        state.draw(&mut term);
+        assert_eq!(state.buf.folds, vec![(1, 3)]);
+    });
+}
+
+#[test]
+fn test_undo_memory() {
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nundo_memory = 4").unwrap();
+    let buf = Buffer::new(&syntax_parent, &config);
+    let mut state = BufferMode::new(buf);
+
+    let line = "x".repeat(20);
+    for i in 0..20 {
+        state.command_esc(&format!("o{}{}", i, line));
+    }
+    assert_eq!(state.buf.core.buffer().len_lines(), 21);
+    // The oldest steps are dropped to stay in 4 KiB
+    assert!(state.buf.core.undo_len() < 20);
+    assert!(state.buf.core.undo_len() >= 1);
+    assert!(state.buf.core.history_size() <= 4096);
+
+    // Recent changes are still undone
+    state.command("u");
+    assert_eq!(state.buf.core.buffer().len_lines(), 20);
+    assert!(!state.buf.core.get_string().contains(&format!("19{}", line)));
+    state.command("U");
+    assert_eq!(state.buf.core.buffer().len_lines(), 21);
+
+    // but not the oldest ones
+    for _ in 0..20 {
+        state.command("u");
+    }
+    assert!(state.buf.core.get_string().contains(&format!("0{}", line)));
+    assert!(state.buf.core.buffer().len_lines() > 1);
+}
+
+#[test]
+fn test_paragraph_text_object() {
+    let text = "a\nb\n\nc\nd\n\n\ne\nf";
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":4\ndip");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\n\n\ne\nf");
+        assert_eq!(state.buf.core.cursor().row, 3);
+    });
+    with_buffer_mode_from(text, |mut state| {
+        // Takes the blank lines after the paragraph
+        state.command(":5\ndap");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\ne\nf");
+        state.command("u");
+        state.command("Gdap");
+        // or before the last paragraph
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\nc\nd");
+        state.command("ggyap");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\nc\nd");
+        state.command("Gp");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\nc\nd\na\nb\n");
+    });
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":4\nvipd");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\n\n\ne\nf");
+        // Nothing on a blank line
+        state.command(":3\ndip");
+        assert_eq!(state.buf.core.get_string(), "a\nb\n\n\n\ne\nf");
+    });
+}
+
+#[test]
+fn test_block_text_objects() {
+    let text = "fn f() {\n    if x {\n        a(b(c), d);\n    }\n}";
+    with_buffer_mode_from(text, |mut state| {
+        // The innermost block around the cursor
+        state.command(":3\nwwwdiB");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n    }\n}"
+        );
+        state.command("u:3\nciB");
+        state.command_esc("g();");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\ng();\n    }\n}"
+        );
+    });
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":3\nfccib");
+        state.command_esc("e");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        a(b(e), d);\n    }\n}"
+        );
+        state.command("F(hcib");
+        state.command_esc("z");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        a(z);\n    }\n}"
+        );
+        state.command("dab");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        a;\n    }\n}"
+        );
+        state.command("ggdaB");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "fn f() {\n    if x {\n        a;\n    }\n}"
+        );
+        state.command("$daB");
+        assert_eq!(state.buf.core.get_string(), "fn f() ");
+    });
+}
+
+#[test]
+fn test_cycle_line_numbers() {
+    with_buffer_mode_from("a\nb\nc\nd", |mut state| {
+        let mut term = accepted::draw::Term::new(6, 20);
+        state.command(":3\n");
+        state.draw(&mut term);
+        assert_eq!(state.buf.line_numbers, LineNumbers::Absolute);
+        assert!(term.row_string(0).starts_with("1  a"));
+
+        state.event(Event::Key(Key::Ctrl('k')));
+        assert_eq!(state.buf.line_numbers, LineNumbers::Relative);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("2  a"));
+        assert!(term.row_string(2).starts_with("3  c"));
+        assert!(term.row_string(3).starts_with("1  d"));
+
+        state.event(Event::Key(Key::Ctrl('k')));
+        assert_eq!(state.buf.line_numbers, LineNumbers::Off);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("a"));
+
+        state.event(Event::Key(Key::Ctrl('k')));
+        assert_eq!(state.buf.line_numbers, LineNumbers::Absolute);
+        state.draw(&mut term);
+        assert!(term.row_string(0).starts_with("1  a"));
+
+        state.command(":set rnu\n");
+        assert_eq!(state.buf.line_numbers, LineNumbers::Relative);
+        state.command(":set nonu\n");
+        assert_eq!(state.buf.line_numbers, LineNumbers::Off);
+    });
+}
+
+#[test]
+fn test_filetype_config() {
+    let dir = std::env::temp_dir().join(format!("acc_test_filetype_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default(
+        "[file_default]\nindent_width = 4\n\n[filetype.python]\nindent_width = 2\ncomment = \"#\"\n\n[filetype.make]\nexpandtab = false",
+    )
+    .unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(dir.join("a.py"));
+    let mut state = BufferMode::new(buf);
+
+    assert_eq!(state.buf.filetype(), Some("python"));
+    assert_eq!(state.buf.indent_width(), 2);
+    state.command_esc("i{\nx");
+    assert_eq!(state.buf.core.get_string(), "{\n  x\n}");
+
+    state.command(":setfiletype make\n");
+    assert_eq!(state.buf.filetype(), Some("make"));
+    assert_eq!(state.buf.indent_width(), 4);
+    assert_eq!(state.buf.indent_unit(), "\t");
+
+    // Files without a type use `[file_default]`
+    state.buf.open(dir.join("a.txt"));
+    assert_eq!(state.buf.filetype(), None);
+    assert_eq!(state.buf.indent_width(), 4);
+    assert_eq!(state.buf.indent_unit(), "    ");
+
+    // Keys not in the file type section come from `[file_default]`
+    let config = config::parse_config_with_default(
+        "[file_default]\nindent_width = 8\n\n[filetype.python]\ntextwidth = 88",
+    )
+    .unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(dir.join("a.py"));
+    assert_eq!(buf.indent_width(), 8);
+    assert_eq!(buf.text_width(), 88);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_split_open() {
+    let dir = std::env::temp_dir().join(format!("acc_test_split_open_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("b.txt");
+    std::fs::write(&path, "x\ny\nz\n").unwrap();
+
+    with_buffer_mode_from("1\n2\n3\n4", |mut state| {
+        // The new window starts at the cursor of the current one and moves on its own
+        state.command("jj");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("s");
+        assert_eq!(state.window_count(), 2);
+        assert_eq!(state.buf.core.cursor().row, 2);
+        state.command("G");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("j");
+        assert_eq!(state.buf.core.cursor().row, 2);
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("v");
+        assert_eq!(state.window_count(), 3);
+
+        // A file would replace the buffer of all windows
+        for command in [":split", ":split!", ":vsplit"] {
+            state.command(&format!("{} {}\n", command, path.display()));
+            assert_eq!(state.window_count(), 3);
+            assert_eq!(state.buf.path(), None);
+            assert_eq!(
+                state.buf.messages.back().map(String::as_str),
+                Some("Windows share the buffer. Open a file with :e")
+            );
+        }
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        // Every window still shows the original text
+        let top: Vec<char> = term.row_string(0).chars().collect();
+        for (_, col, _, _) in state.window_rects(24, 80) {
+            let text: String = top[col..col + 4].iter().collect();
+            assert_eq!(text, "1  1");
+        }
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_strip_ansi() {
+    let text = "\x1b[1;31merror\x1b[0m: bad\n\x1b]0;title\x07plain\x1b(B\tok\nbell\x07 \x1b[K";
+    with_buffer_mode_from(text, |mut state| {
+        state.command(":2AnsiStrip\n");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "\x1b[1;31merror\x1b[0m: bad\nplain\tok\nbell\x07 \x1b[K"
+        );
+        state.command(":AnsiStrip\n");
+        assert_eq!(state.buf.core.get_string(), "error: bad\nplain\tok\nbell ");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("2 lines changed")
+        );
+        state.command("u");
+        assert!(state.buf.core.get_string().starts_with("\x1b[1;31merror"));
+    });
+
+    // On load
+    let dir = std::env::temp_dir().join(format!("acc_test_strip_ansi_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("build.log");
+    std::fs::write(&path, "\x1b[32mok\x1b[0m\n").unwrap();
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default("[file_default]\nstrip_ansi = true").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.open(&path);
+    assert_eq!(buf.core.get_string(), "ok\n");
+    assert!(buf.is_modified());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_keymap() {
+    use accepted::keymap::{parse_key, Action, Keymap};
+
+    // The default keymap leaves keys as they are
+    let keymap = Keymap::default();
+    for c in "hjklwbe0$G%()+-fF/nNiaIAoOsSCrxJpPuU.&dcyvV:".chars() {
+        let event = Event::Key(Key::Char(c));
+        assert_eq!(keymap.translate(event.clone()), Some(event));
+    }
+    assert_eq!(keymap.action(Key::Ctrl('w')), Some(Action::Window));
+    assert_eq!(
+        keymap.translate(Event::Key(Key::Char('q'))),
+        Some(Event::Key(Key::Char('q')))
+    );
+    assert_eq!(Action::from_name("undo"), Some(Action::Undo));
+    assert_eq!(Action::Undo.name(), "undo");
+    assert_eq!(parse_key("<C-r>"), Some(Key::Ctrl('r')));
+    assert_eq!(parse_key("<Space>"), Some(Key::Char(' ')));
+    assert_eq!(parse_key("<Nope>"), None);
+
+    with_buffer_mode_from("abc def\nghi", |mut state| {
+        state.command("xwx");
+        assert_eq!(state.buf.core.get_string(), "bc ef\nghi");
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "bc def\nghi");
+    });
+
+    // Swap undo and redo and move `j` and `k`
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::parse_config_with_default(
+        "[file_default.keymap]\nundo = \"U\"\nredo = \"<C-r>\"\ndown = \"k\"\nup = \"<C-k>\"",
+    )
+    .unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a\nb\nc".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.command("k");
+    assert_eq!(state.buf.core.cursor().row, 1);
+    // `j` is no longer bound
+    state.command("j");
+    assert_eq!(state.buf.core.cursor().row, 1);
+    state.command("2k");
+    assert_eq!(state.buf.core.cursor().row, 2);
+    state.event(Event::Key(Key::Ctrl('k')));
+    assert_eq!(state.buf.core.cursor().row, 1);
+
+    state.command("x");
+    assert_eq!(state.buf.core.get_string(), "a\n\nc");
+    state.command("u");
+    assert_eq!(state.buf.core.get_string(), "a\n\nc");
+    state.command("U");
+    assert_eq!(state.buf.core.get_string(), "a\nb\nc");
+    state.event(Event::Key(Key::Ctrl('r')));
+    assert_eq!(state.buf.core.get_string(), "a\n\nc");
+    // Keys in the middle of a command are not remapped
+    state.command(":2\ndk");
+    assert_eq!(state.buf.core.get_string(), "c");
+
+    // The keymap of the file type replaces it
+    let config =
+        config::parse_config_with_default("[filetype.make.keymap]\ndown = \"<C-j>\"").unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a\nb\nc".into(), true);
+    let mut state = BufferMode::new(buf);
+    state.event(Event::Key(Key::Ctrl('j')));
+    assert_eq!(state.buf.core.cursor().row, 0);
+    state.command(":setfiletype make\n");
+    state.event(Event::Key(Key::Ctrl('j')));
+    assert_eq!(state.buf.core.cursor().row, 1);
+}
+
+#[test]
+fn test_goto_window() {
+    with_buffer_mode_from("1\n2\n3\n4\n5\n6", |mut state| {
+        state.command(":split\n:split\n");
+        assert_eq!(state.window_count(), 3);
+        assert_eq!(state.current_window(), 0);
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("2");
+        assert_eq!(state.current_window(), 1);
+        state.command("G");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("3");
+        assert_eq!(state.current_window(), 2);
+        assert_eq!(state.buf.core.cursor().row, 0);
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("2");
+        assert_eq!(state.buf.core.cursor().row, 5);
+
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("4");
+        assert_eq!(state.current_window(), 1);
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("No window 4")
+        );
+
+        // Other windows show their numbers
+        let mut term = accepted::draw::Term::new(30, 40);
+        state.draw(&mut term);
+        assert!(term.row_string(9).starts_with("1 [No Name]"));
+        assert!(term.row_string(29).starts_with("3 [No Name]"));
+    });
+}
+
+#[test]
+fn test_cancel_pending_keys() {
+    use std::time::{Duration, Instant};
+
+    with_buffer_mode_from("abc def\nghi", |mut state| {
+        // `x` is not a motion
+        state.command("dx");
+        assert_eq!(state.buf.core.get_string(), "abc def\nghi");
+        assert!(state.showcmd().is_empty());
+        // and the operator does not go on to the next key
+        state.command("w");
+        assert_eq!(state.buf.core.get_string(), "abc def\nghi");
+        assert_eq!(state.buf.core.cursor().col, 4);
+
+        state.command("d");
+        state.event(Event::Key(Key::Ctrl('x')));
+        assert!(state.showcmd().is_empty());
+
+        // A text object which is not found
+        state.command("di(");
+        assert_eq!(state.buf.core.get_string(), "abc def\nghi");
+        state.command("diw");
+        assert_eq!(state.buf.core.get_string(), "abc \nghi");
+
+        // Incomplete sequences time out
+        state.command("g");
+        assert_eq!(state.showcmd(), "g");
+        assert!(!state.check_timeout(Instant::now()));
+        assert!(state.check_timeout(Instant::now() + Duration::from_secs(2)));
+        assert!(state.showcmd().is_empty());
+        state.command("j");
+        assert_eq!(state.buf.core.cursor().row, 1);
+
+        state.command("ggd");
+        assert!(state.check_timeout(Instant::now() + Duration::from_secs(2)));
+        state.command("j");
+        assert_eq!(state.buf.core.get_string(), "abc \nghi");
+        assert_eq!(state.buf.core.cursor().row, 1);
+
+        state.command("f");
+        assert!(state.check_timeout(Instant::now() + Duration::from_secs(2)));
+        // Nothing to cancel
+        assert!(!state.check_timeout(Instant::now() + Duration::from_secs(2)));
+    });
+}
+
+#[test]
+fn test_numbered_registers() {
+    with_buffer_mode_from("a\nb\nc\nd e", |mut state| {
+        state.command("dddddd");
+        assert_eq!(state.buf.registers[&'1'].content, "c");
+        assert_eq!(state.buf.registers[&'2'].content, "b");
+        assert_eq!(state.buf.registers[&'3'].content, "a");
+
+        // A delete within a line
+        state.command("dw");
+        assert_eq!(state.buf.registers[&'-'].content, "d ");
+        assert_eq!(state.buf.registers[&'1'].content, "c");
+
+        // Yanks do not shift them
+        state.command("yy");
+        assert_eq!(state.buf.registers[&'1'].content, "c");
+
+        state.command("\"2p");
+        assert_eq!(state.buf.core.get_string(), "e\nb");
+        state.command("\"-P");
+        assert_eq!(state.buf.core.get_string(), "e\nd b");
+    });
+}
+
+#[test]
+fn test_subword_motion() {
+    let stops = |text: &str| {
+        let mut res = Vec::new();
+        with_buffer_mode_from(text, |mut state| loop {
+            res.push(state.buf.core.cursor().col);
+            state.command("]w");
+            if state.buf.core.cursor().col == *res.last().unwrap() {
+                break;
+            }
+        });
+        res
+    };
+    assert_eq!(stops("camelCaseWord"), vec![0, 5, 9]);
+    assert_eq!(stops("snake_case_word"), vec![0, 6, 11]);
+    assert_eq!(stops("HTTPServer x"), vec![0, 4, 11]);
+
+    with_buffer_mode_from("fooBarBaz", |mut state| {
+        state.command("$[w");
+        assert_eq!(state.buf.core.cursor().col, 6);
+        state.command("2[w");
+        assert_eq!(state.buf.core.cursor().col, 0);
+        state.command("2]w");
+        assert_eq!(state.buf.core.cursor().col, 6);
+    });
+
+    // Across lines
+    with_buffer_mode_from("fooBar\n\n  barBaz", |mut state| {
+        state.command("3l]w");
+        let cursor = state.buf.core.cursor();
+        assert_eq!((cursor.row, cursor.col), (2, 2));
+        state.command("]w");
+        assert_eq!(state.buf.core.cursor().col, 5);
+        state.command("2[w");
+        let cursor = state.buf.core.cursor();
+        assert_eq!((cursor.row, cursor.col), (0, 3));
+    });
+
+    simple_test("fooBarBaz", "d]w", "BarBaz");
+    simple_test("foo_bar_baz", "4ld]w", "foo_baz");
+    simple_test("fooBarBaz", "$hd[w", "fooBarz");
+    simple_test("fooBar\nbaz", "3ld]w", "foo\nbaz");
+    simple_test("fooBarBaz", "c]wqux", "quxBarBaz");
+}
+
+#[test]
+fn test_last_insert_register() {
+    with_buffer_mode_from("", |mut state| {
+        state.command_esc("ihello");
+        assert_eq!(state.buf.registers[&'.'].content, "hello");
+        state.command("A ");
+        state.event(Event::Key(Key::Ctrl('a')));
+        state.command_esc("");
+        assert_eq!(state.buf.core.get_string(), "hello hello");
+        assert_eq!(state.buf.registers[&'.'].content, " hello");
+
+        // Backspace removes typed characters and line breaks are kept
+        state.command("oabx");
+        state.event(Event::Key(Key::Backspace));
+        state.command_esc("\nc");
+        assert_eq!(state.buf.registers[&'.'].content, "ab\nc");
+        state.command("o");
+        state.event(Event::Key(Key::Ctrl('a')));
+        state.command_esc("");
+        assert_eq!(state.buf.core.get_string(), "hello hello\nab\nc\nab\nc");
+
+        state.command("gg\".P");
+        assert_eq!(
+            state.buf.core.get_string(),
+            "ab\nchello hello\nab\nc\nab\nc"
+        );
+
+        // The text left by keys which delete or insert more than a character
+        state.command("Go foo bar");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("baz");
+        state.event(Event::Key(Key::Ctrl('r')));
+        state.command_esc(".");
+        assert_eq!(state.buf.registers[&'.'].content, " foo bazab\nc");
+        state.command("ox");
+        state.event(Event::Key(Key::Ctrl('u')));
+        state.command_esc("y");
+        assert_eq!(state.buf.registers[&'.'].content, "y");
+    });
+}
+
+#[test]
+fn test_bracketed_paste() {
+    let paste = |state: &mut BufferMode, text: &str| {
+        state.event(Event::Unsupported(b"\x1b[200~".to_vec()));
+        state.command(text);
+        state.event(Event::Unsupported(b"\x1b[201~".to_vec()));
+    };
+    with_buffer_mode_from("", |mut state| {
+        // Typed, the braces are paired and indented
+        state.command_esc("iif (a) {\nb");
+        assert_eq!(state.buf.core.get_string(), "if (a) {\n    b\n}");
+    });
+    with_buffer_mode_from("", |mut state| {
+        state.command("i");
+        paste(&mut state, "if (a) {\n\tb\n}");
+        state.command_esc("");
+        assert_eq!(state.buf.core.get_string(), "if (a) {\n\tb\n}");
+
+        // In Normal mode a paste inserts the text and stays in Normal mode
+        state.command("gg0");
+        paste(&mut state, "(x\n");
+        assert_eq!(state.buf.core.get_string(), "(x\nif (a) {\n\tb\n}");
+        assert_eq!(state.buf.registers[&'.'].content, "(x\n");
+        state.command("x");
+        assert_eq!(state.buf.core.get_string(), "(x\nf (a) {\n\tb\n}");
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "(x\nif (a) {\n\tb\n}");
+    });
+}
+
+#[test]
+fn test_percent_motion() {
+    let text = (1..=100)
+        .map(|i| format!("  {}", i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    with_buffer_mode_from(&text, |mut state| {
+        state.command("50%");
+        assert_eq!(state.buf.core.cursor().row, 49);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("1%");
+        assert_eq!(state.buf.core.cursor().row, 0);
+        state.command("100%");
+        assert_eq!(state.buf.core.cursor().row, 99);
+        state.command("101%");
+        assert_eq!(state.buf.core.cursor().row, 99);
+        // Back to before the jump
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(state.buf.core.cursor().row, 0);
+    });
+
+    // Without a count it matches brackets
+    with_buffer_mode_from("(a [b])", |mut state| {
+        state.command("%");
+        assert_eq!(state.buf.core.cursor().col, 6);
+        state.command("%");
+        assert_eq!(state.buf.core.cursor().col, 0);
+    });
+}
+
+#[test]
+fn test_word_completion_menu() {
+    with_buffer_mode_from("foobar fizz\nfoo\n\nfood", |mut state| {
+        state.command("jjifo");
+        state.event(Event::Key(Key::Ctrl('n')));
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        // Below the word and as wide as the longest candidate
+        let col = term.row_string(2).find("fo").unwrap();
+        for (row, candidate) in [" foo    ", " food   ", " foobar "].iter().enumerate() {
+            assert_eq!(&term.row_string(row + 3)[col - 1..col + 7], *candidate);
+        }
+        assert_eq!(term.style(3, col), Some(accepted::draw::styles::HIGHLIGHT));
+
+        // Enter accepts the candidate without a line break
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command("\n");
+        assert_eq!(state.buf.core.current_line().to_string(), "food");
+        let mut term = accepted::draw::Term::new(24, 80);
+        state.draw(&mut term);
+        assert!(!term.row_string(5).contains("foobar"));
+
+        // Esc goes back to the typed prefix and stays in Insert mode
+        state.command(" fo");
+        state.event(Event::Key(Key::Ctrl('p')));
+        assert_eq!(state.buf.core.current_line().to_string(), "food foobar");
+        state.event(Event::Key(Key::Esc));
+        state.command_esc("x");
+        assert_eq!(state.buf.core.current_line().to_string(), "food fox");
+    });
+
+    // Below the cursor if it fits
+    assert_eq!(
+        accepted::draw::popup_rect((2, 5), (3, 10), (24, 80)),
+        (3, 5, 3, 10)
+    );
+    // Above the cursor near the bottom
+    assert_eq!(
+        accepted::draw::popup_rect((22, 5), (3, 10), (24, 80)),
+        (19, 5, 3, 10)
+    );
+    // Shifted left at the right edge
+    assert_eq!(
+        accepted::draw::popup_rect((2, 75), (3, 10), (24, 80)),
+        (3, 70, 3, 10)
+    );
+    // Cut to the larger side if it fits neither
+    assert_eq!(
+        accepted::draw::popup_rect((3, 0), (10, 10), (10, 5)),
+        (4, 0, 6, 5)
+    );
+    assert_eq!(
+        accepted::draw::popup_rect((7, 0), (10, 10), (10, 80)),
+        (0, 0, 7, 10)
+    );
+}
+
+#[test]
+fn test_cursor_at_line_end() {
+    // The Normal mode cursor rests one past the last character without an option
+    with_buffer_mode_from("abc\nde", |mut state| {
+        // `l` reaches the end of the line but not beyond
+        state.command("llll");
+        assert_eq!(state.buf.core.cursor().col, 3);
+        state.command("j");
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("k$");
+        assert_eq!(state.buf.core.cursor().col, 3);
+
+        state.command_esc("ax");
+        assert_eq!(state.buf.core.get_string(), "abcx\nde");
+        state.command_esc("$iy");
+        assert_eq!(state.buf.core.get_string(), "abcxy\nde");
+    });
+}
+
+#[test]
+fn test_multiline_search() {
+    with_buffer_mode_from("foo\nbar foo\nbar\nfoo", |mut state| {
+        // Without the option the pattern stays within a line
+        state.command(":%s/foo\\nbar/x/\n");
+        assert_eq!(state.buf.core.get_string(), "foo\nbar foo\nbar\nfoo");
+
+        state.command(":set multiline\n");
+        state.command("/foo\\nbar\n");
+        state.command("n");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 4);
+        state.command("n");
+        assert_eq!(state.buf.core.cursor().row, 0);
+        state.command("N");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 4);
+
+        // The line break counts as one character for the end offset
+        state.command("G/");
+        for _ in 0..state.buf.search.len() {
+            state.event(Event::Key(Key::Backspace));
+        }
+        state.command("foo\\nbar/e\nn");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.cursor().col, 2);
+        state.command("n");
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert_eq!(state.buf.core.cursor().col, 2);
+
+        state.command(":%s/foo\\nbar/x/g\n");
+        assert_eq!(state.buf.core.get_string(), "x x\nfoo");
+        state.command("u");
+
+        // Without `g`, the first match starting on each line
+        state.command(":%s/o\\n?/-/\n");
+        assert_eq!(state.buf.core.get_string(), "f-o\nbar f-o\nbar\nf-o");
+        state.command("u");
+        state.command(":%s/o\\n/-/\n");
+        assert_eq!(state.buf.core.get_string(), "fo-bar fo-bar\nfoo");
+        state.command("u");
+
+        // The last search is used for an empty pattern, and `\r` inserts a line break
+        state.command(":%s//a\\rb/g\n");
+        assert_eq!(state.buf.core.get_string(), "a\nb a\nb\nfoo");
+    });
+}
+
+#[test]
+fn test_scratch_buffer() {
+    let dir = std::env::temp_dir().join(format!("acc_test_scratch_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.txt");
+
+    with_buffer_mode_from("", |mut state| {
+        state.command_esc("iresult");
+        assert!(state.buf.is_modified());
+        state.command(":enew\n");
+        assert_eq!(state.buf.core.get_string(), "result");
+
+        state.command(":set buftype=nofile\n");
+        assert!(!state.buf.is_modified());
+        // `:w path` still exports it
+        state.command(&format!(":w {}\n", path.display()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "result\n");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some(format!("Saved to {}", path.display()).as_str())
+        );
+        state.command_esc("A more");
+        assert!(!state.buf.is_modified());
+
+        // Closed without a save prompt even when modified
+        state.command(":enew\n");
+        assert_eq!(state.buf.core.get_string(), "");
+        assert!(!state.buf.scratch);
+        state.command_esc("inew");
+        assert!(state.buf.is_modified());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_quit_last_window() {
+    with_buffer_mode_from("abc", |mut state| {
+        state.command("x");
+        assert!(state.buf.is_modified());
+
+        // One of several views of the buffer closes at once
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.command("s");
+        assert_eq!(state.window_count(), 2);
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert!(!state.event(Event::Key(Key::Char('q'))));
+        assert_eq!(state.window_count(), 1);
+
+        // The last view of a modified buffer asks first
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert!(!state.event(Event::Key(Key::Char('q'))));
+        assert!(!state.event(Event::Key(Key::Char('n'))));
+        assert_eq!(state.buf.core.get_string(), "bc");
+        state.event(Event::Key(Key::Ctrl('w')));
+        state.event(Event::Key(Key::Char('q')));
+        assert!(state.event(Event::Key(Key::Char('y'))));
+    });
+
+    with_buffer_mode_from("abc", |mut state| {
+        // `:close` keeps the last window
+        state.command("x:close\n");
+        assert_eq!(
+            state.buf.messages.back().map(String::as_str),
+            Some("Cannot close last window")
+        );
+        // Space q is the same quit
+        state.command(" ");
+        assert!(!state.event(Event::Key(Key::Char('q'))));
+        assert!(state.event(Event::Key(Key::Char('y'))));
+    });
+
+    // Nothing to ask without changes
+    with_buffer_mode(|mut state| {
+        assert!(!state.buf.is_modified());
+        state.event(Event::Key(Key::Ctrl('w')));
+        assert!(state.event(Event::Key(Key::Char('q'))));
+    });
+}
+
+#[test]
+fn test_offscreen_selection() {
+    use accepted::draw::styles::SELECTION_EDGE;
+
+    let text = (0..100)
+        .map(|i| format!("line {}", i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    with_buffer_mode_from(&text, |mut state| {
+        state.resize(10, 40);
+        let marks = |state: &mut BufferMode| {
+            let mut term = accepted::draw::Term::new(10, 40);
+            state.draw(&mut term);
+            let mark = |row: usize| {
+                let c = term.row_string(row).chars().last();
+                c.filter(|_| term.style(row, 39) == Some(SELECTION_EDGE))
+            };
+            (mark(0), mark(9))
+        };
+
+        // Fully visible
+        state.command("Vjj");
+        assert_eq!(marks(&mut state), (None, None));
+
+        // Scrolled off the top
+        state.resize(10, 40);
+        state.command(&"j".repeat(30));
+        assert_eq!(marks(&mut state), (Some('▲'), None));
+        assert!(state.buf.row_offset() > 0);
+
+        // Continues below the screen
+        state.event(Event::Key(Key::Esc));
+        state.resize(10, 40);
+        state.command(&format!("V{}", "k".repeat(20)));
+        assert_eq!(marks(&mut state), (None, Some('▼')));
+
+        // Charwise, extended upward from below the screen
+        state.event(Event::Key(Key::Esc));
+        state.resize(10, 40);
+        state.command(&format!("60Gv{}", "k".repeat(20)));
+        assert_eq!(marks(&mut state), (None, Some('▼')));
+
+        // Next to the scrollbar
+        state.event(Event::Key(Key::Esc));
+        state.command(":set scrollbar\n");
+        state.resize(10, 40);
+        state.command(&format!("V{}", "j".repeat(20)));
+        let mut term = accepted::draw::Term::new(10, 40);
+        state.draw(&mut term);
+        let row: Vec<char> = term.row_string(0).chars().collect();
+        assert_eq!(row[38], '▲');
+        assert!(row[39] == '│' || row[39] == '█');
+    });
+}